@@ -8,21 +8,148 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 //
 
+// Measure the throughput of reading events from a video stream written by pravegasink.
+// The stream is read with each of the following methods:
+//   copy:      read each event into a Vec, then copy the payload into a new gst::Buffer
+//              (the method used by pravegasrc before zero-copy reads)
+//   zero-copy: read the event header, then read the payload directly into a pooled gst::Buffer
+//              (the method used by pravegasrc)
+//   pipeline:  run the pipeline "pravegasrc ! fakesink"
+
+use clap::Clap;
 use gst::prelude::*;
+use gstpravega::buffer_pools::SizedBufferPools;
+use pravega_client::client_factory::ClientFactory;
+use pravega_video::event_serde::EventReader;
+use pravega_video::storage::{FileStorage, PravegaStorage, Storage, StorageReader, StorageType, StreamLocation};
+use pravega_video::utils::{self, CurrentHead};
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-fn main() {
-    // Initialize GStreamer
-    gst::init().unwrap();
+#[derive(Clap)]
+struct Opts {
+    /// Pravega controller in format "127.0.0.1:9090"
+    #[clap(short, long, default_value = "127.0.0.1:9090")]
+    controller: String,
+    /// Stream in the format scope/stream or file:///root/scope/stream
+    #[clap(long)]
+    stream: String,
+    /// Comma-separated list of read methods: copy, zero-copy, pipeline
+    #[clap(long, default_value = "copy,zero-copy,pipeline")]
+    methods: String,
+    /// Number of times to read the stream with each method
+    #[clap(long, default_value = "3")]
+    iterations: u32,
+    /// Size of the read buffer
+    #[clap(long, default_value = "8388608")]
+    buffer_size: usize,
+    /// Pravega keycloak file
+    #[clap(long, default_value = "", setting(clap::ArgSettings::AllowEmptyValues))]
+    keycloak_file: String,
+}
+
+#[derive(Default)]
+struct Stats {
+    events: u64,
+    bytes: u64,
+    elapsed: Duration,
+}
+
+impl Stats {
+    fn report(&self, method: &str, iteration: u32) {
+        let seconds = self.elapsed.as_secs_f64();
+        println!("{:10} {:4} {:10} {:14} {:10.3} {:12.1} {:12.1} {:10.3}",
+            method, iteration, self.events, self.bytes, seconds,
+            self.events as f64 / seconds,
+            self.bytes as f64 / seconds / 1e6,
+            1e6 * seconds / self.events as f64);
+    }
+}
+
+/// Read all events in the stream. `read_event` reads one event and returns its payload length.
+fn read_stream<F>(reader: Box<dyn StorageReader>, buffer_size: usize, mut read_event: F) -> Stats
+where
+    F: FnMut(&mut BufReader<std::io::Take<Box<dyn StorageReader>>>) -> std::io::Result<usize>,
+{
+    let mut reader = reader;
+    // Read from the head to the current tail instead of blocking at the end of an unsealed stream.
+    let head = reader.current_head().expect("get head");
+    reader.seek(SeekFrom::Start(head)).expect("seek to head");
+    let tail = reader.current_tail().expect("get tail");
+    let mut reader = BufReader::with_capacity(buffer_size, reader.take(tail - head));
+    let mut stats = Stats::default();
+    let start = Instant::now();
+    loop {
+        match read_event(&mut reader) {
+            Ok(payload_length) => {
+                stats.events += 1;
+                stats.bytes += payload_length as u64;
+            },
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => panic!("Failed to read event: {}", e),
+        }
+    }
+    stats.elapsed = start.elapsed();
+    stats
+}
+
+fn read_with_copy(reader: Box<dyn StorageReader>, buffer_size: usize) -> Stats {
+    read_stream(reader, buffer_size, |reader| {
+        let mut event_reader = EventReader::new();
+        let required_buffer_length = event_reader.read_required_buffer_length(reader)?;
+        let mut read_buffer: Vec<u8> = vec![0; required_buffer_length];
+        let event = event_reader.read_event(reader, &mut read_buffer[..])?;
+        let mut gst_buffer = gst::Buffer::with_size(event.payload.len()).unwrap();
+        {
+            let buffer_ref = gst_buffer.get_mut().unwrap();
+            let mut buffer_map = buffer_ref.map_writable().unwrap();
+            buffer_map.as_mut_slice().copy_from_slice(event.payload);
+        }
+        Ok(gst_buffer.size())
+    })
+}
+
+fn read_with_zero_copy(reader: Box<dyn StorageReader>, buffer_size: usize) -> Stats {
+    let mut pools = SizedBufferPools::new();
+    read_stream(reader, buffer_size, |reader| {
+        let mut event_reader = EventReader::new();
+        event_reader.read_required_buffer_length(reader)?;
+        event_reader.read_header(reader)?;
+        let mut gst_buffer = pools.acquire_buffer(event_reader.payload_length()).unwrap();
+        {
+            let buffer_ref = gst_buffer.get_mut().unwrap();
+            let mut buffer_map = buffer_ref.map_writable().unwrap();
+            event_reader.read_payload(reader, buffer_map.as_mut_slice())?;
+        }
+        Ok(gst_buffer.size())
+    })
+}
 
-    let pipeline_description = concat!(
-        "   filesrc location=with-timestamps5.ts",
-        // "   pravegasrc stream=examples/with-timestamps1",
-        " ! timestampremove",
-        " ! fakesink",
-    );
-    let pipeline = gst::parse_launch(pipeline_description).unwrap();
+fn read_with_pipeline(opts: &Opts) -> Stats {
+    let pipeline_description = format!(
+        "pravegasrc name=src stream={} controller={} buffer-size={} end-mode=latest ! fakesink name=sink",
+        opts.stream, opts.controller, opts.buffer_size);
+    let pipeline = gst::parse_launch(&pipeline_description).unwrap().dynamic_cast::<gst::Pipeline>().unwrap();
+    if !opts.keycloak_file.is_empty() {
+        let src = pipeline.by_name("src").unwrap();
+        src.set_property("keycloak-file", &opts.keycloak_file).unwrap();
+    }
+
+    // Count buffers and bytes received by the sink.
+    let stats = Arc::new(Mutex::new(Stats::default()));
+    let sink_pad = pipeline.by_name("sink").unwrap().static_pad("sink").unwrap();
+    let stats_clone = stats.clone();
+    sink_pad.add_probe(gst::PadProbeType::BUFFER, move |_, probe_info| {
+        if let Some(gst::PadProbeData::Buffer(ref buffer)) = probe_info.data {
+            let mut stats = stats_clone.lock().unwrap();
+            stats.events += 1;
+            stats.bytes += buffer.size() as u64;
+        }
+        gst::PadProbeReturn::Ok
+    });
 
-    // Start playing
+    let start = Instant::now();
     pipeline
         .set_state(gst::State::Playing)
         .expect("Unable to set the pipeline to the `Playing` state");
@@ -35,20 +162,81 @@ fn main() {
         match msg.view() {
             MessageView::Eos(..) => break,
             MessageView::Error(err) => {
-                println!(
+                panic!(
                     "Error from {:?}: {} ({:?})",
                     err.src().map(|s| s.path_string()),
                     err.error(),
                     err.debug()
                 );
-                break;
             }
             _ => (),
         }
     }
+    let elapsed = start.elapsed();
 
     // Shutdown pipeline
     pipeline
         .set_state(gst::State::Null)
         .expect("Unable to set the pipeline to the `Null` state");
+
+    let mut stats = stats.lock().unwrap();
+    stats.elapsed = elapsed;
+    std::mem::take(&mut *stats)
+}
+
+fn main() {
+    env_logger::init();
+    gst::init().unwrap();
+    let opts: Opts = Opts::parse();
+
+    let location = StreamLocation::parse(&opts.stream).expect("parse stream");
+    // Keep the client factory to keep the tokio runtime (Pravega storage only).
+    let (storage, _client_factory): (Box<dyn Storage>, Option<ClientFactory>) = match location.storage_type {
+        StorageType::Pravega => {
+            let keycloak_file = if opts.keycloak_file.is_empty() {
+                None
+            } else {
+                Some(opts.keycloak_file.clone())
+            };
+            let client_config = utils::create_client_config(opts.controller.clone(), keycloak_file).expect("creating config");
+            let client_factory = ClientFactory::new(client_config);
+            (Box::new(PravegaStorage::new(client_factory.to_async())), Some(client_factory))
+        },
+        StorageType::File { ref root } => (Box::new(FileStorage::new(root.clone())), None),
+    };
+
+    println!("{:10} {:>4} {:>10} {:>14} {:>10} {:>12} {:>12} {:>10}",
+        "method", "iter", "events", "bytes", "seconds", "events/sec", "MB/sec", "usec/event");
+    let mut totals: Vec<(String, Stats)> = Vec::new();
+    for method in opts.methods.split(',') {
+        let mut total = Stats::default();
+        for iteration in 0..opts.iterations {
+            let stats = match method {
+                "copy" | "zero-copy" => {
+                    let reader = storage.create_reader(&location.scope, &location.stream).expect("create reader");
+                    if method == "copy" {
+                        read_with_copy(reader, opts.buffer_size)
+                    } else {
+                        read_with_zero_copy(reader, opts.buffer_size)
+                    }
+                },
+                "pipeline" => read_with_pipeline(&opts),
+                _ => panic!("Unknown method {}", method),
+            };
+            stats.report(method, iteration);
+            total.events += stats.events;
+            total.bytes += stats.bytes;
+            total.elapsed += stats.elapsed;
+        }
+        totals.push((method.to_owned(), total));
+    }
+
+    println!();
+    for (method, total) in totals.iter() {
+        total.report(method, opts.iterations);
+    }
+    let seconds_of = |method: &str| totals.iter().find(|(m, _)| m == method).map(|(_, t)| t.elapsed.as_secs_f64());
+    if let (Some(copy_seconds), Some(zero_copy_seconds)) = (seconds_of("copy"), seconds_of("zero-copy")) {
+        println!("zero-copy speedup over copy: {:.2}x", copy_seconds / zero_copy_seconds);
+    }
 }
@@ -0,0 +1,243 @@
+//
+// Copyright (c) Dell Inc., or its subsidiaries. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+
+// A long-running service that applies retention policies to video streams written by the pravegasink GStreamer plugin.
+// This allows streams to be truncated even when the pipeline that writes them is not running.
+
+use clap::Clap;
+use futures::{StreamExt, future};
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::{thread, time};
+
+use gstpravega::pravegatc::checkpoint_store::{CheckpointStore, CheckpointStoreLocation, FileCheckpointStore, TableCheckpointStore};
+use gstpravega::pravegatc::recovery::read_persistent_state;
+use pravega_client::client_factory::{ClientFactory, ClientFactoryAsync};
+use pravega_client_shared::Scope;
+use pravega_controller_client::paginator::{list_scopes, list_streams_for_tag};
+use pravega_video::retention::{ConsumerRetention, RetentionConfig, RetentionRule, truncate_stream};
+use pravega_video::storage::PravegaStorage;
+use pravega_video::timestamp::PravegaTimestamp;
+use pravega_video::utils;
+
+/// Periodically truncate all video streams according to the retention rules in a policy file.
+/// Video streams are discovered by their tag. Each rule matches streams by scope and stream patterns.
+/// If a rule lists consumers, data that has not been processed by all of them is kept,
+/// unless it exceeds the maximum retention of the rule.
+/// A JSON report of each truncation is written to stdout.
+#[derive(Clap)]
+struct Opts {
+    /// Pravega controller in format "127.0.0.1:9090"
+    #[clap(short, long, default_value = "127.0.0.1:9090")]
+    controller: String,
+    /// Pravega keycloak file
+    #[clap(long, default_value = "", setting(clap::ArgSettings::AllowEmptyValues))]
+    keycloak_file: String,
+    /// JSON file with retention rules. It is reloaded for each pass.
+    #[clap(long)]
+    policy_file: PathBuf,
+    /// Pravega scopes to search for video streams. If not specified, all scopes are searched.
+    #[clap(long)]
+    scope: Vec<String>,
+    /// Number of seconds between passes.
+    #[clap(long, default_value = "60")]
+    interval_sec: u64,
+    /// Perform a single pass and exit.
+    #[clap(long)]
+    once: bool,
+}
+
+fn main() {
+    env_logger::init();
+    let opts: Opts = Opts::parse();
+    let keycloak_file = if opts.keycloak_file.is_empty() {
+        None
+    } else {
+        Some(opts.keycloak_file.clone())
+    };
+    let client_config = utils::create_client_config(opts.controller.clone(), keycloak_file).expect("creating config");
+    let client_factory = ClientFactory::new(client_config);
+    let storage = PravegaStorage::new(client_factory.to_async());
+    let mut consumer_stores = ConsumerStores::new(client_factory.to_async());
+    let mut config = match RetentionConfig::load(&opts.policy_file) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("Unable to load {}: {}", opts.policy_file.display(), err);
+            std::process::exit(1);
+        },
+    };
+    info!("Loaded {} retention rules from {}", config.rules.len(), opts.policy_file.display());
+
+    loop {
+        match RetentionConfig::load(&opts.policy_file) {
+            Ok(new_config) => {
+                if new_config != config {
+                    info!("Reloaded {} retention rules from {}", new_config.rules.len(), opts.policy_file.display());
+                    config = new_config;
+                }
+            },
+            Err(err) => warn!("Unable to reload {}; using previous rules: {}", opts.policy_file.display(), err),
+        }
+        run_pass(&client_factory, &storage, &mut consumer_stores, &config, &opts.scope);
+        if opts.once {
+            break;
+        }
+        thread::sleep(time::Duration::from_secs(opts.interval_sec));
+    }
+}
+
+/// Apply the retention rules to all video streams in the scopes.
+fn run_pass(client_factory: &ClientFactory, storage: &PravegaStorage, consumer_stores: &mut ConsumerStores,
+        config: &RetentionConfig, scopes: &[String]) {
+    let scopes = if scopes.is_empty() {
+        match list_all_scopes(client_factory) {
+            Ok(scopes) => scopes,
+            Err(err) => {
+                warn!("{}", err);
+                return;
+            },
+        }
+    } else {
+        scopes.to_vec()
+    };
+    let now = PravegaTimestamp::now();
+    for scope in scopes.iter() {
+        let streams = match list_video_streams(client_factory, scope) {
+            Ok(streams) => streams,
+            Err(err) => {
+                warn!("{}", err);
+                continue;
+            },
+        };
+        for stream in streams.iter() {
+            let rule = match config.find_rule(scope, stream) {
+                Some(rule) => rule,
+                None => continue,
+            };
+            info!("Applying retention rule {:?} to stream {}/{}", rule, scope, stream);
+            let consumers = consumer_stores.consumer_retention(rule, scope, stream);
+            match truncate_stream(storage, scope, stream, now, rule.retention_days, rule.retention_bytes, consumers.as_ref()) {
+                Ok(Some(report)) => {
+                    info!("Truncated stream {}/{} at offset {}", scope, stream, report.data_offset);
+                    println!("{}", serde_json::to_string(&report).unwrap());
+                },
+                Ok(None) => {},
+                Err(err) => warn!("Unable to truncate stream {}/{}: {}", scope, stream, err),
+            }
+        }
+    }
+}
+
+/// The pravegatc checkpoint stores of the consumers in the retention rules.
+/// Stores are opened when first needed and kept for later passes.
+struct ConsumerStores {
+    client_factory: ClientFactoryAsync,
+    stores: HashMap<String, Box<dyn CheckpointStore>>,
+}
+
+impl ConsumerStores {
+    fn new(client_factory: ClientFactoryAsync) -> ConsumerStores {
+        ConsumerStores {
+            client_factory,
+            stores: HashMap::new(),
+        }
+    }
+
+    /// Returns the consumer retention of a stream, or None if the rule has no consumers.
+    fn consumer_retention(&mut self, rule: &RetentionRule, scope: &str, stream: &str) -> Option<ConsumerRetention> {
+        if rule.consumers.is_empty() {
+            return None;
+        }
+        Some(ConsumerRetention {
+            oldest_checkpoint: self.oldest_checkpoint(&rule.consumers, &format!("{}/{}", scope, stream)),
+            max_days: rule.max_retention_days,
+            max_bytes: rule.max_retention_bytes,
+        })
+    }
+
+    /// Returns the oldest checkpoint of the stream among the consumers.
+    /// A consumer without a checkpoint, or whose checkpoint cannot be read, has not processed any data
+    /// and its checkpoint is PravegaTimestamp::NONE.
+    fn oldest_checkpoint(&mut self, consumers: &[String], stream: &str) -> PravegaTimestamp {
+        let mut oldest: Option<PravegaTimestamp> = None;
+        for consumer in consumers.iter() {
+            let checkpoint = match self.read_checkpoint(consumer, stream) {
+                Ok(Some(checkpoint)) => checkpoint,
+                Ok(None) => {
+                    warn!("Consumer {} does not have a checkpoint for stream {}", consumer, stream);
+                    PravegaTimestamp::NONE
+                },
+                Err(err) => {
+                    warn!("Unable to read checkpoint of consumer {} for stream {}: {}", consumer, stream, err);
+                    PravegaTimestamp::NONE
+                },
+            };
+            debug!("Consumer {} checkpoint for stream {} is {}", consumer, stream, checkpoint);
+            if checkpoint.is_none() {
+                return PravegaTimestamp::NONE;
+            }
+            if oldest.map_or(true, |oldest| checkpoint < oldest) {
+                oldest = Some(checkpoint);
+            }
+        }
+        oldest.unwrap_or(PravegaTimestamp::NONE)
+    }
+
+    fn read_checkpoint(&mut self, consumer: &str, stream: &str) -> anyhow::Result<Option<PravegaTimestamp>> {
+        if !self.stores.contains_key(consumer) {
+            let store: Box<dyn CheckpointStore> = match CheckpointStoreLocation::parse(consumer).map_err(anyhow::Error::msg)? {
+                CheckpointStoreLocation::File { dir } => Box::new(FileCheckpointStore::new(dir)?),
+                CheckpointStoreLocation::Table { scope, table } => {
+                    Box::new(TableCheckpointStore::open_read_only(&self.client_factory, &scope, &table)?)
+                },
+            };
+            self.stores.insert(consumer.to_owned(), store);
+        }
+        let store = self.stores.get_mut(consumer).unwrap();
+        let persistent_state = read_persistent_state(store.as_mut(), stream)?;
+        Ok(persistent_state.map(|persistent_state| PravegaTimestamp::from_nanoseconds(Some(persistent_state.resume_at_pts))))
+    }
+}
+
+fn list_all_scopes(client_factory: &ClientFactory) -> anyhow::Result<Vec<String>> {
+    let controller_client = client_factory.controller_client();
+    let mut scopes = Vec::new();
+    let mut had_error = false;
+    client_factory.runtime().block_on(list_scopes(controller_client).for_each(|scope| {
+        match scope {
+            Ok(scope) => scopes.push(scope.name),
+            Err(_) => had_error = true,
+        }
+        future::ready(())
+    }));
+    if had_error {
+        anyhow::bail!("Error listing scopes");
+    }
+    Ok(scopes)
+}
+
+fn list_video_streams(client_factory: &ClientFactory, scope_name: &str) -> anyhow::Result<Vec<String>> {
+    let controller_client = client_factory.controller_client();
+    let scope = Scope { name: scope_name.to_owned() };
+    let mut streams = Vec::new();
+    let mut had_error = false;
+    client_factory.runtime().block_on(list_streams_for_tag(scope, utils::get_video_tag_query(), controller_client).for_each(|stream| {
+        match stream {
+            Ok(scoped_stream) => streams.push(scoped_stream.stream.name),
+            Err(_) => had_error = true,
+        }
+        future::ready(())
+    }));
+    if had_error {
+        anyhow::bail!("Error listing streams for scope={}", scope_name);
+    }
+    Ok(streams)
+}
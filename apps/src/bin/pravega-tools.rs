@@ -11,14 +11,18 @@
 // A CLI that provides tools to manage Pravega streams.
 
 use clap::Clap;
-use std::time::{Duration, SystemTime};
+use std::convert::TryFrom;
+use std::io::Write;
 
 use pravega_client::client_factory::ClientFactory;
 use pravega_client_config::ClientConfigBuilder;
-use pravega_client_shared::{Scope, Stream, ScopedStream};
-use pravega_video::index::{IndexSearcher, SearchMethod, get_index_stream_name};
+use pravega_video::index::{IndexSearcher, IndexSpacing, get_index_stream_name, rebuild_index};
+use pravega_video::integrity::{IntegritySigner, IntegrityVerifier, get_integrity_stream_name, read_checkpoints, verify_integrity};
+use pravega_video::retention;
+use pravega_video::storage::{PravegaStorage, Storage, StorageReader, StorageWriter};
 use pravega_video::timestamp::PravegaTimestamp;
-use pravega_video::utils::{parse_controller_uri, SyncByteReader};
+use pravega_video::utils::{parse_controller_uri, CurrentHead};
+use pravega_video::verify::{VerifyOptions, verify_stream};
 
 /// Tools to manage Pravega streams.
 #[derive(Clap)]
@@ -33,6 +37,10 @@ struct Opts {
 #[derive(Clap)]
 enum SubCommand {
     TruncateStream(TruncateStream),
+    RebuildIndex(RebuildIndex),
+    Verify(Verify),
+    VerifyIntegrity(VerifyIntegrity),
+    IntegrityPublicKey(IntegrityPublicKey),
 }
 
 /// Truncate a stream written by the pravegasink GStreamer plugin.
@@ -49,6 +57,86 @@ struct TruncateStream {
     age_days: f64,
 }
 
+/// Rebuild the index of a stream written by the pravegasink GStreamer plugin.
+/// The index is derived from the flags and timestamps in the data stream.
+/// Use this if the index stream was deleted or truncated incorrectly.
+#[derive(Clap)]
+struct RebuildIndex {
+    /// Pravega scope
+    #[clap(long)]
+    scope: String,
+    /// Pravega data stream
+    #[clap(long)]
+    stream: String,
+    /// Pravega index stream to write. Defaults to the index stream of the data stream.
+    #[clap(long)]
+    index_stream: Option<String>,
+    /// Do not create an index record at a key frame if the previous index record is less than this many seconds earlier.
+    #[clap(long, default_value = "0.5")]
+    index_min_sec: f64,
+    /// Create an index record at a delta frame if there have been no key frames for this many seconds.
+    #[clap(long, default_value = "10.0")]
+    index_max_sec: f64,
+    /// Allow writing to an index stream that already has records. Existing records are truncated after the new index is written.
+    #[clap(long)]
+    force: bool,
+}
+
+/// Check the consistency of a stream written by the pravegasink GStreamer plugin and its index.
+/// A JSON report of violations and gaps is written to stdout.
+/// The exit code is 0 if there are no errors, 2 if there are errors.
+#[derive(Clap)]
+struct Verify {
+    /// Pravega scope
+    #[clap(long)]
+    scope: String,
+    /// Pravega data stream
+    #[clap(long)]
+    stream: String,
+    /// Pravega index stream. Defaults to the index stream of the data stream.
+    #[clap(long)]
+    index_stream: Option<String>,
+    /// Report a gap if consecutive index records are more than this many seconds apart.
+    #[clap(long, default_value = "30.0")]
+    max_gap_sec: f64,
+    /// Maximum number of violations to include in the report.
+    #[clap(long, default_value = "1000")]
+    max_violations: usize,
+}
+
+/// Verify a stream written by the pravegasink GStreamer plugin against the signed checkpoints in its integrity stream.
+/// The stream must have been written with the integrity-key-file property.
+/// The data between the checkpoints that surround the time range is hashed and compared with the checkpoints.
+/// A JSON report of verified ranges, unverified ranges, and violations is written to stdout.
+/// The exit code is 0 if the entire time range was verified, 2 otherwise.
+#[derive(Clap)]
+struct VerifyIntegrity {
+    /// Pravega scope
+    #[clap(long)]
+    scope: String,
+    /// Pravega data stream
+    #[clap(long)]
+    stream: String,
+    /// File containing the Ed25519 public key as 64 hexadecimal digits.
+    #[clap(long)]
+    public_key_file: String,
+    /// Beginning of the time range to verify in RFC 3339 format, such as "2021-12-28T23:41:45.691Z".
+    /// Defaults to the head of the stream.
+    #[clap(long)]
+    start_utc: Option<String>,
+    /// End of the time range to verify in RFC 3339 format. Defaults to the tail of the stream.
+    #[clap(long)]
+    end_utc: Option<String>,
+}
+
+/// Print the Ed25519 public key for an integrity private key file, for use with verify-integrity.
+#[derive(Clap)]
+struct IntegrityPublicKey {
+    /// File containing the Ed25519 private key as 64 hexadecimal digits.
+    #[clap(long)]
+    private_key_file: String,
+}
+
 fn main() {
     env_logger::init();
     let opts: Opts = Opts::parse();
@@ -56,43 +144,143 @@ fn main() {
         SubCommand::TruncateStream(c) => {
             truncate_stream(opts.controller, c.scope, c.stream, c.age_days);
         }
+        SubCommand::RebuildIndex(c) => {
+            rebuild_index_stream(opts.controller, c);
+        }
+        SubCommand::Verify(c) => {
+            verify(opts.controller, c);
+        }
+        SubCommand::VerifyIntegrity(c) => {
+            verify_integrity_stream(opts.controller, c);
+        }
+        SubCommand::IntegrityPublicKey(c) => {
+            let signer = IntegritySigner::from_file(&c.private_key_file).unwrap();
+            println!("{}", signer.public_key_hex());
+        }
     }
 }
 
-fn truncate_stream(controller: String, scope_name: String, stream_name: String, age_days: f64) {
-    let age_seconds = age_days * 24.0 * 60.0 * 60.0;
-    let age = Duration::from_secs_f64(age_seconds);
-    let truncate_at_time = SystemTime::now() - age;
-    let truncate_at_timestamp: PravegaTimestamp = truncate_at_time.into();
-    println!("Truncating stream {}/{} at {}", scope_name, stream_name, truncate_at_timestamp);
-    let index_stream_name = get_index_stream_name(&stream_name);
-    let scope = Scope::from(scope_name);
-    let stream = Stream::from(stream_name);
-    let index_stream = Stream::from(index_stream_name);
+fn create_client_factory(controller: String) -> ClientFactory {
     let controller_uri = parse_controller_uri(controller).unwrap();
     let client_config = ClientConfigBuilder::default()
         .controller_uri(controller_uri)
         .build()
         .expect("creating config");
-    let client_factory = ClientFactory::new(client_config);
-    let runtime = client_factory.runtime();
-    let scoped_stream = ScopedStream {
-        scope: scope.clone(),
-        stream: stream.clone(),
+    ClientFactory::new(client_config)
+}
+
+fn rebuild_index_stream(controller: String, c: RebuildIndex) {
+    let index_stream_name = c.index_stream.unwrap_or_else(|| get_index_stream_name(&c.stream));
+    let spacing = IndexSpacing {
+        index_min_nanos: (c.index_min_sec * 1e9) as u64,
+        index_max_nanos: (c.index_max_sec * 1e9) as u64,
     };
-    let writer = runtime.block_on(client_factory.create_byte_writer(scoped_stream));
-    let index_scoped_stream = ScopedStream {
-        scope: scope.clone(),
-        stream: index_stream.clone(),
+    if spacing.index_min_nanos > spacing.index_max_nanos {
+        eprintln!("index-min-sec must be less than or equal to index-max-sec");
+        std::process::exit(1);
+    }
+    println!("Rebuilding index of stream {}/{} into {}/{}", c.scope, c.stream, c.scope, index_stream_name);
+    let client_factory = create_client_factory(controller);
+    let storage = PravegaStorage::new(client_factory.to_async());
+    storage.create_stream(&c.scope, &index_stream_name, None).unwrap();
+
+    // The index stream must be empty, unless existing records are replaced.
+    let mut index_reader = storage.create_reader(&c.scope, &index_stream_name).unwrap();
+    let index_head = index_reader.current_head().unwrap();
+    let index_tail = index_reader.current_tail().unwrap();
+    if index_tail > index_head && !c.force {
+        eprintln!("Index stream {}/{} has {} bytes of records. Use --force to replace them.",
+            c.scope, index_stream_name, index_tail - index_head);
+        std::process::exit(1);
+    }
+
+    // Build the new index in memory so that the existing records remain intact if the rebuild fails.
+    let mut data_reader = storage.create_reader(&c.scope, &c.stream).unwrap();
+    let data_tail = data_reader.current_tail().unwrap();
+    let mut index_buffer: Vec<u8> = Vec::new();
+    let summary = match rebuild_index(&mut data_reader, data_tail, &mut index_buffer, spacing) {
+        Ok(summary) => summary,
+        Err(e) => {
+            eprintln!("Unable to rebuild index: {}", e);
+            std::process::exit(1);
+        },
     };
-    let index_writer = runtime.block_on(client_factory.create_byte_writer(index_scoped_stream.clone()));
-    let index_reader = runtime.block_on(client_factory.create_byte_reader(index_scoped_stream.clone()));
-    let mut index_searcher = IndexSearcher::new(SyncByteReader::new(index_reader, client_factory.runtime_handle()));
-    let index_record = index_searcher.search_timestamp_and_return_index_offset(
-        truncate_at_timestamp, SearchMethod::Before).unwrap();
-    println!("Truncating prior to {:?}", index_record);
-    runtime.block_on(index_writer.truncate_data_before(index_record.1 as i64)).unwrap();
-    println!("Index truncated at offset {}", index_record.1);
-    runtime.block_on(writer.truncate_data_before(index_record.0.offset as i64)).unwrap();
-    println!("Data truncated at offset {}", index_record.0.offset);
+
+    // Append the new records, then truncate the existing records before them.
+    let mut index_writer = storage.create_writer(&c.scope, &index_stream_name).unwrap();
+    index_writer.write_all(&index_buffer).unwrap();
+    index_writer.flush().unwrap();
+    if index_tail > index_head {
+        index_writer.truncate_data_before(index_tail).unwrap();
+        println!("Truncated existing index records before offset {}", index_tail);
+    }
+    println!("Read {} events from offset {} to {}", summary.events, summary.begin_offset, summary.end_offset);
+    println!("Wrote {} index records", summary.index_records);
+    println!("First index record: {:?}", summary.first_record);
+    println!("Last index record: {:?}", summary.last_record);
+}
+
+fn verify(controller: String, c: Verify) {
+    let index_stream_name = c.index_stream.unwrap_or_else(|| get_index_stream_name(&c.stream));
+    let options = VerifyOptions {
+        max_violations: c.max_violations,
+        max_gap_nanos: (c.max_gap_sec * 1e9) as u64,
+    };
+    let client_factory = create_client_factory(controller);
+    let storage = PravegaStorage::new(client_factory.to_async());
+    let mut data_reader = storage.create_reader(&c.scope, &c.stream).unwrap();
+    let data_tail = data_reader.current_tail().unwrap();
+    let mut index_reader = storage.create_reader(&c.scope, &index_stream_name).unwrap();
+    let index_tail = index_reader.current_tail().unwrap();
+    let report = verify_stream(&mut data_reader, data_tail, &mut index_reader, index_tail, options).unwrap();
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    if !report.is_ok() {
+        std::process::exit(2);
+    }
+}
+
+fn verify_integrity_stream(controller: String, c: VerifyIntegrity) {
+    let verifier = IntegrityVerifier::from_file(&c.public_key_file).unwrap();
+    let start_timestamp = PravegaTimestamp::try_from(c.start_utc.as_deref()).unwrap();
+    let end_timestamp = PravegaTimestamp::try_from(c.end_utc.as_deref()).unwrap();
+    let client_factory = create_client_factory(controller);
+    let storage = PravegaStorage::new(client_factory.to_async());
+    let mut data_reader = storage.create_reader(&c.scope, &c.stream).unwrap();
+    let data_head = data_reader.current_head().unwrap();
+    let data_tail = data_reader.current_tail().unwrap();
+
+    // Map the time range to offsets in the data stream using the index.
+    let index_reader = storage.create_reader(&c.scope, &get_index_stream_name(&c.stream)).unwrap();
+    let mut index_searcher = IndexSearcher::new(index_reader);
+    let begin_offset = if start_timestamp.is_some() {
+        u64::max(data_head, index_searcher.search_timestamp(start_timestamp).unwrap().offset)
+    } else {
+        data_head
+    };
+    let end_offset = if end_timestamp.is_some() {
+        let record = index_searcher.search_timestamp_after(end_timestamp).unwrap();
+        // The index record is before the end timestamp only if the end timestamp is after the last index record.
+        if record.timestamp < end_timestamp { data_tail } else { record.offset }
+    } else {
+        data_tail
+    };
+    eprintln!("Verifying stream {}/{} from offset {} to {}", c.scope, c.stream, begin_offset, end_offset);
+
+    let mut integrity_reader = storage.create_reader(&c.scope, &get_integrity_stream_name(&c.stream)).unwrap();
+    let checkpoints = read_checkpoints(&mut integrity_reader).unwrap();
+    let report = verify_integrity(&mut data_reader, data_tail, &checkpoints, &verifier, begin_offset, end_offset).unwrap();
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    if !report.is_ok() {
+        std::process::exit(2);
+    }
+}
+
+fn truncate_stream(controller: String, scope_name: String, stream_name: String, age_days: f64) {
+    println!("Truncating stream {}/{} to {} days", scope_name, stream_name, age_days);
+    let client_factory = create_client_factory(controller);
+    let storage = PravegaStorage::new(client_factory.to_async());
+    match retention::truncate_stream(&storage, &scope_name, &stream_name, PravegaTimestamp::now(), Some(age_days), None, None).unwrap() {
+        Some(report) => println!("{}", serde_json::to_string_pretty(&report).unwrap()),
+        None => println!("Nothing to truncate"),
+    }
 }
@@ -21,8 +21,10 @@ use gtk::prelude::*;
 use gtk::{Box, DrawingArea, Inhibit, Orientation, Window, WindowType};
 use pravega_video::timestamp::PravegaTimestamp;
 use std::{convert::TryInto, os::raw::c_void, time::SystemTime};
+use std::cell::Cell;
 use std::process;
 use std::ops;
+use std::rc::Rc;
 #[allow(unused_imports)]
 use tracing::{error, warn, info, debug, trace, event, Level, span};
 use tracing_subscriber::fmt::format::FmtSpan;
@@ -35,6 +37,9 @@ pub const DEFAULT_GST_DEBUG: &str = "FIXME";
 /// Valid levels are: error, warn, info, debug, trace
 pub const DEFAULT_RUST_LOG: &str = "pravega_video_player=info,warn";
 
+/// The maximum trick mode playback rate for fast-forward and rewind.
+const MAX_TRICK_MODE_RATE: f64 = 256.0;
+
 /// Pravega video player.
 #[derive(Clap)]
 struct Opts {
@@ -86,6 +91,39 @@ fn format_nanos_since_epoch(nanos_since_epoch: u64) -> String {
     formatted_time.to_string()
 }
 
+// Change the playback rate, continuing from the current position.
+// When the rate is not 1.0, pravegasrc will only emit key frames.
+fn seek_with_rate(pipeline: &gst::Pipeline, rate: f64) -> bool {
+    let position = match pipeline.query_position::<gst::ClockTime>() {
+        Some(position) => position,
+        None => {
+            error!("seek_with_rate: Unable to query position");
+            return false;
+        },
+    };
+    info!("seek_with_rate: rate={}, position={:?}", rate, position);
+    let mut flags = gst::SeekFlags::FLUSH | gst::SeekFlags::KEY_UNIT;
+    if rate != 1.0 {
+        flags |= gst::SeekFlags::TRICKMODE | gst::SeekFlags::TRICKMODE_KEY_UNITS;
+    }
+    let result = if rate > 0.0 {
+        pipeline.seek(rate, flags,
+            gst::SeekType::Set, position,
+            gst::SeekType::None, gst::CLOCK_TIME_NONE)
+    } else {
+        pipeline.seek(rate, flags,
+            gst::SeekType::Set, gst::ClockTime::from_nseconds(0),
+            gst::SeekType::Set, position)
+    };
+    match result {
+        Ok(_) => true,
+        Err(err) => {
+            error!("seek_with_rate: Seeking with rate {} failed: {}", rate, err);
+            false
+        },
+    }
+}
+
 // This creates all the GTK+ widgets that compose our application, and registers the callbacks.
 fn create_ui(playbin: &gst::Pipeline, video_sink: &gst::Element) -> AppWindow {
     let main_window = Window::new(WindowType::Toplevel);
@@ -94,16 +132,47 @@ fn create_ui(playbin: &gst::Pipeline, video_sink: &gst::Element) -> AppWindow {
         Inhibit(false)
     });
 
+    // The current playback rate. Negative rates play in reverse.
+    let rate = Rc::new(Cell::new(1.0));
+
     let play_button =
         gtk::Button::from_icon_name(Some("media-playback-start"), gtk::IconSize::SmallToolbar);
     let pipeline = playbin.clone();
+    let play_rate = rate.clone();
     play_button.connect_clicked(move |_| {
         let pipeline = &pipeline;
+        if play_rate.get() != 1.0 && seek_with_rate(pipeline, 1.0) {
+            play_rate.set(1.0);
+        }
         pipeline
             .set_state(gst::State::Playing)
             .expect("Unable to set the pipeline to the `Playing` state");
     });
 
+    // Each click doubles the rewind rate.
+    let rewind_button =
+        gtk::Button::from_icon_name(Some("media-seek-backward"), gtk::IconSize::SmallToolbar);
+    let pipeline = playbin.clone();
+    let rewind_rate = rate.clone();
+    rewind_button.connect_clicked(move |_| {
+        let new_rate = if rewind_rate.get() > 0.0 { -2.0 } else { f64::max(-MAX_TRICK_MODE_RATE, 2.0 * rewind_rate.get()) };
+        if seek_with_rate(&pipeline, new_rate) {
+            rewind_rate.set(new_rate);
+        }
+    });
+
+    // Each click doubles the fast-forward rate.
+    let fast_forward_button =
+        gtk::Button::from_icon_name(Some("media-seek-forward"), gtk::IconSize::SmallToolbar);
+    let pipeline = playbin.clone();
+    let fast_forward_rate = rate.clone();
+    fast_forward_button.connect_clicked(move |_| {
+        let new_rate = if fast_forward_rate.get() < 2.0 { 2.0 } else { f64::min(MAX_TRICK_MODE_RATE, 2.0 * fast_forward_rate.get()) };
+        if seek_with_rate(&pipeline, new_rate) {
+            fast_forward_rate.set(new_rate);
+        }
+    });
+
     let pause_button =
         gtk::Button::from_icon_name(Some("media-playback-pause"), gtk::IconSize::SmallToolbar);
     let pipeline = playbin.clone();
@@ -136,10 +205,12 @@ fn create_ui(playbin: &gst::Pipeline, video_sink: &gst::Element) -> AppWindow {
         1.0 as f64,
     );
     let pipeline = playbin.clone();
+    let slider_rate = rate.clone();
     let slider_update_signal_id = slider.connect_value_changed(move |slider| {
         let pipeline = &pipeline;
         let value = slider.value() as u64;
         info!("create_ui: handling slider change; value={}", value);
+        // A simple seek resumes playback at the normal rate.
         if pipeline
             .seek_simple(
                 gst::SeekFlags::FLUSH | gst::SeekFlags::KEY_UNIT,
@@ -148,6 +219,8 @@ fn create_ui(playbin: &gst::Pipeline, video_sink: &gst::Element) -> AppWindow {
             .is_err()
         {
             error!("Seeking to {} failed", value);
+        } else {
+            slider_rate.set(1.0);
         }
     });
 
@@ -224,8 +297,10 @@ fn create_ui(playbin: &gst::Pipeline, video_sink: &gst::Element) -> AppWindow {
     });
 
     let controls = Box::new(Orientation::Horizontal, 0);
+    controls.pack_start(&rewind_button, false, false, 0);
     controls.pack_start(&play_button, false, false, 0);
     controls.pack_start(&pause_button, false, false, 0);
+    controls.pack_start(&fast_forward_button, false, false, 0);
     controls.pack_start(&stop_button, false, false, 0);
     controls.pack_start(&slider, true, true, 2);
 
@@ -12,7 +12,7 @@ use clap::Clap;
 
 use pravega_client::client_factory::ClientFactory;
 use pravega_client_shared::{Scope, Stream, ScopedStream};
-use pravega_video::event_serde::EventReader;
+use pravega_video::event_serde::{EventReader, is_checksum_error};
 use pravega_video::index::{IndexRecord, IndexRecordReader};
 use pravega_video::utils;
 use pravega_video::utils::{CurrentHead, SyncByteReader};
@@ -93,6 +93,7 @@ fn main() {
                 stream_reader.seek(SeekFrom::Start(stream_begin_offset)).expect("seek to stream begin offset");
                 let mut reader = stream_reader.take(stream_end_offset - stream_begin_offset);
                 loop {
+                    let event_offset = stream_end_offset - reader.limit();
                     let mut event_reader = EventReader::new();
                     let required_buffer_length =
                         match event_reader.read_required_buffer_length(&mut reader) {
@@ -106,8 +107,12 @@ fn main() {
                             },
                     };
                     let mut read_buffer: Vec<u8> = vec![0; required_buffer_length];
-                    let event = event_reader.read_event(&mut reader, &mut read_buffer[..]).expect("read event");
-                    println!("{:?}", event.header);
+                    match event_reader.read_event(&mut reader, &mut read_buffer[..]) {
+                        Ok(event) => println!("{:?}", event.header),
+                        // The event was read completely, so the following events can still be dumped.
+                        Err(e) if is_checksum_error(&e) => println!("Event at offset {}: {}", event_offset, e),
+                        Err(e) => panic!("read event: {:?}", e),
+                    }
                 }
                 stream_reader = reader.into_inner();
             }
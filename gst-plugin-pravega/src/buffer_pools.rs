@@ -0,0 +1,94 @@
+//
+// Copyright (c) Dell Inc., or its subsidiaries. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+
+use gst::prelude::*;
+
+/// The smallest pooled buffer is 64 KiB.
+const MIN_SIZE_CLASS_BITS: u32 = 16;
+/// The largest pooled buffer is 8 MiB, which is the maximum event size.
+const MAX_SIZE_CLASS_BITS: u32 = 23;
+const NUM_SIZE_CLASSES: usize = (MAX_SIZE_CLASS_BITS - MIN_SIZE_CLASS_BITS + 1) as usize;
+
+/// A set of buffer pools, one for each power-of-2 buffer size.
+/// Buffers of varying sizes are reused without allocating the maximum size for every buffer.
+/// Pools are created when they are first needed.
+/// A buffer returns to its pool when its last reference is dropped, even after the pools are dropped.
+pub struct SizedBufferPools {
+    pools: Vec<Option<gst::BufferPool>>,
+}
+
+impl SizedBufferPools {
+    pub fn new() -> Self {
+        SizedBufferPools {
+            pools: vec![None; NUM_SIZE_CLASSES],
+        }
+    }
+
+    /// Returns a writable buffer with a size of exactly `size` bytes.
+    /// The contents of the buffer are undefined.
+    /// Buffers larger than the largest size class are allocated without a pool.
+    pub fn acquire_buffer(&mut self, size: usize) -> Result<gst::Buffer, gst::FlowError> {
+        let size_class_bits = u32::max(MIN_SIZE_CLASS_BITS, size.next_power_of_two().trailing_zeros());
+        if size_class_bits > MAX_SIZE_CLASS_BITS {
+            return gst::Buffer::with_size(size).map_err(|_| gst::FlowError::Error);
+        }
+        let pool = match &mut self.pools[(size_class_bits - MIN_SIZE_CLASS_BITS) as usize] {
+            Some(pool) => pool,
+            empty => empty.insert(SizedBufferPools::create_pool(1 << size_class_bits)?),
+        };
+        let mut buffer = pool.acquire_buffer(None)?;
+        // The pool restores the original size when the buffer is released.
+        buffer.get_mut().unwrap().set_size(size);
+        Ok(buffer)
+    }
+
+    fn create_pool(buffer_size: u32) -> Result<gst::BufferPool, gst::FlowError> {
+        let pool = gst::BufferPool::new();
+        let mut config = pool.config();
+        config.set_params(None, buffer_size, 0, 0);
+        pool.set_config(config).map_err(|_| gst::FlowError::Error)?;
+        pool.set_active(true).map_err(|_| gst::FlowError::Error)?;
+        Ok(pool)
+    }
+}
+
+impl Default for SizedBufferPools {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for SizedBufferPools {
+    fn drop(&mut self) {
+        for pool in self.pools.iter().flatten() {
+            let _ = pool.set_active(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sized_buffer_pools() {
+        gst::init().unwrap();
+        let mut pools = SizedBufferPools::new();
+        for size in [0, 1, 188, 65536, 65537, 8 * 1024 * 1024, 8 * 1024 * 1024 + 1].iter() {
+            let buffer = pools.acquire_buffer(*size).unwrap();
+            assert_eq!(buffer.size(), *size);
+            assert!(buffer.is_writable());
+        }
+        assert!(pools.pools[0].is_some());
+        assert!(pools.pools[1].is_some());
+        assert!(pools.pools[2].is_none());
+        assert!(pools.pools[NUM_SIZE_CLASSES - 1].is_some());
+    }
+}
@@ -41,6 +41,13 @@ impl<T: Write + Seek> CountingWriter<T> {
     pub fn get_mut(&mut self) -> &mut T {
         &mut self.inner
     }
+
+    /// Gets the offset from the underlying writer.
+    /// This must be called after the underlying writer is repositioned, such as when it is truncated.
+    pub fn refresh_offset(&mut self) -> Result<u64> {
+        self.offset = self.inner.seek(SeekFrom::Current(0))?;
+        Ok(self.offset)
+    }
 }
 
 impl<T: Write + Seek> Write for CountingWriter<T> {
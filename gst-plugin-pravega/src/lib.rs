@@ -10,14 +10,14 @@
 
 use gst::glib;
 
+pub mod buffer_pools;
 mod counting_reader;
 mod counting_writer;
 mod numeric;
 mod fragmp4pay;
 mod pravegasink;
 mod pravegasrc;
-mod pravegatc;
-mod seekable_byte_stream_writer;
+pub mod pravegatc;
 mod seekable_take;
 mod timestampcvt;
 pub mod utils;
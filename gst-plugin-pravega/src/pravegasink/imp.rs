@@ -15,13 +15,14 @@
 use gst::glib;
 use gst::prelude::*;
 use gst::subclass::prelude::*;
-use gst::{debug, error, fixme, info, log, trace, memdump};
+use gst::{debug, error, fixme, info, log, trace, memdump, warning};
 use gst_base::subclass::prelude::*;
 
 use std::cmp;
+use std::collections::{HashMap, VecDeque};
 use std::convert::TryInto;
-use std::io::{BufWriter, Seek, SeekFrom, Write};
-use std::sync::Mutex;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
 use std::time::Duration;
 use std::sync::mpsc::{self, Sender, Receiver, RecvTimeoutError};
@@ -29,19 +30,28 @@ use std::sync::mpsc::{self, Sender, Receiver, RecvTimeoutError};
 use once_cell::sync::Lazy;
 
 use pravega_client::client_factory::ClientFactoryAsync;
-use pravega_client::byte::ByteWriter;
-use pravega_client_shared::{Scope, Stream, StreamConfiguration, ScopedStream, Scaling, ScaleType};
-use pravega_video::event_serde::{EventWithHeader, EventWriter};
-use pravega_video::index::{IndexRecord, IndexRecordWriter, IndexSearcher, SearchMethod, get_index_stream_name};
-use pravega_video::timestamp::{PravegaTimestamp, SECOND};
+use pravega_video::encryption::{KeyRing, encryption_overhead};
+use pravega_video::event_serde::{EventHeaderExtension, EventReader, EventWithHeader, EventWriter};
+use pravega_video::index::{IndexRecord, IndexRecordReader, IndexRecordWriter, IndexSearcher, STREAM_RECORD_NUMBER_LOOKBACK, Track,
+    get_index_stream_name, get_stream_record_number};
+use pravega_video::integrity::{HashChain, IntegrityCheckpoint, IntegrityCheckpointWriter, IntegritySigner, get_integrity_stream_name};
+use pravega_video::metadata::{MetadataRecord, MetadataRecordWriter, MetadataSearcher, StreamMetadata, get_metadata_stream_name};
+use pravega_video::mp4;
+use pravega_video::mpegts::MpegTsParser;
+use pravega_video::retention::{days_to_seconds, hold_truncation_point, search_truncation_point};
+use pravega_video::storage::{FileStorage, PravegaStorage, Storage, StorageReader, StorageType, StorageWriter, StreamLocation};
+use pravega_video::timestamp::PravegaTimestamp;
 use pravega_video::utils;
-use pravega_video::utils::SyncByteReader;
+use pravega_video::utils::CurrentHead;
 
 use tokio::runtime::Runtime;
 
 use crate::counting_writer::CountingWriter;
 use crate::numeric::u64_to_i64_saturating_sub;
-use crate::seekable_byte_stream_writer::SeekableByteWriter;
+use crate::pravegatc::checkpoint_store::{CheckpointStore, CheckpointStoreLocation, FileCheckpointStore, TableCheckpointStore};
+use crate::pravegatc::recovery::read_persistent_state;
+use crate::utils::{CHECKPOINT_FIELD_OFFSET, CHECKPOINT_FIELD_STREAM, CHECKPOINT_QUERY_NAME, RESUME_EVENT_NAME,
+    RETENTION_HELD_MESSAGE_NAME};
 
 const PROPERTY_NAME_STREAM: &str = "stream";
 const PROPERTY_NAME_CONTROLLER: &str = "controller";
@@ -50,12 +60,22 @@ const PROPERTY_NAME_BUFFER_SIZE: &str = "buffer-size";
 const PROPERTY_NAME_TIMESTAMP_MODE: &str = "timestamp-mode";
 const PROPERTY_NAME_INDEX_MIN_SEC: &str = "index-min-sec";
 const PROPERTY_NAME_INDEX_MAX_SEC: &str = "index-max-sec";
+const PROPERTY_NAME_INDEX_PER_TRACK: &str = "index-per-track";
 const PROPERTY_NAME_ALLOW_CREATE_SCOPE: &str = "allow-create-scope";
 const PROPERTY_NAME_KEYCLOAK_FILE: &str = "keycloak-file";
 const PROPERTY_NAME_RETENTION_TYPE: &str = "retention-type";
 const PROPERTY_NAME_RETENTION_DAYS: &str = "retention-days";
 const PROPERTY_NAME_RETENTION_BYTES: &str = "retention-bytes";
 const PROPERTY_NAME_RETENTION_MAINTENANCE_INTERVAL_SECONDS: &str = "retention-maintenance-interval-seconds";
+const PROPERTY_NAME_RETENTION_CONSUMERS: &str = "retention-consumers";
+const PROPERTY_NAME_RETENTION_MAX_DAYS: &str = "retention-max-days";
+const PROPERTY_NAME_RETENTION_MAX_BYTES: &str = "retention-max-bytes";
+const PROPERTY_NAME_ENCRYPTION_KEY_FILE: &str = "encryption-key-file";
+const PROPERTY_NAME_ENCRYPTION_KEY_ENV: &str = "encryption-key-env";
+const PROPERTY_NAME_CHECKSUM: &str = "checksum";
+const PROPERTY_NAME_EXTENDED_HEADER: &str = "extended-header";
+const PROPERTY_NAME_INTEGRITY_KEY_FILE: &str = "integrity-key-file";
+const PROPERTY_NAME_INTEGRITY_CHECKPOINT_INTERVAL_SEC: &str = "integrity-checkpoint-interval-sec";
 
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
 #[repr(u32)]
@@ -133,78 +153,150 @@ impl RetentionPolicy {
     }
 }
 
+/// The pravegatc checkpoint store of a pipeline that reads this stream.
+struct RetentionConsumer {
+    location: CheckpointStoreLocation,
+    store: Box<dyn CheckpointStore>,
+}
+
 struct RetentionMaintainer {
     element: super::PravegaSink,
     interval_seconds: u64,
     retention_policy: RetentionPolicy,
-    factory: ClientFactoryAsync,
-    index_searcher: IndexSearcher<SyncByteReader>,
-    index_writer: ByteWriter,
-    data_writer: ByteWriter,
+    // Data older than this or beyond this size will be deleted even if consumers have not processed it.
+    max_days: Option<f64>,
+    max_bytes: Option<u64>,
+    // Data will not be deleted if these consumers have not processed it.
+    consumers: Vec<RetentionConsumer>,
+    // The stream as it appears in consumer checkpoints.
+    stream: String,
+    index_searcher: IndexSearcher<Box<dyn StorageReader>>,
+    index_writer: Box<dyn StorageWriter>,
+    data_writer: Box<dyn StorageWriter>,
 }
 
 impl RetentionMaintainer {
-    fn new(element: super::PravegaSink, interval_seconds: u64, retention_policy: RetentionPolicy, factory: ClientFactoryAsync, index_scoped_stream: ScopedStream, data_scoped_stream: ScopedStream) -> Self {
-        let index_reader = factory.runtime_handle().block_on(factory.create_byte_reader(index_scoped_stream.clone()));
-        let index_writer = factory.runtime_handle().block_on(factory.create_byte_writer(index_scoped_stream));
-        let data_writer = factory.runtime_handle().block_on(factory.create_byte_writer(data_scoped_stream));
-        let index_searcher = IndexSearcher::new(SyncByteReader::new(index_reader, factory.runtime_handle()));
-        Self {
+    fn new(element: super::PravegaSink, interval_seconds: u64, retention_policy: RetentionPolicy,
+            max_days: Option<f64>, max_bytes: Option<u64>, consumers: Vec<RetentionConsumer>, storage: &dyn Storage,
+            stream_location: &StreamLocation, index_stream_name: &str) -> std::io::Result<Self> {
+        let scope_name = &stream_location.scope;
+        let stream_name = &stream_location.stream;
+        let index_reader = storage.create_reader(scope_name, index_stream_name)?;
+        let index_writer = storage.create_writer(scope_name, index_stream_name)?;
+        let data_writer = storage.create_writer(scope_name, stream_name)?;
+        let index_searcher = IndexSearcher::new(index_reader);
+        Ok(Self {
             element,
             interval_seconds,
             retention_policy,
-            factory,
+            max_days,
+            max_bytes,
+            consumers,
+            stream: stream_location.to_string(),
             index_searcher,
             index_writer,
             data_writer,
-        }
+        })
     }
 
-    fn days_to_seconds(days: f64) -> i128 {
-        let seconds = days * 24.0 * 60.0 * 60.0;
-        seconds.round() as i128
+    /// Returns the index record and index offset before which data should be truncated
+    /// so that data is not older than the given age and does not exceed the given size.
+    fn search(&mut self, seconds: Option<i128>, bytes: Option<u64>) -> Option<(IndexRecord, u64)> {
+        debug!(CAT, obj: &self.element, "Searching for data prior to {:?} seconds ago or larger than {:?} bytes", seconds, bytes);
+        search_truncation_point(&mut self.index_searcher, PravegaTimestamp::now(), seconds, bytes)
     }
 
-    fn run(mut self, thread_stop_rx: Receiver<()>) -> Option<JoinHandle<()>> {
+    /// Returns the oldest checkpoint of all consumers and the consumer that has it.
+    /// A consumer without a checkpoint, or whose checkpoint cannot be read, has not processed any data
+    /// and its checkpoint is returned as PravegaTimestamp::NONE.
+    fn oldest_consumer_checkpoint(&mut self) -> Option<(PravegaTimestamp, String)> {
+        let mut oldest: Option<(PravegaTimestamp, String)> = None;
+        for consumer in self.consumers.iter_mut() {
+            let checkpoint = match read_persistent_state(consumer.store.as_mut(), &self.stream) {
+                Ok(Some(persistent_state)) => PravegaTimestamp::from_nanoseconds(Some(persistent_state.resume_at_pts)),
+                Ok(None) => {
+                    warning!(CAT, obj: &self.element, "Consumer {} does not have a checkpoint for stream {}", consumer.location, self.stream);
+                    PravegaTimestamp::NONE
+                },
+                Err(error) => {
+                    warning!(CAT, obj: &self.element, "Failed to read checkpoint of consumer {}: {}", consumer.location, error);
+                    PravegaTimestamp::NONE
+                },
+            };
+            debug!(CAT, obj: &self.element, "Consumer {} checkpoint is {}", consumer.location, checkpoint);
+            let is_older = match oldest {
+                None => true,
+                Some((oldest_checkpoint, _)) => oldest_checkpoint.is_some() && (checkpoint.is_none() || checkpoint < oldest_checkpoint),
+            };
+            if is_older {
+                oldest = Some((checkpoint, consumer.location.to_string()));
+            }
+        }
+        oldest
+    }
+
+    /// Determine the truncation point from the retention policy, limited by the consumer checkpoints
+    /// and the hard maximum age and size, and truncate the data and index streams.
+    fn maintain(&mut self) -> std::io::Result<()> {
         let (seconds, bytes) = match self.retention_policy {
-            RetentionPolicy::Days(days) => (Some(RetentionMaintainer::days_to_seconds(days)), None),
+            RetentionPolicy::Days(days) => (Some(days_to_seconds(days)), None),
             RetentionPolicy::Bytes(bytes) => (None, Some(bytes)),
-            RetentionPolicy::DaysAndBytes(days, bytes) => (Some(RetentionMaintainer::days_to_seconds(days)), Some(bytes)),
+            RetentionPolicy::DaysAndBytes(days, bytes) => (Some(days_to_seconds(days)), Some(bytes)),
             _ => (None, None),
         };
+        let mut target = self.search(seconds, bytes);
+
+        // Do not truncate data that has not been processed by all consumers.
+        if let (Some(requested), Some((checkpoint, consumer))) = (target, self.oldest_consumer_checkpoint()) {
+            let limit = hold_truncation_point(&mut self.index_searcher, requested, checkpoint);
+            if limit.map_or(true, |limit| requested.0.offset > limit.0.offset) {
+                info!(CAT, obj: &self.element, "Retention held back from offset {} to {:?} by consumer {} with checkpoint {}",
+                    requested.0.offset, limit.map(|limit| limit.0.offset), consumer, checkpoint);
+                let structure = gst::Structure::builder(RETENTION_HELD_MESSAGE_NAME)
+                    .field("stream", &self.stream)
+                    .field("requested-offset", requested.0.offset)
+                    .field_if_some("offset", limit.map(|limit| limit.0.offset))
+                    .field("consumer", &consumer)
+                    .field_if_some("consumer-timestamp", checkpoint.nanoseconds())
+                    .build();
+                let _ = self.element.post_message(gst::message::Element::builder(structure).src(&self.element).build());
+                target = limit;
+            }
+        }
+
+        // Truncate data beyond the hard maximum age or size, even if consumers have not processed it.
+        let max_seconds = self.max_days.map(days_to_seconds);
+        if let Some(max_target) = self.search(max_seconds, self.max_bytes) {
+            if target.map_or(true, |target| max_target.0.offset > target.0.offset) {
+                warning!(CAT, obj: &self.element, "Truncating data before offset {} that may not have been processed by all consumers, \
+                    due to maximum retention", max_target.0.offset);
+                target = Some(max_target);
+            }
+        }
 
-        if seconds.is_none() && bytes.is_none() {
-            return None;
+        if let Some((index_record, index_offset)) = target {
+            info!(CAT, obj: &self.element, "Truncating prior to {:?}", index_record);
+            self.index_writer.truncate_data_before(index_offset)?;
+            info!(CAT, obj: &self.element, "Index truncated at offset {}", index_offset);
+            self.data_writer.truncate_data_before(index_record.offset)?;
+            info!(CAT, obj: &self.element, "Data truncated at offset {}", index_record.offset);
+        }
+        Ok(())
+    }
+
+    fn run(mut self, thread_stop_rx: Receiver<()>) -> Option<JoinHandle<()>> {
+        if let RetentionPolicy::None = self.retention_policy {
+            if self.max_days.is_none() && self.max_bytes.is_none() {
+                return None;
+            }
         }
 
         info!(CAT, obj: &self.element, "start: retention_maintainer_interval_seconds={}", self.interval_seconds);
         let handle = thread::spawn(move || {
             loop {
-                if let Some(sec) = seconds {
-                    let truncate_at_timestamp = PravegaTimestamp::now() - sec * SECOND;
-                    info!(CAT, obj: &self.element, "Truncating prior to {}", truncate_at_timestamp);
-
-                    let search_result = self.index_searcher.search_timestamp_and_return_index_offset(truncate_at_timestamp, SearchMethod::Before);
-                    if let Ok(result) = search_result {
-                        let runtime = self.factory.runtime_handle();
-                        runtime.block_on(self.index_writer.truncate_data_before(result.1 as i64)).unwrap();
-                        info!(CAT, obj: &self.element, "Index truncated at offset {}", result.1);
-                        runtime.block_on(self.data_writer.truncate_data_before(result.0.offset as i64)).unwrap();
-                        info!(CAT, obj: &self.element, "Data truncated at offset {}", result.0.offset);
-                    }
-                }
-
-                if let Some(bytes) = bytes {
-                    info!(CAT, obj: &self.element, "Truncating larger than {} bytes", bytes);
-
-                    let search_result = self.index_searcher.search_size_and_return_index_offset(bytes, SearchMethod::Before);
-                    if let Ok(result) = search_result {
-                        let runtime = self.factory.runtime_handle();
-                        runtime.block_on(self.index_writer.truncate_data_before(result.1 as i64)).unwrap();
-                        info!(CAT, obj: &self.element, "Index truncated at offset {}", result.1);
-                        runtime.block_on(self.data_writer.truncate_data_before(result.0.offset as i64)).unwrap();
-                        info!(CAT, obj: &self.element, "Data truncated at offset {}", result.0.offset);
-                    }
+                // A failed truncation is retried in the next interval.
+                if let Err(error) = self.maintain() {
+                    error!(CAT, obj: &self.element, "Failed to truncate stream {}: {}", self.stream, error);
                 }
 
                 // break the loop to stop the thread
@@ -227,9 +319,12 @@ const DEFAULT_INDEX_MIN_SEC: f64 = 0.5;
 const DEFAULT_INDEX_MAX_SEC: f64 = 10.0;
 const DEFAULT_RETENTION_TYPE: RetentionType = RetentionType::None;
 const DEFAULT_RETENTION_MAINTENANCE_INTERVAL_SECONDS: u64 = 15 * 60;
+const DEFAULT_INTEGRITY_CHECKPOINT_INTERVAL_SEC: f64 = 60.0;
+const MAX_INTEGRITY_CHECKPOINT_INTERVAL_SEC: f64 = 365.0 * 24.0 * 60.0 * 60.0;
 
 #[derive(Debug)]
 struct Settings {
+    storage_type: StorageType,
     scope: Option<String>,
     stream: Option<String>,
     controller: Option<String>,
@@ -238,17 +333,28 @@ struct Settings {
     timestamp_mode: TimestampMode,
     index_min_nanos: u64,
     index_max_nanos: u64,
+    index_per_track: bool,
     allow_create_scope: bool,
     keycloak_file: Option<String>,
     retention_type: RetentionType,
     retention_days: Option<f64>,
     retention_bytes: Option<u64>,
     retention_maintenance_interval_seconds: u64,
+    retention_consumers: Vec<CheckpointStoreLocation>,
+    retention_max_days: Option<f64>,
+    retention_max_bytes: Option<u64>,
+    encryption_key_file: Option<String>,
+    encryption_key_env: Option<String>,
+    checksum: bool,
+    extended_header: bool,
+    integrity_key_file: Option<String>,
+    integrity_checkpoint_interval_nanos: u64,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Settings {
+            storage_type: StorageType::Pravega,
             scope: None,
             stream: None,
             controller: utils::default_pravega_controller_uri(),
@@ -257,12 +363,218 @@ impl Default for Settings {
             timestamp_mode: DEFAULT_TIMESTAMP_MODE,
             index_min_nanos: (DEFAULT_INDEX_MIN_SEC * 1e9) as u64,
             index_max_nanos: (DEFAULT_INDEX_MAX_SEC * 1e9) as u64,
+            index_per_track: false,
             allow_create_scope: true,
             keycloak_file: utils::default_keycloak_file(),
             retention_type: DEFAULT_RETENTION_TYPE,
             retention_days: None,
             retention_bytes: None,
             retention_maintenance_interval_seconds: DEFAULT_RETENTION_MAINTENANCE_INTERVAL_SECONDS,
+            retention_consumers: Vec::new(),
+            retention_max_days: None,
+            retention_max_bytes: None,
+            encryption_key_file: None,
+            encryption_key_env: None,
+            checksum: false,
+            extended_header: false,
+            integrity_key_file: None,
+            integrity_checkpoint_interval_nanos: (DEFAULT_INTEGRITY_CHECKPOINT_INTERVAL_SEC * 1e9) as u64,
+        }
+    }
+}
+
+/// A buffer that was written by a previous instance after the last committed checkpoint of pravegatc.
+#[derive(Debug)]
+struct UncommittedBuffer {
+    offset: u64,
+    end_offset: u64,
+    timestamp: PravegaTimestamp,
+    // The length of the buffer, excluding any encryption overhead, summed over all fragments.
+    length: usize,
+    include_in_index: bool,
+}
+
+/// Read the event headers between the begin and end offsets of the data stream.
+/// Events that continue a fragmented buffer are combined with the first event.
+/// If the last buffer is incomplete because its remaining fragments were not written, it is not returned,
+/// so that it will be written again.
+fn read_uncommitted_buffers<R: Read + Seek>(reader: &mut R, begin: u64, end: u64) -> std::io::Result<VecDeque<UncommittedBuffer>> {
+    reader.seek(SeekFrom::Start(begin))?;
+    let mut reader = BufReader::new(reader);
+    let mut buffers: VecDeque<UncommittedBuffer> = VecDeque::new();
+    let mut offset = begin;
+    let mut more_fragments = false;
+    while offset < end {
+        let mut event_reader = EventReader::new();
+        let required_buffer_length = event_reader.read_required_buffer_length(&mut reader)?;
+        let header = event_reader.read_header(&mut reader)?;
+        let mut length = event_reader.payload_length();
+        let mut skip_length = event_reader.remaining_length();
+        if header.encrypted && length > 0 {
+            // The first byte of an encrypted payload is the length of the key id.
+            let mut key_id_length = [0; 1];
+            reader.read_exact(&mut key_id_length)?;
+            length = length.saturating_sub(encryption_overhead(key_id_length[0] as usize));
+            skip_length -= 1;
+        }
+        reader.seek_relative(skip_length as i64)?;
+        let event_offset = offset;
+        offset += required_buffer_length as u64;
+        if header.continuation {
+            let buffer = buffers.back_mut().filter(|_| more_fragments).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData,
+                format!("Event at offset {} continues a buffer that was not written after the committed offset", event_offset)))?;
+            buffer.end_offset = offset;
+            buffer.length += length;
+        } else {
+            buffers.push_back(UncommittedBuffer {
+                offset: event_offset,
+                end_offset: offset,
+                timestamp: header.timestamp,
+                length,
+                include_in_index: header.include_in_index,
+            });
+        }
+        more_fragments = header.more_fragments;
+    }
+    if more_fragments {
+        buffers.pop_back();
+    }
+    Ok(buffers)
+}
+
+/// Assigns stream record numbers, which are written in the extended header of events.
+/// See get_stream_record_number in index.rs.
+#[derive(Debug)]
+struct StreamRecordNumbering {
+    // The number of the next stream record.
+    next_number: u64,
+    // The offset of the last stream record and the number of the first stream record with this offset.
+    last_stream_record: Option<(u64, u64)>,
+}
+
+impl StreamRecordNumbering {
+    /// Assign the next number to a stream record with the offset.
+    /// Returns the number to write in the event at the offset, which is the number of the first stream record with this offset.
+    fn add(&mut self, offset: u64) -> u64 {
+        let number = self.next_number;
+        self.next_number += 1;
+        match self.last_stream_record {
+            Some((last_offset, first_number)) if last_offset == offset => first_number,
+            _ => {
+                self.last_stream_record = Some((offset, number));
+                number
+            },
+        }
+    }
+}
+
+/// Read the last records of the index to continue the stream record numbers.
+/// Returns the numbering and true if the event of the last stream record has a stream record number.
+fn read_stream_record_numbering<I, D>(index_reader: &mut I, data_reader: &mut D) -> std::io::Result<(StreamRecordNumbering, bool)>
+where
+    I: Read + Seek + CurrentHead,
+    D: Read + Seek + CurrentHead,
+{
+    let record_size = IndexRecord::RECORD_SIZE as u64;
+    let head_offset = index_reader.current_head()?;
+    let tail_offset = index_reader.seek(SeekFrom::End(0))?;
+    // Ignore an incomplete record at the tail.
+    let tail_offset = tail_offset - (tail_offset - head_offset) % record_size;
+    let num_records = cmp::min((tail_offset - head_offset) / record_size, STREAM_RECORD_NUMBER_LOOKBACK as u64 + 1);
+    let begin_offset = tail_offset - num_records * record_size;
+    index_reader.seek(SeekFrom::Start(begin_offset))?;
+    let mut index_record_reader = IndexRecordReader::new();
+    let records = (0..num_records).map(|i| {
+        Ok((index_record_reader.read(index_reader)?, begin_offset + i * record_size))
+    }).collect::<std::io::Result<Vec<_>>>()?;
+    match records.iter().rposition(|(record, _)| record.is_stream_record()) {
+        Some(position) => {
+            let (record, index_offset) = records[position];
+            let number = get_stream_record_number(&records, position, data_reader)?;
+            let numbered = number.is_some();
+            let number = number.unwrap_or(index_offset / record_size);
+            let same_offset_stream_records = records[..position].iter().rev()
+                .take_while(|(other, _)| other.offset == record.offset)
+                .filter(|(other, _)| other.is_stream_record())
+                .count() as u64;
+            Ok((StreamRecordNumbering {
+                next_number: number + 1,
+                last_stream_record: Some((record.offset, number - same_offset_stream_records)),
+            }, numbered))
+        },
+        None => Ok((StreamRecordNumbering {
+            next_number: tail_offset / record_size,
+            last_stream_record: None,
+        }, false)),
+    }
+}
+
+/// Returns the offset in the index of the first record that refers to the data offset or later.
+/// The index is read backwards from its tail. An incomplete record at the tail is also after the returned offset.
+fn find_index_truncation_offset<R: Read + Seek + CurrentHead>(index_reader: &mut R, data_offset: u64) -> std::io::Result<u64> {
+    let record_size = IndexRecord::RECORD_SIZE as u64;
+    let head_offset = index_reader.current_head()?;
+    let tail_offset = index_reader.seek(SeekFrom::End(0))?;
+    let mut index_offset = tail_offset - (tail_offset - head_offset) % record_size;
+    let mut index_record_reader = IndexRecordReader::new();
+    while index_offset > head_offset {
+        index_reader.seek(SeekFrom::Start(index_offset - record_size))?;
+        let record = index_record_reader.read(index_reader)?;
+        if record.offset < data_offset {
+            break;
+        }
+        index_offset -= record_size;
+    }
+    Ok(index_offset)
+}
+
+/// Maintains the hash chain over the events written by this instance and writes signed checkpoints.
+/// See integrity.rs.
+struct IntegrityState {
+    writer: Box<dyn StorageWriter>,
+    signer: IntegritySigner,
+    checkpoint_interval_nanos: u64,
+    // The chain begins at the first buffer written by this instance.
+    chain: Option<HashChain>,
+    chain_begin_offset: u64,
+    // The last checkpoint written to the integrity stream.
+    last_checkpoint: Option<IntegrityCheckpoint>,
+}
+
+impl IntegrityState {
+    /// Returns true if a checkpoint should be written before the buffer at the given offset.
+    /// A new chain is started if no buffers have been written or if the offset does not continue the chain.
+    fn is_checkpoint_due(&mut self, offset: u64, timestamp: PravegaTimestamp, include_in_index: bool) -> bool {
+        if self.chain.as_ref().map_or(true, |chain| chain.offset() != offset) {
+            self.chain = Some(HashChain::new(offset));
+            self.chain_begin_offset = offset;
+            return true;
+        }
+        if !include_in_index {
+            return false;
+        }
+        match (self.last_checkpoint.as_ref().and_then(|c| c.timestamp.nanoseconds()), timestamp.nanoseconds()) {
+            (Some(last_time), Some(time)) => time >= last_time.saturating_add(self.checkpoint_interval_nanos),
+            _ => true,
+        }
+    }
+
+    /// Sign and write a checkpoint for all events that have been added to the chain.
+    fn write_checkpoint(&mut self, timestamp: PravegaTimestamp, index_offset: u64) -> std::io::Result<IntegrityCheckpoint> {
+        let chain = self.chain.as_ref().unwrap();
+        let mut checkpoint = IntegrityCheckpoint::new(timestamp, chain, index_offset, self.chain_begin_offset);
+        self.signer.sign(&mut checkpoint);
+        IntegrityCheckpointWriter::new().write(&checkpoint, &mut self.writer)?;
+        self.writer.flush()?;
+        self.last_checkpoint = Some(checkpoint.clone());
+        Ok(checkpoint)
+    }
+
+    /// Returns true if events have been added to the chain since the last checkpoint.
+    fn has_unsigned_events(&self) -> bool {
+        match (self.chain.as_ref(), self.last_checkpoint.as_ref()) {
+            (Some(chain), Some(checkpoint)) => chain.offset() > checkpoint.offset,
+            _ => false,
         }
     }
 }
@@ -270,18 +582,48 @@ impl Default for Settings {
 enum State {
     Stopped,
     Started {
-        runtime: Runtime,
-        writer: CountingWriter<BufWriter<SeekableByteWriter>>,
-        index_writer: SeekableByteWriter,
+        // The Tokio runtime is only used by Pravega storage.
+        runtime: Option<Runtime>,
+        writer: CountingWriter<BufWriter<Box<dyn StorageWriter>>>,
+        // Used to read buffers written after the committed offset when resuming from a pravegatc checkpoint.
+        data_reader: Box<dyn StorageReader>,
+        // Buffers written after the committed offset by a previous instance, if the streams could not be truncated.
+        // Received buffers that match these are dropped instead of being written again.
+        uncommitted_buffers: VecDeque<UncommittedBuffer>,
+        // If set, a received buffer did not match the uncommitted buffers.
+        // Received buffers are discarded until their timestamp is after this timestamp of the last uncommitted buffer.
+        discard_until: Option<PravegaTimestamp>,
+        index_writer: Box<dyn StorageWriter>,
+        // Used to truncate the index when resuming from a pravegatc checkpoint.
+        index_reader: Box<dyn StorageReader>,
+        metadata_writer: Box<dyn StorageWriter>,
+        // The last metadata record written to the metadata stream.
+        last_metadata_record: Option<MetadataRecord>,
+        // Metadata from new caps that will be written before the next buffer.
+        pending_metadata: Option<StreamMetadata>,
         // First received PTS that is not None.
         first_valid_time: PravegaTimestamp,
         // PTS of last written index record.
         last_index_time: PravegaTimestamp,
+        // Used to identify random access points of each track when index-per-track is enabled.
+        mpegts_parser: Option<MpegTsParser>,
+        // PTS of last written index record for each track.
+        last_track_index_times: HashMap<Track, PravegaTimestamp>,
         // The timestamp that will be written to the index upon end-of-stream.
         final_timestamp: PravegaTimestamp,
         // The offset that will be written to the index upon end-of-stream.
         final_offset: Option<u64>,
         buffers_written: u64,
+        // If set, buffers are encrypted with the active key.
+        key_ring: Option<Arc<KeyRing>>,
+        // If true, a checksum is written in the type code of each event.
+        checksum: bool,
+        // If true, the DTS, duration, and flags of each buffer are written in the extended header.
+        extended_header: bool,
+        // If set, stream record numbers are written in the extended header.
+        stream_record_numbering: Option<StreamRecordNumbering>,
+        // If set, a hash chain is maintained and signed checkpoints are written to the integrity stream.
+        integrity: Option<IntegrityState>,
         retention_thread_stop_tx: Sender<()>,
         retention_thread_handle: Option<JoinHandle<()>>,
     },
@@ -313,24 +655,22 @@ impl PravegaSink {
         stream: Option<String>,
     ) -> Result<(), glib::Error> {
         let mut settings = self.settings.lock().unwrap();
-        let (scope, stream) = match stream {
+        let (storage_type, scope, stream) = match stream {
             Some(stream) => {
-                let components: Vec<&str> = stream.split('/').collect();
-                if components.len() != 2 {
-                    return Err(glib::Error::new(
+                let location = StreamLocation::parse(&stream).map_err(|error| {
+                    glib::Error::new(
                         gst::URIError::BadUri,
-                        format!("stream parameter '{}' is formatted incorrectly. It must be specified as scope/stream.", stream).as_str(),
-                    ));
-                }
-                let scope = components[0].to_owned();
-                let stream = components[1].to_owned();
-                (Some(scope), Some(stream))
+                        format!("stream parameter '{}' is formatted incorrectly. It must be specified as scope/stream or file:///root/scope/stream. {}", stream, error).as_str(),
+                    )
+                })?;
+                (location.storage_type, Some(location.scope), Some(location.stream))
             }
             None => {
                 info!(CAT, imp: self, "Resetting `{}` to None", PROPERTY_NAME_STREAM);
-                (None, None)
+                (StorageType::Pravega, None, None)
             }
         };
+        settings.storage_type = storage_type;
         settings.scope = scope;
         settings.stream = stream;
         Ok(())
@@ -345,6 +685,180 @@ impl PravegaSink {
         settings.controller = controller;
         Ok(())
     }
+
+    /// Determine the per-track index records to write for this buffer when index-per-track is enabled.
+    /// Returns (include_in_index, tracks).
+    /// A random access record is written for each track in tracks, in the order video, audio, other.
+    /// If tracks is empty and include_in_index is true, a record for the entire stream is forced.
+    fn get_track_index_decision(
+        &self,
+        mpegts_parser: &mut MpegTsParser,
+        payload: &[u8],
+        timestamp: PravegaTimestamp,
+        first_valid_time: PravegaTimestamp,
+        last_index_time: PravegaTimestamp,
+        last_track_index_times: &HashMap<Track, PravegaTimestamp>,
+        index_min_nanos: u64,
+        index_max_nanos: u64,
+    ) -> (bool, Vec<Track>) {
+        let mut random_access_tracks = mpegts_parser.parse(payload);
+        let timestamp = match timestamp.nanoseconds() {
+            Some(timestamp) => timestamp,
+            // Buffer has an invalid timestamp. Never index.
+            None => return (false, Vec::new()),
+        };
+        random_access_tracks.sort_by_key(|track| track.track_type);
+        random_access_tracks.retain(|track| {
+            match last_track_index_times.get(track).and_then(|t| t.nanoseconds()) {
+                Some(last_track_index_time) if timestamp < last_track_index_time + index_min_nanos => {
+                    trace!(CAT, imp: self, "render: Skipping creation of index record for {:?}", track);
+                    false
+                },
+                _ => {
+                    debug!(CAT, imp: self, "render: Creating index record at random access point for {:?}", track);
+                    true
+                },
+            }
+        });
+        if !random_access_tracks.is_empty() {
+            return (true, random_access_tracks);
+        }
+        // If no index record for the entire stream or a video track has been written in a while,
+        // force an index record for the entire stream.
+        // This bounds the distance between records that can be used to start reading the stream.
+        if let Some(last_time) = last_index_time.nanoseconds().or(first_valid_time.nanoseconds()) {
+            if timestamp > last_time + index_max_nanos {
+                let interval_sec = u64_to_i64_saturating_sub(timestamp, last_time) as f64 * 1e-9;
+                fixme!(CAT, imp: self,
+                    "render: Forcing index record because no video random access point has been received for {} sec", interval_sec);
+                return (true, Vec::new());
+            }
+        }
+        (false, Vec::new())
+    }
+
+    /// Flush the data and index streams and return the committed offset of the data stream.
+    /// This is called when pravegatc records an exactly-once checkpoint.
+    fn checkpoint(&self, structure: &mut gst::StructureRef) -> bool {
+        let stream = {
+            let settings = self.settings.lock().unwrap();
+            format!("{}/{}", settings.scope.clone().unwrap_or_default(), settings.stream.clone().unwrap_or_default())
+        };
+        let mut state = self.state.lock().unwrap();
+        let (writer, index_writer, uncommitted_buffers) = match *state {
+            State::Started { ref mut writer, ref mut index_writer, ref uncommitted_buffers, .. } => (writer, index_writer, uncommitted_buffers),
+            State::Stopped => return false,
+        };
+        let result = writer.flush().and_then(|_| index_writer.flush()).and_then(|_| writer.seek(SeekFrom::Current(0)));
+        match result {
+            Ok(tail_offset) => {
+                // Buffers written by a previous instance that have not been received again are not committed.
+                let offset = uncommitted_buffers.front().map_or(tail_offset, |buffer| buffer.offset);
+                debug!(CAT, imp: self, "checkpoint: stream={}, offset={}, uncommitted_buffers={}", stream, offset, uncommitted_buffers.len());
+                structure.set(CHECKPOINT_FIELD_STREAM, stream);
+                structure.set(CHECKPOINT_FIELD_OFFSET, offset);
+                true
+            },
+            Err(error) => {
+                gst::element_imp_error!(self, gst::ResourceError::Write, ["Failed to flush Pravega streams for checkpoint: {}", error]);
+                false
+            },
+        }
+    }
+
+    /// Prepare to resume from a pravegatc checkpoint.
+    /// The index and data written after the committed offset are truncated, so that the replayed buffers will be written again.
+    /// Pravega streams cannot be truncated at the tail, so for them, buffers that were written after the committed offset are kept.
+    /// The next received buffers are expected to be identical replays of these buffers. They will be dropped instead of being written again.
+    /// If any of them differs, the remaining replayed buffers are discarded. See render.
+    fn resume(&self, stream: &str, committed_offset: u64) -> Result<(), gst::ErrorMessage> {
+        let expected_stream = {
+            let settings = self.settings.lock().unwrap();
+            format!("{}/{}", settings.scope.clone().unwrap_or_default(), settings.stream.clone().unwrap_or_default())
+        };
+        if stream != expected_stream {
+            debug!(CAT, imp: self, "resume: ignoring checkpoint for stream {}", stream);
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap();
+        let (writer, data_reader, uncommitted_buffers, index_writer, index_reader, stream_record_numbering) = match *state {
+            State::Started {
+                ref mut writer,
+                ref mut data_reader,
+                ref mut uncommitted_buffers,
+                ref mut index_writer,
+                ref mut index_reader,
+                ref mut stream_record_numbering,
+                ..
+            } => (writer, data_reader, uncommitted_buffers, index_writer, index_reader, stream_record_numbering),
+            State::Stopped => {
+                return Err(gst::error_msg!(gst::ResourceError::Settings, ["PravegaSink not started"]));
+            },
+        };
+        let tail_offset = writer.seek(SeekFrom::Current(0)).unwrap();
+        info!(CAT, imp: self, "resume: committed_offset={}, tail_offset={}", committed_offset, tail_offset);
+        if committed_offset > tail_offset {
+            return Err(gst::error_msg!(gst::ResourceError::Settings,
+                ["Committed offset {} is beyond the tail {} of stream {}", committed_offset, tail_offset, stream]));
+        }
+        if committed_offset == tail_offset {
+            return Ok(());
+        }
+        // Truncate the index first so that it never refers to data beyond the tail.
+        let index_offset = find_index_truncation_offset(index_reader, committed_offset).map_err(|error| {
+            gst::error_msg!(gst::ResourceError::Read, ["Failed to read index after the committed offset: {}", error])
+        })?;
+        let result = writer.flush()
+            .and_then(|_| index_writer.flush())
+            .and_then(|_| index_writer.truncate_data_after(index_offset));
+        match result {
+            Ok(()) => {
+                writer.get_mut().get_mut().truncate_data_after(committed_offset)
+                    .and_then(|_| writer.refresh_offset())
+                    .map_err(|error| {
+                        gst::error_msg!(gst::ResourceError::Write, ["Failed to truncate data stream at the committed offset: {}", error])
+                    })?;
+                info!(CAT, imp: self, "resume: Truncated data stream at offset {} and index at offset {}", committed_offset, index_offset);
+                // Stream record numbers continue from the remaining index records.
+                if let Some(numbering) = stream_record_numbering.as_mut() {
+                    *numbering = read_stream_record_numbering(index_reader, data_reader).map_err(|error| {
+                        gst::error_msg!(gst::ResourceError::Read, ["Failed to read stream record number from index: {}", error])
+                    })?.0;
+                    info!(CAT, imp: self, "resume: stream_record_numbering={:?}", numbering);
+                }
+            },
+            Err(error) if error.kind() == ErrorKind::Unsupported => {
+                *uncommitted_buffers = read_uncommitted_buffers(data_reader, committed_offset, tail_offset).map_err(|error| {
+                    gst::error_msg!(gst::ResourceError::Read, ["Failed to read data stream after the committed offset: {}", error])
+                })?;
+                info!(CAT, imp: self, "resume: {} buffers were written after the committed offset", uncommitted_buffers.len());
+            },
+            Err(error) => {
+                return Err(gst::error_msg!(gst::ResourceError::Write, ["Failed to truncate index after the committed offset: {}", error]));
+            },
+        }
+        Ok(())
+    }
+
+    fn write_metadata_record(
+        &self,
+        metadata_writer: &mut Box<dyn StorageWriter>,
+        record: &MetadataRecord,
+    ) -> Result<(), gst::FlowError> {
+        let mut metadata_record_writer = MetadataRecordWriter::new();
+        metadata_record_writer.write(record, metadata_writer)
+            .and_then(|_| metadata_writer.flush())
+            .map_err(|err| {
+                gst::element_imp_error!(
+                    self,
+                    gst::ResourceError::Write,
+                    ["Failed to write metadata: {}", err]
+                );
+                gst::FlowError::Error
+            })?;
+        info!(CAT, imp: self, "render: Wrote metadata record {:?}", record);
+        Ok(())
+    }
 }
 
 #[glib::object_subclass]
@@ -376,7 +890,7 @@ impl ObjectImpl for PravegaSink {
         static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| { vec![
             glib::ParamSpecString::builder(PROPERTY_NAME_STREAM)
                 .nick("Stream")
-                .blurb("scope/stream")
+                .blurb("scope/stream for Pravega, or file:///root/scope/stream for a local directory")
                 .mutable_ready()
                 .build(),
             glib::ParamSpecString::builder(PROPERTY_NAME_CONTROLLER)
@@ -422,6 +936,15 @@ impl ObjectImpl for PravegaSink {
                 .default_value(DEFAULT_INDEX_MAX_SEC.try_into().unwrap())
                 .mutable_ready()
                 .build(),
+            glib::ParamSpecBoolean::builder(PROPERTY_NAME_INDEX_PER_TRACK)
+                .nick("Index per track")
+                .blurb("If true, the input must be an MPEG transport stream with 188-byte packets. \
+                    Index records will be written at random access points of each elementary stream (track), \
+                    identified by the track type and PID. The minimum index interval applies to each track. \
+                    The extended header will be written because it contains the numbers of the index records.")
+                .default_value(false)
+                .mutable_ready()
+                .build(),
             glib::ParamSpecBoolean::builder(PROPERTY_NAME_ALLOW_CREATE_SCOPE)
                 .nick("Allow create scope")
                 .blurb("If true, the Pravega scope will be created if needed.")
@@ -465,6 +988,72 @@ impl ObjectImpl for PravegaSink {
                 .default_value(DEFAULT_RETENTION_MAINTENANCE_INTERVAL_SECONDS)
                 .mutable_ready()
                 .build(),
+            glib::ParamSpecString::builder(PROPERTY_NAME_RETENTION_CONSUMERS)
+                .nick("Retention consumers")
+                .blurb("Comma-separated locations of the pravegatc persistent state of pipelines that read this stream, \
+                    in the same format as the table property of pravegatc ('scope/table' or 'file:///path/to/dir'). \
+                    Data that has not been processed by all of these pipelines will not be deleted, \
+                    unless it exceeds retention-max-days or retention-max-bytes.")
+                .mutable_ready()
+                .build(),
+            glib::ParamSpecDouble::builder(PROPERTY_NAME_RETENTION_MAX_DAYS)
+                .nick("Retention maximum days")
+                .blurb("Data older than this number of days will be deleted, even if it has not been processed by the retention consumers.")
+                .minimum(0.0)
+                .maximum(std::f64::INFINITY)
+                .default_value(0.0)
+                .mutable_ready()
+                .build(),
+            glib::ParamSpecUInt64::builder(PROPERTY_NAME_RETENTION_MAX_BYTES)
+                .nick("Retention maximum bytes")
+                .blurb("The oldest data will be deleted so that the data size does not exceed this number of bytes, \
+                    even if it has not been processed by the retention consumers.")
+                .minimum(0)
+                .maximum(std::u64::MAX)
+                .default_value(0)
+                .mutable_ready()
+                .build(),
+            glib::ParamSpecString::builder(PROPERTY_NAME_ENCRYPTION_KEY_FILE)
+                .nick("Encryption key file")
+                .blurb("If specified, buffers will be encrypted with AES-256-GCM using the first key in this file. \
+                    Each line of the file contains a key in the format key_id:hex_key.")
+                .mutable_ready()
+                .build(),
+            glib::ParamSpecString::builder(PROPERTY_NAME_ENCRYPTION_KEY_ENV)
+                .nick("Encryption key environment variable")
+                .blurb("If specified, buffers will be encrypted with AES-256-GCM using the first key in this environment variable. \
+                    It contains comma-separated keys in the format key_id:hex_key.")
+                .mutable_ready()
+                .build(),
+            glib::ParamSpecBoolean::builder(PROPERTY_NAME_CHECKSUM)
+                .nick("Checksum")
+                .blurb("If true, a CRC32C checksum will be written in the type code of each event. \
+                    pravegasrc will verify it. Readers that do not support checksums will ignore it.")
+                .default_value(false)
+                .mutable_ready()
+                .build(),
+            glib::ParamSpecBoolean::builder(PROPERTY_NAME_EXTENDED_HEADER)
+                .nick("Extended header")
+                .blurb("If true, the DTS, duration, and flags of each buffer will be written in an extended event header \
+                    and restored by pravegasrc. Readers that do not support the extended header will see it as the first bytes of the payload.")
+                .default_value(false)
+                .mutable_ready()
+                .build(),
+            glib::ParamSpecString::builder(PROPERTY_NAME_INTEGRITY_KEY_FILE)
+                .nick("Integrity key file")
+                .blurb("If specified, a SHA-256 hash chain will be maintained over the written events and checkpoints \
+                    signed with this Ed25519 private key will be written to the integrity stream. \
+                    The file contains the 32-byte private key as 64 hexadecimal digits.")
+                .mutable_ready()
+                .build(),
+            glib::ParamSpecDouble::builder(PROPERTY_NAME_INTEGRITY_CHECKPOINT_INTERVAL_SEC)
+                .nick("Integrity checkpoint interval")
+                .blurb("The minimum number of seconds between integrity checkpoints. Checkpoints are written at index records.")
+                .minimum(0.0)
+                .maximum(MAX_INTEGRITY_CHECKPOINT_INTERVAL_SEC)
+                .default_value(DEFAULT_INTEGRITY_CHECKPOINT_INTERVAL_SEC)
+                .mutable_ready()
+                .build(),
         ]});
         PROPERTIES.as_ref()
     }
@@ -567,6 +1156,19 @@ impl ObjectImpl for PravegaSink {
                     error!(CAT, obj: obj, "Failed to set property `{}`: {}", PROPERTY_NAME_INDEX_MAX_SEC, err);
                 }
             },
+            PROPERTY_NAME_INDEX_PER_TRACK => {
+                let res: Result<(), glib::Error> = match value.get::<bool>() {
+                    Ok(index_per_track) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.index_per_track = index_per_track;
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, obj: obj, "Failed to set property `{}`: {}", PROPERTY_NAME_INDEX_PER_TRACK, err);
+                }
+            },
             PROPERTY_NAME_ALLOW_CREATE_SCOPE => {
                 let res: Result<(), glib::Error> = match value.get::<bool>() {
                     Ok(allow_create_scope) => {
@@ -648,7 +1250,144 @@ impl ObjectImpl for PravegaSink {
                 if let Err(err) = res {
                     error!(CAT, obj: obj, "Failed to set property `{}`: {}", PROPERTY_NAME_RETENTION_MAINTENANCE_INTERVAL_SECONDS, err);
                 }
-            },       
+            },
+            PROPERTY_NAME_RETENTION_CONSUMERS => {
+                let res: Result<(), glib::Error> = match value.get::<String>() {
+                    Ok(consumers) => {
+                        consumers.split(',')
+                            .map(|consumer| consumer.trim())
+                            .filter(|consumer| !consumer.is_empty())
+                            .map(|consumer| CheckpointStoreLocation::parse(consumer).map_err(|error| {
+                                glib::Error::new(gst::URIError::BadUri, error.as_str())
+                            }))
+                            .collect::<Result<Vec<_>, _>>()
+                            .map(|consumers| {
+                                let mut settings = self.settings.lock().unwrap();
+                                settings.retention_consumers = consumers;
+                            })
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, obj: obj, "Failed to set property `{}`: {}", PROPERTY_NAME_RETENTION_CONSUMERS, err);
+                }
+            },
+            PROPERTY_NAME_RETENTION_MAX_DAYS => {
+                let res: Result<(), glib::Error> = match value.get::<f64>() {
+                    Ok(days) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.retention_max_days = Some(days);
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, obj: obj, "Failed to set property `{}`: {}", PROPERTY_NAME_RETENTION_MAX_DAYS, err);
+                }
+            },
+            PROPERTY_NAME_RETENTION_MAX_BYTES => {
+                let res: Result<(), glib::Error> = match value.get::<u64>() {
+                    Ok(bytes) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.retention_max_bytes = Some(bytes);
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, obj: obj, "Failed to set property `{}`: {}", PROPERTY_NAME_RETENTION_MAX_BYTES, err);
+                }
+            },
+            PROPERTY_NAME_ENCRYPTION_KEY_FILE => {
+                let res: Result<(), glib::Error> = match value.get::<String>() {
+                    Ok(key_file) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.encryption_key_file = if key_file.is_empty() {
+                            None
+                        } else {
+                            Some(key_file)
+                        };
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, obj: obj, "Failed to set property `{}`: {}", PROPERTY_NAME_ENCRYPTION_KEY_FILE, err);
+                }
+            },
+            PROPERTY_NAME_ENCRYPTION_KEY_ENV => {
+                let res: Result<(), glib::Error> = match value.get::<String>() {
+                    Ok(key_env) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.encryption_key_env = if key_env.is_empty() {
+                            None
+                        } else {
+                            Some(key_env)
+                        };
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, obj: obj, "Failed to set property `{}`: {}", PROPERTY_NAME_ENCRYPTION_KEY_ENV, err);
+                }
+            },
+            PROPERTY_NAME_CHECKSUM => {
+                let res: Result<(), glib::Error> = match value.get::<bool>() {
+                    Ok(checksum) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.checksum = checksum;
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, obj: obj, "Failed to set property `{}`: {}", PROPERTY_NAME_CHECKSUM, err);
+                }
+            },
+            PROPERTY_NAME_EXTENDED_HEADER => {
+                let res: Result<(), glib::Error> = match value.get::<bool>() {
+                    Ok(extended_header) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.extended_header = extended_header;
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, obj: obj, "Failed to set property `{}`: {}", PROPERTY_NAME_EXTENDED_HEADER, err);
+                }
+            },
+            PROPERTY_NAME_INTEGRITY_KEY_FILE => {
+                let res: Result<(), glib::Error> = match value.get::<String>() {
+                    Ok(key_file) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.integrity_key_file = if key_file.is_empty() {
+                            None
+                        } else {
+                            Some(key_file)
+                        };
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, obj: obj, "Failed to set property `{}`: {}", PROPERTY_NAME_INTEGRITY_KEY_FILE, err);
+                }
+            },
+            PROPERTY_NAME_INTEGRITY_CHECKPOINT_INTERVAL_SEC => {
+                let res: Result<(), glib::Error> = match value.get::<f64>() {
+                    Ok(interval_sec) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.integrity_checkpoint_interval_nanos = (interval_sec * 1e9) as u64;
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, obj: obj, "Failed to set property `{}`: {}", PROPERTY_NAME_INTEGRITY_CHECKPOINT_INTERVAL_SEC, err);
+                }
+            },
         _ => unimplemented!(),
         };
     }
@@ -704,7 +1443,8 @@ impl BaseSinkImpl for PravegaSink {
             }
 
             let settings = self.settings.lock().unwrap();
-            info!(CAT, imp: self, "start: index_min_nanos={}, index_max_nanos={}", settings.index_min_nanos, settings.index_max_nanos);
+            info!(CAT, imp: self, "start: index_min_nanos={}, index_max_nanos={}, index_per_track={}",
+                settings.index_min_nanos, settings.index_max_nanos, settings.index_per_track);
             if !(settings.index_min_nanos <= settings.index_max_nanos) {
                 return Err(gst::error_msg!(gst::ResourceError::Settings,
                     ["{} must be <= {}", PROPERTY_NAME_INDEX_MIN_SEC, PROPERTY_NAME_INDEX_MAX_SEC]))
@@ -716,96 +1456,142 @@ impl BaseSinkImpl for PravegaSink {
                 gst::error_msg!(gst::ResourceError::Settings, ["Stream is not defined"])
             })?;
             let index_stream_name = get_index_stream_name(&stream_name);
-            let scope = Scope::from(scope_name);
-            let stream = Stream::from(stream_name);
-            let index_stream = Stream::from(index_stream_name);
-            info!(CAT, imp: self, "start: scope={}, stream={}, index_stream={}", scope, stream, index_stream);
+            let metadata_stream_name = get_metadata_stream_name(&stream_name);
+            let key_ring = KeyRing::load(settings.encryption_key_file.as_deref(), settings.encryption_key_env.as_deref()).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::Settings, ["Failed to load encryption keys: {}", error])
+            })?.map(Arc::new);
+            info!(CAT, imp: self, "start: key_ring={:?}, checksum={}, extended_header={}", key_ring, settings.checksum, settings.extended_header);
+            let integrity_signer = match settings.integrity_key_file {
+                Some(ref key_file) => Some(IntegritySigner::from_file(key_file).map_err(|error| {
+                    gst::error_msg!(gst::ResourceError::Settings, ["Failed to load integrity key: {}", error])
+                })?),
+                None => None,
+            };
+            let integrity_stream_name = get_integrity_stream_name(&stream_name);
+            info!(CAT, imp: self, "start: storage_type={:?}, scope={}, stream={}, index_stream={}, metadata_stream={}",
+                settings.storage_type, scope_name, stream_name, index_stream_name, metadata_stream_name);
             info!(CAT, imp: self, "start: timestamp_mode={:?}", settings.timestamp_mode);
 
-            let controller = settings.controller.clone().ok_or_else(|| {
-                gst::error_msg!(gst::ResourceError::Settings, ["Controller is not defined"])
-            })?;
-            info!(CAT, imp: self, "start: controller={}", controller);
-            let keycloak_file = settings.keycloak_file.clone();
-            info!(CAT, imp: self, "start: keycloak_file={:?}", keycloak_file);
-            let config = utils::create_client_config(controller, keycloak_file).map_err(|error| {
-                gst::error_msg!(gst::ResourceError::Settings, ["Failed to create Pravega client config: {}", error])
-            })?;
-            trace!(CAT, imp: self, "start: config={:?}", config);
-            info!(CAT, imp: self, "start: controller_uri={}:{}", config.controller_uri.domain_name(), config.controller_uri.port());
-            info!(CAT, imp: self, "start: is_tls_enabled={}", config.is_tls_enabled);
-            info!(CAT, imp: self, "start: is_auth_enabled={}", config.is_auth_enabled);
-
-            let runtime = Runtime::new().unwrap();
-            let client_factory = ClientFactoryAsync::new(config, runtime.handle().to_owned());
-            let controller_client = client_factory.controller_client();
+            let (storage, mut runtime, mut client_factory): (Box<dyn Storage>, Option<Runtime>, Option<ClientFactoryAsync>) = match settings.storage_type {
+                StorageType::File { ref root } => {
+                    (Box::new(FileStorage::new(root.clone())), None, None)
+                },
+                StorageType::Pravega => {
+                    let controller = settings.controller.clone().ok_or_else(|| {
+                        gst::error_msg!(gst::ResourceError::Settings, ["Controller is not defined"])
+                    })?;
+                    info!(CAT, imp: self, "start: controller={}", controller);
+                    let keycloak_file = settings.keycloak_file.clone();
+                    info!(CAT, imp: self, "start: keycloak_file={:?}", keycloak_file);
+                    let config = utils::create_client_config(controller, keycloak_file).map_err(|error| {
+                        gst::error_msg!(gst::ResourceError::Settings, ["Failed to create Pravega client config: {}", error])
+                    })?;
+                    trace!(CAT, imp: self, "start: config={:?}", config);
+                    info!(CAT, imp: self, "start: controller_uri={}:{}", config.controller_uri.domain_name(), config.controller_uri.port());
+                    info!(CAT, imp: self, "start: is_tls_enabled={}", config.is_tls_enabled);
+                    info!(CAT, imp: self, "start: is_auth_enabled={}", config.is_auth_enabled);
+
+                    let runtime = Runtime::new().unwrap();
+                    let client_factory = ClientFactoryAsync::new(config, runtime.handle().to_owned());
+                    (Box::new(PravegaStorage::new(client_factory.clone())), Some(runtime), Some(client_factory))
+                },
+            };
 
             // Create scope.
             info!(CAT, imp: self, "start: allow_create_scope={}", settings.allow_create_scope);
             if settings.allow_create_scope {
                 // This is expected to fail in some environments, even if the scope already exists.
                 // We will log the error and continue.
-                let _ = runtime.block_on(controller_client.create_scope(&scope)).map_err(|error| {
-                    debug!(CAT, imp: self, "Failed to create Pravega scope. This is normal if the scope already exists: {:?}", error);
+                let _ = storage.create_scope(&scope_name).map_err(|error| {
+                    debug!(CAT, imp: self, "Failed to create scope. This is normal if the scope already exists: {:?}", error);
                 });
             }
 
             // Create data stream.
-            let stream_config = StreamConfiguration {
-                scoped_stream: ScopedStream {
-                    scope: scope.clone(),
-                    stream: stream.clone(),
-                },
-                scaling: Scaling {
-                    scale_type: ScaleType::FixedNumSegments,
-                    min_num_segments: 1,
-                    ..Default::default()
-                },
-                retention: Default::default(),
-                tags: utils::get_video_tags(),
-            };
-            runtime.block_on(controller_client.create_stream(&stream_config)).map_err(|error| {
-                gst::error_msg!(gst::ResourceError::Settings, ["Failed to create Pravega data stream: {:?}", error])
+            storage.create_stream(&scope_name, &stream_name, utils::get_video_tags()).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::Settings, ["Failed to create data stream: {:?}", error])
             })?;
 
             // Create index stream.
-            let index_stream_config = StreamConfiguration {
-                scoped_stream: ScopedStream {
-                    scope: scope.clone(),
-                    stream: index_stream.clone(),
-                },
-                scaling: Scaling {
-                    scale_type: ScaleType::FixedNumSegments,
-                    min_num_segments: 1,
-                    ..Default::default()
-                },
-                retention: Default::default(),
-                tags: None,
-            };
-            runtime.block_on(controller_client.create_stream(&index_stream_config)).map_err(|error| {
-                gst::error_msg!(gst::ResourceError::Settings, ["Failed to create Pravega index stream: {:?}", error])
+            storage.create_stream(&scope_name, &index_stream_name, None).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::Settings, ["Failed to create index stream: {:?}", error])
+            })?;
+
+            // Create metadata stream.
+            storage.create_stream(&scope_name, &metadata_stream_name, None).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::Settings, ["Failed to create metadata stream: {:?}", error])
+            })?;
+
+            let writer = storage.create_writer(&scope_name, &stream_name).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::OpenWrite, ["Failed to open writer for data: {}", error])
             })?;
+            info!(CAT, imp: self, "start: Opened writer for data");
 
-            let scoped_stream = ScopedStream {
-                scope: scope.clone(),
-                stream: stream.clone(),
+            let mut data_reader = storage.create_reader(&scope_name, &stream_name).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::OpenRead, ["Failed to open reader for data: {}", error])
+            })?;
+
+            let index_writer = storage.create_writer(&scope_name, &index_stream_name).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::OpenWrite, ["Failed to open writer for index: {}", error])
+            })?;
+            info!(CAT, imp: self, "start: Opened writer for index");
+
+            // Continue the stream record numbers from the end of the index.
+            // Once a stream has stream record numbers, they must be written even if index-per-track is disabled.
+            let mut index_reader = storage.create_reader(&scope_name, &index_stream_name).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::OpenRead, ["Failed to open reader for index: {}", error])
+            })?;
+            let (stream_record_numbering, numbered) = read_stream_record_numbering(&mut index_reader, &mut data_reader).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::Read, ["Failed to read stream record number from index: {}", error])
+            })?;
+            let stream_record_numbering = if settings.index_per_track || numbered {
+                Some(stream_record_numbering)
+            } else {
+                None
             };
-            let writer = runtime.block_on(client_factory.create_byte_writer(scoped_stream.clone()));
-            let mut seekable_writer = SeekableByteWriter::new(writer, runtime.handle().to_owned());
-            info!(CAT, imp: self, "start: Opened Pravega writer for data");
-            seekable_writer.seek_to_tail();
-
-            let index_scoped_stream = ScopedStream {
-                scope: scope.clone(),
-                stream: index_stream.clone(),
+            info!(CAT, imp: self, "start: stream_record_numbering={:?}", stream_record_numbering);
+
+            let metadata_writer = storage.create_writer(&scope_name, &metadata_stream_name).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::OpenWrite, ["Failed to open writer for metadata: {}", error])
+            })?;
+            info!(CAT, imp: self, "start: Opened writer for metadata");
+
+            // Create integrity stream.
+            let integrity = match integrity_signer {
+                Some(signer) => {
+                    storage.create_stream(&scope_name, &integrity_stream_name, None).map_err(|error| {
+                        gst::error_msg!(gst::ResourceError::Settings, ["Failed to create integrity stream: {:?}", error])
+                    })?;
+                    let writer = storage.create_writer(&scope_name, &integrity_stream_name).map_err(|error| {
+                        gst::error_msg!(gst::ResourceError::OpenWrite, ["Failed to open writer for integrity: {}", error])
+                    })?;
+                    info!(CAT, imp: self, "start: Opened writer for integrity stream {}; public_key={}, checkpoint_interval_nanos={}",
+                        integrity_stream_name, signer.public_key_hex(), settings.integrity_checkpoint_interval_nanos);
+                    Some(IntegrityState {
+                        writer,
+                        signer,
+                        checkpoint_interval_nanos: settings.integrity_checkpoint_interval_nanos,
+                        chain: None,
+                        chain_begin_offset: 0,
+                        last_checkpoint: None,
+                    })
+                },
+                None => None,
             };
-            let index_writer = runtime.block_on(client_factory.create_byte_writer(index_scoped_stream.clone()));
-            let mut index_writer = SeekableByteWriter::new(index_writer, runtime.handle().to_owned());
-            info!(CAT, imp: self, "start: Opened Pravega writer for index");
-            index_writer.seek_to_tail();
+
+            // Read the last metadata record so that we only write a new record if the caps have changed.
+            let metadata_reader = storage.create_reader(&scope_name, &metadata_stream_name).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::OpenRead, ["Failed to open reader for metadata: {}", error])
+            })?;
+            let mut metadata_searcher = MetadataSearcher::new(metadata_reader);
+            metadata_searcher.refresh().map_err(|error| {
+                gst::error_msg!(gst::ResourceError::Read, ["Failed to read metadata stream: {}", error])
+            })?;
+            let last_metadata_record = metadata_searcher.get_last_record().cloned();
+            info!(CAT, imp: self, "start: last_metadata_record={:?}", last_metadata_record);
 
             info!(CAT, imp: self, "start: Buffer size is {}", settings.buffer_size);
-            let buf_writer = BufWriter::with_capacity(settings.buffer_size, seekable_writer);
+            let buf_writer = BufWriter::with_capacity(settings.buffer_size, writer);
             let counting_writer = CountingWriter::new(buf_writer).unwrap();
 
             let retention_policy = RetentionPolicy::new(settings.retention_type, settings.retention_days, settings.retention_bytes).map_err(|error| {
@@ -813,20 +1599,78 @@ impl BaseSinkImpl for PravegaSink {
             })?;
             info!(CAT, imp: self, "start: retention_policy={:?}", retention_policy);
 
-            let retention_maintainer = RetentionMaintainer::new(self.obj().clone(), settings.retention_maintenance_interval_seconds, retention_policy, client_factory.clone(),
-                index_scoped_stream, scoped_stream);
+            info!(CAT, imp: self, "start: retention_consumers={:?}, retention_max_days={:?}, retention_max_bytes={:?}",
+                settings.retention_consumers, settings.retention_max_days, settings.retention_max_bytes);
+            let mut retention_consumers = Vec::new();
+            for location in settings.retention_consumers.iter() {
+                let store: Box<dyn CheckpointStore> = match location {
+                    CheckpointStoreLocation::File { dir } => {
+                        Box::new(FileCheckpointStore::new(dir.clone()).map_err(|error| {
+                            gst::error_msg!(gst::ResourceError::OpenRead, ["Failed to open checkpoint directory of retention consumer: {}", error])
+                        })?)
+                    },
+                    CheckpointStoreLocation::Table { scope, table } => {
+                        // Consumer tables are read with the client factory of the stream, or with one shared by all consumers.
+                        if client_factory.is_none() {
+                            let controller = settings.controller.clone().ok_or_else(|| {
+                                gst::error_msg!(gst::ResourceError::Settings, ["Controller is not defined"])
+                            })?;
+                            let config = utils::create_client_config(controller, settings.keycloak_file.clone()).map_err(|error| {
+                                gst::error_msg!(gst::ResourceError::Settings, ["Failed to create Pravega client config: {}", error])
+                            })?;
+                            let consumer_runtime = Runtime::new().unwrap();
+                            client_factory = Some(ClientFactoryAsync::new(config, consumer_runtime.handle().to_owned()));
+                            runtime = Some(consumer_runtime);
+                        }
+                        let client_factory = client_factory.as_ref().unwrap();
+                        Box::new(TableCheckpointStore::open_read_only(client_factory, scope, table).map_err(|error| {
+                            gst::error_msg!(gst::ResourceError::OpenRead, ["Failed to open checkpoint table of retention consumer: {}", error])
+                        })?)
+                    },
+                };
+                retention_consumers.push(RetentionConsumer {
+                    location: location.clone(),
+                    store,
+                });
+            }
+
+            let stream_location = StreamLocation {
+                storage_type: settings.storage_type.clone(),
+                scope: scope_name.clone(),
+                stream: stream_name.clone(),
+            };
+            let retention_maintainer = RetentionMaintainer::new(self.obj().clone(), settings.retention_maintenance_interval_seconds, retention_policy,
+                settings.retention_max_days, settings.retention_max_bytes, retention_consumers, storage.as_ref(),
+                &stream_location, &index_stream_name).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::OpenWrite, ["Failed to create retention maintainer: {}", error])
+            })?;
             let (retention_thread_stop_tx, retention_thread_stop_rx) = mpsc::channel();
             let retention_thread_handle = retention_maintainer.run(retention_thread_stop_rx);
 
             *state = State::Started {
                 runtime,
                 writer: counting_writer,
+                data_reader,
+                uncommitted_buffers: VecDeque::new(),
+                discard_until: None,
                 index_writer,
+                index_reader,
+                metadata_writer,
+                last_metadata_record,
+                pending_metadata: None,
                 first_valid_time: PravegaTimestamp::NONE,
                 last_index_time: PravegaTimestamp::NONE,
+                mpegts_parser: if settings.index_per_track { Some(MpegTsParser::new()) } else { None },
+                last_track_index_times: HashMap::new(),
                 final_timestamp: PravegaTimestamp::NONE,
                 final_offset: None,
                 buffers_written: 0,
+                key_ring,
+                checksum: settings.checksum,
+                // Stream record numbers are written in the extended header.
+                extended_header: settings.extended_header || stream_record_numbering.is_some(),
+                stream_record_numbering,
+                integrity,
                 retention_thread_stop_tx,
                 retention_thread_handle,
             };
@@ -837,6 +1681,35 @@ impl BaseSinkImpl for PravegaSink {
         result
     }
 
+    /// The caps will be written to the metadata stream before the next buffer.
+    fn set_caps(&self, caps: &gst::Caps) -> Result<(), gst::LoggableError> {
+        info!(CAT, imp: self, "set_caps: caps={:?}", caps);
+        // Stream headers are also written to the data stream so they are not stored in the metadata.
+        // However, an MP4 stream header will contain the moov box that describes the codecs.
+        let mut codecs = None;
+        let mut caps = caps.copy();
+        for structure in caps.make_mut().iter_mut() {
+            if let Ok(streamheader) = structure.get::<gst::ArrayRef>("streamheader") {
+                for value in streamheader.iter() {
+                    if let Ok(buffer) = value.get::<gst::Buffer>() {
+                        if let Ok(map) = buffer.map_readable() {
+                            codecs = codecs.or_else(|| mp4::get_codecs(map.as_slice()));
+                        }
+                    }
+                }
+            }
+            structure.remove_field("streamheader");
+        }
+        let mut metadata = StreamMetadata::new(caps.to_string());
+        metadata.codecs = codecs;
+        debug!(CAT, imp: self, "set_caps: metadata={:?}", metadata);
+        let mut state = self.state.lock().unwrap();
+        if let State::Started { ref mut pending_metadata, .. } = *state {
+            *pending_metadata = Some(metadata);
+        }
+        Ok(())
+    }
+
     fn render(
         &self,
         buffer: &gst::Buffer,
@@ -845,28 +1718,64 @@ impl BaseSinkImpl for PravegaSink {
         let result = (|| {
             let mut state = self.state.lock().unwrap();
             let (writer,
+                uncommitted_buffers,
+                discard_until,
                 index_writer,
+                metadata_writer,
+                last_metadata_record,
+                pending_metadata,
                 first_valid_time,
                 last_index_time,
+                mpegts_parser,
+                last_track_index_times,
                 final_timestamp,
                 final_offset,
-                buffers_written) = match *state {
+                buffers_written,
+                key_ring,
+                checksum,
+                extended_header,
+                stream_record_numbering,
+                integrity) = match *state {
                 State::Started {
                     ref mut writer,
+                    ref mut uncommitted_buffers,
+                    ref mut discard_until,
                     ref mut index_writer,
+                    ref mut metadata_writer,
+                    ref mut last_metadata_record,
+                    ref mut pending_metadata,
                     ref mut first_valid_time,
                     ref mut last_index_time,
+                    ref mut mpegts_parser,
+                    ref mut last_track_index_times,
                     ref mut final_timestamp,
                     ref mut final_offset,
                     ref mut buffers_written,
+                    ref key_ring,
+                    checksum,
+                    extended_header,
+                    ref mut stream_record_numbering,
+                    ref mut integrity,
                     ..
                 } => (writer,
+                    uncommitted_buffers,
+                    discard_until,
                     index_writer,
+                    metadata_writer,
+                    last_metadata_record,
+                    pending_metadata,
                     first_valid_time,
                     last_index_time,
+                    mpegts_parser,
+                    last_track_index_times,
                     final_timestamp,
                     final_offset,
-                    buffers_written),
+                    buffers_written,
+                    key_ring,
+                    checksum,
+                    extended_header,
+                    stream_record_numbering,
+                    integrity),
                 State::Stopped => {
                     gst::element_imp_error!(self, gst::CoreError::Failed, ["Not started yet"]);
                     return Err(gst::FlowError::Error);
@@ -874,6 +1783,7 @@ impl BaseSinkImpl for PravegaSink {
             };
 
             let pts = buffer.pts();
+            let dts = buffer.dts();
             let duration = buffer.duration();
 
             let map = buffer.map_readable().map_err(|_| {
@@ -887,7 +1797,8 @@ impl BaseSinkImpl for PravegaSink {
                 (settings.timestamp_mode, settings.index_min_nanos, settings.index_max_nanos)
             };
 
-            let timestamp = match timestamp_mode {
+            // The DTS is converted in the same way as the PTS.
+            let to_timestamp = |pts: Option<gst::ClockTime>| match timestamp_mode {
                 TimestampMode::RealtimeClock => {
                     // pts is time between beginning of play and beginning of this buffer.
                     // base_time is the value of the pipeline clock (time since Unix epoch) at the beginning of play.
@@ -908,6 +1819,7 @@ impl BaseSinkImpl for PravegaSink {
                     PravegaTimestamp::from_nanoseconds(pts.map(gst::ClockTime::nseconds))
                 }
             };
+            let timestamp = to_timestamp(pts);
 
             if first_valid_time.is_none() {
                 *first_valid_time = timestamp;
@@ -916,6 +1828,50 @@ impl BaseSinkImpl for PravegaSink {
             // Get the writer offset before writing. This offset will be used in the index.
             let writer_offset = writer.seek(SeekFrom::Current(0)).unwrap();
 
+            // When resuming from a pravegatc checkpoint, buffers that were written after the committed offset
+            // by a previous instance are not written again.
+            // Each received buffer must have the same valid timestamp and length as the next uncommitted buffer.
+            if let Some(uncommitted_buffer) = uncommitted_buffers.pop_front() {
+                if timestamp.is_none() || timestamp != uncommitted_buffer.timestamp || payload.len() != uncommitted_buffer.length {
+                    // The uncommitted buffers cannot be removed from the data stream, so the remaining replayed buffers are discarded
+                    // instead. This ensures that the data stream and index do not have the same time range twice.
+                    let last_timestamp = uncommitted_buffers.iter()
+                        .map(|buffer| buffer.timestamp)
+                        .fold(uncommitted_buffer.timestamp, cmp::max);
+                    gst::element_imp_warning!(self, gst::StreamError::Failed,
+                        ["Buffer with timestamp {:?} and length {} does not match the buffer at offset {} with timestamp {:?} and length {} \
+                        that was written after the committed offset; received buffers will be discarded until after timestamp {:?}",
+                        timestamp, payload.len(), uncommitted_buffer.offset, uncommitted_buffer.timestamp, uncommitted_buffer.length,
+                        last_timestamp]);
+                    uncommitted_buffers.clear();
+                    *discard_until = Some(last_timestamp);
+                    *final_timestamp = PravegaTimestamp::from_nanoseconds(last_timestamp.nanoseconds().map(|t| t + 1));
+                    *final_offset = Some(writer_offset);
+                } else {
+                    log!(CAT, imp: self, "render: Dropping buffer that was already written at offset {}; timestamp={:?}",
+                        uncommitted_buffer.offset, timestamp);
+                    if uncommitted_buffer.include_in_index {
+                        *last_index_time = timestamp;
+                    }
+                    *buffers_written += 1;
+                    let duration = cmp::max(1, duration.unwrap_or_default().nseconds());
+                    *final_timestamp = PravegaTimestamp::from_nanoseconds(
+                        timestamp.nanoseconds().map(|t| t + duration));
+                    *final_offset = Some(uncommitted_buffer.end_offset);
+                    return Ok(gst::FlowSuccess::Ok);
+                }
+            }
+            let mut replay_discarded = false;
+            if let Some(last_timestamp) = *discard_until {
+                if timestamp.is_none() || timestamp <= last_timestamp {
+                    log!(CAT, imp: self, "render: Discarding replayed buffer with timestamp {:?}", timestamp);
+                    return Ok(gst::FlowSuccess::Ok);
+                }
+                info!(CAT, imp: self, "render: Writing buffers after discarded replayed buffers; timestamp={:?}", timestamp);
+                *discard_until = None;
+                replay_discarded = true;
+            }
+
             log!(CAT, imp: self, "render: timestamp={:?}, pts={}, base_time={}, duration={}, size={}, writer_offset={}",
                 timestamp, pts.unwrap_or_default(), self.obj().base_time().unwrap_or_default(), buffer.duration().unwrap_or_default(), buffer.size(), writer_offset);
 
@@ -925,67 +1881,107 @@ impl BaseSinkImpl for PravegaSink {
             let buffer_flags = buffer.flags();
             let is_delta_unit = buffer_flags.contains(gst::BufferFlags::DELTA_UNIT);
             let random_access = !is_delta_unit;
-            let include_in_index = match timestamp.nanoseconds() {
-                Some(timestamp) => {
-                    match last_index_time.nanoseconds() {
-                        Some(last_index_time) => {
-                            let interval_sec = u64_to_i64_saturating_sub(timestamp, last_index_time) as f64 * 1e-9;
-                            if is_delta_unit {
-                                // We are at a delta frame.
-                                if timestamp > last_index_time + index_max_nanos {
-                                    fixme!(CAT, imp: self,
-                                        "render: Forcing index record at delta unit because no key frame has been received for {} sec", interval_sec);
-                                    true
-                                } else {
-                                    false
-                                }
-                            } else {
-                                // We are at a key frame.
-                                if timestamp < last_index_time + index_min_nanos {
-                                    debug!(CAT, imp: self,
-                                        "render: Skipping creation of index record because an index record was created {} sec ago", interval_sec);
-                                    false
-                                } else {
-                                    debug!(CAT, imp: self,
-                                        "render: Creating index record at key frame; last index record was created {} sec ago", interval_sec);
-                                    true
-                                }
-                            }
-                        },
-                        None => {
-                            // An index record has not been written by this element yet.
-                            // The timestamp is valid.
-                            if random_access {
-                                true
-                            } else {
-                                // We are at a delta frame.
-                                // Do not write an index record. unless no index record has been written for a while.
-                                match first_valid_time.nanoseconds() {
-                                    Some(first_valid_time) => {
-                                        if timestamp > first_valid_time + index_max_nanos {
-                                            let interval_sec = u64_to_i64_saturating_sub(timestamp, first_valid_time) as f64 * 1e-9;
+            let (include_in_index, index_random_access, index_tracks) = match mpegts_parser.as_mut() {
+                Some(mpegts_parser) => {
+                    let (include_in_index, index_tracks) = self.get_track_index_decision(mpegts_parser, payload, timestamp,
+                        *first_valid_time, *last_index_time, last_track_index_times, index_min_nanos, index_max_nanos);
+                    (include_in_index, !index_tracks.is_empty(), index_tracks)
+                },
+                None => {
+                    let include_in_index = match timestamp.nanoseconds() {
+                        Some(timestamp) => {
+                            match last_index_time.nanoseconds() {
+                                Some(last_index_time) => {
+                                    let interval_sec = u64_to_i64_saturating_sub(timestamp, last_index_time) as f64 * 1e-9;
+                                    if is_delta_unit {
+                                        // We are at a delta frame.
+                                        if timestamp > last_index_time + index_max_nanos {
                                             fixme!(CAT, imp: self,
-                                                "render: Forcing first index record at delta unit because no key frame has been received for {} sec", interval_sec);
+                                                "render: Forcing index record at delta unit because no key frame has been received for {} sec", interval_sec);
                                             true
                                         } else {
                                             false
                                         }
-                                    },
-                                    None => {
-                                        // Should be unreachable.
-                                        false
-                                    },
-                                }
+                                    } else {
+                                        // We are at a key frame.
+                                        if timestamp < last_index_time + index_min_nanos {
+                                            debug!(CAT, imp: self,
+                                                "render: Skipping creation of index record because an index record was created {} sec ago", interval_sec);
+                                            false
+                                        } else {
+                                            debug!(CAT, imp: self,
+                                                "render: Creating index record at key frame; last index record was created {} sec ago", interval_sec);
+                                            true
+                                        }
+                                    }
+                                },
+                                None => {
+                                    // An index record has not been written by this element yet.
+                                    // The timestamp is valid.
+                                    if random_access {
+                                        true
+                                    } else {
+                                        // We are at a delta frame.
+                                        // Do not write an index record. unless no index record has been written for a while.
+                                        match first_valid_time.nanoseconds() {
+                                            Some(first_valid_time) => {
+                                                if timestamp > first_valid_time + index_max_nanos {
+                                                    let interval_sec = u64_to_i64_saturating_sub(timestamp, first_valid_time) as f64 * 1e-9;
+                                                    fixme!(CAT, imp: self,
+                                                        "render: Forcing first index record at delta unit because no key frame has been received for {} sec", interval_sec);
+                                                    true
+                                                } else {
+                                                    false
+                                                }
+                                            },
+                                            None => {
+                                                // Should be unreachable.
+                                                false
+                                            },
+                                        }
+                                    }
+                                },
                             }
                         },
-                    }
-                },
-                None => {
-                    // Buffer has an invalid timestamp. Never index.
-                    false
+                        None => {
+                            // Buffer has an invalid timestamp. Never index.
+                            false
+                        },
+                        };
+                    (include_in_index, random_access, Vec::new())
                 },
             };
 
+            // Write a metadata record if the caps have changed.
+            // The metadata record is flushed before the buffer is written so that readers will see it first.
+            let mut metadata_changed = false;
+            if let Some(mut metadata) = pending_metadata.take() {
+                if metadata.needs_codecs() {
+                    metadata.codecs = mp4::get_codecs(payload);
+                }
+                let changed = match last_metadata_record {
+                    Some(record) => record.metadata.caps != metadata.caps
+                        || (metadata.codecs.is_some() && record.metadata.codecs != metadata.codecs),
+                    None => true,
+                };
+                if changed {
+                    let record = MetadataRecord::new(timestamp, writer_offset, metadata);
+                    self.write_metadata_record(metadata_writer, &record)?;
+                    *last_metadata_record = Some(record);
+                    metadata_changed = true;
+                }
+            } else if let Some(record) = last_metadata_record.as_mut() {
+                // The codecs are in the moov box which may be in any of the header buffers.
+                if record.metadata.needs_codecs()
+                    && (buffer_flags.contains(gst::BufferFlags::HEADER) || record.offset == writer_offset) {
+                    if let Some(codecs) = mp4::get_codecs(payload) {
+                        // Write a new record with the same offset that replaces the previous one.
+                        record.metadata.codecs = Some(codecs);
+                        self.write_metadata_record(metadata_writer, record)?;
+                    }
+                }
+            }
+
             // Per the index constraints defined in index.rs, if we are writing an index record now,
             // we must flush any data writes prior to this buffer, so that reads do not block waiting on this writer.
             let flush = include_in_index;
@@ -1009,57 +2005,127 @@ impl BaseSinkImpl for PravegaSink {
             //   1) upstream has indicated a discontinuity (or resync) in the buffer
             //   3) this will be the first buffer written to the data stream from this instance
             //   2) this will be the first index record written from this instance
+            //   4) the caps have changed
+            //   5) replayed buffers before this buffer were discarded
             let discontinuity =
                    buffer_flags.contains(gst::BufferFlags::DISCONT)
                 || buffer_flags.contains(gst::BufferFlags::RESYNC)
                 || *buffers_written == 0
-                || (include_in_index && last_index_time.nanoseconds().is_none());
+                || metadata_changed
+                || replay_discarded
+                || (include_in_index && last_index_time.nanoseconds().is_none() && last_track_index_times.is_empty());
             if discontinuity {
                 debug!(CAT, imp: self, "render: Recording discontinuity");
             }
 
+            // Write an integrity checkpoint for the events before this buffer.
+            // The checkpoint refers to the offset of the index record that will be written for this buffer.
+            if let Some(integrity) = integrity.as_mut() {
+                if integrity.is_checkpoint_due(writer_offset, timestamp, include_in_index) {
+                    let index_offset = index_writer.seek(SeekFrom::Current(0)).unwrap();
+                    let checkpoint = integrity.write_checkpoint(timestamp, index_offset).map_err(|err| {
+                        gst::element_imp_error!(
+                            self,
+                            gst::ResourceError::Write,
+                            ["Failed to write integrity checkpoint: {}", err]
+                        );
+                        gst::FlowError::Error
+                    })?;
+                    debug!(CAT, imp: self, "render: Wrote integrity checkpoint {:?}", checkpoint);
+                }
+            }
+
             // Write index record.
             // We write the index record before the buffer so that any readers blocked on reading the
             // index will unblock as soon as possible.
+            // With index-per-track, a record is written for each track with a random access point in this buffer.
+            // Only records for the entire stream or a video track can be used to start reading the stream,
+            // so only these update last_index_time and are numbered.
+            let mut stream_record_number = None;
             if include_in_index {
-                let index_record = IndexRecord::new(timestamp, writer_offset,
-                    random_access, discontinuity);
-                let mut index_record_writer = IndexRecordWriter::new();
-                index_record_writer.write(&index_record, index_writer).map_err(|err| {
-                    gst::element_imp_error!(
-                        self,
-                        gst::ResourceError::Write,
-                        ["Failed to write index: {}", err]
-                    );
-                    gst::FlowError::Error
-                })?;
-                debug!(CAT, imp: self, "render: Wrote index record {:?}", index_record);
-                *last_index_time = timestamp;
+                let index_records = if index_tracks.is_empty() {
+                    vec![IndexRecord::new(timestamp, writer_offset, index_random_access, discontinuity)]
+                } else {
+                    index_tracks.iter().map(|&track| IndexRecord::new_with_track(timestamp, writer_offset,
+                        index_random_access, discontinuity, track)).collect()
+                };
+                for index_record in index_records.iter() {
+                    let mut index_record_writer = IndexRecordWriter::new();
+                    index_record_writer.write(index_record, index_writer).map_err(|err| {
+                        gst::element_imp_error!(
+                            self,
+                            gst::ResourceError::Write,
+                            ["Failed to write index: {}", err]
+                        );
+                        gst::FlowError::Error
+                    })?;
+                    debug!(CAT, imp: self, "render: Wrote index record {:?}", index_record);
+                    if index_record.is_stream_record() {
+                        *last_index_time = timestamp;
+                        if let Some(numbering) = stream_record_numbering.as_mut() {
+                            let number = numbering.add(writer_offset);
+                            stream_record_number = stream_record_number.or(Some(number));
+                        }
+                    }
+                    if let Some(track) = index_record.track {
+                        last_track_index_times.insert(track, timestamp);
+                    }
+                }
             }
 
             // Write buffer to Pravega byte stream.
             // If buffer is greater than ~8 MiB, it will be fragmented into multiple atomic writes, each with an EventHeader.
-            // Once fragmented, buffers will not be reassembled by pravegasrc.
-            // However, demuxers such as qtdemux can correctly handled fragmented buffers.
+            // All but the last fragment are marked with more_fragments and all but the first with continuation,
+            // allowing pravegasrc to reassemble the original buffer.
             // In the event of an ungraceful pravegasink termination before all fragments are written,
-            // it will mark the first buffer after starting as a discontinuity,
+            // pravegasrc will drop the incomplete buffer. The new pravegasink
+            // will mark the first buffer after starting as a discontinuity,
             // allowing elements downstream from pravegasrc to reinitialize.
+            // If encryption is enabled, each event is encrypted separately.
+            // If integrity is enabled, each serialized event is added to the hash chain.
+            // If the extended header is enabled, the DTS, duration, flags, and stream record number are written with the first event.
+            let mut event_writer = match key_ring {
+                Some(key_ring) => EventWriter::with_key_ring(key_ring.clone()),
+                None => EventWriter::new(),
+            }.with_checksum(checksum).with_extended_header(extended_header);
+            let extension = EventHeaderExtension {
+                dts: to_timestamp(dts),
+                duration: duration.map(gst::ClockTime::nseconds),
+                buffer_flags: buffer_flags.bits(),
+                stream_record_number,
+            };
             let mut pos_to_write = 0;
             loop {
-                let length_to_write = usize::min(payload.len() - pos_to_write, EventWithHeader::max_payload_size());
+                let length_to_write = usize::min(payload.len() - pos_to_write, event_writer.max_payload_size());
                 if length_to_write == 0 { break };
+                let more_fragments = pos_to_write + length_to_write < payload.len();
                 let event = if pos_to_write == 0 {
                     EventWithHeader::new(&payload[pos_to_write..pos_to_write+length_to_write],
                         timestamp, include_in_index, random_access, discontinuity)
+                        .with_extension(extension)
+                        .with_fragment(more_fragments, false)
                 } else {
                     debug!(CAT, imp: self, "render: buffer exceeds atomic write size and has been fragmented; writing additional payload of {} bytes", length_to_write);
                     // Additional writes must not be indexed and must not be marked as a discontinuity as that would reset the demuxer.
                     EventWithHeader::new(&payload[pos_to_write..pos_to_write+length_to_write],
                         timestamp, false, false, false)
+                        .with_extension(EventHeaderExtension {
+                            buffer_flags: gst::BufferFlags::DELTA_UNIT.bits(),
+                            ..Default::default()
+                        })
+                        .with_fragment(more_fragments, true)
                 };
                 memdump!(CAT, imp: self, "render: writing event={:?}", event);
-                let mut event_writer = EventWriter::new();
-                event_writer.write(&event, writer).map_err(|err| {
+                let result = match integrity.as_mut().and_then(|integrity| integrity.chain.as_mut()) {
+                    Some(chain) => {
+                        let mut serialized = Vec::with_capacity(length_to_write + 64);
+                        event_writer.write(&event, &mut serialized)
+                            .and_then(|_| writer.write_all(&serialized[..]))
+                            .map(|_| chain.add_event(&serialized[..]))
+                    },
+                    None => event_writer.write(&event, writer),
+                };
+                result.map_err(|err| {
                     gst::element_imp_error!(
                         self,
                         gst::ResourceError::Write,
@@ -1107,6 +2173,32 @@ impl BaseSinkImpl for PravegaSink {
         result
     }
 
+    fn event(&self, event: gst::Event) -> bool {
+        if let gst::EventView::CustomDownstream(custom) = event.view() {
+            if let Some(structure) = custom.structure().filter(|structure| structure.name() == RESUME_EVENT_NAME) {
+                let stream = structure.get::<String>(CHECKPOINT_FIELD_STREAM).unwrap_or_default();
+                let offset = structure.get::<u64>(CHECKPOINT_FIELD_OFFSET).unwrap_or_default();
+                return match self.resume(&stream, offset) {
+                    Ok(()) => true,
+                    Err(error) => {
+                        self.post_error_message(error);
+                        false
+                    },
+                };
+            }
+        }
+        self.parent_event(event)
+    }
+
+    fn query(&self, query: &mut gst::QueryRef) -> bool {
+        if let gst::QueryViewMut::Custom(custom) = query.view_mut() {
+            if custom.structure().map_or(false, |structure| structure.name() == CHECKPOINT_QUERY_NAME) {
+                return self.checkpoint(custom.structure_mut());
+            }
+        }
+        BaseSinkImplExt::parent_query(self, query)
+    }
+
     fn stop(&self) -> Result<(), gst::ErrorMessage> {
         info!(CAT, imp: self, "stop: BEGIN");
         let result = (|| {
@@ -1116,27 +2208,30 @@ impl BaseSinkImpl for PravegaSink {
             };
 
             let mut state = self.state.lock().unwrap();
-            let (runtime,
-                writer,
+            let (writer,
                 index_writer,
+                metadata_writer,
                 final_timestamp,
                 final_offset,
+                integrity,
                 retention_thread_stop_tx,
                 retention_thread_handle) = match *state {
                 State::Started {
-                    ref runtime,
                     ref mut writer,
                     ref mut index_writer,
+                    ref mut metadata_writer,
                     ref mut final_timestamp,
                     ref mut final_offset,
+                    ref mut integrity,
                     ref mut retention_thread_stop_tx,
                     ref mut retention_thread_handle,
                     ..
-                } => (runtime,
-                    writer,
+                } => (writer,
                     index_writer,
+                    metadata_writer,
                     final_timestamp,
                     final_offset,
+                    integrity,
                     retention_thread_stop_tx,
                     retention_thread_handle),
                 State::Stopped => {
@@ -1151,6 +2246,20 @@ impl BaseSinkImpl for PravegaSink {
                 gst::error_msg!(gst::ResourceError::Write, ["Failed to flush Pravega data stream: {}", error])
             })?;
 
+            // Write final integrity checkpoint so that the last events can be verified.
+            // It refers to the offset of the final index record.
+            if let Some(integrity) = integrity.as_mut() {
+                if integrity.has_unsigned_events() {
+                    let index_offset = index_writer.seek(SeekFrom::Current(0)).map_err(|error| {
+                        gst::error_msg!(gst::ResourceError::Write, ["Failed to get offset of Pravega index stream: {}", error])
+                    })?;
+                    let checkpoint = integrity.write_checkpoint(*final_timestamp, index_offset).map_err(|error| {
+                        gst::error_msg!(gst::ResourceError::Write, ["Failed to write Pravega integrity stream: {}", error])
+                    })?;
+                    info!(CAT, imp: self, "stop: Wrote final integrity checkpoint {:?}", checkpoint);
+                }
+            }
+
             // Write final index record.
             // The timestamp will be the the buffer timestamp + duration of the final buffer.
             // The offset will be current write position.
@@ -1172,13 +2281,20 @@ impl BaseSinkImpl for PravegaSink {
 
             if seal {
                 info!(CAT, imp: self, "stop: Sealing streams");
-                let writer = writer.get_mut().get_mut().get_mut();
-                runtime.block_on(writer.seal()).map_err(|error| {
+                writer.get_mut().get_mut().seal().map_err(|error| {
                     gst::error_msg!(gst::ResourceError::Write, ["Failed to seal Pravega data stream: {}", error])
                 })?;
                 index_writer.seal().map_err(|error| {
                     gst::error_msg!(gst::ResourceError::Write, ["Failed to seal Pravega index stream: {}", error])
                 })?;
+                metadata_writer.seal().map_err(|error| {
+                    gst::error_msg!(gst::ResourceError::Write, ["Failed to seal Pravega metadata stream: {}", error])
+                })?;
+                if let Some(integrity) = integrity.as_mut() {
+                    integrity.writer.seal().map_err(|error| {
+                        gst::error_msg!(gst::ResourceError::Write, ["Failed to seal Pravega integrity stream: {}", error])
+                    })?;
+                }
                 info!(CAT, imp: self, "stop: Streams sealed");
             }
 
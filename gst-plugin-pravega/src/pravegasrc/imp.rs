@@ -16,24 +16,28 @@ use gst::ClockTime;
 use gst::glib;
 use gst::prelude::*;
 use gst::subclass::prelude::*;
-use gst::{debug, error, info, log, trace, memdump};
+use gst::{debug, error, info, log, trace, memdump, warning};
 use gst_base::prelude::*;
 use gst_base::subclass::{base_src::CreateSuccess, prelude::*};
 
 use std::convert::{TryInto, TryFrom};
 use std::io::{BufReader, ErrorKind, Seek, SeekFrom};
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::u8;
 
 use once_cell::sync::Lazy;
 
 use pravega_client::client_factory::ClientFactory;
-use pravega_client_shared::{Scope, Stream, StreamConfiguration, ScopedStream, Scaling, ScaleType};
-use pravega_video::event_serde::EventReader;
-use pravega_video::index::{IndexSearcher, get_index_stream_name};
+use pravega_video::encryption::KeyRing;
+use pravega_video::event_serde::{EventHeader, EventReader, decrypt_payload, is_checksum_error};
+use pravega_video::index::{IndexRecord, IndexSearcher, SearchMethod, get_index_stream_name};
+use pravega_video::metadata::{MetadataSearcher, get_metadata_stream_name};
+use pravega_video::storage::{FileStorage, PravegaStorage, Storage, StorageReader, StorageType, StreamLocation};
 use pravega_video::timestamp::PravegaTimestamp;
 use pravega_video::utils;
-use pravega_video::utils::{CurrentHead, SyncByteReader};
+use pravega_video::utils::CurrentHead;
+use crate::buffer_pools::SizedBufferPools;
 use crate::counting_reader::CountingReader;
 use crate::seekable_take::SeekableTake;
 use crate::utils::{clocktime_to_pravega, pravega_to_clocktime};
@@ -49,6 +53,9 @@ const PROPERTY_NAME_START_UTC: &str = "start-utc";
 const PROPERTY_NAME_END_UTC: &str = "end-utc";
 const PROPERTY_NAME_ALLOW_CREATE_SCOPE: &str = "allow-create-scope";
 const PROPERTY_NAME_KEYCLOAK_FILE: &str = "keycloak-file";
+const PROPERTY_NAME_ENCRYPTION_KEY_FILE: &str = "encryption-key-file";
+const PROPERTY_NAME_ENCRYPTION_KEY_ENV: &str = "encryption-key-env";
+const PROPERTY_NAME_CHECKSUM_MISMATCH_MODE: &str = "checksum-mismatch-mode";
 
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
 #[repr(u32)]
@@ -121,14 +128,39 @@ pub enum EndMode {
     Timestamp = 3,
 }
 
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstChecksumMismatchMode")]
+pub enum ChecksumMismatchMode {
+    #[enum_value(
+        name = "Post an error message and stop.",
+        nick = "error"
+    )]
+    Error = 0,
+    #[enum_value(
+        name = "Drop the buffer and set the discontinuity flag on the next buffer.",
+        nick = "drop"
+    )]
+    Drop = 1,
+    #[enum_value(
+        name = "Post a warning message and output the buffer.",
+        nick = "warn"
+    )]
+    Warn = 2,
+}
+
 const DEFAULT_BUFFER_SIZE: usize = 128*1024;
 const DEFAULT_START_MODE: StartMode = StartMode::Earliest;
 const DEFAULT_END_MODE: EndMode = EndMode::Unbounded;
+const DEFAULT_CHECKSUM_MISMATCH_MODE: ChecksumMismatchMode = ChecksumMismatchMode::Error;
 const DEFAULT_START_TIMESTAMP: u64 = 0;
 const DEFAULT_END_TIMESTAMP: u64 = u64::MAX;
+// In trick mode, key frames will be skipped so that no more than this many are emitted per second of playback.
+const TRICK_MODE_MAX_KEY_UNITS_PER_SEC: f64 = 10.0;
 
 #[derive(Debug)]
 struct Settings {
+    storage_type: StorageType,
     scope: Option<String>,
     stream: Option<String>,
     controller: Option<String>,
@@ -139,11 +171,15 @@ struct Settings {
     end_timestamp: u64,
     allow_create_scope: bool,
     keycloak_file: Option<String>,
+    encryption_key_file: Option<String>,
+    encryption_key_env: Option<String>,
+    checksum_mismatch_mode: ChecksumMismatchMode,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Settings {
+            storage_type: StorageType::Pravega,
             scope: None,
             stream: None,
             controller: utils::default_pravega_controller_uri(),
@@ -154,17 +190,81 @@ impl Default for Settings {
             end_timestamp: DEFAULT_END_TIMESTAMP,
             allow_create_scope: true,
             keycloak_file: utils::default_keycloak_file(),
+            encryption_key_file: None,
+            encryption_key_env: None,
+            checksum_mismatch_mode: DEFAULT_CHECKSUM_MISMATCH_MODE,
+        }
+    }
+}
+
+/// The state of trick mode playback, which is used when the segment rate is not 1.0.
+/// Only key frames are emitted, by jumping between random access records in the index.
+#[derive(Debug)]
+struct TrickMode {
+    rate: f64,
+    // The index record of the next key frame to emit. None at the end of the segment.
+    next_record: Option<IndexRecord>,
+    start: PravegaTimestamp,
+    stop: PravegaTimestamp,
+}
+
+impl TrickMode {
+    /// Returns the index record of the key frame that should be emitted after the key frame at the timestamp.
+    /// Returns None if there is no such key frame in the segment.
+    /// An error is returned if the index cannot be read.
+    fn find_next_record(
+        &self,
+        index_searcher: &mut IndexSearcher<Box<dyn StorageReader>>,
+        timestamp: PravegaTimestamp,
+    ) -> Result<Option<IndexRecord>, std::io::Error> {
+        let timestamp = match timestamp.nanoseconds() {
+            Some(timestamp) => timestamp,
+            None => return Ok(None),
+        };
+        let step_nanos = u64::max(1, (self.rate.abs() * 1e9 / TRICK_MODE_MAX_KEY_UNITS_PER_SEC) as u64);
+        let (target, method) = if self.rate > 0.0 {
+            (timestamp.saturating_add(step_nanos), SearchMethod::After)
+        } else {
+            match timestamp.checked_sub(step_nanos) {
+                Some(target) => (target, SearchMethod::Before),
+                None => return Ok(None),
+            }
+        };
+        match index_searcher.search_random_access(PravegaTimestamp::from_nanoseconds(Some(target)), method) {
+            Ok((index_record, _)) => {
+                let in_segment = if self.rate > 0.0 {
+                    index_record.timestamp <= self.stop
+                } else {
+                    index_record.timestamp >= self.start
+                };
+                Ok(Some(index_record).filter(|_| in_segment))
+            },
+            // There are no more key frames in the index.
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
         }
     }
 }
 
+struct MetadataState {
+    searcher: MetadataSearcher<Box<dyn StorageReader>>,
+    // The caps that were most recently set on the source pad.
+    caps: Option<String>,
+}
+
 enum State {
     Stopped,
     Started {
-        reader: Arc<Mutex<CountingReader<BufReader<SeekableTake<SyncByteReader>>>>>,
-        index_searcher: Arc<Mutex<IndexSearcher<SyncByteReader>>>,
-        // save client facotry to keep the tokio runtime
-        client_factory: ClientFactory,
+        reader: Arc<Mutex<CountingReader<BufReader<SeekableTake<Box<dyn StorageReader>>>>>>,
+        index_searcher: Arc<Mutex<IndexSearcher<Box<dyn StorageReader>>>>,
+        metadata: Arc<Mutex<MetadataState>>,
+        trick_mode: Arc<Mutex<Option<TrickMode>>>,
+        buffer_pools: Arc<Mutex<SizedBufferPools>>,
+        // Used to decrypt encrypted buffers.
+        key_ring: Option<Arc<KeyRing>>,
+        checksum_mismatch_mode: ChecksumMismatchMode,
+        // save client factory to keep the tokio runtime (Pravega storage only)
+        client_factory: Option<ClientFactory>,
     },
 }
 
@@ -195,24 +295,22 @@ impl PravegaSrc {
         stream: Option<String>,
     ) -> Result<(), glib::Error> {
         let mut settings = self.settings.lock().unwrap();
-        let (scope, stream) = match stream {
+        let (storage_type, scope, stream) = match stream {
             Some(stream) => {
-                let components: Vec<&str> = stream.split('/').collect();
-                if components.len() != 2 {
-                    return Err(glib::Error::new(
+                let location = StreamLocation::parse(&stream).map_err(|error| {
+                    glib::Error::new(
                         gst::URIError::BadUri,
-                        format!("stream parameter '{}' is formatted incorrectly. It must be specified as scope/stream.", stream).as_str(),
-                    ));
-                }
-                let scope = components[0].to_owned();
-                let stream = components[1].to_owned();
-                (Some(scope), Some(stream))
+                        format!("stream parameter '{}' is formatted incorrectly. It must be specified as scope/stream or file:///root/scope/stream. {}", stream, error).as_str(),
+                    )
+                })?;
+                (location.storage_type, Some(location.scope), Some(location.stream))
             }
             None => {
                 info!(CAT, obj: element, "Resetting `{}` to None", PROPERTY_NAME_STREAM);
-                (None, None)
+                (StorageType::Pravega, None, None)
             }
         };
+        settings.storage_type = storage_type;
         settings.scope = scope;
         settings.stream = stream;
         Ok(())
@@ -227,6 +325,68 @@ impl PravegaSrc {
         settings.controller = controller;
         Ok(())
     }
+
+    /// Set the caps of the source pad from the metadata record that applies to the data at the offset.
+    /// If refresh is true, new records will be read from the metadata stream.
+    /// If the stream has no metadata, the caps will not be set.
+    fn update_caps(
+        &self,
+        metadata: &Mutex<MetadataState>,
+        offset: u64,
+        refresh: bool,
+    ) -> Result<(), gst::FlowError> {
+        let mut metadata = metadata.lock().unwrap();
+        if refresh {
+            if let Err(err) = metadata.searcher.refresh() {
+                warning!(CAT, imp: self, "update_caps: Failed to read metadata stream: {}", err);
+            }
+        }
+        let caps_string = match metadata.searcher.search_offset(offset) {
+            Some(record) => record.metadata.caps.clone(),
+            None => return Ok(()),
+        };
+        if metadata.caps.as_ref() == Some(&caps_string) {
+            return Ok(());
+        }
+        let caps = match gst::Caps::from_str(&caps_string) {
+            Ok(caps) => caps,
+            Err(err) => {
+                warning!(CAT, imp: self, "update_caps: Unable to parse caps {}: {}", caps_string, err);
+                return Ok(());
+            },
+        };
+        info!(CAT, imp: self, "update_caps: Setting caps {:?} at offset {}", caps, offset);
+        self.obj().set_caps(&caps).map_err(|err| {
+            gst::element_imp_error!(self, gst::CoreError::Negotiation, ["Failed to set caps {:?}: {}", caps, err]);
+            gst::FlowError::NotNegotiated
+        })?;
+        metadata.caps = Some(caps_string);
+        Ok(())
+    }
+
+    /// Handle an event whose checksum does not match, according to the checksum-mismatch-mode property.
+    /// Returns true if the event should be dropped.
+    fn handle_checksum_mismatch(
+        &self,
+        mode: ChecksumMismatchMode,
+        offset: u64,
+        err: &std::io::Error,
+    ) -> Result<bool, gst::FlowError> {
+        match mode {
+            ChecksumMismatchMode::Error => {
+                gst::element_imp_error!(self, gst::ResourceError::Read, ["Event at offset {} is corrupt: {}", offset, err]);
+                Err(gst::FlowError::Error)
+            },
+            ChecksumMismatchMode::Drop => {
+                warning!(CAT, imp: self, "Dropping corrupt event at offset {}: {}", offset, err);
+                Ok(true)
+            },
+            ChecksumMismatchMode::Warn => {
+                gst::element_imp_warning!(self, gst::ResourceError::Read, ["Event at offset {} is corrupt: {}", offset, err]);
+                Ok(false)
+            },
+        }
+    }
 }
 
 #[glib::object_subclass]
@@ -258,7 +418,7 @@ impl ObjectImpl for PravegaSrc {
         static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| { vec![
             glib::ParamSpecString::builder(PROPERTY_NAME_STREAM)
                 .nick("Stream")
-                .blurb("scope/stream")
+                .blurb("scope/stream for Pravega, or file:///root/scope/stream for a local directory")
                 .mutable_ready()
                 .build(),
             glib::ParamSpecString::builder(PROPERTY_NAME_CONTROLLER)
@@ -331,6 +491,24 @@ impl ObjectImpl for PravegaSrc {
                     utils::ENV_KEYCLOAK_SERVICE_ACCOUNT_FILE).as_str())
                 .mutable_ready()
                 .build(),
+            glib::ParamSpecString::builder(PROPERTY_NAME_ENCRYPTION_KEY_FILE)
+                .nick("Encryption key file")
+                .blurb("The file containing the keys used to decrypt encrypted buffers. \
+                    Each line of the file contains a key in the format key_id:hex_key. \
+                    Old keys should be kept so that buffers encrypted with them can be read.")
+                .mutable_ready()
+                .build(),
+            glib::ParamSpecString::builder(PROPERTY_NAME_ENCRYPTION_KEY_ENV)
+                .nick("Encryption key environment variable")
+                .blurb("The environment variable containing the keys used to decrypt encrypted buffers. \
+                    It contains comma-separated keys in the format key_id:hex_key.")
+                .mutable_ready()
+                .build(),
+            glib::ParamSpecEnum::builder_with_default(PROPERTY_NAME_CHECKSUM_MISMATCH_MODE, DEFAULT_CHECKSUM_MISMATCH_MODE)
+                .nick("Checksum mismatch mode")
+                .blurb("The action to take when the checksum of an event does not match its contents")
+                .mutable_ready()
+                .build(),
         ]});
         PROPERTIES.as_ref()
     }
@@ -490,9 +668,130 @@ impl ObjectImpl for PravegaSrc {
                     error!(CAT, obj: obj, "Failed to set property `{}`: {}", PROPERTY_NAME_KEYCLOAK_FILE, err);
                 }
             },
+            PROPERTY_NAME_ENCRYPTION_KEY_FILE => {
+                let res: Result<(), glib::Error> = match value.get::<String>() {
+                    Ok(key_file) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.encryption_key_file = if key_file.is_empty() {
+                            None
+                        } else {
+                            Some(key_file)
+                        };
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, obj: obj, "Failed to set property `{}`: {}", PROPERTY_NAME_ENCRYPTION_KEY_FILE, err);
+                }
+            },
+            PROPERTY_NAME_ENCRYPTION_KEY_ENV => {
+                let res: Result<(), glib::Error> = match value.get::<String>() {
+                    Ok(key_env) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.encryption_key_env = if key_env.is_empty() {
+                            None
+                        } else {
+                            Some(key_env)
+                        };
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, obj: obj, "Failed to set property `{}`: {}", PROPERTY_NAME_ENCRYPTION_KEY_ENV, err);
+                }
+            },
+            PROPERTY_NAME_CHECKSUM_MISMATCH_MODE => {
+                let res: Result<(), glib::Error> = match value.get::<ChecksumMismatchMode>() {
+                    Ok(checksum_mismatch_mode) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.checksum_mismatch_mode = checksum_mismatch_mode;
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, obj: obj, "Failed to set property `{}`: {}", PROPERTY_NAME_CHECKSUM_MISMATCH_MODE, err);
+                }
+            },
         _ => unimplemented!(),
         };
     }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            PROPERTY_NAME_STREAM => {
+                let settings = self.settings.lock().unwrap();
+                let stream = match (&settings.scope, &settings.stream) {
+                    (Some(scope), Some(stream)) => Some(StreamLocation {
+                        storage_type: settings.storage_type.clone(),
+                        scope: scope.clone(),
+                        stream: stream.clone(),
+                    }.to_string()),
+                    _ => None,
+                };
+                stream.to_value()
+            },
+            PROPERTY_NAME_CONTROLLER => {
+                let settings = self.settings.lock().unwrap();
+                settings.controller.to_value()
+            },
+            PROPERTY_NAME_BUFFER_SIZE => {
+                let settings = self.settings.lock().unwrap();
+                let buffer_size: u32 = settings.buffer_size.try_into().unwrap_or(std::u32::MAX);
+                buffer_size.to_value()
+            },
+            PROPERTY_NAME_START_MODE => {
+                let settings = self.settings.lock().unwrap();
+                settings.start_mode.to_value()
+            },
+            PROPERTY_NAME_END_MODE => {
+                let settings = self.settings.lock().unwrap();
+                settings.end_mode.to_value()
+            },
+            PROPERTY_NAME_START_TIMESTAMP => {
+                let settings = self.settings.lock().unwrap();
+                settings.start_timestamp.to_value()
+            },
+            PROPERTY_NAME_END_TIMESTAMP => {
+                let settings = self.settings.lock().unwrap();
+                settings.end_timestamp.to_value()
+            },
+            PROPERTY_NAME_START_UTC => {
+                let settings = self.settings.lock().unwrap();
+                // The default timestamp is not reported.
+                let start_timestamp = Some(settings.start_timestamp).filter(|&t| t != DEFAULT_START_TIMESTAMP);
+                PravegaTimestamp::from_nanoseconds(start_timestamp).to_iso_8601().to_value()
+            },
+            PROPERTY_NAME_END_UTC => {
+                let settings = self.settings.lock().unwrap();
+                let end_timestamp = Some(settings.end_timestamp).filter(|&t| t != DEFAULT_END_TIMESTAMP);
+                PravegaTimestamp::from_nanoseconds(end_timestamp).to_iso_8601().to_value()
+            },
+            PROPERTY_NAME_ALLOW_CREATE_SCOPE => {
+                let settings = self.settings.lock().unwrap();
+                settings.allow_create_scope.to_value()
+            },
+            PROPERTY_NAME_KEYCLOAK_FILE => {
+                let settings = self.settings.lock().unwrap();
+                settings.keycloak_file.to_value()
+            },
+            PROPERTY_NAME_ENCRYPTION_KEY_FILE => {
+                let settings = self.settings.lock().unwrap();
+                settings.encryption_key_file.to_value()
+            },
+            PROPERTY_NAME_ENCRYPTION_KEY_ENV => {
+                let settings = self.settings.lock().unwrap();
+                settings.encryption_key_env.to_value()
+            },
+            PROPERTY_NAME_CHECKSUM_MISMATCH_MODE => {
+                let settings = self.settings.lock().unwrap();
+                settings.checksum_mismatch_mode.to_value()
+            },
+            _ => unimplemented!(),
+        }
+    }
 }
 
 impl ElementImpl for PravegaSrc {
@@ -542,95 +841,87 @@ impl BaseSrcImpl for PravegaSrc {
                 gst::error_msg!(gst::ResourceError::Settings, ["Stream is not defined"])
             })?;
             let index_stream_name = get_index_stream_name(&stream_name);
-            let scope = Scope::from(scope_name);
-            let stream = Stream::from(stream_name);
-            let index_stream = Stream::from(index_stream_name);
-            info!(CAT, imp: self, "start: scope={}, stream={}, index_stream={}", scope, stream, index_stream);
+            let metadata_stream_name = get_metadata_stream_name(&stream_name);
+            info!(CAT, imp: self, "start: storage_type={:?}, scope={}, stream={}, index_stream={}, metadata_stream={}",
+                settings.storage_type, scope_name, stream_name, index_stream_name, metadata_stream_name);
             info!(CAT, imp: self, "start: start_mode={:?}, start_timestamp={:?}",
                 settings.start_mode, PravegaTimestamp::from_nanoseconds(Some(settings.start_timestamp)));
             info!(CAT, imp: self, "start: end_mode={:?}, end_timestamp={:?}",
                 settings.end_mode, PravegaTimestamp::from_nanoseconds(Some(settings.end_timestamp)));
+            let key_ring = KeyRing::load(settings.encryption_key_file.as_deref(), settings.encryption_key_env.as_deref()).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::Settings, ["Failed to load encryption keys: {}", error])
+            })?.map(Arc::new);
+            info!(CAT, imp: self, "start: key_ring={:?}, checksum_mismatch_mode={:?}", key_ring, settings.checksum_mismatch_mode);
 
-            let controller = settings.controller.clone().ok_or_else(|| {
-                gst::error_msg!(gst::ResourceError::Settings, ["Controller is not defined"])
-            })?;
-            info!(CAT, imp: self, "start: controller={}", controller);
-            let keycloak_file = settings.keycloak_file.clone();
-            info!(CAT, imp: self, "start: keycloak_file={:?}", keycloak_file);
-            let config = utils::create_client_config(controller, keycloak_file).map_err(|error| {
-                gst::error_msg!(gst::ResourceError::Settings, ["Failed to create Pravega client config: {}", error])
-            })?;
-            trace!(CAT, imp: self, "start: config={:?}", config);
-            info!(CAT, imp: self, "start: controller_uri={}:{}", config.controller_uri.domain_name(), config.controller_uri.port());
-            info!(CAT, imp: self, "start: is_tls_enabled={}", config.is_tls_enabled);
-            info!(CAT, imp: self, "start: is_auth_enabled={}", config.is_auth_enabled);
+            let (storage, client_factory): (Box<dyn Storage>, Option<ClientFactory>) = match settings.storage_type {
+                StorageType::File { ref root } => {
+                    (Box::new(FileStorage::new(root.clone())), None)
+                },
+                StorageType::Pravega => {
+                    let controller = settings.controller.clone().ok_or_else(|| {
+                        gst::error_msg!(gst::ResourceError::Settings, ["Controller is not defined"])
+                    })?;
+                    info!(CAT, imp: self, "start: controller={}", controller);
+                    let keycloak_file = settings.keycloak_file.clone();
+                    info!(CAT, imp: self, "start: keycloak_file={:?}", keycloak_file);
+                    let config = utils::create_client_config(controller, keycloak_file).map_err(|error| {
+                        gst::error_msg!(gst::ResourceError::Settings, ["Failed to create Pravega client config: {}", error])
+                    })?;
+                    trace!(CAT, imp: self, "start: config={:?}", config);
+                    info!(CAT, imp: self, "start: controller_uri={}:{}", config.controller_uri.domain_name(), config.controller_uri.port());
+                    info!(CAT, imp: self, "start: is_tls_enabled={}", config.is_tls_enabled);
+                    info!(CAT, imp: self, "start: is_auth_enabled={}", config.is_auth_enabled);
 
-            let client_factory = ClientFactory::new(config);
-            let controller_client = client_factory.controller_client();
-            let runtime = client_factory.runtime();
+                    let client_factory = ClientFactory::new(config);
+                    (Box::new(PravegaStorage::new(client_factory.to_async())), Some(client_factory))
+                },
+            };
 
             // Create scope.
             info!(CAT, imp: self, "start: allow_create_scope={}", settings.allow_create_scope);
             if settings.allow_create_scope {
                 // This is expected to fail in some environments, even if the scope already exists.
                 // We will log the error and continue.
-                let _ = runtime.block_on(controller_client.create_scope(&scope)).map_err(|error| {
-                    debug!(CAT, imp: self, "Failed to create Pravega scope. This is normal if the scope already exists: {:?}", error);
+                let _ = storage.create_scope(&scope_name).map_err(|error| {
+                    debug!(CAT, imp: self, "Failed to create scope. This is normal if the scope already exists: {:?}", error);
                 });
             }
 
             // Create data stream.
-            let stream_config = StreamConfiguration {
-                scoped_stream: ScopedStream {
-                    scope: scope.clone(),
-                    stream: stream.clone(),
-                },
-                scaling: Scaling {
-                    scale_type: ScaleType::FixedNumSegments,
-                    min_num_segments: 1,
-                    ..Default::default()
-                },
-                retention: Default::default(),
-                tags: utils::get_video_tags(),
-            };
-            runtime.block_on(controller_client.create_stream(&stream_config)).map_err(|error| {
-                gst::error_msg!(gst::ResourceError::Settings, ["Failed to create Pravega data stream: {:?}", error])
+            storage.create_stream(&scope_name, &stream_name, utils::get_video_tags()).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::Settings, ["Failed to create data stream: {:?}", error])
             })?;
 
             // Create index stream.
-            let index_stream_config = StreamConfiguration {
-                scoped_stream: ScopedStream {
-                    scope: scope.clone(),
-                    stream: index_stream.clone(),
-                },
-                scaling: Scaling {
-                    scale_type: ScaleType::FixedNumSegments,
-                    min_num_segments: 1,
-                    ..Default::default()
-                },
-                retention: Default::default(),
-                tags: None,
-            };
-            runtime.block_on(controller_client.create_stream(&index_stream_config)).map_err(|error| {
-                gst::error_msg!(gst::ResourceError::Settings, ["Failed to create Pravega index stream: {:?}", error])
+            storage.create_stream(&scope_name, &index_stream_name, None).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::Settings, ["Failed to create index stream: {:?}", error])
             })?;
 
-            let scoped_stream = ScopedStream {
-                scope: scope.clone(),
-                stream: stream.clone(),
-            };
-            let reader = runtime.block_on(client_factory.create_byte_reader(scoped_stream));
-            let mut reader = SyncByteReader::new(reader, client_factory.runtime_handle());
-            info!(CAT, imp: self, "start: Opened Pravega reader for data");
+            // Create metadata stream. Streams written by older versions of pravegasink will not have metadata.
+            storage.create_stream(&scope_name, &metadata_stream_name, None).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::Settings, ["Failed to create metadata stream: {:?}", error])
+            })?;
 
-            let index_scoped_stream = ScopedStream {
-                scope: scope.clone(),
-                stream: index_stream.clone(),
-            };
-            let index_reader = runtime.block_on(client_factory.create_byte_reader(index_scoped_stream));
-            info!(CAT, imp: self, "start: Opened Pravega reader for index");
+            let mut reader = storage.create_reader(&scope_name, &stream_name).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::OpenRead, ["Failed to open reader for data: {}", error])
+            })?;
+            info!(CAT, imp: self, "start: Opened reader for data");
 
-            let mut index_searcher = IndexSearcher::new(SyncByteReader::new(index_reader, client_factory.runtime_handle()));
+            let index_reader = storage.create_reader(&scope_name, &index_stream_name).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::OpenRead, ["Failed to open reader for index: {}", error])
+            })?;
+            info!(CAT, imp: self, "start: Opened reader for index");
+
+            let mut index_searcher = IndexSearcher::new(index_reader);
+
+            let metadata_reader = storage.create_reader(&scope_name, &metadata_stream_name).map_err(|error| {
+                gst::error_msg!(gst::ResourceError::OpenRead, ["Failed to open reader for metadata: {}", error])
+            })?;
+            let mut metadata_searcher = MetadataSearcher::new(metadata_reader);
+            metadata_searcher.refresh().map_err(|error| {
+                gst::error_msg!(gst::ResourceError::Read, ["Failed to read metadata stream: {}", error])
+            })?;
+            info!(CAT, imp: self, "start: metadata_records={:?}", metadata_searcher.records());
 
             // TODO: Run below based on CAT threshold.
             // debug!(CAT, imp: self, "index_records={:?}", index_searcher.get_index_records());
@@ -666,6 +957,14 @@ impl BaseSrcImpl for PravegaSrc {
             *state = State::Started {
                 reader: Arc::new(Mutex::new(counting_reader)),
                 index_searcher: Arc::new(Mutex::new(index_searcher)),
+                metadata: Arc::new(Mutex::new(MetadataState {
+                    searcher: metadata_searcher,
+                    caps: None,
+                })),
+                trick_mode: Arc::new(Mutex::new(None)),
+                buffer_pools: Arc::new(Mutex::new(SizedBufferPools::new())),
+                key_ring,
+                checksum_mismatch_mode: settings.checksum_mismatch_mode,
                 client_factory,
             };
             info!(CAT, imp: self, "start: Started");
@@ -696,6 +995,13 @@ impl BaseSrcImpl for PravegaSrc {
     ///    the values from the located index record.
     /// 3) The segment times will be set so that each buffer will have a PTS and position equal to
     ///    the number of nanoseconds since 1970-01-01 0:00:00 TAI.
+    ///
+    /// If the segment rate is negative or greater than 1.0, or the TRICKMODE_KEY_UNITS flag is set,
+    /// this will enable trick mode. In trick mode, only key frames are emitted.
+    /// Forward playback begins at the random-access point on or before the segment start.
+    /// Reverse playback begins at the random-access point on or before the segment stop and
+    /// emits earlier key frames until the segment start is reached.
+    /// Key frames are skipped at high rates.
     fn do_seek(&self, segment: &mut gst::Segment) -> bool {
         info!(CAT, imp: self, "do_seek: BEGIN: segment={:?}", segment);
         let result = (|| {
@@ -722,12 +1028,13 @@ impl BaseSrcImpl for PravegaSrc {
 
             let mut state = self.state.lock().unwrap();
 
-            let (reader, index_searcher) = match *state {
+            let (reader, index_searcher, trick_mode) = match *state {
                 State::Started {
                     ref mut reader,
                     ref mut index_searcher,
+                    ref mut trick_mode,
                     ..
-                } => (reader, index_searcher),
+                } => (reader, index_searcher, trick_mode),
                 State::Stopped => {
                     panic!("Not started yet");
                 }
@@ -735,9 +1042,12 @@ impl BaseSrcImpl for PravegaSrc {
 
             let reader = reader.clone();
             let index_searcher = index_searcher.clone();
+            let trick_mode = trick_mode.clone();
             drop(state);
             let mut reader = reader.lock().unwrap();
             let mut index_searcher = index_searcher.lock().unwrap();
+            let mut trick_mode = trick_mode.lock().unwrap();
+            *trick_mode = None;
 
             let segment = segment.downcast_mut::<gst::format::Time>().unwrap();
 
@@ -748,6 +1058,46 @@ impl BaseSrcImpl for PravegaSrc {
                 segment.start().unwrap().nseconds() == 0 &&
                 segment.position().unwrap().nseconds() == 0;
             info!(CAT, imp: self, "do_seek: initial_seek={}", initial_seek);
+
+            let rate = segment.rate();
+            let trick_mode_key_units = segment.flags().contains(gst::SegmentFlags::TRICKMODE_KEY_UNITS);
+            if !initial_seek && (rate < 0.0 || rate > 1.0 || trick_mode_key_units) {
+                let start = clocktime_to_pravega(segment.start()).or(PravegaTimestamp::MIN);
+                let stop = clocktime_to_pravega(segment.stop()).or(PravegaTimestamp::MAX);
+                info!(CAT, imp: self, "do_seek: trick mode; rate={}, start={:?}, stop={:?}", rate, start, stop);
+                let index_record = if rate > 0.0 {
+                    index_searcher.search_random_access(start, SearchMethod::Before)
+                        .or_else(|_| index_searcher.search_random_access(start, SearchMethod::After))
+                } else {
+                    index_searcher.search_random_access(stop, SearchMethod::Before)
+                };
+                info!(CAT, imp: self, "do_seek: index_record={:?}", index_record);
+                return match index_record {
+                    Ok((index_record, _)) => {
+                        if rate > 0.0 {
+                            // The segment will start at the indexed time.
+                            segment.set_start(pravega_to_clocktime(index_record.timestamp));
+                            segment.set_time(pravega_to_clocktime(index_record.timestamp));
+                        } else {
+                            segment.set_time(segment.start());
+                        }
+                        segment.set_position(ClockTime::NONE);
+                        *trick_mode = Some(TrickMode {
+                            rate,
+                            next_record: Some(index_record),
+                            start,
+                            stop,
+                        });
+                        info!(CAT, imp: self, "do_seek: trick_mode={:?}, segment={:?}", *trick_mode, segment);
+                        true
+                    },
+                    Err(err) => {
+                        warning!(CAT, imp: self, "do_seek: Failed to find key frame for trick mode: {}", err);
+                        false
+                    },
+                };
+            }
+
             let no_seek = initial_seek && start_mode == StartMode::NoSeek;
             let seek_using_index = !no_seek;
             if seek_using_index {
@@ -891,11 +1241,17 @@ impl PushSrcImpl for PravegaSrc {
 
             let mut state = self.state.lock().unwrap();
 
-            let reader = match *state {
+            let (reader, index_searcher, metadata, trick_mode, buffer_pools, key_ring, checksum_mismatch_mode) = match *state {
                 State::Started {
                     ref mut reader,
+                    ref mut index_searcher,
+                    ref mut metadata,
+                    ref mut trick_mode,
+                    ref mut buffer_pools,
+                    ref key_ring,
+                    checksum_mismatch_mode,
                     ..
-                } => reader,
+                } => (reader, index_searcher, metadata, trick_mode, buffer_pools, key_ring, checksum_mismatch_mode),
                 State::Stopped => {
                     gst::element_imp_error!(self, gst::CoreError::Failed, ["Not started yet"]);
                     panic!("Not started yet");
@@ -903,37 +1259,164 @@ impl PushSrcImpl for PravegaSrc {
             };
 
             let reader = reader.clone();
+            let index_searcher = index_searcher.clone();
+            let metadata = metadata.clone();
+            let trick_mode = trick_mode.clone();
+            let buffer_pools = buffer_pools.clone();
+            let key_ring = key_ring.clone();
             drop(state);
             let mut reader = reader.lock().unwrap();
             let reader = &mut (*reader);
 
-            let mut event_reader = EventReader::new();
-            let offset = reader.stream_position().unwrap();
-            let required_buffer_length = event_reader.read_required_buffer_length(reader).map_err(|err| {
-                if err.kind() == ErrorKind::UnexpectedEof {
-                    info!(CAT, imp: self, "create: reached EOF when trying to read event length");
-                    gst::FlowError::Eos
-                } else {
-                    gst::element_imp_error!(self, gst::CoreError::Failed, ["Failed to read event length from stream: {}", err]);
-                    gst::FlowError::Error
+            // In trick mode, position the reader at the next key frame and locate the one after it.
+            let is_trick_mode = trick_mode.lock().unwrap().is_some();
+            if is_trick_mode {
+                // Lock in the same order as do_seek.
+                let mut index_searcher = index_searcher.lock().unwrap();
+                let mut trick_mode = trick_mode.lock().unwrap();
+                if let Some(trick_mode) = trick_mode.as_mut() {
+                    let index_record = match trick_mode.next_record.take() {
+                        Some(index_record) => index_record,
+                        None => {
+                            info!(CAT, imp: self, "create: reached end of segment in trick mode");
+                            return Err(gst::FlowError::Eos);
+                        },
+                    };
+                    log!(CAT, imp: self, "create: trick mode; index_record={:?}", index_record);
+                    reader.seek(SeekFrom::Start(index_record.offset)).map_err(|err| {
+                        gst::element_imp_error!(self, gst::CoreError::Failed, ["Failed to seek to key frame: {}", err]);
+                        gst::FlowError::Error
+                    })?;
+                    trick_mode.next_record = trick_mode.find_next_record(&mut index_searcher, index_record.timestamp).map_err(|err| {
+                        gst::element_imp_error!(self, gst::ResourceError::Read, ["Failed to search index for next key frame: {}", err]);
+                        gst::FlowError::Error
+                    })?;
                 }
-            })?;
+            }
+
+            // Set when an event is dropped because of a checksum mismatch or an incomplete fragmented buffer.
+            let mut discont_after_drop = false;
+            // When pravegasink splits a large buffer into multiple events, the payloads are appended to the first fragment.
+            let mut fragments: Option<(u64, EventHeader, gst::Buffer, usize)> = None;
+            let (offset, header, mut gst_buffer, payload_length) = loop {
+                let mut event_reader = EventReader::new();
+                let offset = reader.stream_position().unwrap();
+                event_reader.read_required_buffer_length(reader).map_err(|err| {
+                    if err.kind() == ErrorKind::UnexpectedEof {
+                        info!(CAT, imp: self, "create: reached EOF when trying to read event length");
+                        if let Some((first_offset, ..)) = fragments.as_ref() {
+                            warning!(CAT, imp: self, "create: Dropping incomplete fragmented buffer at offset {}", first_offset);
+                        }
+                        gst::FlowError::Eos
+                    } else {
+                        gst::element_imp_error!(self, gst::CoreError::Failed, ["Failed to read event length from stream: {}", err]);
+                        gst::FlowError::Error
+                    }
+                })?;
+                let map_read_err = |err: std::io::Error| {
+                    if err.kind() == ErrorKind::UnexpectedEof {
+                        info!(CAT, imp: self, "create: reached EOF when trying to read event payload");
+                        gst::FlowError::Eos
+                    } else {
+                        gst::element_imp_error!(self, gst::CoreError::Failed, ["Failed to read event payload from stream: {}", err]);
+                        gst::FlowError::Error
+                    }
+                };
+                let header = event_reader.read_header(reader).map_err(map_read_err)?;
+                let payload_length = event_reader.payload_length();
 
-            // TODO: Read directly into GstBuffer.
-            let mut read_buffer: Vec<u8> = vec![0; required_buffer_length];
-            let event = event_reader.read_event(reader, &mut read_buffer[..]).map_err(|err| {
-                if err.kind() == ErrorKind::UnexpectedEof {
-                    info!(CAT, imp: self, "create: reached EOF when trying to read event payload");
-                    gst::FlowError::Eos
+                let event = if header.encrypted {
+                    // Decrypt the payload in place, then copy the plaintext into a pooled buffer.
+                    let mut payload = vec![0; payload_length];
+                    let drop_event = match event_reader.read_payload(reader, &mut payload[..]) {
+                        Ok(()) => false,
+                        Err(err) if is_checksum_error(&err) => self.handle_checksum_mismatch(checksum_mismatch_mode, offset, &err)?,
+                        Err(err) => return Err(map_read_err(err)),
+                    };
+                    if drop_event {
+                        None
+                    } else {
+                        let plaintext = decrypt_payload(key_ring.as_deref(), &header, event_reader.header_bytes(), &mut payload[..]).map_err(|err| {
+                            gst::element_imp_error!(self, gst::ResourceError::Read, ["Failed to decrypt event at offset {}: {}", offset, err]);
+                            gst::FlowError::Error
+                        })?;
+                        let mut gst_buffer = buffer_pools.lock().unwrap().acquire_buffer(plaintext.len())?;
+                        gst_buffer.get_mut().unwrap().copy_from_slice(0, &payload[plaintext.clone()]).map_err(|_| {
+                            gst::element_imp_error!(self, gst::CoreError::Failed, ["Failed to copy to buffer"]);
+                            gst::FlowError::Error
+                        })?;
+                        Some((gst_buffer, plaintext.len()))
+                    }
                 } else {
-                    gst::element_imp_error!(self, gst::CoreError::Failed, ["Failed to read event payload from stream: {}", err]);
-                    gst::FlowError::Error
+                    // Read the payload directly into a pooled buffer to avoid an intermediate copy.
+                    let mut gst_buffer = buffer_pools.lock().unwrap().acquire_buffer(payload_length)?;
+                    let drop_event = {
+                        let buffer_ref = gst_buffer.get_mut().unwrap();
+                        let mut buffer_map = buffer_ref.map_writable().map_err(|_| {
+                            gst::element_imp_error!(self, gst::CoreError::Failed, ["Failed to map buffer"]);
+                            gst::FlowError::Error
+                        })?;
+                        match event_reader.read_payload(reader, buffer_map.as_mut_slice()) {
+                            Ok(()) => false,
+                            Err(err) if is_checksum_error(&err) => self.handle_checksum_mismatch(checksum_mismatch_mode, offset, &err)?,
+                            Err(err) => return Err(map_read_err(err)),
+                        }
+                    };
+                    if drop_event {
+                        None
+                    } else {
+                        Some((gst_buffer, payload_length))
+                    }
+                };
+                let (gst_buffer, payload_length) = match event {
+                    Some(event) => event,
+                    None => {
+                        discont_after_drop = true;
+                        if let Some((first_offset, ..)) = fragments.take() {
+                            warning!(CAT, imp: self, "create: Dropping fragmented buffer at offset {} because a fragment was dropped", first_offset);
+                        }
+                        continue;
+                    },
+                };
+
+                // Reassemble a buffer that was split into multiple events by pravegasink.
+                if header.continuation {
+                    match fragments.take() {
+                        Some((first_offset, first_header, mut first_buffer, first_length)) => {
+                            log!(CAT, imp: self, "create: appending fragment at offset {} to buffer at offset {}", offset, first_offset);
+                            first_buffer.append(gst_buffer);
+                            let length = first_length + payload_length;
+                            if header.more_fragments {
+                                fragments = Some((first_offset, first_header, first_buffer, length));
+                                continue;
+                            }
+                            break (first_offset, first_header, first_buffer, length);
+                        },
+                        None => {
+                            // This happens when reading begins after the first fragment.
+                            debug!(CAT, imp: self, "create: Dropping fragment at offset {} without the preceding fragments", offset);
+                            discont_after_drop = true;
+                            continue;
+                        },
+                    }
                 }
-            })?;
-            memdump!(CAT, imp: self, "create: event={:?}", event);
+                // If pravegasink terminated before writing the last fragment, the next event will not be a continuation.
+                if let Some((first_offset, ..)) = fragments.take() {
+                    warning!(CAT, imp: self, "create: Dropping incomplete fragmented buffer at offset {}", first_offset);
+                    discont_after_drop = true;
+                }
+                if header.more_fragments {
+                    fragments = Some((offset, header, gst_buffer, payload_length));
+                    continue;
+                }
+                break (offset, header, gst_buffer, payload_length);
+            };
+            memdump!(CAT, imp: self, "create: header={:?}, payload_length={}", header, payload_length);
             let offset_end = reader.stream_position().unwrap();
 
-            let mut gst_buffer = gst::Buffer::with_size(event.payload.len()).unwrap();
+            // Caps may change at a discontinuity. pravegasink writes the metadata before the data.
+            self.update_caps(&metadata, offset, header.discontinuity)?;
+
             {
                 let buffer_ref = gst_buffer.get_mut().unwrap();
 
@@ -943,23 +1426,30 @@ impl PushSrcImpl for PravegaSrc {
                     .downcast::<gst::format::Time>()
                     .unwrap();
                 trace!(CAT, imp: self, "create: segment={:?}", segment);
-                let pts = pravega_to_clocktime(event.header.timestamp);
+                let pts = pravega_to_clocktime(header.timestamp);
                 log!(CAT, imp: self, "create: timestamp={:?}, pts={}, payload_len={}",
-                    event.header.timestamp, pts.unwrap_or_default(), event.payload.len());
+                    header.timestamp, pts.unwrap_or_default(), payload_length);
 
                 buffer_ref.set_pts(pts);
                 buffer_ref.set_offset(offset);
                 buffer_ref.set_offset_end(offset_end);
-                if !event.header.random_access {
-                    buffer_ref.set_flags(gst::BufferFlags::DELTA_UNIT);
+                match header.extension {
+                    // The extended header has the DTS, duration, and flags of the buffer written by pravegasink.
+                    Some(extension) => {
+                        buffer_ref.set_dts(pravega_to_clocktime(extension.dts));
+                        buffer_ref.set_duration(extension.duration.map(gst::ClockTime::from_nseconds));
+                        buffer_ref.set_flags(gst::BufferFlags::from_bits_truncate(extension.buffer_flags) - gst::BufferFlags::TAG_MEMORY);
+                    },
+                    None => {
+                        if !header.random_access {
+                            buffer_ref.set_flags(gst::BufferFlags::DELTA_UNIT);
+                        }
+                    },
                 }
-                if event.header.discontinuity {
+                // In trick mode, each buffer follows a jump in the stream.
+                if header.discontinuity || is_trick_mode || discont_after_drop {
                     buffer_ref.set_flags(gst::BufferFlags::DISCONT);
                 }
-
-                let mut buffer_map = buffer_ref.map_writable().unwrap();
-                let slice = buffer_map.as_mut_slice();
-                slice.copy_from_slice(event.payload);
             }
 
             Ok(CreateSuccess::NewBuffer(gst_buffer))
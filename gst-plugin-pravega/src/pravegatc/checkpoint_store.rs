@@ -0,0 +1,433 @@
+//
+// Copyright (c) Dell Inc., or its subsidiaries. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+
+//! Persistent storage of the pravegatc checkpoints.
+
+use pravega_client::client_factory::{ClientFactory, ClientFactoryAsync};
+use pravega_client::sync::table::{Table, Version};
+use pravega_client_shared::Scope;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+
+const FILE_URI_PREFIX: &str = "file://";
+const FILE_EXTENSION: &str = "json";
+const TEMP_FILE_EXTENSION: &str = "tmp";
+// Names of the file that holds a pending update of multiple keys. Key files always end with
+// FILE_EXTENSION or TEMP_FILE_EXTENSION, so these cannot collide with them.
+const BATCH_FILE_NAME: &str = "pending.batch";
+const TEMP_BATCH_FILE_NAME: &str = "pending.batch-tmp";
+// The version of a key that does not exist in a Pravega table.
+const KEY_NOT_EXISTS: Version = -1;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PersistentState {
+    pub resume_at_pts: u64,
+    // Set to 0 when resume_at_pts is set. Incremented when resuming.
+    // This is an Option to allow new applications to read old state.
+    pub resume_count: Option<u64>,
+    // The pravegasink data stream (scope/stream) and its committed offset when resume_at_pts was recorded.
+    // These are only set when exactly-once is enabled.
+    #[serde(default)]
+    pub sink_stream: Option<String>,
+    #[serde(default)]
+    pub sink_offset: Option<u64>,
+}
+
+/// A range of PTS that was skipped when resuming. Buffers in this range were never processed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SkippedRange {
+    // The PTS where the previous attempts resumed. This is the first skipped PTS.
+    pub begin_pts: u64,
+    // The PTS where this attempt resumed. This is the first PTS that was not skipped.
+    pub end_pts: u64,
+    pub resume_count: u64,
+    // The time when the range was skipped.
+    pub skipped_at: u64,
+}
+
+/// A key-value store for checkpoints.
+/// A store shared by multiple writers may reject a write to a key that another writer changed
+/// after this store last read or wrote it.
+pub trait CheckpointStore: Send {
+    fn get_persistent_state(&mut self, key: &str) -> Result<Option<PersistentState>>;
+
+    fn put_persistent_state(&mut self, key: &str, persistent_state: &PersistentState) -> Result<()>;
+
+    /// Write the same persistent state to multiple keys in a single update.
+    fn put_persistent_states(&mut self, keys: &[String], persistent_state: &PersistentState) -> Result<()>;
+
+    /// Returns an empty list if the key does not exist.
+    fn get_skipped_ranges(&mut self, key: &str) -> Result<Vec<SkippedRange>>;
+
+    fn put_skipped_ranges(&mut self, key: &str, skipped_ranges: &[SkippedRange]) -> Result<()>;
+}
+
+/// The location of a checkpoint store, parsed from a URI.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckpointStoreLocation {
+    /// A Pravega key-value table, specified as `scope/table`.
+    Table { scope: String, table: String },
+    /// A local directory, specified as `file:///path/to/dir`.
+    File { dir: PathBuf },
+}
+
+impl CheckpointStoreLocation {
+    /// Parse `scope/table` or `file:///path/to/dir`.
+    pub fn parse(uri: &str) -> std::result::Result<CheckpointStoreLocation, String> {
+        if let Some(path) = uri.strip_prefix(FILE_URI_PREFIX) {
+            let dir = Path::new(path);
+            if !dir.is_absolute() {
+                return Err(format!("File URI must have an absolute path: {}", uri));
+            }
+            Ok(CheckpointStoreLocation::File { dir: dir.to_path_buf() })
+        } else {
+            let components: Vec<&str> = uri.split('/').collect();
+            if components.len() != 2 || components[0].is_empty() || components[1].is_empty() {
+                return Err(format!("Table must be in the format scope/table or file:///path/to/dir: {}", uri));
+            }
+            Ok(CheckpointStoreLocation::Table {
+                scope: components[0].to_owned(),
+                table: components[1].to_owned(),
+            })
+        }
+    }
+}
+
+impl fmt::Display for CheckpointStoreLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointStoreLocation::Table { scope, table } => write!(f, "{}/{}", scope, table),
+            CheckpointStoreLocation::File { dir } => write!(f, "{}{}", FILE_URI_PREFIX, dir.display()),
+        }
+    }
+}
+
+//
+// Pravega key-value table
+//
+
+/// Stores checkpoints in a Pravega key-value table.
+/// The version of each key that was read or written is remembered and all writes are conditional on it.
+/// If another writer has updated a key since, the write fails.
+pub struct TableCheckpointStore {
+    client_factory: ClientFactoryAsync,
+    table: Table,
+    versions: HashMap<String, Version>,
+    // If true, all writes fail.
+    read_only: bool,
+    // Keeps the runtime of the client factory alive when this store owns it.
+    _owned_client_factory: Option<ClientFactory>,
+}
+
+impl TableCheckpointStore {
+    /// Open the table, creating it if needed.
+    pub fn new(client_factory: ClientFactory, scope: &str, table_name: &str) -> TableCheckpointStore {
+        let client_factory_async = client_factory.to_async();
+        let table = client_factory.runtime().block_on(
+            client_factory_async.create_table(Scope::from(scope.to_owned()), table_name.to_owned()));
+        TableCheckpointStore {
+            client_factory: client_factory_async,
+            table,
+            versions: HashMap::new(),
+            read_only: false,
+            _owned_client_factory: Some(client_factory),
+        }
+    }
+
+    /// Open the table of another application for reading, using a shared client factory.
+    /// This fails if the scope does not exist. The table is not changed if it already exists.
+    /// Writes to the returned store fail.
+    pub fn open_read_only(client_factory: &ClientFactoryAsync, scope: &str, table_name: &str) -> Result<TableCheckpointStore> {
+        let runtime = client_factory.runtime_handle();
+        let scope = Scope::from(scope.to_owned());
+        let scope_exists = runtime.block_on(client_factory.controller_client().check_scope_exists(&scope))
+            .map_err(|err| Error::new(ErrorKind::Other, format!("Failed to check scope {}: {}", scope, err)))?;
+        if !scope_exists {
+            return Err(Error::new(ErrorKind::NotFound, format!("Scope {} of table {} does not exist", scope, table_name)));
+        }
+        let table = runtime.block_on(client_factory.create_table(scope, table_name.to_owned()));
+        Ok(TableCheckpointStore {
+            client_factory: client_factory.clone(),
+            table,
+            versions: HashMap::new(),
+            read_only: true,
+            _owned_client_factory: None,
+        })
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::new(ErrorKind::PermissionDenied, "Pravega table was opened read-only"));
+        }
+        Ok(())
+    }
+
+    fn get<V: Serialize + DeserializeOwned>(&mut self, key: &str) -> Result<Option<V>> {
+        let value: Option<(V, Version)> = self.client_factory.runtime_handle().block_on(self.table.get(&key.to_owned()))
+            .map_err(|err| Error::new(ErrorKind::Other, format!("Failed to read from Pravega table: {}", err)))?;
+        self.versions.insert(key.to_owned(), value.as_ref().map_or(KEY_NOT_EXISTS, |(_, version)| *version));
+        Ok(value.map(|(value, _)| value))
+    }
+
+    /// Write a value if the key has not been changed by another writer since it was last read or written.
+    fn put<V: Serialize + DeserializeOwned>(&mut self, key: &str, value: &V) -> Result<()> {
+        self.check_writable()?;
+        let version = self.version::<V>(key)?;
+        let version = self.client_factory.runtime_handle().block_on(self.table.insert_conditionally(&key.to_owned(), value, version, -1))
+            .map_err(|err| Error::new(ErrorKind::Other, format!("Failed to write to Pravega table: {}", err)))?;
+        self.versions.insert(key.to_owned(), version);
+        Ok(())
+    }
+
+    /// Returns the version of a key when it was last read or written.
+    fn version<V: Serialize + DeserializeOwned>(&mut self, key: &str) -> Result<Version> {
+        if !self.versions.contains_key(key) {
+            self.get::<V>(key)?;
+        }
+        Ok(self.versions[key])
+    }
+}
+
+impl CheckpointStore for TableCheckpointStore {
+    fn get_persistent_state(&mut self, key: &str) -> Result<Option<PersistentState>> {
+        self.get(key)
+    }
+
+    fn put_persistent_state(&mut self, key: &str, persistent_state: &PersistentState) -> Result<()> {
+        self.put(key, persistent_state)
+    }
+
+    fn put_persistent_states(&mut self, keys: &[String], persistent_state: &PersistentState) -> Result<()> {
+        self.check_writable()?;
+        let versions = keys.iter().map(|key| self.version::<PersistentState>(key)).collect::<Result<Vec<_>>>()?;
+        let entries: Vec<(&String, &PersistentState, Version)> = keys.iter().zip(versions)
+            .map(|(key, version)| (key, persistent_state, version))
+            .collect();
+        let new_versions = self.client_factory.runtime_handle().block_on(self.table.insert_conditionally_all(entries, -1))
+            .map_err(|err| Error::new(ErrorKind::Other, format!("Failed to write to Pravega table: {}", err)))?;
+        for (key, version) in keys.iter().zip(new_versions) {
+            self.versions.insert(key.clone(), version);
+        }
+        Ok(())
+    }
+
+    fn get_skipped_ranges(&mut self, key: &str) -> Result<Vec<SkippedRange>> {
+        Ok(self.get(key)?.unwrap_or_default())
+    }
+
+    fn put_skipped_ranges(&mut self, key: &str, skipped_ranges: &[SkippedRange]) -> Result<()> {
+        self.put(key, &skipped_ranges.to_vec())
+    }
+}
+
+//
+// Local file
+//
+
+/// Stores checkpoints in a local directory, with one JSON file per key.
+/// A file is replaced by writing a temporary file and renaming it, so a crash will leave either
+/// the old or the new value.
+/// An update of multiple keys is first written to a single batch file. Once the batch file has been
+/// renamed into place, the update is complete; if a crash interrupts applying it to the key files,
+/// it is applied again before the store is next read or written.
+pub struct FileCheckpointStore {
+    dir: PathBuf,
+}
+
+impl FileCheckpointStore {
+    /// Open the directory, creating it if needed.
+    pub fn new(dir: PathBuf) -> Result<FileCheckpointStore> {
+        fs::create_dir_all(&dir)?;
+        Ok(FileCheckpointStore { dir })
+    }
+
+    fn key_path(&self, key: &str, extension: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", escape_key(key), extension))
+    }
+
+    fn get<V: DeserializeOwned>(&mut self, key: &str) -> Result<Option<V>> {
+        self.apply_pending_batch()?;
+        match fs::read(self.key_path(key, FILE_EXTENSION)) {
+            Ok(bytes) => {
+                let value = serde_json::from_slice(&bytes)
+                    .map_err(|err| Error::new(ErrorKind::InvalidData, format!("Failed to parse checkpoint {}: {}", key, err)))?;
+                Ok(Some(value))
+            },
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn put<V: Serialize>(&mut self, key: &str, value: &V) -> Result<()> {
+        self.apply_pending_batch()?;
+        let bytes = serialize(key, value)?;
+        self.replace_file(&self.key_path(key, TEMP_FILE_EXTENSION), &self.key_path(key, FILE_EXTENSION), &bytes)
+    }
+
+    /// Write the value to all keys atomically, using a batch file.
+    fn put_all<K: AsRef<str>, V: Serialize>(&mut self, keys: &[K], value: &V) -> Result<()> {
+        self.apply_pending_batch()?;
+        let mut batch = HashMap::new();
+        for key in keys.iter() {
+            let key = key.as_ref();
+            let value = serde_json::to_value(value)
+                .map_err(|err| Error::new(ErrorKind::InvalidInput, format!("Failed to serialize checkpoint {}: {}", key, err)))?;
+            batch.insert(key.to_owned(), value);
+        }
+        let bytes = serialize(BATCH_FILE_NAME, &batch)?;
+        self.replace_file(&self.dir.join(TEMP_BATCH_FILE_NAME), &self.dir.join(BATCH_FILE_NAME), &bytes)?;
+        self.apply_pending_batch()
+    }
+
+    /// If a batch file exists, write each of its keys and then remove it.
+    fn apply_pending_batch(&mut self) -> Result<()> {
+        let batch_path = self.dir.join(BATCH_FILE_NAME);
+        let bytes = match fs::read(&batch_path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let batch: HashMap<String, serde_json::Value> = serde_json::from_slice(&bytes)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, format!("Failed to parse checkpoint batch {}: {}", batch_path.display(), err)))?;
+        for (key, value) in batch.iter() {
+            let bytes = serialize(key, value)?;
+            self.replace_file(&self.key_path(key, TEMP_FILE_EXTENSION), &self.key_path(key, FILE_EXTENSION), &bytes)?;
+        }
+        fs::remove_file(&batch_path)?;
+        fs::File::open(&self.dir)?.sync_all()
+    }
+
+    /// Write bytes to a temporary file, then rename it to the destination path.
+    fn replace_file(&self, temp_path: &Path, path: &Path, bytes: &[u8]) -> Result<()> {
+        let mut file = fs::File::create(temp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(temp_path, path)?;
+        // Persist the rename.
+        fs::File::open(&self.dir)?.sync_all()
+    }
+}
+
+fn serialize<V: Serialize>(key: &str, value: &V) -> Result<Vec<u8>> {
+    serde_json::to_vec(value)
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, format!("Failed to serialize checkpoint {}: {}", key, err)))
+}
+
+impl CheckpointStore for FileCheckpointStore {
+    fn get_persistent_state(&mut self, key: &str) -> Result<Option<PersistentState>> {
+        self.get(key)
+    }
+
+    fn put_persistent_state(&mut self, key: &str, persistent_state: &PersistentState) -> Result<()> {
+        self.put(key, persistent_state)
+    }
+
+    fn put_persistent_states(&mut self, keys: &[String], persistent_state: &PersistentState) -> Result<()> {
+        self.put_all(keys, persistent_state)
+    }
+
+    fn get_skipped_ranges(&mut self, key: &str) -> Result<Vec<SkippedRange>> {
+        Ok(self.get(key)?.unwrap_or_default())
+    }
+
+    fn put_skipped_ranges(&mut self, key: &str, skipped_ranges: &[SkippedRange]) -> Result<()> {
+        self.put(key, &skipped_ranges)
+    }
+}
+
+/// Convert a key to a file name. Keys contain stream names which may contain slashes.
+/// Characters other than ASCII letters, digits, '-', '_' and '.' are percent-encoded.
+fn escape_key(key: &str) -> String {
+    key.bytes().map(|b| {
+        if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.' {
+            (b as char).to_string()
+        } else {
+            format!("%{:02X}", b)
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_store_location_parse() {
+        assert_eq!(CheckpointStoreLocation::parse("scope1/table1").unwrap(), CheckpointStoreLocation::Table {
+            scope: "scope1".to_owned(),
+            table: "table1".to_owned(),
+        });
+        assert_eq!(CheckpointStoreLocation::parse("file:///var/lib/pravegatc").unwrap(), CheckpointStoreLocation::File {
+            dir: PathBuf::from("/var/lib/pravegatc"),
+        });
+        assert!(CheckpointStoreLocation::parse("table1").is_err());
+        assert!(CheckpointStoreLocation::parse("a/b/c").is_err());
+        assert!(CheckpointStoreLocation::parse("file://pravegatc").is_err());
+    }
+
+    #[test]
+    fn test_escape_key() {
+        assert_eq!(escape_key("pravegatc.PersistentState.scope1/stream-1"), "pravegatc.PersistentState.scope1%2Fstream-1");
+    }
+
+    #[test]
+    fn test_file_checkpoint_store() {
+        let dir = std::env::temp_dir().join(format!("pravegatc-checkpoint-store-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let key = "pravegatc.PersistentState.scope1/stream1";
+        let persistent_state = PersistentState {
+            resume_at_pts: 1000,
+            resume_count: Some(0),
+            sink_stream: Some("scope1/stream2".to_owned()),
+            sink_offset: Some(200),
+        };
+        {
+            let mut store = FileCheckpointStore::new(dir.clone()).unwrap();
+            assert_eq!(store.get_persistent_state(key).unwrap(), None);
+            assert_eq!(store.get_skipped_ranges(key).unwrap(), vec![]);
+            store.put_persistent_state(key, &persistent_state).unwrap();
+            assert_eq!(store.get_persistent_state(key).unwrap(), Some(persistent_state.clone()));
+        }
+        // Reopen the store and replace the value.
+        let mut store = FileCheckpointStore::new(dir.clone()).unwrap();
+        assert_eq!(store.get_persistent_state(key).unwrap(), Some(persistent_state.clone()));
+        let persistent_state = PersistentState {
+            resume_count: Some(1),
+            ..persistent_state
+        };
+        store.put_persistent_state(key, &persistent_state).unwrap();
+        assert_eq!(store.get_persistent_state(key).unwrap(), Some(persistent_state));
+        // The temporary file should have been renamed.
+        assert!(!store.key_path(key, TEMP_FILE_EXTENSION).exists());
+        // Write multiple keys.
+        let keys = vec![key.to_owned(), "pravegatc.PersistentState.scope1/stream2".to_owned()];
+        store.put_persistent_states(&keys, &persistent_state).unwrap();
+        assert_eq!(store.get_persistent_state(&keys[0]).unwrap(), Some(persistent_state.clone()));
+        assert_eq!(store.get_persistent_state(&keys[1]).unwrap(), Some(persistent_state.clone()));
+        assert!(!dir.join(BATCH_FILE_NAME).exists());
+        // A batch file left by a crash is applied when the store is next read.
+        let persistent_state = PersistentState {
+            resume_count: Some(2),
+            ..persistent_state
+        };
+        let batch: HashMap<&String, &PersistentState> = keys.iter().map(|key| (key, &persistent_state)).collect();
+        fs::write(dir.join(BATCH_FILE_NAME), serde_json::to_vec(&batch).unwrap()).unwrap();
+        let mut store = FileCheckpointStore::new(dir.clone()).unwrap();
+        assert_eq!(store.get_persistent_state(&keys[1]).unwrap(), Some(persistent_state.clone()));
+        assert_eq!(store.get_persistent_state(&keys[0]).unwrap(), Some(persistent_state));
+        assert!(!dir.join(BATCH_FILE_NAME).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
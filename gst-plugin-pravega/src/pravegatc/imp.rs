@@ -16,16 +16,17 @@ use gst::subclass::prelude::*;
 use gst::{debug, error, warning, info, log, trace};
 use once_cell::sync::Lazy;
 use pravega_client::client_factory::ClientFactory;
-use pravega_client::sync::table::{Table, TableError, Version};
-use pravega_client_shared::Scope;
-use pravega_video::timestamp::{PravegaTimestamp, NSECOND, SECOND};
+use pravega_video::timestamp::{PravegaTimestamp, NSECOND};
 use pravega_video::utils;
-use crate::utils::clocktime_to_pravega;
-use serde::{Deserialize, Serialize};
+use crate::utils::{clocktime_to_pravega, CHECKPOINT_FIELD_OFFSET, CHECKPOINT_FIELD_STREAM, CHECKPOINT_QUERY_NAME,
+    RESUME_EVENT_NAME};
 use std::cmp;
 use std::env;
 use std::fmt;
-use std::sync::{Arc, Mutex};
+use std::sync::Mutex;
+use super::checkpoint_store::{CheckpointStore, CheckpointStoreLocation, FileCheckpointStore, PersistentState, TableCheckpointStore};
+use super::recovery::{Resume, RecoveryPolicy, persistent_state_key, resume_stream, DEFAULT_INITIAL_SKIP_SEC,
+    DEFAULT_MAX_EXACT_RESUME_COUNT, DEFAULT_MAX_SKIP_SEC, DEFAULT_SKIP_BACKOFF_FACTOR};
 
 pub const ELEMENT_NAME: &str = "pravegatc";
 const ELEMENT_CLASS_NAME: &str = "PravegaTC";
@@ -34,11 +35,22 @@ const ELEMENT_DESCRIPTION: &str = "\
 This element can be used in a pipeline with a pravegasrc element to provide failure recovery. \
 A pipeline that includes these elements can be restarted after a failure and the pipeline will \
 resume from where it left off. \
-The current implementation is best-effort which means that some buffers may be processed more than once or never at all. \
+Unless exactly-once is enabled, recovery is best-effort which means that some buffers may be processed more than once or never at all. \
 The pravegatc element periodically writes the PTS of the current buffer to a Pravega table. \
 When the pravegatc element starts, if it finds a PTS in this Pravega table, it sets the start-timestamp property of the pravegasrc element. \
 If pipeline recovery is attempted more than once from the same PTS, it is assumed that the input stream is defective, and subsequent recovery attempts \
-will skip over increasing amounts of data.\
+will skip over increasing amounts of data. \
+The number of exact recovery attempts and the amount of data to skip can be configured, or recovery can fail instead of skipping. \
+Each skipped range of PTS is recorded with the persistent state. \
+The persistent state can be stored in a Pravega table or in a local directory. \
+If exactly-once is enabled, this element must be linked directly to a pravegasink element. \
+Checkpoints are recorded at key frames and include the committed offset of the pravegasink data stream. \
+When resuming, buffers before the checkpoint are dropped and pravegasink truncates its output after the committed offset. \
+Pravega streams cannot be truncated at the tail, so pravegasink instead drops replayed buffers that it already wrote after the committed offset. \
+If a replayed buffer differs, the buffers already written are kept and replayed buffers are discarded until they are after them. \
+The persistent state is stored separately for each pravegasrc stream. \
+By default, this element controls all pravegasrc elements in the pipeline, including those in nested bins. \
+Use the sources property to control specific pravegasrc elements, for instance when a pipeline has multiple pravegatc elements.\
 ";
 const ELEMENT_AUTHOR: &str = "Claudio Fahey <claudio.fahey@dell.com>";
 const DEBUG_CATEGORY: &str = ELEMENT_NAME;
@@ -46,48 +58,57 @@ const DEBUG_CATEGORY: &str = ELEMENT_NAME;
 const PROPERTY_NAME_TABLE: &str = "table";
 const PROPERTY_NAME_CONTROLLER: &str = "controller";
 const PROPERTY_NAME_KEYCLOAK_FILE: &str = "keycloak-file";
-
-const DEFAULT_RECORD_PERIOD_MSECOND: u64 = 1000;
-
-const PERSISTENT_STATE_TABLE_KEY: &str = "pravegatc.PersistentState";
-
-#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
-struct PersistentState {
-    resume_at_pts: u64,
-    // Set to 0 when resume_at_pts is set. Incremented when resuming.
-    // This is an Option to allow new applications to read old state.
-    resume_count: Option<u64>,
-}
-
+const PROPERTY_NAME_EXACTLY_ONCE: &str = "exactly-once";
+const PROPERTY_NAME_SOURCES: &str = "sources";
+const PROPERTY_NAME_RECORD_PERIOD_SEC: &str = "record-period-sec";
+const PROPERTY_NAME_MAX_EXACT_RESUME_COUNT: &str = "max-exact-resume-count";
+const PROPERTY_NAME_INITIAL_SKIP_SEC: &str = "initial-skip-sec";
+const PROPERTY_NAME_SKIP_BACKOFF_FACTOR: &str = "skip-backoff-factor";
+const PROPERTY_NAME_MAX_SKIP_SEC: &str = "max-skip-sec";
+const PROPERTY_NAME_FAIL_INSTEAD_OF_SKIP: &str = "fail-instead-of-skip";
+
+const DEFAULT_RECORD_PERIOD_SEC: f64 = 1.0;
+// The maximum value of properties in seconds, which must be representable as a ClockTime.
+const MAX_DURATION_SEC: f64 = 100.0 * 365.0 * 24.0 * 60.0 * 60.0;
 #[derive(Debug)]
 struct Settings {
-    scope: Option<String>,
-    table: Option<String>,
+    store_location: Option<CheckpointStoreLocation>,
     controller: Option<String>,
     keycloak_file: Option<String>,
     fault_injection_pts: Option<ClockTime>,
     record_period: ClockTime,
+    exactly_once: bool,
+    // Names of the pravegasrc elements controlled by this element. If empty, all pravegasrc elements are controlled.
+    sources: Vec<String>,
+    recovery_policy: RecoveryPolicy,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Settings {
-            scope: None,
-            table: None,
+            store_location: None,
             controller: utils::default_pravega_controller_uri(),
             keycloak_file: utils::default_keycloak_file(),
             fault_injection_pts: ClockTime::NONE,
-            record_period: ClockTime::from_mseconds(DEFAULT_RECORD_PERIOD_MSECOND),
+            record_period: ClockTime::from_nseconds((DEFAULT_RECORD_PERIOD_SEC * 1e9) as u64),
+            exactly_once: false,
+            sources: Vec::new(),
+            recovery_policy: Default::default(),
         }
     }
 }
 
 struct StartedState {
-    client_factory: ClientFactory,
-    table: Arc<Mutex<Table>>,
+    store: Box<dyn CheckpointStore>,
+    // Keys of the persistent state of each controlled pravegasrc stream.
+    persistent_state_keys: Vec<String>,
     last_recorded_pts: Option<ClockTime>,
     // The resume_at_pts that will be written to the persistent state upon end-of-stream.
     final_resume_at_pts: PravegaTimestamp,
+    // When exactly-once is enabled, buffers before this PTS were processed before the checkpoint and are dropped.
+    drop_before_pts: PravegaTimestamp,
+    // When exactly-once is enabled, this event is sent to pravegasink before the first buffer.
+    resume_event: Option<gst::Event>,
 }
 
 impl fmt::Debug for StartedState {
@@ -130,26 +151,22 @@ impl PravegaTC {
         table: Option<String>,
     ) -> Result<(), glib::Error> {
         let mut settings = self.settings.lock().unwrap();
-        let (scope, table) = match table {
+        let store_location = match table {
             Some(table) => {
-                let components: Vec<&str> = table.split('/').collect();
-                if components.len() != 2 {
-                    return Err(glib::Error::new(
+                let store_location = CheckpointStoreLocation::parse(&table).map_err(|error| {
+                    glib::Error::new(
                         gst::URIError::BadUri,
-                        format!("table parameter '{}' is formatted incorrectly. It must be specified as scope/table.", table).as_str(),
-                    ));
-                }
-                let scope = components[0].to_owned();
-                let table = components[1].to_owned();
-                (Some(scope), Some(table))
+                        format!("table parameter '{}' is formatted incorrectly. It must be specified as scope/table or file:///path/to/dir. {}", table, error).as_str(),
+                    )
+                })?;
+                Some(store_location)
             }
             None => {
                 info!(CAT, imp: self, "Resetting `{}` to None", PROPERTY_NAME_TABLE);
-                (None, None)
+                None
             }
         };
-        settings.scope = scope;
-        settings.table = table;
+        settings.store_location = store_location;
         Ok(())
     }
 
@@ -179,92 +196,109 @@ impl PravegaTC {
                 warning!(CAT, imp: self, "start: fault_injection_pts={:?}", settings.fault_injection_pts);
             }
 
-            let scope_name: String = settings.scope.clone().ok_or_else(|| {
-                gst::error_msg!(gst::ResourceError::Settings, ["Scope is not defined"])
-            })?;
-            let table_name = settings.table.clone().ok_or_else(|| {
+            let store_location = settings.store_location.clone().ok_or_else(|| {
                 gst::error_msg!(gst::ResourceError::Settings, ["Table is not defined"])
             })?;
-            let scope = Scope::from(scope_name);
-            info!(CAT, imp: self, "start: scope={}, table_name={}", scope, table_name);
-            let controller = settings.controller.clone().ok_or_else(|| {
-                gst::error_msg!(gst::ResourceError::Settings, ["Controller is not defined"])
-            })?;
-            info!(CAT, imp: self, "start: controller={}", controller);
-            let keycloak_file = settings.keycloak_file.clone();
-            info!(CAT, imp: self, "start: keycloak_file={:?}", keycloak_file);
-            let config = utils::create_client_config(controller, keycloak_file).map_err(|error| {
-                gst::error_msg!(gst::ResourceError::Settings, ["Failed to create Pravega client config: {}", error])
-            })?;
-            trace!(CAT, imp: self, "start: config={:?}", config);
-            info!(CAT, imp: self, "start: controller_uri={}:{}", config.controller_uri.domain_name(), config.controller_uri.port());
-            info!(CAT, imp: self, "start: is_tls_enabled={}", config.is_tls_enabled);
-            info!(CAT, imp: self, "start: is_auth_enabled={}", config.is_auth_enabled);
-
-            let client_factory = ClientFactory::new(config);
-            let runtime = client_factory.runtime();
-
-            // Create Pravega table.
-            let table = runtime.block_on(client_factory.create_table(scope, table_name));
-
-            // Get last checkpointed state (pts) from Pravega table.
-            let persistent_state: Result<Option<(PersistentState, Version)>, TableError> = runtime.block_on(table.get(&PERSISTENT_STATE_TABLE_KEY.to_string()));
-            debug!(CAT, imp: self, "start: persistent_state={:?}", persistent_state);
-            let persistent_state = persistent_state.unwrap();
-            match persistent_state {
-                Some((mut persistent_state, version)) => {                    
-                    // Increment resume_count every time we attempt to resume.
-                    persistent_state.resume_count = Some(persistent_state.resume_count.unwrap_or_default() + 1);
-                    log!(CAT, imp: self, "start: writing persistent state {:?}", persistent_state);
-                    runtime.block_on(table.insert_conditionally(&PERSISTENT_STATE_TABLE_KEY.to_string(), &persistent_state, version, -1)).map_err(|error| {
-                        gst::error_msg!(gst::CoreError::Failed, ["Failed to write to Pravega table: {}", error])
-                    })?;
-                    
-                    // If resume count indicates multiple failures at the same point, then skip ahead.
-                    // resume_time_delta will start at 2 seconds and double until 1 year.
-                    let original_resume_at_pts = PravegaTimestamp::from_nanoseconds(Some(persistent_state.resume_at_pts));
-                    let resume_count = persistent_state.resume_count.unwrap_or_default();
-                    let max_exact_resume_count = 1;
-                    let initial_skip_time_delta = 2 * SECOND;
-                    let (resume_time_delta, start_mode) = if resume_count >= max_exact_resume_count + 1 {
-                        let resume_time_delta = u64::pow(2, u32::min(24, (resume_count - max_exact_resume_count - 1) as u32)) * initial_skip_time_delta;
-                        warning!(CAT, imp: self, "start: Skipping {:?} due to {} resume attempts", resume_time_delta, resume_count);
-                        (resume_time_delta, "timestamp")
-                    } else {
-                        (0 * SECOND, "timestamp-exact")
-                    };
-                    let resume_at_pts: PravegaTimestamp = original_resume_at_pts + resume_time_delta;
-                    
-                    info!(CAT, imp: self, "start: Resuming at PTS {:?}", resume_at_pts);
-                    let pipeline = self.obj().parent().unwrap().downcast::<gst::Pipeline>().unwrap();
-                    let children = pipeline.children();
-                    // Find all pravegasrc elements and set start-timestamp property.
-                    let mut elements_found = false;
-                    for child in children {
-                        trace!(CAT, imp: self, "start: child={:?}", child);
-                        let child_type_name = child.type_().name();
-                        if child_type_name == "PravegaSrc" {
-                            debug!(CAT, imp: self, "start: Setting start-timestamp of element {:?}", child.name());
-                            child.set_property_from_str("start-mode", start_mode);
-                            child.set_property("start-timestamp", &resume_at_pts.nanoseconds().unwrap());
-                            elements_found = true;
-                        }
-                    }
-                    if !elements_found {
-                        return Err(gst::error_msg!(gst::ResourceError::Settings, ["PravegaSrc element not found in pipeline"]));
-                    }
+            info!(CAT, imp: self, "start: store_location={}", store_location);
+            let mut store: Box<dyn CheckpointStore> = match store_location {
+                CheckpointStoreLocation::File { dir } => {
+                    Box::new(FileCheckpointStore::new(dir).map_err(|error| {
+                        gst::error_msg!(gst::ResourceError::OpenReadWrite, ["Failed to open checkpoint directory: {}", error])
+                    })?)
                 },
-                None => {
-                    info!(CAT, imp: self, "start: No persistent state found.");
+                CheckpointStoreLocation::Table { scope, table } => {
+                    let controller = settings.controller.clone().ok_or_else(|| {
+                        gst::error_msg!(gst::ResourceError::Settings, ["Controller is not defined"])
+                    })?;
+                    info!(CAT, imp: self, "start: controller={}", controller);
+                    let keycloak_file = settings.keycloak_file.clone();
+                    info!(CAT, imp: self, "start: keycloak_file={:?}", keycloak_file);
+                    let config = utils::create_client_config(controller, keycloak_file).map_err(|error| {
+                        gst::error_msg!(gst::ResourceError::Settings, ["Failed to create Pravega client config: {}", error])
+                    })?;
+                    trace!(CAT, imp: self, "start: config={:?}", config);
+                    info!(CAT, imp: self, "start: controller_uri={}:{}", config.controller_uri.domain_name(), config.controller_uri.port());
+                    info!(CAT, imp: self, "start: is_tls_enabled={}", config.is_tls_enabled);
+                    info!(CAT, imp: self, "start: is_auth_enabled={}", config.is_auth_enabled);
+
+                    // Create Pravega table.
+                    let client_factory = ClientFactory::new(config);
+                    Box::new(TableCheckpointStore::new(client_factory, &scope, &table))
                 },
+            };
+
+            // Find the pravegasrc elements controlled by this element.
+            let sources = self.find_controlled_sources(&settings.sources)?;
+
+            let mut persistent_state_keys = Vec::new();
+            let mut drop_before_pts = PravegaTimestamp::NONE;
+            let mut resume_event = None;
+            let mut sources_without_state = Vec::new();
+            for (source, stream) in sources {
+                // Get last checkpointed state (pts) of this stream and determine where to resume.
+                let resume = resume_stream(store.as_mut(), &stream, &settings.recovery_policy, PravegaTimestamp::now())
+                    .map_err(|error| {
+                        gst::error_msg!(gst::ResourceError::ReadWrite, ["Failed to access checkpoint store: {}", error])
+                    })?;
+                debug!(CAT, imp: self, "start: stream={}, resume={:?}", stream, resume);
+                match resume {
+                    Resume::Resume { resume_at_pts, exact, persistent_state } => {
+                        let start_mode = if exact {
+                            "timestamp-exact"
+                        } else {
+                            let original_resume_at_pts = PravegaTimestamp::from_nanoseconds(Some(persistent_state.resume_at_pts));
+                            warning!(CAT, imp: self, "start: Stream {} will not be processed from {} to {} due to {} resume attempts",
+                                stream, original_resume_at_pts, resume_at_pts, persistent_state.resume_count.unwrap_or_default());
+                            "timestamp"
+                        };
+
+                        info!(CAT, imp: self, "start: Resuming element {} stream {} at PTS {:?}", source.name(), stream, resume_at_pts);
+                        source.set_property_from_str("start-mode", start_mode);
+                        source.set_property("start-timestamp", &resume_at_pts.nanoseconds().unwrap());
+
+                        if settings.exactly_once {
+                            // Buffers before the resume PTS were committed by the previous instance.
+                            // If sources resume at different PTS, only buffers before the earliest one can be dropped.
+                            if drop_before_pts.is_none() || resume_at_pts < drop_before_pts {
+                                drop_before_pts = resume_at_pts;
+                                // Tell pravegasink where the committed output ends.
+                                resume_event = if let (Some(sink_stream), Some(sink_offset)) = (persistent_state.sink_stream.clone(), persistent_state.sink_offset) {
+                                    info!(CAT, imp: self, "start: Resuming pravegasink stream {} at committed offset {}", sink_stream, sink_offset);
+                                    Some(gst::event::CustomDownstream::new(
+                                        gst::Structure::builder(RESUME_EVENT_NAME)
+                                            .field(CHECKPOINT_FIELD_STREAM, sink_stream)
+                                            .field(CHECKPOINT_FIELD_OFFSET, sink_offset)
+                                            .build()))
+                                } else {
+                                    warning!(CAT, imp: self, "start: Persistent state does not have a committed offset; output may be duplicated");
+                                    None
+                                };
+                            }
+                        }
+                    },
+                    Resume::Fail { resume_at_pts, resume_count } => {
+                        return Err(gst::error_msg!(gst::CoreError::Failed,
+                            ["Recovery of stream {} at PTS {:?} failed {} times", stream, resume_at_pts, resume_count - 1]));
+                    },
+                    Resume::NoState => {
+                        info!(CAT, imp: self, "start: No persistent state found for stream {}.", stream);
+                        sources_without_state.push(stream.clone());
+                    },
+                }
+                persistent_state_keys.push(persistent_state_key(&stream));
+            }
+            if drop_before_pts.is_some() && !sources_without_state.is_empty() {
+                warning!(CAT, imp: self, "start: Streams {:?} have no persistent state and will not be resumed", sources_without_state);
             }
 
             *state = State::Started {
                 state: StartedState {
-                    client_factory,
-                    table: Arc::new(Mutex::new(table)),
+                    store,
+                    persistent_state_keys,
                     last_recorded_pts: ClockTime::NONE,
                     final_resume_at_pts: PravegaTimestamp::none(),
+                    drop_before_pts,
+                    resume_event,
                 },
             };
             info!(CAT, imp: self, "start: Started");
@@ -274,6 +308,84 @@ impl PravegaTC {
         result
     }
 
+    /// Find the pravegasrc elements controlled by this element and return them along with their stream.
+    /// If names is empty, all pravegasrc elements in the pipeline, including those in nested bins, are returned.
+    /// Otherwise, the pravegasrc elements with the given names are returned.
+    fn find_controlled_sources(&self, names: &[String]) -> Result<Vec<(gst::Element, String)>, gst::ErrorMessage> {
+        // Search from the top-level bin because this element may be in a different bin than the pravegasrc elements.
+        let mut top_level = self.obj().upcast_ref::<gst::Object>().clone();
+        while let Some(parent) = top_level.parent() {
+            top_level = parent;
+        }
+        let top_level = top_level.downcast::<gst::Bin>().map_err(|_| {
+            gst::error_msg!(gst::ResourceError::Settings, ["Element is not in a pipeline"])
+        })?;
+        let mut sources = Vec::new();
+        find_pravega_sources(&top_level, &mut sources);
+        trace!(CAT, imp: self, "find_controlled_sources: sources={:?}", sources);
+        let sources = if names.is_empty() {
+            if sources.is_empty() {
+                return Err(gst::error_msg!(gst::ResourceError::Settings, ["PravegaSrc element not found in pipeline"]));
+            }
+            sources
+        } else {
+            names.iter().map(|name| {
+                sources.iter().find(|source| source.name().as_str() == name.as_str()).cloned().ok_or_else(|| {
+                    gst::error_msg!(gst::ResourceError::Settings, ["PravegaSrc element {} not found in pipeline", name])
+                })
+            }).collect::<Result<Vec<_>, _>>()?
+        };
+        sources.into_iter().map(|source| {
+            let stream = source.property::<Option<String>>("stream").ok_or_else(|| {
+                gst::error_msg!(gst::ResourceError::Settings, ["Stream of PravegaSrc element {} is not defined", source.name()])
+            })?;
+            debug!(CAT, imp: self, "find_controlled_sources: element {} reads stream {}", source.name(), stream);
+            Ok((source, stream))
+        }).collect()
+    }
+
+    /// Query pravegasink for the stream and committed offset of its output.
+    /// pravegasink will flush all buffers that it received.
+    fn query_sink_checkpoint(&self) -> Result<(String, u64), gst::ErrorMessage> {
+        let mut query = gst::query::Custom::new(gst::Structure::new_empty(CHECKPOINT_QUERY_NAME));
+        if !self.srcpad.peer_query(&mut query) {
+            return Err(gst::error_msg!(gst::CoreError::Failed,
+                ["Checkpoint query failed. With exactly-once, pravegatc must be linked directly to pravegasink."]));
+        }
+        let structure = query.structure().ok_or_else(|| {
+            gst::error_msg!(gst::CoreError::Failed, ["Checkpoint query returned no result"])
+        })?;
+        let stream = structure.get::<String>(CHECKPOINT_FIELD_STREAM).map_err(|error| {
+            gst::error_msg!(gst::CoreError::Failed, ["Checkpoint query returned no stream: {}", error])
+        })?;
+        let offset = structure.get::<u64>(CHECKPOINT_FIELD_OFFSET).map_err(|error| {
+            gst::error_msg!(gst::CoreError::Failed, ["Checkpoint query returned no offset: {}", error])
+        })?;
+        Ok((stream, offset))
+    }
+
+    /// Write the persistent state, including the committed output offset if exactly-once is enabled.
+    fn write_persistent_state(&self, state: &mut StartedState, resume_at_pts: PravegaTimestamp, exactly_once: bool)
+            -> Result<(), gst::ErrorMessage> {
+        let (sink_stream, sink_offset) = if exactly_once {
+            let (stream, offset) = self.query_sink_checkpoint()?;
+            (Some(stream), Some(offset))
+        } else {
+            (None, None)
+        };
+        let persistent_state = PersistentState {
+            resume_at_pts: resume_at_pts.nanoseconds().unwrap(),
+            resume_count: Some(0),
+            sink_stream,
+            sink_offset,
+        };
+        log!(CAT, imp: self, "write_persistent_state: writing persistent state {:?}", persistent_state);
+        // All streams are written in a single update so that they always resume from the same checkpoint.
+        state.store.put_persistent_states(&state.persistent_state_keys, &persistent_state).map_err(|error| {
+            gst::error_msg!(gst::ResourceError::Write, ["Failed to write to checkpoint store: {}", error])
+        })
+    }
+
     fn sink_chain(
         &self,
         pad: &gst::Pad,
@@ -281,9 +393,9 @@ impl PravegaTC {
     ) -> Result<gst::FlowSuccess, gst::FlowError> {
         trace!(CAT, obj: pad, "sink_chain: Handling buffer {:?}", buffer);
 
-        let (fault_injection_pts, record_period) = {
+        let (fault_injection_pts, record_period, exactly_once) = {
             let settings = self.settings.lock().unwrap();
-            (settings.fault_injection_pts, settings.record_period)
+            (settings.fault_injection_pts, settings.record_period, settings.exactly_once)
         };
 
         let mut st = self.state.lock().unwrap();
@@ -306,6 +418,37 @@ impl PravegaTC {
             return Err(gst::FlowError::Error)
         }
 
+        if let Some(resume_event) = state.resume_event.take() {
+            debug!(CAT, imp: self, "sink_chain: sending resume event {:?}", resume_event);
+            if !self.srcpad.push_event(resume_event) {
+                gst::element_imp_error!(self, gst::CoreError::Failed, ["pravegasink did not accept the resume event"]);
+                return Err(gst::FlowError::Error)
+            }
+        }
+
+        if exactly_once {
+            // Buffers before the resume PTS were committed before the checkpoint.
+            if state.drop_before_pts.is_some() && clocktime_to_pravega(buffer_pts) < state.drop_before_pts {
+                log!(CAT, imp: self, "sink_chain: dropping buffer before resume PTS {:?}", state.drop_before_pts);
+                return Ok(gst::FlowSuccess::Ok)
+            }
+            // Checkpoints are recorded before key frames so that the pipeline can resume at a key frame.
+            // The committed offset is the offset of the sink output before this buffer.
+            let is_key_frame = !buffer.flags().contains(gst::BufferFlags::DELTA_UNIT);
+            let end_pts = match (state.last_recorded_pts, Some(record_period)) {
+                (Some(x), Some(y)) => Some(x + y),
+                _ => None,
+            };
+            if buffer_pts.is_some() && is_key_frame && (state.last_recorded_pts.is_none() || end_pts <= buffer_pts) {
+                debug!(CAT, imp: self, "sink_chain: writing checkpoint to resume at {:?}", buffer_pts);
+                self.write_persistent_state(state, clocktime_to_pravega(buffer_pts), exactly_once).map_err(|error| {
+                    self.post_error_message(error);
+                    gst::FlowError::Error
+                })?;
+                state.last_recorded_pts = buffer_pts;
+            }
+        }
+
         self.srcpad.push(buffer)?;
 
         if buffer_pts.is_some() {
@@ -319,17 +462,10 @@ impl PravegaTC {
                 (Some(x), Some(y)) => Some(x + y),
                 _ => None,
             };
-            if state.last_recorded_pts.is_none() || end_pts <= buffer_pts {
+            if !exactly_once && (state.last_recorded_pts.is_none() || end_pts <= buffer_pts) {
                 debug!(CAT, imp: self, "sink_chain: writing persistent state to resume at {:?}", resume_at_pts);
-                let runtime = state.client_factory.runtime();
-                let table = state.table.lock().unwrap();
-                let persistent_state = PersistentState {
-                    resume_at_pts: resume_at_pts.nanoseconds().unwrap(),
-                    resume_count: Some(0),
-                };
-                log!(CAT, imp: self, "sink_chain: writing persistent state {:?}", persistent_state);
-                runtime.block_on(table.insert(&PERSISTENT_STATE_TABLE_KEY.to_string(), &persistent_state, -1)).map_err(|error| {
-                    gst::element_imp_error!(self, gst::CoreError::Failed, ["Failed to write to Pravega table: {}", error]);
+                self.write_persistent_state(state, resume_at_pts, exactly_once).map_err(|error| {
+                    self.post_error_message(error);
                     gst::FlowError::Error
                 })?;
                 state.last_recorded_pts = buffer_pts;
@@ -341,6 +477,24 @@ impl PravegaTC {
     }
 
     fn sink_event(&self, _pad: &gst::Pad, event: gst::Event) -> bool {
+        // With exactly-once, record the final checkpoint before pravegasink receives end-of-stream.
+        if let gst::EventView::Eos(_) = event.view() {
+            let exactly_once = self.settings.lock().unwrap().exactly_once;
+            if exactly_once {
+                let mut st = self.state.lock().unwrap();
+                if let State::Started { ref mut state } = *st {
+                    if state.final_resume_at_pts.is_some() {
+                        info!(CAT, imp: self, "sink_event: writing final checkpoint to resume at {:?}", state.final_resume_at_pts);
+                        let final_resume_at_pts = state.final_resume_at_pts;
+                        if let Err(error) = self.write_persistent_state(state, final_resume_at_pts, exactly_once) {
+                            self.post_error_message(error);
+                            return false;
+                        }
+                        state.final_resume_at_pts = PravegaTimestamp::NONE;
+                    }
+                }
+            }
+        }
         self.srcpad.push_event(event)
     }
 
@@ -359,6 +513,7 @@ impl PravegaTC {
     fn stop(&self) -> Result<(), gst::ErrorMessage> {
         info!(CAT, imp: self, "stop: BEGIN");
         let result = (|| {
+            let exactly_once = self.settings.lock().unwrap().exactly_once;
             let mut st = self.state.lock().unwrap();
             let state = match *st {
                 State::Started {
@@ -369,17 +524,12 @@ impl PravegaTC {
                     return Ok(())
                 }
             };
-            if state.final_resume_at_pts.is_some() {
+            // With exactly-once, the final checkpoint is written upon end-of-stream because pravegasink
+            // will have stopped by now. If end-of-stream was not received, the last checkpoint is kept.
+            if state.final_resume_at_pts.is_some() && !exactly_once {
                 info!(CAT, imp: self, "stop: writing final persistent state to resume at {:?}", state.final_resume_at_pts);
-                let runtime = state.client_factory.runtime();
-                let table = state.table.lock().unwrap();
-                let persistent_state = PersistentState {
-                    resume_at_pts: state.final_resume_at_pts.nanoseconds().unwrap(),
-                    resume_count: Some(0),
-                };
-                runtime.block_on(table.insert(&PERSISTENT_STATE_TABLE_KEY.to_string(), &persistent_state, -1)).map_err(|error| {
-                    gst::error_msg!(gst::ResourceError::Write, ["Failed to write to Pravega table: {}", error])
-                })?;
+                let final_resume_at_pts = state.final_resume_at_pts;
+                self.write_persistent_state(state, final_resume_at_pts, exactly_once)?;
             }
             *st = State::Stopped;
             Ok(())
@@ -389,6 +539,17 @@ impl PravegaTC {
     }
 }
 
+/// Find all pravegasrc elements in a bin, including those in nested bins.
+fn find_pravega_sources(bin: &gst::Bin, sources: &mut Vec<gst::Element>) {
+    for child in bin.children() {
+        if child.type_().name() == "PravegaSrc" {
+            sources.push(child);
+        } else if let Some(child_bin) = child.downcast_ref::<gst::Bin>() {
+            find_pravega_sources(child_bin, sources);
+        }
+    }
+}
+
 #[glib::object_subclass]
 impl ObjectSubclass for PravegaTC {
     const NAME: &'static str = ELEMENT_CLASS_NAME;
@@ -463,7 +624,8 @@ impl ObjectImpl for PravegaTC {
         static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| { vec![
             glib::ParamSpecString::builder(PROPERTY_NAME_TABLE)
                 .nick("Table")
-                .blurb("The scope and table name that will be used for storing the persistent state. The format must be 'scope/table'.")
+                .blurb("The location that will be used for storing the persistent state. \
+                    Use 'scope/table' for a Pravega key-value table or 'file:///path/to/dir' for a local directory.")
                 .mutable_ready()
                 .build(),
             glib::ParamSpecString::builder(PROPERTY_NAME_CONTROLLER)
@@ -474,6 +636,67 @@ impl ObjectImpl for PravegaTC {
                     utils::ENV_PRAVEGA_CONTROLLER_URI, utils::DEFAULT_PRAVEGA_CONTROLLER_URI).as_str())
                 .mutable_ready()
                 .build(),
+            glib::ParamSpecBoolean::builder(PROPERTY_NAME_EXACTLY_ONCE)
+                .nick("Exactly once")
+                .blurb("If true, checkpoints include the committed offset of the pravegasink element linked to this element. \
+                    When resuming, buffers will not be written more than once by pravegasink. \
+                    Checkpoints are only recorded at key frames.")
+                .default_value(false)
+                .mutable_ready()
+                .build(),
+            glib::ParamSpecString::builder(PROPERTY_NAME_SOURCES)
+                .nick("Sources")
+                .blurb("Comma-separated names of the pravegasrc elements controlled by this element. \
+                    If empty, all pravegasrc elements in the pipeline, including those in nested bins, are controlled.")
+                .mutable_ready()
+                .build(),
+            glib::ParamSpecDouble::builder(PROPERTY_NAME_RECORD_PERIOD_SEC)
+                .nick("Record period")
+                .blurb("The number of seconds between writes of the persistent state.")
+                .minimum(0.0)
+                .maximum(MAX_DURATION_SEC)
+                .default_value(DEFAULT_RECORD_PERIOD_SEC)
+                .mutable_ready()
+                .build(),
+            glib::ParamSpecUInt64::builder(PROPERTY_NAME_MAX_EXACT_RESUME_COUNT)
+                .nick("Maximum exact resume count")
+                .blurb("The number of recovery attempts from the same PTS that will resume exactly at the PTS. \
+                    Subsequent attempts will skip ahead or fail.")
+                .minimum(0)
+                .maximum(std::u64::MAX)
+                .default_value(DEFAULT_MAX_EXACT_RESUME_COUNT)
+                .mutable_ready()
+                .build(),
+            glib::ParamSpecDouble::builder(PROPERTY_NAME_INITIAL_SKIP_SEC)
+                .nick("Initial skip")
+                .blurb("The number of seconds to skip on the first recovery attempt after the exact attempts.")
+                .minimum(0.0)
+                .maximum(MAX_DURATION_SEC)
+                .default_value(DEFAULT_INITIAL_SKIP_SEC)
+                .mutable_ready()
+                .build(),
+            glib::ParamSpecDouble::builder(PROPERTY_NAME_SKIP_BACKOFF_FACTOR)
+                .nick("Skip backoff factor")
+                .blurb("The number of seconds to skip is multiplied by this factor on each subsequent recovery attempt.")
+                .minimum(1.0)
+                .maximum(std::f64::INFINITY)
+                .default_value(DEFAULT_SKIP_BACKOFF_FACTOR)
+                .mutable_ready()
+                .build(),
+            glib::ParamSpecDouble::builder(PROPERTY_NAME_MAX_SKIP_SEC)
+                .nick("Maximum skip")
+                .blurb("The maximum number of seconds to skip.")
+                .minimum(0.0)
+                .maximum(MAX_DURATION_SEC)
+                .default_value(DEFAULT_MAX_SKIP_SEC)
+                .mutable_ready()
+                .build(),
+            glib::ParamSpecBoolean::builder(PROPERTY_NAME_FAIL_INSTEAD_OF_SKIP)
+                .nick("Fail instead of skip")
+                .blurb("If true, recovery will fail after the exact recovery attempts instead of skipping ahead.")
+                .default_value(false)
+                .mutable_ready()
+                .build(),
             glib::ParamSpecString::builder(PROPERTY_NAME_KEYCLOAK_FILE)
                 .nick("Keycloak file")
                 .blurb(format!("The filename containing the Keycloak credentials JSON. \
@@ -536,6 +759,113 @@ impl ObjectImpl for PravegaTC {
                     error!(CAT, imp: self, "Failed to set property `{}`: {}", PROPERTY_NAME_KEYCLOAK_FILE, err);
                 }
             },
+            PROPERTY_NAME_EXACTLY_ONCE => {
+                let res: Result<(), glib::Error> = match value.get::<bool>() {
+                    Ok(exactly_once) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.exactly_once = exactly_once;
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, imp: self, "Failed to set property `{}`: {}", PROPERTY_NAME_EXACTLY_ONCE, err);
+                }
+            },
+            PROPERTY_NAME_SOURCES => {
+                let res: Result<(), glib::Error> = match value.get::<String>() {
+                    Ok(sources) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.sources = sources.split(',')
+                            .map(|name| name.trim().to_owned())
+                            .filter(|name| !name.is_empty())
+                            .collect();
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, imp: self, "Failed to set property `{}`: {}", PROPERTY_NAME_SOURCES, err);
+                }
+            },
+            PROPERTY_NAME_RECORD_PERIOD_SEC => {
+                let res: Result<(), glib::Error> = match value.get::<f64>() {
+                    Ok(value) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.record_period = ClockTime::from_nseconds((value * 1e9) as u64);
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, imp: self, "Failed to set property `{}`: {}", PROPERTY_NAME_RECORD_PERIOD_SEC, err);
+                }
+            },
+            PROPERTY_NAME_MAX_EXACT_RESUME_COUNT => {
+                let res: Result<(), glib::Error> = match value.get::<u64>() {
+                    Ok(value) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.recovery_policy.max_exact_resume_count = value;
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, imp: self, "Failed to set property `{}`: {}", PROPERTY_NAME_MAX_EXACT_RESUME_COUNT, err);
+                }
+            },
+            PROPERTY_NAME_INITIAL_SKIP_SEC => {
+                let res: Result<(), glib::Error> = match value.get::<f64>() {
+                    Ok(value) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.recovery_policy.initial_skip = ClockTime::from_nseconds((value * 1e9) as u64);
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, imp: self, "Failed to set property `{}`: {}", PROPERTY_NAME_INITIAL_SKIP_SEC, err);
+                }
+            },
+            PROPERTY_NAME_SKIP_BACKOFF_FACTOR => {
+                let res: Result<(), glib::Error> = match value.get::<f64>() {
+                    Ok(value) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.recovery_policy.skip_backoff_factor = value;
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, imp: self, "Failed to set property `{}`: {}", PROPERTY_NAME_SKIP_BACKOFF_FACTOR, err);
+                }
+            },
+            PROPERTY_NAME_MAX_SKIP_SEC => {
+                let res: Result<(), glib::Error> = match value.get::<f64>() {
+                    Ok(value) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.recovery_policy.max_skip = ClockTime::from_nseconds((value * 1e9) as u64);
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, imp: self, "Failed to set property `{}`: {}", PROPERTY_NAME_MAX_SKIP_SEC, err);
+                }
+            },
+            PROPERTY_NAME_FAIL_INSTEAD_OF_SKIP => {
+                let res: Result<(), glib::Error> = match value.get::<bool>() {
+                    Ok(value) => {
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.recovery_policy.fail_instead_of_skip = value;
+                        Ok(())
+                    },
+                    Err(_) => unreachable!("type checked upstream"),
+                };
+                if let Err(err) = res {
+                    error!(CAT, imp: self, "Failed to set property `{}`: {}", PROPERTY_NAME_FAIL_INSTEAD_OF_SKIP, err);
+                }
+            },
         _ => unimplemented!(),
         };
     }
@@ -585,10 +915,16 @@ impl ElementImpl for PravegaTC {
         trace!(CAT, imp: self, "change_state: Changing state {:?}", transition);
         match transition {
             gst::StateChange::ReadyToPaused => {
-                self.start().unwrap();
+                self.start().map_err(|error| {
+                    self.post_error_message(error);
+                    gst::StateChangeError
+                })?;
             },
             gst::StateChange::PausedToReady => {
-                self.stop().unwrap();
+                self.stop().map_err(|error| {
+                    self.post_error_message(error);
+                    gst::StateChangeError
+                })?;
             },
             _ => {}
         }
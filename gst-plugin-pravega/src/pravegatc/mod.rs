@@ -11,7 +11,9 @@
 use gst::glib;
 use gst::prelude::*;
 
+pub mod checkpoint_store;
 mod imp;
+pub mod recovery;
 
 glib::wrapper! {
     pub struct PravegaTC(ObjectSubclass<imp::PravegaTC>) @extends gst::Element, gst::Object;
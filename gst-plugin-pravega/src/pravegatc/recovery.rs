@@ -0,0 +1,335 @@
+//
+// Copyright (c) Dell Inc., or its subsidiaries. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+
+//! Determines where pravegatc resumes each pravegasrc stream.
+
+use gst::ClockTime;
+use pravega_video::timestamp::{PravegaTimestamp, NSECOND};
+use std::cmp;
+use std::io::Result;
+use super::checkpoint_store::{CheckpointStore, PersistentState, SkippedRange};
+
+// The persistent state of each pravegasrc stream is stored in the key "pravegatc.PersistentState.{stream}".
+// Older versions stored the persistent state of all streams in the key without a suffix.
+// This is only read when a stream does not have its own key.
+pub const PERSISTENT_STATE_KEY: &str = "pravegatc.PersistentState";
+
+// The list of skipped ranges of each pravegasrc stream is stored in the key "pravegatc.SkippedRanges.{stream}".
+pub const SKIPPED_RANGES_KEY: &str = "pravegatc.SkippedRanges";
+
+pub const DEFAULT_MAX_EXACT_RESUME_COUNT: u64 = 1;
+pub const DEFAULT_INITIAL_SKIP_SEC: f64 = 2.0;
+pub const DEFAULT_SKIP_BACKOFF_FACTOR: f64 = 2.0;
+pub const DEFAULT_MAX_SKIP_SEC: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+
+/// Returns the key of the persistent state of a pravegasrc stream.
+pub fn persistent_state_key(stream: &str) -> String {
+    format!("{}.{}", PERSISTENT_STATE_KEY, stream)
+}
+
+/// Returns the key of the list of skipped ranges of a pravegasrc stream.
+pub fn skipped_ranges_key(stream: &str) -> String {
+    format!("{}.{}", SKIPPED_RANGES_KEY, stream)
+}
+
+/// Determines where to resume when recovery has been attempted from the same PTS multiple times.
+#[derive(Debug, Clone)]
+pub struct RecoveryPolicy {
+    // The number of recovery attempts that will resume exactly at the recorded PTS.
+    pub max_exact_resume_count: u64,
+    // The amount to skip on the first recovery attempt after the exact attempts.
+    pub initial_skip: ClockTime,
+    // The amount to skip is multiplied by this factor on each subsequent attempt.
+    pub skip_backoff_factor: f64,
+    pub max_skip: ClockTime,
+    // If true, recovery will fail instead of skipping.
+    pub fail_instead_of_skip: bool,
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        RecoveryPolicy {
+            max_exact_resume_count: DEFAULT_MAX_EXACT_RESUME_COUNT,
+            initial_skip: ClockTime::from_nseconds((DEFAULT_INITIAL_SKIP_SEC * 1e9) as u64),
+            skip_backoff_factor: DEFAULT_SKIP_BACKOFF_FACTOR,
+            max_skip: ClockTime::from_nseconds((DEFAULT_MAX_SKIP_SEC * 1e9) as u64),
+            fail_instead_of_skip: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ResumeAction {
+    // Resume exactly at the recorded PTS.
+    Exact,
+    // Resume at the recorded PTS plus this amount.
+    Skip(ClockTime),
+    // Do not resume.
+    Fail,
+}
+
+impl RecoveryPolicy {
+    /// Returns how to resume, given the number of recovery attempts from the same PTS, including this one.
+    fn resume_action(&self, resume_count: u64) -> ResumeAction {
+        if resume_count <= self.max_exact_resume_count {
+            ResumeAction::Exact
+        } else if self.fail_instead_of_skip {
+            ResumeAction::Fail
+        } else {
+            let exponent = cmp::min(resume_count - self.max_exact_resume_count - 1, i32::MAX as u64) as i32;
+            let skip = self.initial_skip.nseconds() as f64 * self.skip_backoff_factor.powi(exponent);
+            let skip = skip.min(self.max_skip.nseconds() as f64) as u64;
+            ResumeAction::Skip(ClockTime::from_nseconds(skip))
+        }
+    }
+}
+
+/// How a pravegasrc stream will be resumed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resume {
+    /// There is no persistent state for the stream.
+    NoState,
+    /// Resume the stream at a PTS.
+    Resume {
+        resume_at_pts: PravegaTimestamp,
+        // True if resume_at_pts is the recorded PTS. False if data was skipped.
+        exact: bool,
+        // The persistent state, with the incremented resume count.
+        persistent_state: PersistentState,
+    },
+    /// Recovery from the recorded PTS failed too many times and the policy does not allow skipping.
+    Fail {
+        resume_at_pts: PravegaTimestamp,
+        resume_count: u64,
+    },
+}
+
+/// Read the persistent state of a stream, or the legacy persistent state if the stream does not have its own.
+pub fn read_persistent_state(store: &mut dyn CheckpointStore, stream: &str) -> Result<Option<PersistentState>> {
+    match store.get_persistent_state(&persistent_state_key(stream))? {
+        Some(persistent_state) => Ok(Some(persistent_state)),
+        None => store.get_persistent_state(PERSISTENT_STATE_KEY),
+    }
+}
+
+/// Read the persistent state of a stream and determine where to resume it.
+/// The resume count in the persistent state is incremented, and any skipped range is recorded.
+/// The persistent state is written conditionally on the version that was read, so that concurrent
+/// instances cannot both resume from the same state.
+pub fn resume_stream(store: &mut dyn CheckpointStore, stream: &str, policy: &RecoveryPolicy,
+        now: PravegaTimestamp) -> Result<Resume> {
+    let key = persistent_state_key(stream);
+    let mut persistent_state = match read_persistent_state(store, stream)? {
+        Some(persistent_state) => persistent_state,
+        None => return Ok(Resume::NoState),
+    };
+
+    // Increment resume_count every time we attempt to resume.
+    let resume_count = persistent_state.resume_count.unwrap_or_default() + 1;
+    persistent_state.resume_count = Some(resume_count);
+    store.put_persistent_state(&key, &persistent_state)?;
+
+    // If resume count indicates multiple failures at the same point, then skip ahead.
+    let original_resume_at_pts = PravegaTimestamp::from_nanoseconds(Some(persistent_state.resume_at_pts));
+    match policy.resume_action(resume_count) {
+        ResumeAction::Exact => Ok(Resume::Resume {
+            resume_at_pts: original_resume_at_pts,
+            exact: true,
+            persistent_state,
+        }),
+        ResumeAction::Skip(skip) => {
+            let resume_at_pts = original_resume_at_pts + skip.nseconds() * NSECOND;
+            if resume_at_pts > original_resume_at_pts {
+                let skipped_ranges_key = skipped_ranges_key(stream);
+                let mut skipped_ranges = store.get_skipped_ranges(&skipped_ranges_key)?;
+                skipped_ranges.push(SkippedRange {
+                    begin_pts: persistent_state.resume_at_pts,
+                    end_pts: resume_at_pts.nanoseconds().unwrap(),
+                    resume_count,
+                    skipped_at: now.nanoseconds().unwrap_or_default(),
+                });
+                store.put_skipped_ranges(&skipped_ranges_key, &skipped_ranges)?;
+            }
+            Ok(Resume::Resume {
+                resume_at_pts,
+                exact: false,
+                persistent_state,
+            })
+        },
+        ResumeAction::Fail => Ok(Resume::Fail {
+            resume_at_pts: original_resume_at_pts,
+            resume_count,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pravega_video::timestamp::SECOND;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct MemoryCheckpointStore {
+        persistent_states: HashMap<String, PersistentState>,
+        skipped_ranges: HashMap<String, Vec<SkippedRange>>,
+    }
+
+    impl CheckpointStore for MemoryCheckpointStore {
+        fn get_persistent_state(&mut self, key: &str) -> Result<Option<PersistentState>> {
+            Ok(self.persistent_states.get(key).cloned())
+        }
+
+        fn put_persistent_state(&mut self, key: &str, persistent_state: &PersistentState) -> Result<()> {
+            self.persistent_states.insert(key.to_owned(), persistent_state.clone());
+            Ok(())
+        }
+
+        fn put_persistent_states(&mut self, keys: &[String], persistent_state: &PersistentState) -> Result<()> {
+            for key in keys.iter() {
+                self.persistent_states.insert(key.clone(), persistent_state.clone());
+            }
+            Ok(())
+        }
+
+        fn get_skipped_ranges(&mut self, key: &str) -> Result<Vec<SkippedRange>> {
+            Ok(self.skipped_ranges.get(key).cloned().unwrap_or_default())
+        }
+
+        fn put_skipped_ranges(&mut self, key: &str, skipped_ranges: &[SkippedRange]) -> Result<()> {
+            self.skipped_ranges.insert(key.to_owned(), skipped_ranges.to_vec());
+            Ok(())
+        }
+    }
+
+    fn persistent_state(resume_at_pts: u64) -> PersistentState {
+        PersistentState {
+            resume_at_pts,
+            resume_count: Some(0),
+            sink_stream: None,
+            sink_offset: None,
+        }
+    }
+
+    #[test]
+    fn test_resume_action() {
+        let policy = RecoveryPolicy::default();
+        assert_eq!(policy.resume_action(1), ResumeAction::Exact);
+        assert_eq!(policy.resume_action(2), ResumeAction::Skip(ClockTime::from_seconds(2)));
+        assert_eq!(policy.resume_action(3), ResumeAction::Skip(ClockTime::from_seconds(4)));
+        assert_eq!(policy.resume_action(4), ResumeAction::Skip(ClockTime::from_seconds(8)));
+        assert_eq!(policy.resume_action(100), ResumeAction::Skip(policy.max_skip));
+        assert_eq!(policy.resume_action(u64::MAX), ResumeAction::Skip(policy.max_skip));
+
+        let policy = RecoveryPolicy {
+            max_exact_resume_count: 0,
+            initial_skip: ClockTime::from_seconds(1),
+            skip_backoff_factor: 3.0,
+            max_skip: ClockTime::from_seconds(20),
+            fail_instead_of_skip: false,
+        };
+        assert_eq!(policy.resume_action(1), ResumeAction::Skip(ClockTime::from_seconds(1)));
+        assert_eq!(policy.resume_action(2), ResumeAction::Skip(ClockTime::from_seconds(3)));
+        assert_eq!(policy.resume_action(3), ResumeAction::Skip(ClockTime::from_seconds(9)));
+        assert_eq!(policy.resume_action(4), ResumeAction::Skip(ClockTime::from_seconds(20)));
+
+        let policy = RecoveryPolicy {
+            max_exact_resume_count: 2,
+            fail_instead_of_skip: true,
+            ..Default::default()
+        };
+        assert_eq!(policy.resume_action(2), ResumeAction::Exact);
+        assert_eq!(policy.resume_action(3), ResumeAction::Fail);
+    }
+
+    #[test]
+    fn test_resume_stream() {
+        let stream = "scope1/stream1";
+        let key = persistent_state_key(stream);
+        let policy = RecoveryPolicy::default();
+        let now = PravegaTimestamp::from_nanoseconds(Some(1_000 * SECOND.nanoseconds().unwrap() as u64));
+        let resume_at_pts = PravegaTimestamp::from_nanoseconds(Some(100 * SECOND.nanoseconds().unwrap() as u64));
+        let mut store = MemoryCheckpointStore::default();
+
+        assert_eq!(resume_stream(&mut store, stream, &policy, now).unwrap(), Resume::NoState);
+
+        // The first attempt resumes exactly at the recorded PTS.
+        store.put_persistent_state(&key, &persistent_state(resume_at_pts.nanoseconds().unwrap())).unwrap();
+        match resume_stream(&mut store, stream, &policy, now).unwrap() {
+            Resume::Resume { resume_at_pts: pts, exact, persistent_state } => {
+                assert_eq!(pts, resume_at_pts);
+                assert!(exact);
+                assert_eq!(persistent_state.resume_count, Some(1));
+            },
+            resume => panic!("Unexpected {:?}", resume),
+        }
+        assert_eq!(store.get_persistent_state(&key).unwrap().unwrap().resume_count, Some(1));
+        assert!(store.get_skipped_ranges(&skipped_ranges_key(stream)).unwrap().is_empty());
+
+        // Subsequent attempts skip ahead and record the skipped ranges.
+        for (resume_count, skip) in [(2, 2 * SECOND), (3, 4 * SECOND)].iter() {
+            match resume_stream(&mut store, stream, &policy, now).unwrap() {
+                Resume::Resume { resume_at_pts: pts, exact, persistent_state } => {
+                    assert_eq!(pts, resume_at_pts + *skip);
+                    assert!(!exact);
+                    assert_eq!(persistent_state.resume_count, Some(*resume_count));
+                },
+                resume => panic!("Unexpected {:?}", resume),
+            }
+        }
+        assert_eq!(store.get_skipped_ranges(&skipped_ranges_key(stream)).unwrap(), vec![
+            SkippedRange {
+                begin_pts: resume_at_pts.nanoseconds().unwrap(),
+                end_pts: (resume_at_pts + 2 * SECOND).nanoseconds().unwrap(),
+                resume_count: 2,
+                skipped_at: now.nanoseconds().unwrap(),
+            },
+            SkippedRange {
+                begin_pts: resume_at_pts.nanoseconds().unwrap(),
+                end_pts: (resume_at_pts + 4 * SECOND).nanoseconds().unwrap(),
+                resume_count: 3,
+                skipped_at: now.nanoseconds().unwrap(),
+            },
+        ]);
+
+        // A policy that does not allow skipping will fail.
+        let policy = RecoveryPolicy {
+            fail_instead_of_skip: true,
+            ..Default::default()
+        };
+        assert_eq!(resume_stream(&mut store, stream, &policy, now).unwrap(), Resume::Fail {
+            resume_at_pts,
+            resume_count: 4,
+        });
+        assert_eq!(store.get_skipped_ranges(&skipped_ranges_key(stream)).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_resume_stream_legacy_state() {
+        let stream = "scope1/stream1";
+        let policy = RecoveryPolicy::default();
+        let now = PravegaTimestamp::now();
+        let mut store = MemoryCheckpointStore::default();
+        store.put_persistent_state(PERSISTENT_STATE_KEY, &persistent_state(5000)).unwrap();
+        match resume_stream(&mut store, stream, &policy, now).unwrap() {
+            Resume::Resume { resume_at_pts, exact, .. } => {
+                assert_eq!(resume_at_pts, PravegaTimestamp::from_nanoseconds(Some(5000)));
+                assert!(exact);
+            },
+            resume => panic!("Unexpected {:?}", resume),
+        }
+        // The stream now has its own persistent state.
+        let persistent_state = store.get_persistent_state(&persistent_state_key(stream)).unwrap().unwrap();
+        assert_eq!(persistent_state.resume_at_pts, 5000);
+        assert_eq!(persistent_state.resume_count, Some(1));
+        // The legacy persistent state is unchanged.
+        assert_eq!(store.get_persistent_state(PERSISTENT_STATE_KEY).unwrap().unwrap().resume_count, Some(0));
+    }
+}
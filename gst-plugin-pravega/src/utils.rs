@@ -25,3 +25,19 @@ pub fn pravega_to_clocktime(t: PravegaTimestamp) -> Option<ClockTime> {
 pub fn now_ntp_clocktime() -> ClockTime {
     ClockTime::from_nseconds(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64 + PravegaTimestamp::UNIX_TO_NTP_SECONDS * 1_000_000_000)
 }
+
+/// The name of the custom query that pravegatc sends downstream to pravegasink when recording an exactly-once checkpoint.
+/// pravegasink flushes its writers and sets the fields CHECKPOINT_FIELD_STREAM and CHECKPOINT_FIELD_OFFSET.
+pub const CHECKPOINT_QUERY_NAME: &str = "pravega-checkpoint";
+/// The name of the custom downstream event that pravegatc sends to pravegasink when resuming from an exactly-once checkpoint.
+/// It has the fields CHECKPOINT_FIELD_STREAM and CHECKPOINT_FIELD_OFFSET from the checkpoint.
+pub const RESUME_EVENT_NAME: &str = "pravega-resume";
+/// The data stream written by pravegasink, formatted as scope/stream.
+pub const CHECKPOINT_FIELD_STREAM: &str = "stream";
+/// The committed offset of the data stream (u64).
+pub const CHECKPOINT_FIELD_OFFSET: &str = "offset";
+
+/// The name of the element message that pravegasink posts when retention is held back by a consumer checkpoint.
+/// It has the fields "stream" (string), "requested-offset" (u64), "offset" (u64, if any data can be truncated),
+/// "consumer" (string), and "consumer-timestamp" (u64, if the consumer has a checkpoint).
+pub const RETENTION_HELD_MESSAGE_NAME: &str = "pravega-retention-held";
@@ -213,4 +213,189 @@ mod test {
         assert_between_timestamp("first_pts_run2", first_pts_run2, last_pts_run1 - 2000 * MSECOND, last_pts_run1 + 2000 * MSECOND);
         assert_timestamp_eq("last_pts_run2", last_pts_run2, last_pts_without_pravegatc);
     }
+
+    /// This tests recovery when pravegasrc is in a nested bin and pravegatc explicitly identifies the source.
+    /// Another pravegasrc that is not controlled by pravegatc reads a different stream.
+    #[rstest]
+    #[case(
+        VideoEncoder::H264(H264EncoderConfigBuilder::default().key_int_max_frames(30).build().unwrap()),
+        ContainerFormat::Mp4(Mp4MuxConfigBuilder::default().fragment_duration(1 * MSECOND).build().unwrap()),
+    )]
+    fn test_transaction_coordinator_nested_source(#[case] video_encoder: VideoEncoder, #[case] container_format: ContainerFormat) {
+        let test_config = &get_test_config();
+        info!("test_config={:?}", test_config);
+        let stream_name = &format!("test-pravegatc-{}-{}", test_config.test_id, Uuid::new_v4())[..];
+        let other_stream_name = &format!("test-pravegatc-other-{}-{}", test_config.test_id, Uuid::new_v4())[..];
+        let table_name = &format!("test-pravegatc-table-{}-{}", test_config.test_id, Uuid::new_v4())[..];
+        let summary_written = failure_recovery_test_data_gen(test_config, stream_name, video_encoder.clone(), container_format.clone(), 10).unwrap();
+        debug!("summary_written={}", summary_written);
+        failure_recovery_test_data_gen(test_config, other_stream_name, video_encoder, container_format, 2).unwrap();
+        let first_pts_written = summary_written.first_valid_pts();
+        let last_pts_written = summary_written.last_pts();
+
+        let pipeline_description = format!("\
+            ( name=sourcebin \
+              pravegasrc name=src {pravega_plugin_properties} \
+                start-mode=earliest \
+              ! identity name=before_decode silent=false ) \
+            before_decode. \
+            ! decodebin \
+            ! pravegatc name=pravegatc controller={controller_uri} table={scope}/{table_name} sources=src \
+            ! appsink name=sink \
+              sync=false \
+            pravegasrc name=other {other_pravega_plugin_properties} \
+              start-mode=earliest \
+            ! fakesink sync=false",
+            pravega_plugin_properties = test_config.pravega_plugin_properties(stream_name),
+            other_pravega_plugin_properties = test_config.pravega_plugin_properties(other_stream_name),
+            controller_uri = test_config.client_config.clone().controller_uri.0,
+            scope = test_config.scope,
+            table_name = table_name,
+        );
+
+        info!("#### Decode video stream - run 1, with injected fault");
+        let fault_injection_pts: PravegaTimestamp = first_pts_written + 5510 * MSECOND;
+        env::set_var("FAULT_INJECTION_PTS_pravegatc", format!("{}", fault_injection_pts.nanoseconds().unwrap()));
+        let summary_run1 = match launch_pipeline_and_get_summary(&pipeline_description) {
+            Ok(_) => panic!("Error expected"),
+            Err(LaunchPipelineError { error, buffer_list_summary}) => {
+                debug!("Expected error: {}", error);
+                buffer_list_summary
+            },
+        };
+        debug!("summary_run1={}", summary_run1);
+        let last_pts_run1 = summary_run1.last_pts();
+        assert_between_timestamp("last_pts_run1", last_pts_run1, fault_injection_pts - 34 * MSECOND, fault_injection_pts - 1 * NSECOND);
+
+        info!("#### Decode video stream - run 2, resume from fault");
+        env::remove_var("FAULT_INJECTION_PTS_pravegatc");
+        let summary_run2 = launch_pipeline_and_get_summary(&pipeline_description).unwrap();
+        debug!("summary_run2={}", summary_run2);
+        let first_pts_run2 = summary_run2.first_pts();
+        assert_between_timestamp("first_pts_run2", first_pts_run2, last_pts_run1 - 2000 * MSECOND, last_pts_run1 + 2000 * MSECOND);
+        assert_between_timestamp("last_pts_run2", summary_run2.last_pts(), last_pts_written - 1000 * MSECOND, last_pts_written + 1000 * MSECOND);
+    }
+
+    /// This tests exactly-once processing from one Pravega stream to another.
+    /// The pipeline is restarted after an injected fault.
+    /// The output stream should have exactly the same buffers as the input stream.
+    #[rstest]
+    #[case(
+        VideoEncoder::H264(H264EncoderConfigBuilder::default().key_int_max_frames(30).build().unwrap()),
+        ContainerFormat::Mp4(Mp4MuxConfigBuilder::default().fragment_duration(1 * MSECOND).build().unwrap()),
+    )]
+    fn test_transaction_coordinator_exactly_once(#[case] video_encoder: VideoEncoder, #[case] container_format: ContainerFormat) {
+        let test_config = &get_test_config();
+        info!("test_config={:?}", test_config);
+        let input_stream_name = &format!("test-pravegatc-in-{}-{}", test_config.test_id, Uuid::new_v4())[..];
+        let output_stream_name = &format!("test-pravegatc-out-{}-{}", test_config.test_id, Uuid::new_v4())[..];
+        let table_name = &format!("test-pravegatc-table-{}-{}", test_config.test_id, Uuid::new_v4())[..];
+        let summary_written = failure_recovery_test_data_gen(test_config, input_stream_name, video_encoder, container_format, 10).unwrap();
+        debug!("summary_written={}", summary_written);
+        let first_pts_written = summary_written.first_valid_pts();
+
+        let pipeline_description = format!("\
+            pravegasrc name=src {input_pravega_plugin_properties} \
+              start-mode=earliest \
+            ! pravegatc name=pravegatc controller={controller_uri} table={scope}/{table_name} exactly-once=true \
+            ! pravegasink {output_pravega_plugin_properties} \
+              timestamp-mode=tai sync=false",
+            input_pravega_plugin_properties = test_config.pravega_plugin_properties(input_stream_name),
+            output_pravega_plugin_properties = test_config.pravega_plugin_properties(output_stream_name),
+            controller_uri = test_config.client_config.clone().controller_uri.0,
+            scope = test_config.scope,
+            table_name = table_name,
+        );
+
+        info!("#### Copy stream - run 1, with injected fault");
+        let fault_injection_pts: PravegaTimestamp = first_pts_written + 5510 * MSECOND;
+        env::set_var("FAULT_INJECTION_PTS_pravegatc", format!("{}", fault_injection_pts.nanoseconds().unwrap()));
+        let result_run1 = launch_pipeline(&pipeline_description);
+        debug!("result_run1={:?}", result_run1);
+        assert!(result_run1.is_err(), "Error expected");
+
+        info!("#### Copy stream - run 2, resume from fault");
+        env::remove_var("FAULT_INJECTION_PTS_pravegatc");
+        launch_pipeline(&pipeline_description).unwrap();
+
+        info!("#### Read input and output streams");
+        let read_pipeline_description = |stream_name: &str| format!("\
+            pravegasrc {pravega_plugin_properties} \
+              start-mode=earliest \
+            ! appsink name=sink sync=false",
+            pravega_plugin_properties = test_config.pravega_plugin_properties(stream_name),
+        );
+        let summary_input = launch_pipeline_and_get_summary(&read_pipeline_description(input_stream_name)).unwrap();
+        let summary_output = launch_pipeline_and_get_summary(&read_pipeline_description(output_stream_name)).unwrap();
+        debug!("summary_input= {}", summary_input);
+        debug!("summary_output={}", summary_output);
+        assert_eq!(summary_output.num_buffers(), summary_input.num_buffers());
+        assert_eq!(summary_output.pts(), summary_input.pts());
+    }
+
+    /// The pipeline is restarted after an injected fault, but with a different input stream.
+    /// The buffers replayed after the checkpoint do not match the buffers already written.
+    /// Pravega streams cannot be truncated at the tail, so pravegasink must keep the buffers already written
+    /// and discard the replayed buffers until they are after them.
+    #[rstest]
+    #[case(
+        VideoEncoder::H264(H264EncoderConfigBuilder::default().key_int_max_frames(30).build().unwrap()),
+        VideoEncoder::H264(H264EncoderConfigBuilder::default().key_int_max_frames(45).tune("0".to_owned()).build().unwrap()),
+        ContainerFormat::Mp4(Mp4MuxConfigBuilder::default().fragment_duration(1 * MSECOND).build().unwrap()),
+    )]
+    fn test_transaction_coordinator_exactly_once_diverging_replay(#[case] video_encoder_1: VideoEncoder,
+            #[case] video_encoder_2: VideoEncoder, #[case] container_format: ContainerFormat) {
+        let test_config = &get_test_config();
+        info!("test_config={:?}", test_config);
+        let input_stream_name_1 = &format!("test-pravegatc-in1-{}-{}", test_config.test_id, Uuid::new_v4())[..];
+        let input_stream_name_2 = &format!("test-pravegatc-in2-{}-{}", test_config.test_id, Uuid::new_v4())[..];
+        let output_stream_name = &format!("test-pravegatc-out-{}-{}", test_config.test_id, Uuid::new_v4())[..];
+        let table_name = &format!("test-pravegatc-table-{}-{}", test_config.test_id, Uuid::new_v4())[..];
+        let summary_written = failure_recovery_test_data_gen(test_config, input_stream_name_1, video_encoder_1,
+            container_format.clone(), 10).unwrap();
+        let summary_written_2 = failure_recovery_test_data_gen(test_config, input_stream_name_2, video_encoder_2,
+            container_format, 10).unwrap();
+        let first_pts_written = summary_written.first_valid_pts();
+
+        let pipeline_description = |input_stream_name: &str| format!("\
+            pravegasrc name=src {input_pravega_plugin_properties} \
+              start-mode=earliest \
+            ! pravegatc name=pravegatc controller={controller_uri} table={scope}/{table_name} exactly-once=true \
+            ! pravegasink {output_pravega_plugin_properties} \
+              timestamp-mode=tai sync=false",
+            input_pravega_plugin_properties = test_config.pravega_plugin_properties(input_stream_name),
+            output_pravega_plugin_properties = test_config.pravega_plugin_properties(output_stream_name),
+            controller_uri = test_config.client_config.clone().controller_uri.0,
+            scope = test_config.scope,
+            table_name = table_name,
+        );
+        let read_pipeline_description = format!("\
+            pravegasrc {pravega_plugin_properties} \
+              start-mode=earliest end-mode=latest \
+            ! appsink name=sink sync=false",
+            pravega_plugin_properties = test_config.pravega_plugin_properties(output_stream_name),
+        );
+
+        info!("#### Copy stream 1 - run 1, with injected fault");
+        let fault_injection_pts: PravegaTimestamp = first_pts_written + 5510 * MSECOND;
+        env::set_var("FAULT_INJECTION_PTS_pravegatc", format!("{}", fault_injection_pts.nanoseconds().unwrap()));
+        let result_run1 = launch_pipeline(&pipeline_description(input_stream_name_1));
+        debug!("result_run1={:?}", result_run1);
+        assert!(result_run1.is_err(), "Error expected");
+        env::remove_var("FAULT_INJECTION_PTS_pravegatc");
+        let summary_run1 = launch_pipeline_and_get_summary(&read_pipeline_description).unwrap();
+        debug!("summary_run1={}", summary_run1);
+
+        info!("#### Copy stream 2 - run 2, resume from fault with diverging buffers");
+        launch_pipeline(&pipeline_description(input_stream_name_2)).unwrap();
+        let summary_run2 = launch_pipeline_and_get_summary(&read_pipeline_description).unwrap();
+        debug!("summary_run2={}", summary_run2);
+        let pts_run1 = summary_run1.pts();
+        let pts_run2 = summary_run2.pts();
+        assert!(pts_run2.len() > pts_run1.len());
+        assert_eq!(pts_run2[..pts_run1.len()], pts_run1[..]);
+        let max_pts_run1 = summary_run1.valid_pts().into_iter().max().unwrap();
+        assert!(pts_run2[pts_run1.len()..].iter().all(|&pts| pts.is_none() || pts > max_pts_run1));
+        assert_eq!(summary_run2.last_valid_pts(), summary_written_2.last_valid_pts());
+    }
 }
@@ -11,9 +11,12 @@
 #[cfg(test)]
 mod test {
     use anyhow::Error;
+    use pravega_video::event_serde::{EventWithHeader, EventWriter};
+    use pravega_video::storage::{FileStorage, Storage};
     use pravega_video::timestamp::{PravegaTimestamp, MSECOND, NSECOND};
     use rstest::rstest;
     use std::convert::TryFrom;
+    use std::io::Write;
     use std::time::Instant;
     #[allow(unused_imports)]
     use tracing::{error, info, debug, trace};
@@ -196,4 +199,195 @@ mod test {
         debug!("summary={}", summary);
         assert_eq!(summary.num_buffers(), 0);
     }
+
+    /// With the extended header, pravegasrc outputs exactly the DTS, duration, and flags that were written.
+    /// B-frames make the DTS differ from the PTS.
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_pravegasrc_extended_header(#[case] checksum: bool) {
+        gst_init();
+        let test_config = &get_test_config();
+        info!("test_config={:?}", test_config);
+        let stream_name = &format!("test-pravegasrc-{}-{}", test_config.test_id, Uuid::new_v4())[..];
+        let first_timestamp = PravegaTimestamp::try_from(Some("2001-02-03T04:00:00.000Z".to_owned())).unwrap();
+        info!("#### Write video stream to Pravega");
+        let pipeline_description = format!(
+            "videotestsrc name=src timestamp-offset={timestamp_offset} num-buffers=150 \
+            ! video/x-raw,width=320,height=180,framerate=30/1 \
+            ! videoconvert \
+            ! x264enc key-int-max=30 bframes=2 bitrate=100 \
+            ! tee name=t \
+            t. ! queue ! appsink name=sink sync=false \
+            t. ! pravegasink {pravega_plugin_properties} \
+                 extended-header=true checksum={checksum} seal=true timestamp-mode=tai sync=false",
+            pravega_plugin_properties = test_config.pravega_plugin_properties(stream_name),
+            timestamp_offset = first_timestamp.nanoseconds().unwrap(),
+            checksum = checksum,
+        );
+        let summary_written = launch_pipeline_and_get_summary(&pipeline_description).unwrap();
+        debug!("summary_written={}", summary_written);
+        assert!(summary_written.buffer_summary_list.iter().any(|s| s.dts != s.pts));
+        info!("#### Read video stream");
+        let pipeline_description = format!(
+            "pravegasrc {pravega_plugin_properties} \
+              start-mode=earliest \
+            ! appsink name=sink sync=false",
+            pravega_plugin_properties = test_config.pravega_plugin_properties(stream_name),
+        );
+        let summary = launch_pipeline_and_get_summary(&pipeline_description).unwrap();
+        debug!("summary={}", summary);
+        assert_eq!(summary.buffer_summary_list.len(), summary_written.buffer_summary_list.len());
+        // pravegasrc marks the first buffer as a discontinuity and does not restore TAG_MEMORY.
+        let ignored_flags = gst::BufferFlags::DISCONT | gst::BufferFlags::TAG_MEMORY;
+        for (read, written) in summary.buffer_summary_list.iter().zip(summary_written.buffer_summary_list.iter()) {
+            assert_eq!(read.pts, written.pts);
+            assert_eq!(read.dts, written.dts);
+            assert_eq!(read.duration, written.duration);
+            assert_eq!(read.size, written.size);
+            assert_eq!(read.flags - ignored_flags, written.flags - ignored_flags);
+        }
+        assert!(summary.buffer_summary_list.first().unwrap().flags.contains(gst::BufferFlags::DISCONT));
+    }
+
+    /// Write and read a stream stored in a local directory instead of Pravega.
+    #[test]
+    fn test_pravegasrc_file_storage() {
+        gst_init();
+        let test_config = &get_test_config();
+        let root = std::env::temp_dir().join(format!("test-pravegasrc-file-{}-{}", test_config.test_id, Uuid::new_v4()));
+        let stream_uri = format!("file://{}/{}/test-pravegasrc-file", root.to_str().unwrap(), test_config.scope);
+        info!("stream_uri={}", stream_uri);
+        let first_timestamp = PravegaTimestamp::try_from(Some("2001-02-03T04:00:00.000Z".to_owned())).unwrap();
+        let num_buffers_written = 150;
+        info!("#### Write video stream to local directory");
+        let pipeline_description = format!(
+            "videotestsrc name=src timestamp-offset={timestamp_offset} num-buffers={num_buffers} \
+            ! video/x-raw,width=320,height=180,framerate=30/1 \
+            ! videoconvert \
+            ! x264enc key-int-max=30 bitrate=100 \
+            ! mp4mux streamable=true fragment-duration=100 \
+            ! tee name=t \
+            t. ! queue ! appsink name=sink sync=false \
+            t. ! pravegasink stream={stream_uri} seal=true timestamp-mode=tai sync=false",
+            stream_uri = stream_uri,
+            timestamp_offset = first_timestamp.nanoseconds().unwrap(),
+            num_buffers = num_buffers_written,
+        );
+        let summary_written = launch_pipeline_and_get_summary(&pipeline_description).unwrap();
+        debug!("summary_written={}", summary_written);
+        info!("#### Read video stream from local directory");
+        let pipeline_description = format!(
+            "pravegasrc stream={stream_uri} start-mode=earliest \
+            ! appsink name=sink sync=false",
+            stream_uri = stream_uri,
+        );
+        let summary = launch_pipeline_and_get_summary(&pipeline_description).unwrap();
+        debug!("summary={}", summary);
+        assert_timestamp_eq("first_pts", summary.first_pts(), summary_written.first_valid_pts());
+        assert_timestamp_eq("last_pts", summary.last_pts(), summary_written.last_pts());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    /// Buffers larger than the maximum event size are split into fragments by pravegasink and reassembled by pravegasrc.
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_pravegasrc_fragmented_buffers(#[case] checksum: bool) {
+        gst_init();
+        let test_config = &get_test_config();
+        let root = std::env::temp_dir().join(format!("test-pravegasrc-fragments-{}-{}", test_config.test_id, Uuid::new_v4()));
+        let stream_uri = format!("file://{}/{}/test-pravegasrc-fragments", root.to_str().unwrap(), test_config.scope);
+        info!("stream_uri={}", stream_uri);
+        let first_timestamp = PravegaTimestamp::try_from(Some("2001-02-03T04:00:00.000Z".to_owned())).unwrap();
+        // Each raw frame is 2048 * 1152 * 4 bytes (9 MiB), which requires two events.
+        info!("#### Write video stream to local directory");
+        let pipeline_description = format!(
+            "videotestsrc name=src timestamp-offset={timestamp_offset} num-buffers=5 \
+            ! video/x-raw,format=RGBA,width=2048,height=1152,framerate=30/1 \
+            ! tee name=t \
+            t. ! queue ! appsink name=sink sync=false \
+            t. ! pravegasink stream={stream_uri} checksum={checksum} seal=true timestamp-mode=tai sync=false",
+            stream_uri = stream_uri,
+            timestamp_offset = first_timestamp.nanoseconds().unwrap(),
+            checksum = checksum,
+        );
+        let summary_written = launch_pipeline_and_get_summary(&pipeline_description).unwrap();
+        debug!("summary_written={}", summary_written);
+        assert!(summary_written.buffer_summary_list.iter().all(|s| s.size > 8 * 1024 * 1024));
+        info!("#### Read video stream from local directory");
+        let pipeline_description = format!(
+            "pravegasrc stream={stream_uri} start-mode=earliest \
+            ! appsink name=sink sync=false",
+            stream_uri = stream_uri,
+        );
+        let summary = launch_pipeline_and_get_summary(&pipeline_description).unwrap();
+        debug!("summary={}", summary);
+        assert_eq!(summary.buffer_summary_list, summary_written.buffer_summary_list);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    /// Events are written directly with (payload length, more fragments, continuation) to test how pravegasrc
+    /// handles fragments that pravegasink would only write if it terminated ungracefully or if the stream is corrupt.
+    /// The event at the corrupt index, if any, has a payload that does not match its checksum.
+    /// The expected buffers are (size, discontinuity). The first buffer is always a discontinuity.
+    #[rstest]
+    // A buffer split into three fragments is reassembled.
+    #[case(vec![(100, false, false), (50, true, false), (50, true, true), (50, false, true), (100, false, false)], None,
+        vec![(100, true), (150, false), (100, false)])]
+    // The last buffer is incomplete because the writer terminated before writing its last fragment.
+    #[case(vec![(100, false, false), (50, true, false), (50, true, true)], None,
+        vec![(100, true)])]
+    // An incomplete buffer followed by a new buffer.
+    #[case(vec![(100, false, false), (50, true, false), (100, false, false)], None,
+        vec![(100, true), (100, true)])]
+    // Reading begins after the first fragment.
+    #[case(vec![(50, true, true), (50, false, true), (100, false, false)], None,
+        vec![(100, true)])]
+    // A buffer is dropped if one of its fragments has a checksum mismatch.
+    #[case(vec![(100, false, false), (50, true, false), (50, true, true), (50, false, true), (100, false, false)], Some(2),
+        vec![(100, true), (100, true)])]
+    fn test_pravegasrc_incomplete_fragments(#[case] events: Vec<(usize, bool, bool)>, #[case] corrupt_index: Option<usize>,
+            #[case] expected_buffers: Vec<(u64, bool)>) {
+        gst_init();
+        let test_config = &get_test_config();
+        let root = std::env::temp_dir().join(format!("test-pravegasrc-incomplete-{}-{}", test_config.test_id, Uuid::new_v4()));
+        let stream_name = "test-pravegasrc-incomplete";
+        let stream_uri = format!("file://{}/{}/{}", root.to_str().unwrap(), test_config.scope, stream_name);
+        info!("stream_uri={}", stream_uri);
+        let mut data = Vec::new();
+        let mut timestamp = PravegaTimestamp::try_from(Some("2001-02-03T04:00:00.000Z".to_owned())).unwrap();
+        for (index, (payload_length, more_fragments, continuation)) in events.iter().enumerate() {
+            if !continuation {
+                timestamp = timestamp + 100 * MSECOND;
+            }
+            let payload = vec![index as u8; *payload_length];
+            let event = EventWithHeader::new(&payload[..], timestamp, false, false, false)
+                .with_fragment(*more_fragments, *continuation);
+            EventWriter::new().with_checksum(true).write(&event, &mut data).unwrap();
+            if corrupt_index == Some(index) {
+                // The payload is at the end of the event.
+                *data.last_mut().unwrap() ^= 0xFF;
+            }
+        }
+        let storage = FileStorage::new(root.clone());
+        storage.create_stream(&test_config.scope, stream_name, None).unwrap();
+        let mut writer = storage.create_writer(&test_config.scope, stream_name).unwrap();
+        writer.write_all(&data).unwrap();
+        writer.flush().unwrap();
+        writer.seal().unwrap();
+        info!("#### Read stream from local directory");
+        let pipeline_description = format!(
+            "pravegasrc stream={stream_uri} start-mode=no-seek checksum-mismatch-mode=drop \
+            ! appsink name=sink sync=false",
+            stream_uri = stream_uri,
+        );
+        let summary = launch_pipeline_and_get_summary(&pipeline_description).unwrap();
+        debug!("summary={}", summary);
+        let buffers: Vec<_> = summary.buffer_summary_list.iter()
+            .map(|s| (s.size, s.flags.contains(gst::BufferFlags::DISCONT)))
+            .collect();
+        assert_eq!(buffers, expected_buffers);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
 }
@@ -29,13 +29,23 @@ function load_video() {
     if (end != "") {
         query = query + ((query == "") ? "?" : "&") + "end=" + new Date(end).toISOString();
     }
+    var lowLatency = document.getElementById("low_latency").innerHTML == "true";
+    if (lowLatency) {
+        query = query + ((query == "") ? "?" : "&") + "low_latency=true";
+    }
+    // A signed URL grants access to the stream without credentials.
+    var expires = document.getElementById("expires").innerHTML;
+    var signature = document.getElementById("signature").innerHTML;
+    if (expires != "" && signature != "") {
+        query = query + ((query == "") ? "?" : "&") + "expires=" + expires + "&signature=" + signature;
+    }
 
     var manifestUri = "/scopes/" + scope + "/streams/" + stream + "/m3u8" + query;
     console.log(manifestUri);
 
     if (Hls.isSupported()) {
         video = document.getElementById('video');
-        var hls = new Hls();
+        var hls = new Hls({lowLatencyMode: lowLatency});
         hls.on(Hls.Events.FRAG_CHANGED, function(event, data) {
             // Each time we get a new fragment, revise playStartMillisSinceEpoch.
             playStartMillisSinceEpoch = data.frag.programDateTime - data.frag.startPTS * 1000.0;
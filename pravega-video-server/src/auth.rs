@@ -0,0 +1,469 @@
+//
+// Copyright (c) Dell Inc., or its subsidiaries. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+
+//! Authentication and authorization of HTTP requests.
+//!
+//! Requests are authenticated with a bearer token in the Authorization header,
+//! which can be a static token or a JWT signed by a key in a local JWKS file.
+//! Authenticated principals are authorized by a policy file with rules for each scope and stream.
+//! Alternatively, a request can include a signature in the query parameters that grants read access to
+//! a single stream until it expires. Signed URLs can be shared with viewers that do not have credentials.
+//!
+//! If no authentication method is configured, all requests are allowed.
+
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde_derive::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+#[allow(unused_imports)]
+use tracing::{debug, info, warn};
+use warp::Filter;
+use warp::http::StatusCode;
+
+/// An authenticated user or service.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Principal {
+    pub subject: String,
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// List scopes and streams.
+    List,
+    /// Read playlists, manifests, and media.
+    Read,
+    /// Create signed URLs.
+    Share,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    /// Credentials are missing or invalid.
+    Unauthenticated,
+    /// The credentials do not allow the action.
+    Forbidden,
+}
+
+impl warp::reject::Reject for AuthError {}
+
+/// Validates a bearer token.
+pub trait Authenticator: Send + Sync {
+    /// Returns the principal if the token is valid for this authenticator.
+    fn authenticate(&self, token: &str) -> Option<Principal>;
+}
+
+#[derive(Debug, Deserialize)]
+struct StaticToken {
+    subject: String,
+    #[serde(default)]
+    groups: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StaticTokensFile {
+    tokens: HashMap<String, StaticToken>,
+}
+
+/// Authenticates bearer tokens listed in a JSON file of the form:
+/// `{"tokens": {"secret-token": {"subject": "alice", "groups": ["viewers"]}}}`
+pub struct StaticTokenAuthenticator {
+    tokens: Vec<(String, Principal)>,
+}
+
+impl StaticTokenAuthenticator {
+    pub fn from_file(file_name: &str) -> anyhow::Result<Self> {
+        let file: StaticTokensFile = serde_json::from_str(&std::fs::read_to_string(file_name)?)
+            .with_context(|| format!("Unable to parse tokens file {}", file_name))?;
+        let tokens = file.tokens.into_iter().map(|(token, t)| {
+            (token, Principal { subject: t.subject, groups: t.groups })
+        }).collect();
+        Ok(StaticTokenAuthenticator { tokens })
+    }
+}
+
+impl Authenticator for StaticTokenAuthenticator {
+    fn authenticate(&self, token: &str) -> Option<Principal> {
+        self.tokens.iter()
+            .find(|(t, _)| constant_time_eq(t.as_bytes(), token.as_bytes()))
+            .map(|(_, principal)| principal.clone())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JwtClaims {
+    sub: String,
+    #[serde(default)]
+    groups: Vec<String>,
+}
+
+/// Authenticates JWTs signed by a key in a JWKS file.
+/// The key is selected by the kid in the JWT header.
+/// The expiration time is always validated. The issuer and audience are validated if provided.
+pub struct JwtAuthenticator {
+    jwks: JwkSet,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+impl JwtAuthenticator {
+    pub fn from_file(file_name: &str, issuer: Option<String>, audience: Option<String>) -> anyhow::Result<Self> {
+        let jwks: JwkSet = serde_json::from_str(&std::fs::read_to_string(file_name)?)
+            .with_context(|| format!("Unable to parse JWKS file {}", file_name))?;
+        info!("Loaded {} keys from JWKS file {}", jwks.keys.len(), file_name);
+        Ok(JwtAuthenticator { jwks, issuer, audience })
+    }
+}
+
+/// Returns true if the JWK can verify a signature made with the algorithm.
+/// If the JWK has an alg parameter, only that algorithm is allowed.
+/// Otherwise, the algorithm must belong to the key type (and curve) of the JWK.
+fn jwk_allows_algorithm(jwk: &Jwk, alg: Algorithm) -> bool {
+    if let Some(jwk_alg) = jwk.common.algorithm {
+        return jwk_alg == alg;
+    }
+    match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => matches!(alg,
+            Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 | Algorithm::PS256 | Algorithm::PS384 | Algorithm::PS512),
+        AlgorithmParameters::EllipticCurve(params) => matches!((&params.curve, alg),
+            (EllipticCurve::P256, Algorithm::ES256) | (EllipticCurve::P384, Algorithm::ES384)),
+        AlgorithmParameters::OctetKeyPair(params) => matches!((&params.curve, alg),
+            (EllipticCurve::Ed25519, Algorithm::EdDSA)),
+        AlgorithmParameters::OctetKey(_) => matches!(alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512),
+    }
+}
+
+impl Authenticator for JwtAuthenticator {
+    fn authenticate(&self, token: &str) -> Option<Principal> {
+        let header = jsonwebtoken::decode_header(token).ok()?;
+        let jwk = match header.kid {
+            Some(ref kid) => self.jwks.find(kid)?,
+            None if self.jwks.keys.len() == 1 => &self.jwks.keys[0],
+            None => return None,
+        };
+        // The algorithm in the token header is untrusted. It must match the key.
+        if !jwk_allows_algorithm(jwk, header.alg) {
+            debug!("JWT algorithm {:?} does not match the key", header.alg);
+            return None;
+        }
+        let key = DecodingKey::from_jwk(jwk).ok()?;
+        let mut validation = Validation::new(header.alg);
+        if let Some(ref issuer) = self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        if let Some(ref audience) = self.audience {
+            validation.set_audience(&[audience]);
+        }
+        match jsonwebtoken::decode::<JwtClaims>(token, &key, &validation) {
+            Ok(token_data) => Some(Principal {
+                subject: token_data.claims.sub,
+                groups: token_data.claims.groups,
+            }),
+            Err(e) => {
+                debug!("JWT validation failed: {}", e);
+                None
+            },
+        }
+    }
+}
+
+fn wildcard() -> String {
+    "*".to_owned()
+}
+
+/// A rule in the authorization policy file.
+#[derive(Debug, Deserialize)]
+pub struct PolicyRule {
+    /// Subjects, or groups prefixed with "group:", to which this rule applies. "*" matches all principals.
+    pub principals: Vec<String>,
+    /// Scope name. A trailing "*" matches any suffix.
+    #[serde(default = "wildcard")]
+    pub scope: String,
+    /// Stream name. A trailing "*" matches any suffix.
+    #[serde(default = "wildcard")]
+    pub stream: String,
+    pub actions: Vec<Action>,
+}
+
+/// An authorization policy file of the form:
+/// `{"rules": [{"principals": ["group:viewers"], "scope": "examples", "stream": "camera*", "actions": ["list", "read"]}]}`
+/// An action is allowed if any rule allows it.
+#[derive(Debug, Deserialize)]
+pub struct Policy {
+    pub rules: Vec<PolicyRule>,
+}
+
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == name,
+    }
+}
+
+impl Policy {
+    pub fn from_file(file_name: &str) -> anyhow::Result<Self> {
+        serde_json::from_str(&std::fs::read_to_string(file_name)?)
+            .with_context(|| format!("Unable to parse policy file {}", file_name))
+    }
+
+    /// Returns true if the principal may perform the action.
+    /// If the stream is None, the action applies to the scope and any stream rule matches.
+    pub fn is_allowed(&self, principal: &Principal, action: Action, scope: &str, stream: Option<&str>) -> bool {
+        self.rules.iter().any(|rule| {
+            rule.actions.contains(&action)
+                && matches_pattern(&rule.scope, scope)
+                && stream.map_or(true, |stream| matches_pattern(&rule.stream, stream))
+                && rule.principals.iter().any(|p| {
+                    p == "*" || *p == principal.subject
+                        || p.strip_prefix("group:").map_or(false, |group| principal.groups.iter().any(|g| g == group))
+                })
+        })
+    }
+}
+
+/// Creates and validates signatures that grant read access to a single stream until an expiration time.
+pub struct UrlSigner {
+    key: Vec<u8>,
+}
+
+/// The query parameters of a signed URL.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SignedQuery {
+    /// Expiration time in seconds since the Unix epoch.
+    pub expires: Option<u64>,
+    /// Base64url-encoded HMAC-SHA256 of the scope, stream, and expiration time.
+    pub signature: Option<String>,
+}
+
+impl SignedQuery {
+    /// Returns the query parameters to append to URLs, beginning with "&", or an empty string if not signed.
+    pub fn to_query_suffix(&self) -> String {
+        match (self.expires, &self.signature) {
+            (Some(expires), Some(signature)) => format!("&expires={}&signature={}", expires, signature),
+            _ => String::new(),
+        }
+    }
+}
+
+impl UrlSigner {
+    pub fn from_file(file_name: &str) -> anyhow::Result<Self> {
+        let key = std::fs::read_to_string(file_name)?.trim().as_bytes().to_vec();
+        if key.len() < 32 {
+            anyhow::bail!("URL signing key in {} must have at least 32 bytes", file_name);
+        }
+        Ok(UrlSigner { key })
+    }
+
+    fn mac(&self, scope: &str, stream: &str, expires: u64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).unwrap();
+        mac.update(format!("{}/{}\n{}", scope, stream, expires).as_bytes());
+        mac
+    }
+
+    pub fn sign(&self, scope: &str, stream: &str, expires: u64) -> SignedQuery {
+        let signature = self.mac(scope, stream, expires).finalize().into_bytes();
+        SignedQuery {
+            expires: Some(expires),
+            signature: Some(base64::encode_config(signature, base64::URL_SAFE_NO_PAD)),
+        }
+    }
+
+    /// Returns true if the signature is valid for the stream and has not expired.
+    pub fn verify(&self, query: &SignedQuery, scope: &str, stream: &str, now: u64) -> bool {
+        match (query.expires, &query.signature) {
+            (Some(expires), Some(signature)) if expires >= now => {
+                match base64::decode_config(signature, base64::URL_SAFE_NO_PAD) {
+                    Ok(signature) => self.mac(scope, stream, expires).verify_slice(&signature).is_ok(),
+                    Err(_) => false,
+                }
+            },
+            _ => false,
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn now_seconds() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// The credentials provided with a request.
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    pub authorization: Option<String>,
+    pub signed_query: SignedQuery,
+}
+
+pub struct Auth {
+    authenticators: Vec<Box<dyn Authenticator>>,
+    policy: Option<Policy>,
+    url_signer: Option<UrlSigner>,
+}
+
+impl Auth {
+    pub fn new(authenticators: Vec<Box<dyn Authenticator>>, policy: Option<Policy>, url_signer: Option<UrlSigner>) -> Self {
+        Auth { authenticators, policy, url_signer }
+    }
+
+    /// Returns false if all requests are allowed.
+    pub fn is_enabled(&self) -> bool {
+        !self.authenticators.is_empty() || self.url_signer.is_some()
+    }
+
+    fn authenticate(&self, credentials: &Credentials) -> Option<Principal> {
+        let token = credentials.authorization.as_deref()?.strip_prefix("Bearer ")?.trim();
+        self.authenticators.iter().find_map(|authenticator| authenticator.authenticate(token))
+    }
+
+    /// Returns Ok if the credentials allow the action.
+    /// If the stream is None, the action applies to the scope.
+    pub fn authorize(&self, credentials: &Credentials, action: Action, scope: &str, stream: Option<&str>) -> Result<(), AuthError> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        if let Some(principal) = self.authenticate(credentials) {
+            let allowed = self.policy.as_ref().map_or(true, |policy| policy.is_allowed(&principal, action, scope, stream));
+            debug!("authorize: principal={:?}, action={:?}, scope={}, stream={:?}, allowed={}", principal, action, scope, stream, allowed);
+            return if allowed { Ok(()) } else { Err(AuthError::Forbidden) };
+        }
+        // A signed URL only allows reading the stream that it was signed for.
+        if let (Some(url_signer), Action::Read, Some(stream)) = (&self.url_signer, action, stream) {
+            if url_signer.verify(&credentials.signed_query, scope, stream, now_seconds()) {
+                return Ok(());
+            }
+        }
+        if credentials.authorization.is_some() || credentials.signed_query.signature.is_some() {
+            warn!("authorize: invalid credentials for action={:?}, scope={}, stream={:?}", action, scope, stream);
+        }
+        Err(AuthError::Unauthenticated)
+    }
+
+    /// Returns the query parameters to append to URLs in playlists and manifests
+    /// so that a viewer with a signed URL can retrieve the media.
+    pub fn get_query_suffix(&self, credentials: &Credentials) -> String {
+        match (&self.url_signer, self.authenticate(credentials)) {
+            (Some(_), None) => credentials.signed_query.to_query_suffix(),
+            _ => String::new(),
+        }
+    }
+
+    /// Sign a URL for the stream that expires after the given number of seconds.
+    pub fn sign(&self, scope: &str, stream: &str, ttl_seconds: u64) -> Option<SignedQuery> {
+        self.url_signer.as_ref().map(|url_signer| url_signer.sign(scope, stream, now_seconds() + ttl_seconds))
+    }
+}
+
+/// Extract the credentials from the Authorization header and the signed URL query parameters.
+pub fn with_credentials() -> impl Filter<Extract = (Credentials,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::query::<SignedQuery>())
+        .map(|authorization, signed_query| Credentials { authorization, signed_query })
+}
+
+/// Convert authentication and authorization rejections to HTTP responses.
+pub async fn handle_rejection(rejection: warp::Rejection) -> Result<warp::reply::Response, warp::Rejection> {
+    match rejection.find::<AuthError>() {
+        Some(AuthError::Unauthenticated) => Ok(warp::http::Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header("www-authenticate", "Bearer")
+            .body("Unauthorized".into())
+            .unwrap()),
+        Some(AuthError::Forbidden) => Ok(warp::http::Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Forbidden".into())
+            .unwrap()),
+        None => Err(rejection),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        let policy: Policy = serde_json::from_str(r#"{"rules": [
+            {"principals": ["group:viewers"], "scope": "examples", "stream": "camera*", "actions": ["list", "read"]},
+            {"principals": ["admin"], "actions": ["list", "read", "share"]}
+        ]}"#).unwrap();
+        let viewer = Principal { subject: "alice".to_owned(), groups: vec!["viewers".to_owned()] };
+        let admin = Principal { subject: "admin".to_owned(), groups: vec![] };
+        let other = Principal { subject: "bob".to_owned(), groups: vec!["others".to_owned()] };
+        assert!(policy.is_allowed(&viewer, Action::Read, "examples", Some("camera1")));
+        assert!(policy.is_allowed(&viewer, Action::List, "examples", None));
+        assert!(!policy.is_allowed(&viewer, Action::Read, "examples", Some("secret")));
+        assert!(!policy.is_allowed(&viewer, Action::Read, "other", Some("camera1")));
+        assert!(!policy.is_allowed(&viewer, Action::Share, "examples", Some("camera1")));
+        assert!(policy.is_allowed(&admin, Action::Share, "any", Some("stream")));
+        assert!(!policy.is_allowed(&other, Action::List, "examples", None));
+    }
+
+    #[test]
+    fn test_url_signer() {
+        let url_signer = UrlSigner { key: b"0123456789abcdef0123456789abcdef".to_vec() };
+        let query = url_signer.sign("examples", "camera1", 1000);
+        assert!(url_signer.verify(&query, "examples", "camera1", 999));
+        assert!(url_signer.verify(&query, "examples", "camera1", 1000));
+        assert!(!url_signer.verify(&query, "examples", "camera1", 1001));
+        assert!(!url_signer.verify(&query, "examples", "camera2", 999));
+        let tampered = SignedQuery { expires: Some(2000), signature: query.signature.clone() };
+        assert!(!url_signer.verify(&tampered, "examples", "camera1", 999));
+        assert!(!url_signer.verify(&SignedQuery::default(), "examples", "camera1", 999));
+    }
+
+    #[test]
+    fn test_auth() {
+        let mut tokens = StaticTokenAuthenticator { tokens: Vec::new() };
+        tokens.tokens.push(("token1".to_owned(), Principal { subject: "alice".to_owned(), groups: vec![] }));
+        let url_signer = UrlSigner { key: b"0123456789abcdef0123456789abcdef".to_vec() };
+        let auth = Auth::new(vec![Box::new(tokens)], None, Some(url_signer));
+        let bearer = Credentials { authorization: Some("Bearer token1".to_owned()), ..Default::default() };
+        let invalid = Credentials { authorization: Some("Bearer token2".to_owned()), ..Default::default() };
+        let signed = Credentials { signed_query: auth.sign("examples", "camera1", 60).unwrap(), ..Default::default() };
+        assert!(auth.authorize(&bearer, Action::Share, "examples", Some("camera1")).is_ok());
+        assert!(auth.authorize(&invalid, Action::Read, "examples", Some("camera1")).is_err());
+        assert!(auth.authorize(&Credentials::default(), Action::List, "examples", None).is_err());
+        assert!(auth.authorize(&signed, Action::Read, "examples", Some("camera1")).is_ok());
+        assert!(auth.authorize(&signed, Action::Read, "examples", Some("camera2")).is_err());
+        assert!(auth.authorize(&signed, Action::List, "examples", None).is_err());
+        assert!(auth.get_query_suffix(&signed).starts_with("&expires="));
+        assert_eq!(auth.get_query_suffix(&bearer), "");
+    }
+
+    #[test]
+    fn test_jwt_algorithm() {
+        let jwks: JwkSet = serde_json::from_str(r#"{"keys": [
+            {"kty": "RSA", "kid": "rsa", "use": "sig", "n": "sXchDaQebHnPiGvyDOAT4saGEUetSyo9MKLOoWFsueri23bOdgWp4Dy1WlUzewbgBHod5pcM9H95GQRV3JDXboIRROSBigeC5yjU1hGzHHyXss8UDprecbAYxknTcQkhslANGRUZmdTOQ5qTRsLAt6BTYuyvVRdhS8exSZEy_c4gs_7svlJJQ4H9_NxsiIoLwAEk7-Q3UXERGYw_75IDrGA84-lA_-Ct4eTlXHBIY2EaV7t7LjJaynVJCpkv4LKjTTAumiGUIuQhrNhZLuF_RJLqHpM2kgWFLU7-VTdL1VbC2tejvcI2BlMkEpk1BzBZI0KQB0GaDWFLN-aEAw3vRw", "e": "AQAB"},
+            {"kty": "oct", "kid": "oct", "alg": "HS256", "k": "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY"}
+        ]}"#).unwrap();
+        let authenticator = JwtAuthenticator { jwks, issuer: None, audience: None };
+        let claims = serde_json::json!({"sub": "alice", "exp": 4_000_000_000u64});
+        let secret = b"0123456789abcdef0123456789abcdef";
+        let encode = |kid: &str, alg: Algorithm| {
+            let mut header = jsonwebtoken::Header::new(alg);
+            header.kid = Some(kid.to_owned());
+            jsonwebtoken::encode(&header, &claims, &jsonwebtoken::EncodingKey::from_secret(secret)).unwrap()
+        };
+        assert_eq!(authenticator.authenticate(&encode("oct", Algorithm::HS256)).unwrap().subject, "alice");
+        // An HMAC token must not be verified with an RSA public key.
+        assert!(authenticator.authenticate(&encode("rsa", Algorithm::HS256)).is_none());
+        // The algorithm must match the alg parameter of the key.
+        assert!(authenticator.authenticate(&encode("oct", Algorithm::HS512)).is_none());
+    }
+}
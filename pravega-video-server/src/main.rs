@@ -8,16 +8,24 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 //
 
+mod auth;
+
 use clap::Clap;
+use futures::Future;
 use pravega_client::client_factory::ClientFactoryAsync;
+use pravega_video::encryption::KeyRing;
 use pravega_video::utils;
+use std::net::{IpAddr, SocketAddr};
 use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::runtime::Runtime;
+use tokio::signal::unix::{signal, SignalKind};
 use tracing_subscriber::fmt::format::FmtSpan;
 #[allow(unused_imports)]
 use tracing::{error, info, info_span, warn, trace, event, Level, span};
 use warp::Filter;
-use warp::http::header::{HeaderMap, HeaderValue};
 
 /// Serve HTTP Live Streaming (HLS) from a Pravega Video Stream.
 /// Point your browser to: http://localhost:3030/player?scope=examples&stream=hlsav4
@@ -32,6 +40,110 @@ struct Opts {
     /// Directory containing static files and templates.
     #[clap(long, env = "PRAVEGA_VIDEO_SERVER_RESOURCE_DIR", default_value = "./resources")]
     resource_dir: String,
+    /// Target duration in seconds of partial segments in Low-Latency HLS (LL-HLS) playlists.
+    #[clap(long, env = "PRAVEGA_VIDEO_SERVER_PART_TARGET_SECONDS", default_value = "0.5")]
+    part_target_seconds: f64,
+    /// Maximum number of streams with cached index and metadata records.
+    #[clap(long, env = "PRAVEGA_VIDEO_SERVER_INDEX_CACHE_STREAMS", default_value = "100")]
+    index_cache_streams: usize,
+    /// Maximum number of cached index records per stream. Each record uses about 40 bytes of memory.
+    #[clap(long, env = "PRAVEGA_VIDEO_SERVER_INDEX_CACHE_RECORDS", default_value = "100000")]
+    index_cache_records: usize,
+    /// JSON file with static bearer tokens. If empty, static tokens will not be accepted.
+    #[clap(long, env = "PRAVEGA_VIDEO_SERVER_TOKENS_FILE", default_value = "", setting(clap::ArgSettings::AllowEmptyValues))]
+    tokens_file: String,
+    /// JWKS file with the public keys used to validate JWT bearer tokens. If empty, JWTs will not be accepted.
+    #[clap(long, env = "PRAVEGA_VIDEO_SERVER_JWKS_FILE", default_value = "", setting(clap::ArgSettings::AllowEmptyValues))]
+    jwks_file: String,
+    /// If provided, JWTs must have this issuer (iss).
+    #[clap(long, env = "PRAVEGA_VIDEO_SERVER_JWT_ISSUER")]
+    jwt_issuer: Option<String>,
+    /// If provided, JWTs must have this audience (aud).
+    #[clap(long, env = "PRAVEGA_VIDEO_SERVER_JWT_AUDIENCE")]
+    jwt_audience: Option<String>,
+    /// JSON file with the authorization policy. If empty, all authenticated principals can access all streams.
+    #[clap(long, env = "PRAVEGA_VIDEO_SERVER_POLICY_FILE", default_value = "", setting(clap::ArgSettings::AllowEmptyValues))]
+    policy_file: String,
+    /// File containing the secret key used to sign URLs. If empty, signed URLs will not be accepted.
+    #[clap(long, env = "PRAVEGA_VIDEO_SERVER_URL_SIGNING_KEY_FILE", default_value = "", setting(clap::ArgSettings::AllowEmptyValues))]
+    url_signing_key_file: String,
+    /// File containing the keys used to decrypt encrypted events, in the format key_id:hex_key.
+    /// If empty, encrypted events cannot be served.
+    #[clap(long, env = "PRAVEGA_VIDEO_SERVER_ENCRYPTION_KEY_FILE", default_value = "", setting(clap::ArgSettings::AllowEmptyValues))]
+    encryption_key_file: String,
+    /// Name of an environment variable containing the keys used to decrypt encrypted events.
+    /// This is an alternative to encryption_key_file.
+    #[clap(long, env = "PRAVEGA_VIDEO_SERVER_ENCRYPTION_KEY_ENV", default_value = "", setting(clap::ArgSettings::AllowEmptyValues))]
+    encryption_key_env: String,
+    /// The IP address to listen on.
+    #[clap(long, env = "PRAVEGA_VIDEO_SERVER_BIND_ADDRESS", default_value = "0.0.0.0")]
+    bind_address: IpAddr,
+    /// The TCP port to listen on.
+    #[clap(long, env = "PRAVEGA_VIDEO_SERVER_PORT", default_value = "3030")]
+    port: u16,
+    /// PEM file with the TLS certificate chain. If empty, HTTPS will be disabled.
+    #[clap(long, env = "PRAVEGA_VIDEO_SERVER_TLS_CERT_FILE", default_value = "", setting(clap::ArgSettings::AllowEmptyValues))]
+    tls_cert_file: String,
+    /// PEM file with the TLS private key. Required if a TLS certificate is provided.
+    #[clap(long, env = "PRAVEGA_VIDEO_SERVER_TLS_KEY_FILE", default_value = "", setting(clap::ArgSettings::AllowEmptyValues))]
+    tls_key_file: String,
+    /// Comma-separated list of origins allowed to make cross-origin requests, such as "https://example.com".
+    /// Use "*" to allow any origin. If empty, cross-origin requests will not be allowed.
+    #[clap(long, env = "PRAVEGA_VIDEO_SERVER_CORS_ALLOWED_ORIGINS", default_value = "", setting(clap::ArgSettings::AllowEmptyValues))]
+    cors_allowed_origins: String,
+    /// When SIGTERM or SIGINT is received, the maximum time in seconds to wait for in-flight responses to complete.
+    #[clap(long, env = "PRAVEGA_VIDEO_SERVER_SHUTDOWN_TIMEOUT_SECONDS", default_value = "30")]
+    shutdown_timeout_seconds: u64,
+}
+
+/// Create the CORS (Cross-Origin Resource Sharing) configuration from the options.
+fn create_cors(opts: &Opts) -> warp::cors::Builder {
+    let cors = warp::cors()
+        .allow_methods(vec!["GET", "HEAD", "OPTIONS"])
+        .allow_headers(vec!["authorization", "range"])
+        .expose_headers(vec!["accept-ranges", "content-length", "content-range"]);
+    let origins: Vec<&str> = opts.cors_allowed_origins.split(',')
+        .map(|origin| origin.trim())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+    if origins.contains(&"*") {
+        cors.allow_any_origin()
+    } else {
+        cors.allow_origins(origins)
+    }
+}
+
+/// Wait for SIGTERM or SIGINT.
+async fn wait_for_shutdown_signal() {
+    let mut sigterm = signal(SignalKind::terminate()).expect("installing SIGTERM handler");
+    tokio::select! {
+        _ = sigterm.recv() => info!("Received SIGTERM"),
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+    }
+}
+
+/// Create the request authentication and authorization from the options.
+/// If no tokens file, JWKS file, or URL signing key file is provided, all requests will be allowed.
+fn create_auth(opts: &Opts) -> anyhow::Result<auth::Auth> {
+    let mut authenticators: Vec<Box<dyn auth::Authenticator>> = Vec::new();
+    if !opts.tokens_file.is_empty() {
+        authenticators.push(Box::new(auth::StaticTokenAuthenticator::from_file(&opts.tokens_file)?));
+    }
+    if !opts.jwks_file.is_empty() {
+        authenticators.push(Box::new(auth::JwtAuthenticator::from_file(
+            &opts.jwks_file, opts.jwt_issuer.clone(), opts.jwt_audience.clone())?));
+    }
+    let policy = if opts.policy_file.is_empty() {
+        None
+    } else {
+        Some(auth::Policy::from_file(&opts.policy_file)?)
+    };
+    let url_signer = if opts.url_signing_key_file.is_empty() {
+        None
+    } else {
+        Some(auth::UrlSigner::from_file(&opts.url_signing_key_file)?)
+    };
+    Ok(auth::Auth::new(authenticators, policy, url_signer))
 }
 
 fn main() {
@@ -48,15 +160,37 @@ fn main() {
 
     let static_dir_name = format!("{}/static", opts.resource_dir);
     ensure_extra_files(opts.resource_dir.clone());
+    if !opts.tls_cert_file.is_empty() && opts.tls_key_file.is_empty() {
+        error!("A TLS key file must be provided with the TLS certificate file");
+        std::process::exit(1);
+    }
+    if opts.index_cache_streams == 0 || opts.index_cache_records == 0 {
+        error!("The index cache streams and records must be greater than 0");
+        std::process::exit(1);
+    }
+    let auth = Arc::new(create_auth(&opts).expect("creating auth"));
+    if !auth.is_enabled() {
+        warn!("Authentication is disabled. All scopes and streams can be accessed by anyone.");
+    }
+    let key_ring = KeyRing::load(
+        Some(opts.encryption_key_file.as_str()).filter(|s| !s.is_empty()),
+        Some(opts.encryption_key_env.as_str()).filter(|s| !s.is_empty()),
+    ).expect("loading encryption keys").map(Arc::new);
+    if let Some(key_ring) = key_ring.as_ref() {
+        info!("Loaded encryption keys {:?}", key_ring);
+    }
 
     // Use the Tokio runtime. It will also be used by Warp.
     let runtime  = Runtime::new().unwrap();
-    let config = utils::create_client_config(opts.pravega_controller_uri, Some(opts.keycloak_service_account_file)).expect("creating config");
+    let config = utils::create_client_config(opts.pravega_controller_uri.clone(), Some(opts.keycloak_service_account_file.clone())).expect("creating config");
     let client_factory = ClientFactoryAsync::new(config, runtime.handle().to_owned());
     let client_factory_db = client_factory.clone();
 
     runtime.block_on(async {
-        let db = models::new(client_factory_db);
+        let index_cache = Arc::new(models::IndexCache::new(opts.index_cache_streams, opts.index_cache_records));
+        let metadata_cache = Arc::new(models::MetadataCache::new(opts.index_cache_streams));
+        let initialization_cache = Arc::new(models::InitializationCache::new(opts.index_cache_streams));
+        let db = models::new(client_factory_db, opts.part_target_seconds, auth, index_cache, metadata_cache, initialization_cache, key_ring);
         let api = filters::get_all_filters(db);
         let ui = ui::get_all_filters();
         let static_dir = warp::path("static").and(warp::fs::dir(static_dir_name));
@@ -64,15 +198,46 @@ fn main() {
         //     warp::redirect::temporary(Uri::from_static("/static/hls-js.html"))
         // });
 
-        let mut headers = HeaderMap::new();
-        headers.insert("access-control-allow-origin", HeaderValue::from_static("*"));
         let routes = api
+            .recover(auth::handle_rejection)
             .or(ui)
             .or(static_dir)
-            .with(warp::reply::with::headers(headers))
+            .with(create_cors(&opts))
             .with(warp::trace::request());
-        warp::serve(routes).run(([0, 0, 0, 0], 3030)).await;
-    })
+
+        // When a shutdown signal is received, stop accepting connections and wait for in-flight responses.
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let shutdown = async {
+            shutdown_rx.await.ok();
+        };
+        let bind_addr = SocketAddr::new(opts.bind_address, opts.port);
+        let server: Pin<Box<dyn Future<Output = ()> + Send>> = if opts.tls_cert_file.is_empty() {
+            let (addr, server) = warp::serve(routes).bind_with_graceful_shutdown(bind_addr, shutdown);
+            info!("Listening on http://{}", addr);
+            Box::pin(server)
+        } else {
+            let (addr, server) = warp::serve(routes)
+                .tls()
+                .cert_path(&opts.tls_cert_file)
+                .key_path(&opts.tls_key_file)
+                .bind_with_graceful_shutdown(bind_addr, shutdown);
+            info!("Listening on https://{}", addr);
+            Box::pin(server)
+        };
+        let server = tokio::spawn(server);
+
+        wait_for_shutdown_signal().await;
+        info!("Shutting down; waiting up to {} seconds for in-flight responses", opts.shutdown_timeout_seconds);
+        let _ = shutdown_tx.send(());
+        match tokio::time::timeout(Duration::from_secs(opts.shutdown_timeout_seconds), server).await {
+            Ok(_) => info!("All in-flight responses completed"),
+            Err(_) => warn!("Shutdown timeout expired; closing in-flight responses"),
+        }
+    });
+    // Responses that did not complete are closed when the runtime is dropped.
+    // Blocking threads that read from Pravega stop when their response is closed.
+    runtime.shutdown_timeout(Duration::from_secs(5));
+    info!("main: END");
 }
 
 fn ensure_extra_files(resource_dir: String) {
@@ -86,8 +251,9 @@ fn ensure_extra_files(resource_dir: String) {
 }
 
 mod filters {
+    use super::auth::with_credentials;
     use super::handlers;
-    use super::models::{Db, GetMediaSegmentOptions, GetM3u8PlaylistOptions};
+    use super::models::{Db, GetMediaSegmentOptions, GetM3u8PlaylistOptions, GetMpdOptions, GetSignedUrlOptions};
     use warp::Filter;
 
     pub fn get_all_filters(
@@ -95,18 +261,24 @@ mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         get_media_segment(db.clone())
             .or(get_m3u8_playlist(db.clone()))
+            .or(get_master_m3u8_playlist(db.clone()))
+            .or(get_mpd(db.clone()))
+            .or(get_signed_url(db.clone()))
             .or(list_video_streams(db.clone()))
             .or(list_scopes(db.clone()))
     }
 
     /// GET /scopes/my_scope/streams/my_stream/media?begin=0&end=204
     /// Returns a media segment consisting of fragmented MP4 or MPEG TS.
+    /// An HTTP Range header selects bytes of the media segment, not bytes of the Pravega stream.
     pub fn get_media_segment(
         db: Db,
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("scopes" / String / "streams" / String / "media" )
             .and(warp::get())
             .and(warp::query::<GetMediaSegmentOptions>())
+            .and(warp::header::optional::<String>("range"))
+            .and(with_credentials())
             .and(with_db(db))
             .and_then(handlers::get_media_segment)
     }
@@ -118,11 +290,52 @@ mod filters {
         warp::path!("scopes" / String / "streams" / String / "m3u8" )
             .and(warp::get())
             .and(warp::query::<GetM3u8PlaylistOptions>())
+            .and(with_credentials())
             .and(with_db(db))
             .and_then(handlers::get_m3u8_playlist)
             .with(warp::compression::gzip())
     }
 
+    /// GET /scopes/my_scope/streams/my_stream/master.m3u8?begin=2021-04-19T00:00:00Z&end=2021-04-20T00:00:00Z
+    /// Returns a master playlist with the codecs of the stream and a single variant that refers to the m3u8 playlist.
+    pub fn get_master_m3u8_playlist(
+        db: Db,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("scopes" / String / "streams" / String / "master.m3u8" )
+            .and(warp::get())
+            .and(warp::query::<GetM3u8PlaylistOptions>())
+            .and(with_credentials())
+            .and(with_db(db))
+            .and_then(handlers::get_master_m3u8_playlist)
+    }
+
+    /// GET /scopes/my_scope/streams/my_stream/mpd?begin=2021-04-19T00:00:00Z&end=2021-04-20T00:00:00Z
+    /// Returns an MPEG-DASH manifest with the same media segments as the m3u8 playlist.
+    pub fn get_mpd(
+        db: Db,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("scopes" / String / "streams" / String / "mpd" )
+            .and(warp::get())
+            .and(warp::query::<GetMpdOptions>())
+            .and(with_credentials())
+            .and(with_db(db))
+            .and_then(handlers::get_mpd)
+            .with(warp::compression::gzip())
+    }
+
+    /// GET /scopes/my_scope/streams/my_stream/signed-url?ttl_seconds=3600
+    /// Returns the query parameters of a signed URL that grants read access to the stream until it expires.
+    pub fn get_signed_url(
+        db: Db,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("scopes" / String / "streams" / String / "signed-url" )
+            .and(warp::get())
+            .and(warp::query::<GetSignedUrlOptions>())
+            .and(with_credentials())
+            .and(with_db(db))
+            .and_then(handlers::get_signed_url)
+    }
+
     /// List scopes this player has access to
     /// GET /scopes
     pub fn list_scopes(
@@ -130,6 +343,7 @@ mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("scopes")
             .and(warp::get())
+            .and(with_credentials())
             .and(with_db(db))
             .and_then(handlers::list_scopes)
     }
@@ -141,6 +355,7 @@ mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("scopes" / String / "streams")
             .and(warp::get())
+            .and(with_credentials())
             .and(with_db(db))
             .and_then(handlers::list_video_streams)
     }
@@ -165,6 +380,12 @@ mod ui {
         pub stream_name: Option<String>,
         pub begin: Option<DateTime<Utc>>,
         pub end: Option<DateTime<Utc>>,
+        #[serde(default)]
+        pub low_latency: bool,
+        /// The expiration time of a signed URL.
+        pub expires: Option<u64>,
+        /// The signature of a signed URL.
+        pub signature: Option<String>,
     }
 
     pub fn get_all_filters(
@@ -190,71 +411,416 @@ mod ui {
 }
 
 mod handlers {
-    use std::convert::Infallible;
-    use super::models::{Db, GetMediaSegmentOptions, GetM3u8PlaylistOptions};
+    use super::auth::{Action, Credentials};
+    use super::models::{Db, GetMediaSegmentOptions, GetM3u8PlaylistOptions, GetMpdOptions, GetSignedUrlOptions, SignedUrlResult};
     use super::*;
+    use warp::Reply;
 
     pub async fn get_media_segment(
         scope_name: String,
         stream_name: String,
         opts: GetMediaSegmentOptions,
+        range: Option<String>,
+        credentials: Credentials,
         db: Db,
-    ) -> Result<impl warp::Reply, Infallible> {
-        db.get_media_segment(scope_name, stream_name, opts).await
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        db.auth.authorize(&credentials, Action::Read, &scope_name, Some(&stream_name)).map_err(warp::reject::custom)?;
+        Ok(db.get_media_segment(scope_name, stream_name, opts, range).await.unwrap())
     }
 
     pub async fn get_m3u8_playlist(
         scope_name: String,
         stream_name: String,
         opts: GetM3u8PlaylistOptions,
+        credentials: Credentials,
+        db: Db,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        db.auth.authorize(&credentials, Action::Read, &scope_name, Some(&stream_name)).map_err(warp::reject::custom)?;
+        // LL-HLS requires _HLS_msn whenever _HLS_part is present.
+        if opts.hls_part.is_some() && opts.hls_msn.is_none() {
+            return Ok(warp::reply::with_status("_HLS_part requires _HLS_msn".to_owned(), warp::http::StatusCode::BAD_REQUEST).into_response());
+        }
+        let query_suffix = db.auth.get_query_suffix(&credentials);
+        Ok(db.get_m3u8_playlist(scope_name, stream_name, opts, query_suffix).await.unwrap())
+    }
+
+    pub async fn get_master_m3u8_playlist(
+        scope_name: String,
+        stream_name: String,
+        opts: GetM3u8PlaylistOptions,
+        credentials: Credentials,
         db: Db,
-    ) -> Result<impl warp::Reply, Infallible> {
-        let playlist = db.get_m3u8_playlist(scope_name, stream_name, opts).await.unwrap();
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        db.auth.authorize(&credentials, Action::Read, &scope_name, Some(&stream_name)).map_err(warp::reject::custom)?;
+        let query_suffix = db.auth.get_query_suffix(&credentials);
+        let playlist = db.get_master_m3u8_playlist(scope_name, stream_name, opts, query_suffix).await.unwrap();
         Ok(warp::reply::with_header(playlist, "content-type", "application/x-mpegURL"))
     }
 
+    pub async fn get_mpd(
+        scope_name: String,
+        stream_name: String,
+        opts: GetMpdOptions,
+        credentials: Credentials,
+        db: Db,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        db.auth.authorize(&credentials, Action::Read, &scope_name, Some(&stream_name)).map_err(warp::reject::custom)?;
+        if let (Some(begin), Some(end)) = (opts.begin, opts.end) {
+            if begin > end {
+                return Ok(warp::reply::with_status("begin must not exceed end".to_owned(), warp::http::StatusCode::BAD_REQUEST).into_response());
+            }
+        }
+        let query_suffix = db.auth.get_query_suffix(&credentials);
+        match db.get_mpd(scope_name, stream_name, opts, query_suffix).await {
+            Ok(mpd) => Ok(warp::reply::with_header(mpd, "content-type", "application/dash+xml").into_response()),
+            Err(e) => {
+                error!("get_mpd: {:?}", e);
+                Ok(warp::reply::with_status(e.to_string(), warp::http::StatusCode::INTERNAL_SERVER_ERROR).into_response())
+            },
+        }
+    }
+
+    pub async fn get_signed_url(
+        scope_name: String,
+        stream_name: String,
+        opts: GetSignedUrlOptions,
+        credentials: Credentials,
+        db: Db,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        db.auth.authorize(&credentials, Action::Share, &scope_name, Some(&stream_name)).map_err(warp::reject::custom)?;
+        info!("get_signed_url: scope_name={}, stream_name={}, ttl_seconds={}", scope_name, stream_name, opts.ttl_seconds);
+        match db.auth.sign(&scope_name, &stream_name, opts.ttl_seconds) {
+            Some(signed_query) => {
+                let query = signed_query.to_query_suffix().trim_start_matches('&').to_owned();
+                let stream_path = format!("/scopes/{}/streams/{}", scope_name, stream_name);
+                let result = SignedUrlResult {
+                    expires: signed_query.expires.unwrap_or_default(),
+                    m3u8_url: format!("{}/m3u8?{}", stream_path, query),
+                    mpd_url: format!("{}/mpd?{}", stream_path, query),
+                    player_url: format!("/player?scope={}&stream={}&{}", scope_name, stream_name, query),
+                    query,
+                };
+                Ok(warp::reply::with_status(warp::reply::json(&result), warp::http::StatusCode::OK))
+            },
+            None => Ok(warp::reply::with_status(warp::reply::json(&"URL signing is not enabled"), warp::http::StatusCode::NOT_FOUND)),
+        }
+    }
+
     pub async fn list_scopes(
+        credentials: Credentials,
         db: Db,
-    ) -> Result<impl warp::Reply, Infallible> {
+    ) -> Result<impl warp::Reply, warp::Rejection> {
         info!("list_scopes");
-        let streams = db.list_scopes().await.unwrap();
-        Ok(warp::reply::json(&streams))
+        let mut scopes = db.clone().list_scopes().await.unwrap();
+        scopes.scopes.retain(|scope| db.auth.authorize(&credentials, Action::List, &scope.scope_name, None).is_ok());
+        Ok(warp::reply::json(&scopes))
     }
 
     pub async fn list_video_streams(
         scope_name: String,
+        credentials: Credentials,
         db: Db,
-    ) -> Result<impl warp::Reply, Infallible> {
+    ) -> Result<impl warp::Reply, warp::Rejection> {
         info!("list_video_streams: scope_name={}", scope_name);
-        let streams = db.list_video_streams(scope_name).await.unwrap();
+        db.auth.authorize(&credentials, Action::List, &scope_name, None).map_err(warp::reject::custom)?;
+        let mut streams = db.clone().list_video_streams(scope_name).await.unwrap();
+        streams.streams.retain(|stream| {
+            db.auth.authorize(&credentials, Action::List, &stream.scope_name, Some(&stream.stream_name)).is_ok()
+        });
         Ok(warp::reply::json(&streams))
     }
 }
 
+mod range {
+    /// The part of a representation selected by an HTTP Range header (RFC 7233).
+    #[derive(Debug, PartialEq)]
+    pub enum ByteRange {
+        /// The entire representation should be sent with status 200.
+        Full,
+        /// Bytes first..=last should be sent with status 206.
+        Partial { first: u64, last: u64 },
+        /// The range does not overlap the representation. Status 416 should be returned.
+        Unsatisfiable,
+    }
+
+    /// Evaluate the value of a Range header for a representation with the given length.
+    /// Only a single byte range is supported.
+    /// As allowed by RFC 7233, multiple ranges and invalid ranges are ignored and the full representation is selected.
+    pub fn parse_byte_range(range: &str, length: u64) -> ByteRange {
+        let spec = match range.trim().strip_prefix("bytes=") {
+            Some(spec) if !spec.contains(',') => spec.trim(),
+            _ => return ByteRange::Full,
+        };
+        let (first, last) = match spec.split_once('-') {
+            Some(parts) => parts,
+            None => return ByteRange::Full,
+        };
+        match (first.parse::<u64>().ok(), last.parse::<u64>().ok()) {
+            // bytes=first-last or bytes=first-
+            (Some(first), last_opt) if last_opt.is_some() || last.is_empty() => {
+                if let Some(last) = last_opt {
+                    if last < first {
+                        return ByteRange::Full;
+                    }
+                }
+                if first >= length {
+                    return ByteRange::Unsatisfiable;
+                }
+                let last = last_opt.map_or(length - 1, |last| u64::min(last, length - 1));
+                ByteRange::Partial { first, last }
+            },
+            // bytes=-suffix_length
+            (None, Some(suffix_length)) if first.is_empty() => {
+                if suffix_length == 0 || length == 0 {
+                    return ByteRange::Unsatisfiable;
+                }
+                ByteRange::Partial { first: length - u64::min(suffix_length, length), last: length - 1 }
+            },
+            _ => ByteRange::Full,
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn test_parse_byte_range() {
+            assert_eq!(parse_byte_range("bytes=0-99", 1000), ByteRange::Partial { first: 0, last: 99 });
+            assert_eq!(parse_byte_range("bytes=900-", 1000), ByteRange::Partial { first: 900, last: 999 });
+            assert_eq!(parse_byte_range("bytes=900-2000", 1000), ByteRange::Partial { first: 900, last: 999 });
+            assert_eq!(parse_byte_range("bytes=-100", 1000), ByteRange::Partial { first: 900, last: 999 });
+            assert_eq!(parse_byte_range("bytes=-2000", 1000), ByteRange::Partial { first: 0, last: 999 });
+            assert_eq!(parse_byte_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+            assert_eq!(parse_byte_range("bytes=-0", 1000), ByteRange::Unsatisfiable);
+            assert_eq!(parse_byte_range("bytes=0-", 0), ByteRange::Unsatisfiable);
+            assert_eq!(parse_byte_range("bytes=0-1,5-6", 1000), ByteRange::Full);
+            assert_eq!(parse_byte_range("bytes=5-1", 1000), ByteRange::Full);
+            assert_eq!(parse_byte_range("items=0-1", 1000), ByteRange::Full);
+            assert_eq!(parse_byte_range("bytes=a-b", 1000), ByteRange::Full);
+        }
+    }
+}
+
 mod models {
     use anyhow;
-    use chrono::{DateTime, Utc};
+    use chrono::{DateTime, SecondsFormat, Utc};
     use futures::{StreamExt, future};
     use hyper::body::{Body, Bytes};
     use pravega_client::client_factory::ClientFactoryAsync;
     use pravega_client_shared::{Scope, ScopedStream, Stream};
     use pravega_controller_client::paginator::{list_streams_for_tag, list_scopes};
-    use pravega_video::{event_serde::{EventReader}, index::IndexSearcher};
-    use pravega_video::index::{IndexRecord, IndexRecordReader, SearchMethod, get_index_stream_name};
+    use pravega_video::encryption::{KeyRing, encryption_overhead};
+    use pravega_video::event_serde::{EventHeader, EventReader, decrypt_payload};
+    use pravega_video::index::{CachedIndexSearcher, IndexRecord, STREAM_RECORD_NUMBER_LOOKBACK, SearchMethod, get_index_stream_name,
+        get_stream_record_number};
+    use pravega_video::metadata::{MetadataSearcher, StreamMetadata, CONTENT_TYPE_MP4, get_metadata_stream_name};
     use pravega_video::timestamp::PravegaTimestamp;
     use pravega_video::utils::SyncByteReader;
     use serde_derive::{Deserialize, Serialize};
+    use std::collections::HashMap;
     use std::convert::Infallible;
     use std::io::{ErrorKind, Read, Seek, SeekFrom};
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
     use super::*;
+    use super::auth::Auth;
+    use super::range::{ByteRange, parse_byte_range};
+    use tokio::sync::mpsc;
+    use warp::http::StatusCode;
+
+    /// The number of events that can be read from Pravega ahead of the HTTP client.
+    /// When the channel is full, reading from Pravega waits for the client.
+    const MEDIA_SEGMENT_CHANNEL_CAPACITY: usize = 16;
 
     #[derive(Clone)]
     pub struct Db {
         pub client_factory: ClientFactoryAsync,
+        /// The target duration of LL-HLS partial segments.
+        pub part_target_seconds: f64,
+        pub auth: Arc<Auth>,
+        pub index_cache: Arc<IndexCache>,
+        pub metadata_cache: Arc<MetadataCache>,
+        pub initialization_cache: Arc<InitializationCache>,
+        /// Keys used to decrypt encrypted events.
+        pub key_ring: Option<Arc<KeyRing>>,
+    }
+
+    pub fn new(client_factory: ClientFactoryAsync, part_target_seconds: f64, auth: Arc<Auth>, index_cache: Arc<IndexCache>,
+            metadata_cache: Arc<MetadataCache>, initialization_cache: Arc<InitializationCache>, key_ring: Option<Arc<KeyRing>>) -> Db {
+        Db { client_factory, part_target_seconds, auth, index_cache, metadata_cache, initialization_cache, key_ring }
     }
 
-    pub fn new(client_factory: ClientFactoryAsync) -> Db {
-        Db { client_factory }
+    type CachedIndex = CachedIndexSearcher<SyncByteReader>;
+
+    /// Index searchers for recently used streams.
+    /// Each one caches the most recent index records of a stream, so that repeated playlist requests
+    /// for a live stream only read the index records that were appended since the previous request.
+    pub struct IndexCache {
+        max_streams: usize,
+        max_records: usize,
+        searchers: Mutex<HashMap<String, (Arc<CachedIndex>, Instant)>>,
+    }
+
+    impl IndexCache {
+        pub fn new(max_streams: usize, max_records: usize) -> IndexCache {
+            IndexCache {
+                max_streams,
+                max_records,
+                searchers: Mutex::new(HashMap::new()),
+            }
+        }
+
+        /// Returns the index searcher for a stream, creating it if needed.
+        /// If there are too many streams, the least recently used searcher is removed.
+        /// This will block the thread.
+        fn get(&self, client_factory: &ClientFactoryAsync, scope_name: &str, stream_name: &str) -> Arc<CachedIndex> {
+            let key = format!("{}/{}", scope_name, stream_name);
+            if let Some((searcher, last_used)) = self.searchers.lock().unwrap().get_mut(&key) {
+                *last_used = Instant::now();
+                return searcher.clone();
+            }
+            let scoped_stream = ScopedStream {
+                scope: Scope::from(scope_name.to_owned()),
+                stream: Stream::from(get_index_stream_name(stream_name)),
+            };
+            let index_reader = client_factory.runtime_handle().block_on(client_factory.create_byte_reader(scoped_stream));
+            let index_reader = SyncByteReader::new(index_reader, client_factory.runtime_handle());
+            info!("IndexCache: opened Pravega reader for {}", key);
+            let searcher = Arc::new(CachedIndexSearcher::new(index_reader, self.max_records));
+            let mut searchers = self.searchers.lock().unwrap();
+            if searchers.len() >= self.max_streams && !searchers.contains_key(&key) {
+                let oldest_key = searchers.iter()
+                    .min_by_key(|(_, (_, last_used))| *last_used)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest_key) = oldest_key {
+                    info!("IndexCache: removing {}", oldest_key);
+                    searchers.remove(&oldest_key);
+                }
+            }
+            searchers.entry(key).or_insert((searcher, Instant::now())).0.clone()
+        }
+    }
+
+    type CachedMetadata = Mutex<MetadataSearcher<SyncByteReader>>;
+
+    /// Metadata searchers for recently used streams.
+    /// Each one keeps all records of a metadata stream, so that each request only reads the records
+    /// that were appended since the previous request. See MetadataSearcher::refresh.
+    /// If a stream does not have a metadata stream, this is checked again after MISSING_RECHECK_INTERVAL.
+    pub struct MetadataCache {
+        max_streams: usize,
+        // For each stream, the searcher or None if there is no metadata stream, when it was opened, and when it was last used.
+        searchers: Mutex<HashMap<String, (Option<Arc<CachedMetadata>>, Instant, Instant)>>,
+    }
+
+    impl MetadataCache {
+        const MISSING_RECHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+        pub fn new(max_streams: usize) -> MetadataCache {
+            MetadataCache {
+                max_streams,
+                searchers: Mutex::new(HashMap::new()),
+            }
+        }
+
+        /// Returns the metadata searcher for a stream, creating it if needed.
+        /// Returns None if the stream does not have a metadata stream, such as when written by an older version of pravegasink.
+        /// If there are too many streams, the least recently used searcher is removed.
+        /// This will block the thread.
+        fn get(&self, client_factory: &ClientFactoryAsync, scope_name: &str, stream_name: &str) -> Option<Arc<CachedMetadata>> {
+            let key = format!("{}/{}", scope_name, stream_name);
+            if let Some((searcher, opened, last_used)) = self.searchers.lock().unwrap().get_mut(&key) {
+                if searcher.is_some() || opened.elapsed() < MetadataCache::MISSING_RECHECK_INTERVAL {
+                    *last_used = Instant::now();
+                    return searcher.clone();
+                }
+            }
+            let scoped_stream = ScopedStream {
+                scope: Scope::from(scope_name.to_owned()),
+                stream: Stream::from(get_metadata_stream_name(stream_name)),
+            };
+            let exists = client_factory.runtime_handle().block_on(
+                client_factory.controller_client().check_stream_exists(&scoped_stream));
+            let searcher = match exists {
+                Ok(true) => {
+                    let reader = client_factory.runtime_handle().block_on(client_factory.create_byte_reader(scoped_stream));
+                    let reader = SyncByteReader::new(reader, client_factory.runtime_handle());
+                    info!("MetadataCache: opened Pravega reader for {}", key);
+                    Some(Arc::new(Mutex::new(MetadataSearcher::new(reader))))
+                },
+                Ok(false) => None,
+                Err(e) => {
+                    // Do not cache the result so that the next request will check again.
+                    warn!("Unable to check if metadata stream {:?} exists: {:?}", scoped_stream, e);
+                    return None;
+                },
+            };
+            let mut searchers = self.searchers.lock().unwrap();
+            if searchers.len() >= self.max_streams && !searchers.contains_key(&key) {
+                let oldest_key = searchers.iter()
+                    .min_by_key(|(_, (_, _, last_used))| *last_used)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest_key) = oldest_key {
+                    info!("MetadataCache: removing {}", oldest_key);
+                    searchers.remove(&oldest_key);
+                }
+            }
+            let now = Instant::now();
+            searchers.insert(key, (searcher.clone(), now, now));
+            searcher
+        }
+    }
+
+    /// The results of get_initialization_end_offset for the segments of recently used streams,
+    /// keyed by the begin and end offsets of the segment.
+    /// The data of a segment does not change, so each segment is read (and decrypted) at most once
+    /// instead of each time a DASH manifest is generated.
+    pub struct InitializationCache {
+        max_streams: usize,
+        // For each stream, the cached segments and when they were last used.
+        streams: Mutex<HashMap<String, (HashMap<(u64, u64), Option<u64>>, Instant)>>,
+    }
+
+    impl InitializationCache {
+        /// The maximum number of cached segments per stream. When exceeded, the cached segments of the stream are cleared.
+        const MAX_SEGMENTS_PER_STREAM: usize = 100_000;
+
+        pub fn new(max_streams: usize) -> InitializationCache {
+            InitializationCache {
+                max_streams,
+                streams: Mutex::new(HashMap::new()),
+            }
+        }
+
+        fn get(&self, scope_name: &str, stream_name: &str, segment: &MediaSegment) -> Option<Option<u64>> {
+            let key = format!("{}/{}", scope_name, stream_name);
+            let mut streams = self.streams.lock().unwrap();
+            let (segments, last_used) = streams.get_mut(&key)?;
+            *last_used = Instant::now();
+            segments.get(&(segment.begin_offset, segment.end_offset)).cloned()
+        }
+
+        /// If there are too many streams, the least recently used stream is removed.
+        fn insert(&self, scope_name: &str, stream_name: &str, segment: &MediaSegment, end_offset: Option<u64>) {
+            let key = format!("{}/{}", scope_name, stream_name);
+            let mut streams = self.streams.lock().unwrap();
+            if streams.len() >= self.max_streams && !streams.contains_key(&key) {
+                let oldest_key = streams.iter()
+                    .min_by_key(|(_, (_, last_used))| *last_used)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest_key) = oldest_key {
+                    info!("InitializationCache: removing {}", oldest_key);
+                    streams.remove(&oldest_key);
+                }
+            }
+            let (segments, last_used) = streams.entry(key).or_insert_with(|| (HashMap::new(), Instant::now()));
+            if segments.len() >= InitializationCache::MAX_SEGMENTS_PER_STREAM {
+                segments.clear();
+            }
+            segments.insert((segment.begin_offset, segment.end_offset), end_offset);
+            *last_used = Instant::now();
+        }
     }
 
     // The query parameters for get_media_segment.
@@ -262,15 +828,57 @@ mod models {
     pub struct GetMediaSegmentOptions {
         /// Begin byte offset
         pub begin: u64,
-        /// End byte offset (exclusive)
-        pub end: u64,
+        /// End byte offset (exclusive).
+        /// If missing, a single LL-HLS partial segment beginning at the begin offset is returned,
+        /// waiting for it to be written if necessary. This is used for the LL-HLS preload hint.
+        pub end: Option<u64>,
     }
 
-    // The query parameters for get_m3u8_playlist.
+    // The query parameters for get_m3u8_playlist and get_master_m3u8_playlist.
     #[derive(Debug, Deserialize)]
     pub struct GetM3u8PlaylistOptions {
         pub begin: Option<DateTime<Utc>>,
         pub end: Option<DateTime<Utc>>,
+        /// If true, generate a Low-Latency HLS (LL-HLS) playlist with partial segments.
+        #[serde(default)]
+        pub low_latency: bool,
+        /// LL-HLS blocking playlist reload: wait until the playlist contains this media sequence number.
+        #[serde(rename = "_HLS_msn")]
+        pub hls_msn: Option<u64>,
+        /// LL-HLS blocking playlist reload: wait until the playlist contains this part of the segment in _HLS_msn.
+        #[serde(rename = "_HLS_part")]
+        pub hls_part: Option<usize>,
+    }
+
+    // The query parameters for get_signed_url.
+    #[derive(Debug, Deserialize)]
+    pub struct GetSignedUrlOptions {
+        /// The number of seconds until the signed URL expires.
+        #[serde(default = "default_ttl_seconds")]
+        pub ttl_seconds: u64,
+    }
+
+    fn default_ttl_seconds() -> u64 {
+        3600
+    }
+
+    /// A signed URL for a stream.
+    #[derive(Debug, Deserialize, Serialize, Clone)]
+    pub struct SignedUrlResult {
+        /// Expiration time in seconds since the Unix epoch.
+        pub expires: u64,
+        /// The query parameters to append to any URL for the stream.
+        pub query: String,
+        pub m3u8_url: String,
+        pub mpd_url: String,
+        pub player_url: String,
+    }
+
+    // The query parameters for get_mpd.
+    #[derive(Debug, Deserialize)]
+    pub struct GetMpdOptions {
+        pub begin: Option<DateTime<Utc>>,
+        pub end: Option<DateTime<Utc>>,
     }
 
     #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -298,26 +906,883 @@ mod models {
         pub stream_name: String,
     }
 
+    /// Returns the metadata that applies to the data stream at the offset.
+    /// Returns None if the stream does not have metadata, such as when written by an older version of pravegasink.
+    /// Only the metadata records appended since the previous request for the stream are read.
+    /// This will block the thread.
+    fn get_stream_metadata(
+        client_factory: &ClientFactoryAsync,
+        metadata_cache: &MetadataCache,
+        scope_name: &str,
+        stream_name: &str,
+        offset: u64,
+    ) -> Option<StreamMetadata> {
+        let metadata_searcher = metadata_cache.get(client_factory, scope_name, stream_name)?;
+        let mut metadata_searcher = metadata_searcher.lock().unwrap();
+        if let Err(e) = metadata_searcher.refresh() {
+            warn!("Unable to read metadata stream: {}", e);
+            return None;
+        }
+        let metadata = metadata_searcher.search_offset(offset).map(|record| record.metadata.clone());
+        info!("get_stream_metadata: metadata={:?}", metadata);
+        metadata
+    }
+
+    fn error_response(status: StatusCode, message: String) -> warp::reply::Response {
+        warp::http::Response::builder()
+            .status(status)
+            .body(Body::from(message))
+            .unwrap()
+    }
+
+    /// The location of an event payload in the data stream.
+    pub struct PayloadLocation {
+        pub offset: u64,
+        /// The length of the payload that is sent to the client.
+        /// For encrypted events, this is the length of the plaintext.
+        pub length: u64,
+        /// The length of the payload in the data stream.
+        pub raw_length: u64,
+        pub header: EventHeader,
+        /// The header bytes as they were read, which are the associated data of an encrypted payload.
+        pub header_bytes: Vec<u8>,
+    }
+
+    /// Returns the locations of the payloads of the events between the begin and end offsets.
+    /// Only the event headers are read.
+    /// The end offset must be at an event boundary.
+    fn get_payload_locations<R: Read + Seek>(reader: &mut R, begin: u64, end: u64) -> Result<Vec<PayloadLocation>, std::io::Error> {
+        let mut payload_locations = Vec::new();
+        let mut offset = begin;
+        while offset < end {
+            reader.seek(SeekFrom::Start(offset))?;
+            let mut event_reader = EventReader::new();
+            let required_buffer_length = event_reader.read_required_buffer_length(reader)?;
+            let header = event_reader.read_header(reader)?;
+            let payload_length = event_reader.payload_length();
+            let length = if header.encrypted {
+                // Read the key id length to determine the length of the plaintext.
+                let mut key_id_length = [0; 1];
+                reader.read_exact(&mut key_id_length)?;
+                payload_length.checked_sub(encryption_overhead(key_id_length[0] as usize))
+                    .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData,
+                        format!("Encrypted event at offset {} is too short", offset)))?
+            } else {
+                payload_length
+            };
+            payload_locations.push(PayloadLocation {
+                offset: offset + (required_buffer_length - event_reader.remaining_length()) as u64,
+                length: length as u64,
+                raw_length: payload_length as u64,
+                header,
+                header_bytes: event_reader.header_bytes().to_vec(),
+            });
+            offset += required_buffer_length as u64;
+        }
+        if offset != end {
+            return Err(std::io::Error::new(ErrorKind::InvalidInput,
+                format!("End offset {} is not at an event boundary", end)));
+        }
+        trace!("get_payload_locations: found {} events", payload_locations.len());
+        Ok(payload_locations)
+    }
+
+    /// Send a chunk to the HTTP response body.
+    /// This blocks while the channel is full.
+    /// Returns false if the client has disconnected.
+    fn send_chunk(tx: &mpsc::Sender<Result<Bytes, std::io::Error>>, chunk: Result<Bytes, std::io::Error>) -> bool {
+        if tx.blocking_send(chunk).is_err() {
+            info!("Client disconnected; canceling read");
+            return false;
+        }
+        true
+    }
+
+    /// Read the payload of each event between the begin and end offsets and send it to the HTTP response body.
+    /// If there is no end offset, the events of a single LL-HLS partial segment are sent.
+    /// Encrypted payloads are decrypted.
+    /// This will block the thread.
+    fn send_events<R: Read + Seek>(mut reader: R, begin: u64, end: Option<u64>, part_target_seconds: f64,
+            key_ring: Option<Arc<KeyRing>>, tx: mpsc::Sender<Result<Bytes, std::io::Error>>) {
+        let span = span!(Level::INFO, "send_events: SPAWNED THREAD");
+        span.in_scope(|| {
+            info!("BEGIN");
+            let result = (|| {
+                reader.seek(SeekFrom::Start(begin))?;
+                // Ensure EOF instead of waiting (potentially forever) for appends when we get to the requested end.
+                // Without an end, reads will wait for the partial segment to be written.
+                let mut reader = reader.take(end.map_or(u64::MAX, |end| end - begin));
+                let mut part_builder = PartBuilder::new(begin, part_target_seconds);
+                let mut num_chunks: u64 = 0;
+                loop {
+                    let mut event_reader = EventReader::new();
+                    let required_buffer_length = match event_reader.read_required_buffer_length(&mut reader) {
+                        Ok(n) => n,
+                        Err(e) if e.kind() == ErrorKind::UnexpectedEof && reader.limit() == 0 => {
+                            trace!("Reached requested end");
+                            break;
+                        },
+                        Err(e) => return Err(e),
+                    };
+                    let header = event_reader.read_header(&mut reader)?;
+                    trace!("header={:?}", header);
+                    if end.is_none() && part_builder.add_event(required_buffer_length as u64, &header).is_some() {
+                        trace!("Reached end of partial segment");
+                        break;
+                    }
+                    let mut payload = vec![0; event_reader.payload_length()];
+                    event_reader.read_payload(&mut reader, &mut payload[..])?;
+                    let plaintext = decrypt_payload(key_ring.as_deref(), &header, event_reader.header_bytes(), &mut payload[..])?;
+                    if !send_chunk(&tx, Ok(Bytes::from(payload).slice(plaintext))) {
+                        break;
+                    }
+                    num_chunks += 1;
+                }
+                info!("Sent {} chunks", num_chunks);
+                Ok(())
+            })();
+            if let Err(e) = result {
+                // Sending an error will abort the HTTP response.
+                warn!("Unable to read media segment: {}", e);
+                send_chunk(&tx, Err(e));
+            }
+            info!("END");
+        })
+    }
+
+    /// Send bytes first..end of the concatenated payloads to the HTTP response body.
+    /// Encrypted payloads are read in full and decrypted before the requested bytes are sent.
+    /// This will block the thread.
+    fn send_payload_range<R: Read + Seek>(mut reader: R, payload_locations: Vec<PayloadLocation>, first: u64, end: u64,
+            key_ring: Option<Arc<KeyRing>>, tx: mpsc::Sender<Result<Bytes, std::io::Error>>) {
+        let span = span!(Level::INFO, "send_payload_range: SPAWNED THREAD");
+        span.in_scope(|| {
+            info!("BEGIN");
+            let result = (|| {
+                // The position of the current payload within the concatenated payloads.
+                let mut position: u64 = 0;
+                for location in payload_locations.iter() {
+                    let payload_first = u64::max(first, position);
+                    let payload_end = u64::min(end, position + location.length);
+                    if payload_first < payload_end && location.header.encrypted {
+                        reader.seek(SeekFrom::Start(location.offset))?;
+                        let mut payload = vec![0; location.raw_length as usize];
+                        reader.read_exact(&mut payload[..])?;
+                        let plaintext = decrypt_payload(key_ring.as_deref(), &location.header, &location.header_bytes, &mut payload[..])?;
+                        let chunk_begin = plaintext.start + (payload_first - position) as usize;
+                        let chunk_end = plaintext.start + (payload_end - position) as usize;
+                        if !send_chunk(&tx, Ok(Bytes::from(payload).slice(chunk_begin..chunk_end))) {
+                            break;
+                        }
+                    } else if payload_first < payload_end {
+                        reader.seek(SeekFrom::Start(location.offset + payload_first - position))?;
+                        let mut chunk = vec![0; (payload_end - payload_first) as usize];
+                        reader.read_exact(&mut chunk[..])?;
+                        if !send_chunk(&tx, Ok(Bytes::from(chunk))) {
+                            break;
+                        }
+                    }
+                    position += location.length;
+                    if position >= end {
+                        break;
+                    }
+                }
+                Ok::<_, std::io::Error>(())
+            })();
+            if let Err(e) = result {
+                // Sending an error will abort the HTTP response.
+                warn!("Unable to read media segment: {}", e);
+                send_chunk(&tx, Err(e));
+            }
+            info!("END");
+        })
+    }
+
+    /// An LL-HLS partial segment, consisting of one or more consecutive events in the data stream.
+    #[derive(Debug, Clone)]
+    pub struct Part {
+        pub begin_offset: u64,
+        pub end_offset: u64,
+        pub duration_seconds: f64,
+        /// True if the part begins with a random access point.
+        pub independent: bool,
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct PartStart {
+        offset: u64,
+        timestamp: Option<u64>,
+        independent: bool,
+    }
+
+    /// Groups consecutive events into LL-HLS partial segments.
+    /// A part ends before an event that begins a segment (an event that is included in the index)
+    /// or before the first event that is at least part_target_seconds after the first event of the part.
+    /// Because parts depend only on the events that follow the beginning of the part,
+    /// the same parts are found when reading begins at the beginning of any part.
+    pub struct PartBuilder {
+        part_target_seconds: f64,
+        next_offset: u64,
+        current: Option<PartStart>,
+    }
+
+    impl PartBuilder {
+        pub fn new(offset: u64, part_target_seconds: f64) -> Self {
+            PartBuilder {
+                part_target_seconds,
+                next_offset: offset,
+                current: None,
+            }
+        }
+
+        /// Add the next event in the data stream.
+        /// Returns the completed part if this event begins a new part.
+        pub fn add_event(&mut self, event_length: u64, header: &EventHeader) -> Option<Part> {
+            let offset = self.next_offset;
+            self.next_offset += event_length;
+            let timestamp = header.timestamp.nanoseconds();
+            let completed = match self.current {
+                Some(current) => {
+                    let elapsed_seconds = match (current.timestamp, timestamp) {
+                        (Some(begin), Some(end)) if end >= begin => Some((end - begin) as f64 * 1e-9),
+                        _ => None,
+                    };
+                    if !header.include_in_index && elapsed_seconds.map_or(true, |e| e < self.part_target_seconds) {
+                        return None;
+                    }
+                    Some(Part {
+                        begin_offset: current.offset,
+                        end_offset: offset,
+                        duration_seconds: elapsed_seconds.unwrap_or_default(),
+                        independent: current.independent,
+                    })
+                },
+                None => None,
+            };
+            self.current = Some(PartStart {
+                offset,
+                timestamp,
+                independent: header.random_access,
+            });
+            completed
+        }
+
+        /// Complete the current part at a segment boundary.
+        /// The end timestamp is the timestamp of the first event of the next segment.
+        pub fn finish(self, end_timestamp: PravegaTimestamp) -> Option<Part> {
+            let end_offset = self.next_offset;
+            self.current.map(|current| {
+                let duration_seconds = match (current.timestamp, end_timestamp.nanoseconds()) {
+                    (Some(begin), Some(end)) if end >= begin => (end - begin) as f64 * 1e-9,
+                    _ => 0.0,
+                };
+                Part {
+                    begin_offset: current.offset,
+                    end_offset,
+                    duration_seconds,
+                    independent: current.independent,
+                }
+            })
+        }
+
+        /// Returns the offset at which the next incomplete part begins.
+        pub fn next_part_begin_offset(&self) -> u64 {
+            self.current.map_or(self.next_offset, |current| current.offset)
+        }
+    }
+
+    /// Read the event headers between the begin and end offsets and group the events into parts.
+    /// If the end timestamp is provided, the end offset is a segment boundary and the last part is complete.
+    /// Returns the complete parts and the offset at which the next part begins.
+    fn get_parts<R: Read + Seek>(reader: &mut R, begin: u64, end: u64, end_timestamp: Option<PravegaTimestamp>,
+            part_target_seconds: f64) -> Result<(Vec<Part>, u64), std::io::Error> {
+        let mut parts = Vec::new();
+        let mut part_builder = PartBuilder::new(begin, part_target_seconds);
+        let mut offset = begin;
+        while offset < end {
+            reader.seek(SeekFrom::Start(offset))?;
+            let mut event_reader = EventReader::new();
+            let required_buffer_length = event_reader.read_required_buffer_length(reader)?;
+            let header = event_reader.read_header(reader)?;
+            if let Some(part) = part_builder.add_event(required_buffer_length as u64, &header) {
+                parts.push(part);
+            }
+            offset += required_buffer_length as u64;
+        }
+        let next_part_begin_offset = match end_timestamp {
+            Some(end_timestamp) => {
+                parts.extend(part_builder.finish(end_timestamp));
+                end
+            },
+            None => part_builder.next_part_begin_offset(),
+        };
+        Ok((parts, next_part_begin_offset))
+    }
+
+    /// The next LL-HLS partial segment that will be added to a playlist.
+    #[derive(Debug)]
+    pub struct NextPart {
+        /// The media sequence number of the segment that is being written.
+        pub media_sequence_number: u64,
+        /// The number of the next part within that segment. This is the number of complete parts.
+        pub part_number: usize,
+        /// The tail offset of the data stream when the playlist was generated.
+        pub tail_offset: u64,
+    }
+
+    pub struct M3u8Playlist {
+        pub text: String,
+        pub target_duration_seconds: f64,
+        /// Only provided for LL-HLS playlists that may receive more data.
+        pub next_part: Option<NextPart>,
+    }
+
+    fn push_parts(playlist_body: &mut String, parts: &[Part], query_suffix: &str) {
+        for part in parts {
+            playlist_body.push_str(&format!("#EXT-X-PART:DURATION={:.5},URI=\"media?begin={}&end={}{}\"{}\n",
+                part.duration_seconds, part.begin_offset, part.end_offset, query_suffix,
+                if part.independent { ",INDEPENDENT=YES" } else { "" }));
+        }
+    }
+
+    /// A media segment between two consecutive index records.
+    #[derive(Debug, Clone)]
+    pub struct MediaSegment {
+        /// The media sequence number always equals the index record number, even after truncation.
+        pub media_sequence_number: u64,
+        pub begin_offset: u64,
+        pub end_offset: u64,
+        pub timestamp: PravegaTimestamp,
+        /// The timestamp of the next index record.
+        pub end_timestamp: PravegaTimestamp,
+        pub duration_seconds: f64,
+    }
+
+    /// The interval between two consecutive index records.
+    #[derive(Debug, Clone)]
+    pub enum IndexInterval {
+        Segment(MediaSegment),
+        /// A discontinuity between two index records.
+        /// Any recorded content that falls in this gap may be corrupt so it should not be played.
+        Gap,
+    }
+
+    /// The result of walking the index between two timestamps.
+    /// This is used to generate HLS playlists and DASH manifests.
+    pub struct IndexWalk {
+        pub initial_media_sequence_number: u64,
+        pub intervals: Vec<IndexInterval>,
+        /// An exponential moving average of the segment durations.
+        pub target_duration_seconds: f64,
+        /// True if future appends to the stream cannot change the result.
+        pub have_all_data: bool,
+        /// The last index record read and its record number.
+        pub last_index_record: Option<(u64, IndexRecord)>,
+    }
+
+    impl IndexWalk {
+        pub fn segments(&self) -> impl Iterator<Item = &MediaSegment> {
+            self.intervals.iter().filter_map(|interval| match interval {
+                IndexInterval::Segment(segment) => Some(segment),
+                IndexInterval::Gap => None,
+            })
+        }
+    }
+
+    /// Read the index records between the begin and end timestamps and determine the media segments.
+    /// This will block the thread.
+    fn walk_index(
+        client_factory: &ClientFactoryAsync,
+        index_cache: &IndexCache,
+        scope_name: &str,
+        stream_name: &str,
+        begin_timestamp: PravegaTimestamp,
+        end_timestamp: PravegaTimestamp,
+    ) -> Result<IndexWalk, std::io::Error> {
+        let index_searcher = index_cache.get(client_factory, scope_name, stream_name);
+        let begin_index_record = index_searcher.search_timestamp_and_return_index_offset(
+            begin_timestamp, SearchMethod::After)?;
+        let end_index_record = index_searcher.search_timestamp_and_return_index_offset(
+            end_timestamp, SearchMethod::After)?;
+        // Determine whether we can possibly get more data in the future.
+        // If the caller specified an end time and we already have an index record beyond this, then
+        // future appends will not affect our result.
+        // TODO: We can also guarantee this if the stream has been sealed.
+        let have_all_data = end_index_record.0.timestamp >= end_timestamp;
+        info!("begin_index_record={:?}, end_index_record={:?}, have_all_data={}",
+                begin_index_record, end_index_record, have_all_data);
+
+        // Determine begin and end offsets of the index.
+        let index_begin_offset = begin_index_record.1;
+        let index_end_offset = end_index_record.1 + IndexRecord::RECORD_SIZE as u64;
+        let index_size = index_end_offset - index_begin_offset;
+        info!("index_begin_offset={}, index_end_offset={}, index_size={}", index_begin_offset, index_end_offset, index_size);
+
+        // Records are read from the cache. Only records that were evicted from the cache are read from Pravega.
+        let index_records = index_searcher.get_index_records(index_begin_offset, index_end_offset)?;
+
+        // Media Sequence Number will always equal the stream record number, even after truncation.
+        // Records of audio and other tracks (see IndexRecord::is_stream_record) do not begin media segments
+        // because they may precede the next video key frame. They are not counted.
+        // If the index has only records for the entire stream, the stream record number is the index record number.
+        // Otherwise, it is read from the event of the first stream record. See get_stream_record_number.
+        let initial_media_sequence_number: u64 = match index_records.iter().position(|(record, _)| record.is_stream_record()) {
+            Some(first_position) => {
+                let lookback_begin_offset = index_begin_offset.saturating_sub(
+                    (STREAM_RECORD_NUMBER_LOOKBACK * IndexRecord::RECORD_SIZE) as u64);
+                let mut records = index_searcher.get_index_records(lookback_begin_offset, index_begin_offset)?;
+                let position = records.len() + first_position;
+                records.extend_from_slice(&index_records[..=first_position]);
+                let scoped_stream = ScopedStream {
+                    scope: Scope::from(scope_name.to_owned()),
+                    stream: Stream::from(stream_name.to_owned()),
+                };
+                let reader = client_factory.runtime_handle().block_on(client_factory.create_byte_reader(scoped_stream));
+                let mut data_reader = SyncByteReader::new(reader, client_factory.runtime_handle());
+                get_stream_record_number(&records, position, &mut data_reader)?
+                    .unwrap_or(records[position].1 / IndexRecord::RECORD_SIZE as u64)
+            },
+            None => index_begin_offset / IndexRecord::RECORD_SIZE as u64,
+        };
+        info!("initial_media_sequence_number={}", initial_media_sequence_number);
+
+        // Initial value for target duration. This will be updated with an exponential moving average.
+        let mut target_duration_seconds = 10.0;
+
+        let mut intervals = Vec::new();
+        let mut prev_index_record: Option<IndexRecord> = None;
+        let mut media_sequence_number = initial_media_sequence_number;
+
+        for (index_record, _) in index_records {
+            trace!("index_record={:?}", index_record);
+            if !index_record.is_stream_record() {
+                continue;
+            }
+            if let Some(prev_index_record) = prev_index_record {
+                // If index_record indicates a discontinuity, then assume there is a gap in the data
+                // between the previous record and this one.
+                // It is possible that the duration of the gap in the index is very short or even 0.
+                // However, we still need to count the gap so that the Media Sequence Numbers
+                // correspond to the index offset.
+                // h264parse would add discontinuity flag in each I frames in some conditions. Just simply ignore
+                // discontinuity flag before figure out why.
+
+                let mut discont = false;
+
+                if let Some(timestamp_nanos) = index_record.timestamp.nanoseconds() {
+                    let prev_timestamp_nanos = prev_index_record.timestamp.nanoseconds().unwrap();
+                    if timestamp_nanos < prev_timestamp_nanos {
+                        let rewind_seconds = (prev_timestamp_nanos - timestamp_nanos) as f64 * 1e-9;
+                        warn!("Detected discontinuity; rewind of {:.3} seconds from {} to {}",
+                        rewind_seconds, prev_index_record.timestamp, index_record.timestamp);
+                        discont = true;
+                    } else {
+                        let duration_seconds = (timestamp_nanos - prev_timestamp_nanos) as f64 * 1e-9;
+                        // If the timestamp increased by much more than the target duration,
+                        // then assume we have a discontinuity.
+                        if duration_seconds > target_duration_seconds + 1.0 {
+                            warn!("Detected discontinuity; {:.3} second gap from {} to {}, target_duration_seconds={:.3}",
+                                duration_seconds, prev_index_record.timestamp, index_record.timestamp, target_duration_seconds);
+                            discont = true;
+                        } else {
+                            let ema_alpha = 0.1;
+                            target_duration_seconds = ema_alpha * duration_seconds + (1.0 - ema_alpha) * target_duration_seconds;
+                            intervals.push(IndexInterval::Segment(MediaSegment {
+                                media_sequence_number,
+                                begin_offset: prev_index_record.offset,
+                                end_offset: index_record.offset,
+                                timestamp: prev_index_record.timestamp,
+                                end_timestamp: index_record.timestamp,
+                                duration_seconds,
+                            }));
+                        }
+                    }
+                } else {
+                    warn!("Detected discontinuity; missing timestamp in index at offset {}",
+                        index_record.offset);
+                    discont = true;
+                }
+                if discont {
+                    intervals.push(IndexInterval::Gap);
+                }
+                media_sequence_number += 1;
+            }
+            prev_index_record = Some(index_record);
+        }
+
+        info!("target_duration_seconds={}", target_duration_seconds);
+        Ok(IndexWalk {
+            initial_media_sequence_number,
+            intervals,
+            target_duration_seconds,
+            have_all_data,
+            last_index_record: prev_index_record.map(|record| (media_sequence_number, record)),
+        })
+    }
+
+    /// Generate an HLS playlist from the index.
+    /// If part_target_seconds is provided, a Low-Latency HLS playlist with partial segments is generated.
+    /// The query suffix is appended to each media URI so that signed URLs remain valid for media requests.
+    /// This will block the thread.
+    fn generate_m3u8_playlist(
+        client_factory: &ClientFactoryAsync,
+        index_cache: &IndexCache,
+        scope_name: &str,
+        stream_name: &str,
+        begin_timestamp: PravegaTimestamp,
+        end_timestamp: PravegaTimestamp,
+        part_target_seconds: Option<f64>,
+        query_suffix: &str,
+    ) -> Result<M3u8Playlist, std::io::Error> {
+        let walk = walk_index(client_factory, index_cache, scope_name, stream_name, begin_timestamp, end_timestamp)?;
+
+        // For LL-HLS, partial segments are listed for recent segments, as recommended by the LL-HLS specification.
+        let mut data_reader = part_target_seconds.filter(|_| !walk.have_all_data).map(|_| {
+            let scoped_stream = ScopedStream {
+                scope: Scope::from(scope_name.to_owned()),
+                stream: Stream::from(stream_name.to_owned()),
+            };
+            let reader = client_factory.runtime_handle().block_on(client_factory.create_byte_reader(scoped_stream));
+            SyncByteReader::new(reader, client_factory.runtime_handle())
+        });
+        let mut max_part_duration_seconds: f64 = 0.0;
+        // Partial segments are listed for segments that end in the last 3 target durations.
+        let part_window_begin_nanos = walk.last_index_record.as_ref()
+            .and_then(|(_, record)| record.timestamp.nanoseconds())
+            .map_or(0, |nanos| nanos.saturating_sub((3.0 * walk.target_duration_seconds * 1e9) as u64));
+
+        let mut playlist_body = String::new();
+        let mut next_segment_discont = false;
+
+        for interval in walk.intervals.iter() {
+            match interval {
+                IndexInterval::Segment(segment) => {
+                    if next_segment_discont {
+                        playlist_body.push_str("#EXT-X-DISCONTINUITY\n");
+                        next_segment_discont = false;
+                    }
+                    if let (Some(part_target_seconds), Some(data_reader)) = (part_target_seconds, data_reader.as_mut()) {
+                        if segment.end_timestamp.nanoseconds().unwrap_or_default() >= part_window_begin_nanos {
+                            let (parts, _) = get_parts(data_reader, segment.begin_offset, segment.end_offset,
+                                Some(segment.end_timestamp), part_target_seconds)?;
+                            max_part_duration_seconds = parts.iter().fold(max_part_duration_seconds, |m, p| m.max(p.duration_seconds));
+                            push_parts(&mut playlist_body, &parts, query_suffix);
+                        }
+                    }
+                    // "#EXTINF:10," where 10 is the duration of the segment in seconds
+                    playlist_body.push_str(&format!("#EXTINF:{},\n", segment.duration_seconds));
+                    // "#EXT-X-PROGRAM-DATE-TIME:2010-02-19T14:54:23.123456789Z"
+                    playlist_body.push_str(&format!("#EXT-X-PROGRAM-DATE-TIME:{}\n", segment.timestamp.to_iso_8601().unwrap()));
+                    // "media?begin=0&end=204" where 0 and 204 are the begin and end byte offsets
+                    playlist_body.push_str(&format!("media?begin={}&end={}{}\n", segment.begin_offset, segment.end_offset, query_suffix));
+                },
+                IndexInterval::Gap => {
+                    // Instead of the content in the gap, we'll play a short media segment containing blue video and silent audio.
+                    // The length of this replacement content will be fixed, regardless of the timestamps.
+                    // The EXT-X-GAP tag should be used for this but it doesn't appear to be supported by hls.js.
+                    let gap_content_duration_seconds = 5;
+                    playlist_body.push_str("#EXT-X-DISCONTINUITY\n");
+                    playlist_body.push_str(&format!("#EXTINF:{},\n", gap_content_duration_seconds));
+                    playlist_body.push_str(&format!("/static/gap-{}s.mp4\n", gap_content_duration_seconds));
+                    next_segment_discont = true;
+                },
+            }
+        }
+
+        // For LL-HLS, list the parts of the segment that is being written, after the last index record,
+        // and provide a hint for the next part.
+        let mut next_part = None;
+        if let (Some(part_target_seconds), Some(data_reader), Some((media_sequence_number, last_index_record))) =
+                (part_target_seconds, data_reader.as_mut(), walk.last_index_record.as_ref()) {
+            let tail_offset = data_reader.seek(SeekFrom::End(0))?;
+            let (parts, next_part_begin_offset) = get_parts(data_reader, last_index_record.offset, tail_offset,
+                None, part_target_seconds)?;
+            max_part_duration_seconds = parts.iter().fold(max_part_duration_seconds, |m, p| m.max(p.duration_seconds));
+            if next_segment_discont && !parts.is_empty() {
+                playlist_body.push_str("#EXT-X-DISCONTINUITY\n");
+            }
+            push_parts(&mut playlist_body, &parts, query_suffix);
+            // The preload hint does not have an end offset. The server will respond with the next part when it is written.
+            playlist_body.push_str(&format!("#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"media?begin={}{}\"\n", next_part_begin_offset, query_suffix));
+            next_part = Some(NextPart {
+                media_sequence_number: *media_sequence_number,
+                part_number: parts.len(),
+                tail_offset,
+            });
+        }
+
+        let mut playlist = String::new();
+        let target_duration_seconds = walk.target_duration_seconds.round();
+        match part_target_seconds {
+            None => {
+                playlist.push_str("#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-ALLOW-CACHE:NO\n");
+            },
+            Some(part_target_seconds) => {
+                let part_target_seconds = part_target_seconds.max(max_part_duration_seconds);
+                playlist.push_str("#EXTM3U\n#EXT-X-VERSION:9\n");
+                // PART-HOLD-BACK must be at least 3 times PART-TARGET.
+                playlist.push_str(&format!("#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK={:.3}\n", 3.0 * part_target_seconds));
+                playlist.push_str(&format!("#EXT-X-PART-INF:PART-TARGET={:.3}\n", part_target_seconds));
+            },
+        }
+        playlist.push_str(&format!("#EXT-X-MEDIA-SEQUENCE:{}\n", walk.initial_media_sequence_number));
+        playlist.push_str(&format!("#EXT-X-TARGETDURATION:{}\n", target_duration_seconds));
+        playlist.push_str(&playlist_body);
+
+        // Write ENDLIST if we have all data up to the requested end time.
+        // This will prevent the browser from polling for updated playlists.
+        if walk.have_all_data {
+            playlist.push_str("#EXT-X-ENDLIST\n");
+        }
+        Ok(M3u8Playlist {
+            text: playlist,
+            target_duration_seconds,
+            next_part,
+        })
+    }
+
+    /// Returns the end offset of the events at the beginning of the segment that precede the first media fragment.
+    /// For fragmented MP4, these events contain the ftyp and moov boxes, which form the DASH initialization segment.
+    /// Returns None if the segment does not begin with such events.
+    fn get_initialization_end_offset<R: Read + Seek>(reader: &mut R, begin: u64, end: u64, key_ring: Option<&KeyRing>)
+            -> Result<Option<u64>, std::io::Error> {
+        let mut offset = begin;
+        while offset < end {
+            reader.seek(SeekFrom::Start(offset))?;
+            let mut event_reader = EventReader::new();
+            let required_buffer_length = event_reader.read_required_buffer_length(reader)?;
+            let header = event_reader.read_header(reader)?;
+            // Read the size and type of the first box in the payload.
+            // An encrypted payload must be read in full to decrypt it.
+            let mut box_header = [0; 8];
+            let has_box_header = if header.encrypted {
+                let mut payload = vec![0; event_reader.payload_length()];
+                event_reader.read_payload(reader, &mut payload[..])?;
+                let plaintext = decrypt_payload(key_ring, &header, event_reader.header_bytes(), &mut payload[..])?;
+                if plaintext.len() >= box_header.len() {
+                    box_header.copy_from_slice(&payload[plaintext.start..plaintext.start + box_header.len()]);
+                    true
+                } else {
+                    false
+                }
+            } else if event_reader.payload_length() >= box_header.len() {
+                reader.read_exact(&mut box_header)?;
+                true
+            } else {
+                false
+            };
+            if has_box_header && [b"styp", b"sidx", b"moof"].contains(&&[box_header[4], box_header[5], box_header[6], box_header[7]]) {
+                return Ok(if offset > begin { Some(offset) } else { None });
+            }
+            offset += required_buffer_length as u64;
+        }
+        Ok(None)
+    }
+
+    /// Format a duration as an ISO 8601 duration, as used by DASH.
+    fn format_mpd_duration(seconds: f64) -> String {
+        format!("PT{:.3}S", seconds)
+    }
+
+    /// Generate an MPEG-DASH manifest from the index.
+    /// A new period begins after each gap in the index.
+    /// Each period uses a SegmentList with a SegmentTimeline, with the same media URLs as the HLS playlist.
+    /// If more data may be appended, the manifest is dynamic and players will periodically reload it.
+    /// The query suffix is appended to each media URL.
+    /// This will block the thread.
+    fn generate_mpd(
+        client_factory: &ClientFactoryAsync,
+        index_cache: &IndexCache,
+        metadata_cache: &MetadataCache,
+        initialization_cache: &InitializationCache,
+        key_ring: Option<&KeyRing>,
+        scope_name: &str,
+        stream_name: &str,
+        begin_timestamp: PravegaTimestamp,
+        end_timestamp: PravegaTimestamp,
+        query_suffix: &str,
+    ) -> Result<String, std::io::Error> {
+        let query_suffix = query_suffix.replace('&', "&amp;");
+        // The time unit of the segment timeline.
+        const TIMESCALE: u64 = 1000;
+        let walk = walk_index(client_factory, index_cache, scope_name, stream_name, begin_timestamp, end_timestamp)?;
+        let first_segment = walk.segments().next().cloned();
+        let metadata = get_stream_metadata(client_factory, metadata_cache, scope_name, stream_name,
+            first_segment.as_ref().map_or(0, |segment| segment.begin_offset));
+        let content_type = metadata.as_ref()
+            .and_then(|metadata| metadata.content_type.clone())
+            .unwrap_or_else(|| CONTENT_TYPE_MP4.to_owned());
+        let codecs = metadata.and_then(|metadata| metadata.codecs);
+
+        // Periods begin at the first event with a timestamp in the manifest.
+        let availability_start_timestamp = first_segment.as_ref().map_or(begin_timestamp, |segment| segment.timestamp);
+        let availability_start_nanos = availability_start_timestamp.nanoseconds().unwrap_or_default();
+
+        // Group segments into periods, separated by gaps.
+        let mut periods: Vec<Vec<&MediaSegment>> = vec![Vec::new()];
+        for interval in walk.intervals.iter() {
+            match interval {
+                IndexInterval::Segment(segment) => periods.last_mut().unwrap().push(segment),
+                IndexInterval::Gap => periods.push(Vec::new()),
+            }
+        }
+        periods.retain(|period| !period.is_empty());
+
+        // Estimate the bandwidth from the segment sizes and durations.
+        let (total_bytes, total_seconds) = walk.segments().fold((0, 0.0), |(bytes, seconds), segment| {
+            (bytes + segment.end_offset - segment.begin_offset, seconds + segment.duration_seconds)
+        });
+        let bandwidth = if total_seconds > 0.0 { (total_bytes as f64 * 8.0 / total_seconds).ceil() as u64 } else { 5_000_000 };
+
+        // The initialization segment is at the beginning of each period for fragmented MP4.
+        // The data stream is only read for segments that are not in the initialization cache.
+        let mut data_reader: Option<SyncByteReader> = None;
+        let mut get_cached_initialization_end_offset = |segment: &MediaSegment| -> Result<Option<u64>, std::io::Error> {
+            if let Some(end_offset) = initialization_cache.get(scope_name, stream_name, segment) {
+                return Ok(end_offset);
+            }
+            let data_reader = data_reader.get_or_insert_with(|| {
+                let scoped_stream = ScopedStream {
+                    scope: Scope::from(scope_name.to_owned()),
+                    stream: Stream::from(stream_name.to_owned()),
+                };
+                let reader = client_factory.runtime_handle().block_on(client_factory.create_byte_reader(scoped_stream));
+                SyncByteReader::new(reader, client_factory.runtime_handle())
+            });
+            let end_offset = get_initialization_end_offset(data_reader, segment.begin_offset, segment.end_offset, key_ring)?;
+            initialization_cache.insert(scope_name, stream_name, segment, end_offset);
+            Ok(end_offset)
+        };
+
+        // All segments in the manifest, in order, used to find the initialization segment of each period.
+        let segments: Vec<&MediaSegment> = walk.segments().collect();
+        let mut segment_index = 0;
+        // The index of the first segment of the previous period and its initialization segment.
+        let mut previous_period: Option<(usize, Option<(u64, u64)>)> = None;
+        let mut periods_xml = String::new();
+        for (period_index, period) in periods.iter().enumerate() {
+            let first = period[0];
+            let period_start_seconds = first.timestamp.nanoseconds().unwrap_or_default()
+                .saturating_sub(availability_start_nanos) as f64 * 1e-9;
+            periods_xml.push_str(&format!("  <Period id=\"{}\" start=\"{}\">\n", first.media_sequence_number,
+                format_mpd_duration(period_start_seconds)));
+            periods_xml.push_str(&format!("    <AdaptationSet id=\"0\" mimeType=\"{}\" segmentAlignment=\"true\">\n", content_type));
+            let codecs_attribute = codecs.as_ref().map_or(String::new(), |codecs| format!(" codecs=\"{}\"", codecs));
+            periods_xml.push_str(&format!("      <Representation id=\"{}\" bandwidth=\"{}\"{}>\n", period_index, bandwidth, codecs_attribute));
+            periods_xml.push_str(&format!("        <SegmentList timescale=\"{}\" startNumber=\"{}\">\n", TIMESCALE, first.media_sequence_number));
+            // Media segments of the period begin after the initialization events of the first segment, if any.
+            let mut first_media_offset = first.begin_offset;
+            if content_type == CONTENT_TYPE_MP4 {
+                // If the period does not begin with an initialization segment, use the last one before the period.
+                // Only the segments after the first segment of the previous period are searched because the
+                // segments before it were already searched for the previous period.
+                let search_begin = previous_period.map_or(0, |(index, _)| index + 1);
+                let mut initialization = previous_period.and_then(|(_, initialization)| initialization);
+                for (i, segment) in segments[search_begin..=segment_index].iter().enumerate().rev() {
+                    if let Some(end_offset) = get_cached_initialization_end_offset(*segment)? {
+                        if search_begin + i == segment_index {
+                            first_media_offset = end_offset;
+                        }
+                        initialization = Some((segment.begin_offset, end_offset));
+                        break;
+                    }
+                }
+                previous_period = Some((segment_index, initialization));
+                match initialization {
+                    Some((begin_offset, end_offset)) => {
+                        periods_xml.push_str(&format!("          <Initialization sourceURL=\"media?begin={}&amp;end={}{}\"/>\n",
+                            begin_offset, end_offset, query_suffix));
+                    },
+                    None => warn!("No initialization segment found for period beginning at offset {}", first.begin_offset),
+                }
+            }
+            segment_index += period.len();
+            periods_xml.push_str("          <SegmentTimeline>\n");
+            let period_start_nanos = first.timestamp.nanoseconds().unwrap_or_default();
+            for segment in period.iter() {
+                let t = segment.timestamp.nanoseconds().unwrap_or_default().saturating_sub(period_start_nanos) * TIMESCALE / 1_000_000_000;
+                let d = (segment.duration_seconds * TIMESCALE as f64).round() as u64;
+                periods_xml.push_str(&format!("            <S t=\"{}\" d=\"{}\"/>\n", t, d));
+            }
+            periods_xml.push_str("          </SegmentTimeline>\n");
+            for (i, segment) in period.iter().enumerate() {
+                let begin_offset = if i == 0 { first_media_offset } else { segment.begin_offset };
+                periods_xml.push_str(&format!("          <SegmentURL media=\"media?begin={}&amp;end={}{}\"/>\n",
+                    begin_offset, segment.end_offset, query_suffix));
+            }
+            periods_xml.push_str("        </SegmentList>\n");
+            periods_xml.push_str("      </Representation>\n");
+            periods_xml.push_str("    </AdaptationSet>\n");
+            periods_xml.push_str("  </Period>\n");
+        }
+
+        let presentation_seconds = walk.segments().last().map_or(0.0, |last| {
+            last.end_timestamp.nanoseconds().unwrap_or_default().saturating_sub(availability_start_nanos) as f64 * 1e-9
+        });
+        let mut mpd = String::new();
+        mpd.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        mpd.push_str("<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" profiles=\"urn:mpeg:dash:profile:isoff-live:2011\"");
+        if walk.have_all_data {
+            mpd.push_str(&format!(" type=\"static\" mediaPresentationDuration=\"{}\"", format_mpd_duration(presentation_seconds)));
+        } else {
+            let publish_time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+            mpd.push_str(&format!(" type=\"dynamic\" availabilityStartTime=\"{}\" publishTime=\"{}\"",
+                availability_start_timestamp.to_iso_8601().unwrap_or_else(|| publish_time.clone()), publish_time));
+            mpd.push_str(&format!(" minimumUpdatePeriod=\"{}\" timeShiftBufferDepth=\"{}\"",
+                format_mpd_duration(walk.target_duration_seconds.round()), format_mpd_duration(presentation_seconds)));
+        }
+        mpd.push_str(&format!(" minBufferTime=\"{}\">\n", format_mpd_duration(walk.target_duration_seconds.round())));
+        mpd.push_str(&periods_xml);
+        mpd.push_str("</MPD>\n");
+        Ok(mpd)
+    }
+
+    /// Wait until the tail of the data stream is beyond the given offset or until the deadline.
+    /// This will block the thread.
+    fn wait_for_data(
+        reader: &mut SyncByteReader,
+        tail_offset: u64,
+        deadline: Instant,
+    ) -> Result<(), std::io::Error> {
+        const POLL_INTERVAL: Duration = Duration::from_millis(50);
+        while Instant::now() < deadline {
+            if reader.seek(SeekFrom::End(0))? > tail_offset {
+                break;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        Ok(())
+    }
+
     impl Db {
         pub async fn get_media_segment(
             self,
             scope_name: String,
             stream_name: String,
             opts: GetMediaSegmentOptions,
-        ) -> Result<impl warp::Reply, Infallible> {
-            info!("get_media_segment: scope_name={}, stream_name={}, begin={}, end={}", scope_name, stream_name, opts.begin, opts.end);
-            assert!(opts.begin <= opts.end);
-
-            // TODO: Provide chunks to the HTTP client as a stream instead of buffering the entire response.
+            range: Option<String>,
+        ) -> Result<warp::reply::Response, Infallible> {
+            info!("get_media_segment: scope_name={}, stream_name={}, begin={}, end={:?}, range={:?}",
+                scope_name, stream_name, opts.begin, opts.end, range);
+            if opts.end.map_or(false, |end| opts.begin > end) {
+                return Ok(error_response(StatusCode::BAD_REQUEST, "begin must not exceed end".to_owned()));
+            }
 
             // Use spawn_blocking to allow Pravega non-async methods to block this thread.
             // See https://stackoverflow.com/a/65452213/5890553.
 
-            let chunks = self.client_factory.runtime_handle().spawn_blocking(move || {
+            // Open the reader and, if a range was requested, locate the payloads of all events in the segment.
+            // This reads only the event headers.
+            let (begin, end) = (opts.begin, opts.end);
+            let client_factory = self.client_factory.clone();
+            let metadata_cache = self.metadata_cache.clone();
+            // Ranges of partial segments given by a preload hint are not supported.
+            let range = range.filter(|_| end.is_some());
+            let has_range = range.is_some();
+            let opened = self.client_factory.runtime_handle().spawn_blocking(move || {
                 let span = span!(Level::INFO, "get_media_segment: SPAWNED THREAD");
                 span.in_scope(|| {
-                    info!("BEGIN");
-                    let client_factory = self.client_factory;
+                    let metadata = get_stream_metadata(&client_factory, &metadata_cache, &scope_name, &stream_name, begin);
                     let scoped_stream = ScopedStream {
                         scope: Scope::from(scope_name),
                         stream: Stream::from(stream_name),
@@ -325,53 +1790,76 @@ mod models {
                     let reader = client_factory.runtime_handle().block_on(client_factory.create_byte_reader(scoped_stream));
                     let mut reader = SyncByteReader::new(reader, client_factory.runtime_handle());
                     info!("Opened Pravega reader");
-
-                    reader.seek(SeekFrom::Start(opts.begin)).unwrap();
-                    let limit = opts.end - opts.begin;
-                    let mut reader = reader.take(limit);
-
-                    let mut chunks: Vec<Result<Bytes, std::io::Error>> = Vec::new();
-
-                    loop {
-                        let mut event_reader = EventReader::new();
-                        let required_buffer_length =
-                            match event_reader.read_required_buffer_length(&mut reader) {
-                                Ok(n) => n,
-                                Err(e) if e.kind() == ErrorKind::UnexpectedEof && reader.limit() == 0 => {
-                                    trace!("Reached requested end");
-                                    break;
-                                },
-                                Err(e) => return Err(e),
-                        };
-                        let mut read_buffer: Vec<u8> = vec![0; required_buffer_length];
-                        let event = match event_reader.read_event(&mut reader, &mut read_buffer[..]) {
-                            Ok(n) => n,
-                            Err(e) if e.kind() == ErrorKind::UnexpectedEof && reader.limit() == 0 => {
-                                trace!("Reached requested end");
-                                break;
-                            },
-                            Err(e) => return Err(e),
-                        };
-                        trace!("event={:?}", event);
-                        chunks.push(Ok(Bytes::copy_from_slice(&event.payload)));
-                    }
-                    info!("Created {} chunks", chunks.len());
-                    assert!(reader.limit() == 0);
-                    info!("END");
-                    Ok(chunks)
+                    let payload_locations = match end {
+                        Some(end) if has_range => Some(get_payload_locations(&mut reader, begin, end)?),
+                        _ => None,
+                    };
+                    Ok::<_, std::io::Error>((reader, metadata, payload_locations))
                 })
             })
             .await
-            .unwrap()
             .unwrap();
+            let (reader, metadata, payload_locations) = match opened {
+                Ok(opened) => opened,
+                Err(e) => {
+                    warn!("get_media_segment: unable to read stream: {}", e);
+                    return Ok(error_response(StatusCode::NOT_FOUND, e.to_string()));
+                },
+            };
+
+            // Streams without metadata are assumed to be MP4. "video/mp4" also appears to work for MPEG TS.
+            let content_type = metadata
+                .and_then(|metadata| metadata.content_type)
+                .unwrap_or_else(|| CONTENT_TYPE_MP4.to_owned());
+            let mut response = warp::http::Response::builder()
+                .header("content-type", content_type)
+                .header("accept-ranges", "bytes");
+
+            let (tx, rx) = mpsc::channel(MEDIA_SEGMENT_CHANNEL_CAPACITY);
+            let part_target_seconds = self.part_target_seconds;
+            let key_ring = self.key_ring.clone();
+            match payload_locations {
+                None => {
+                    self.client_factory.runtime_handle().spawn_blocking(move || {
+                        send_events(reader, begin, end, part_target_seconds, key_ring, tx)
+                    });
+                },
+                Some(payload_locations) => {
+                    let length: u64 = payload_locations.iter().map(|location| location.length).sum();
+                    let (range_first, range_end) = match parse_byte_range(range.as_deref().unwrap_or_default(), length) {
+                        ByteRange::Full => {
+                            response = response.header("content-length", length);
+                            (0, length)
+                        },
+                        ByteRange::Partial { first, last } => {
+                            response = response
+                                .status(StatusCode::PARTIAL_CONTENT)
+                                .header("content-range", format!("bytes {}-{}/{}", first, last, length))
+                                .header("content-length", last - first + 1);
+                            (first, last + 1)
+                        },
+                        ByteRange::Unsatisfiable => {
+                            let response = response
+                                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                                .header("content-range", format!("bytes */{}", length))
+                                .body(Body::empty())
+                                .unwrap();
+                            return Ok(response);
+                        },
+                    };
+                    info!("get_media_segment: length={}, range_first={}, range_end={}", length, range_first, range_end);
+                    self.client_factory.runtime_handle().spawn_blocking(move || {
+                        send_payload_range(reader, payload_locations, range_first, range_end, key_ring, tx)
+                    });
+                },
+            }
 
-            trace!("get_media_segment: spawn_blocking done");
-            let stream = futures_util::stream::iter(chunks);
-            let body = Body::wrap_stream(stream);
-            // TODO: Get content type from Pravega stream tag. For now "video/mp4" appears to work for MP4 and MPEG TS.
-            // let content_type = "video/MP2T";
-            let content_type = "video/mp4";
-            Ok(warp::reply::with_header(warp::reply::Response::new(body), "content-type", content_type))
+            // The body ends when the sender is dropped.
+            // If the client disconnects, the receiver is dropped and the sender stops reading from Pravega.
+            let stream = futures::stream::unfold(rx, |mut rx| async move {
+                rx.recv().await.map(|chunk| (chunk, rx))
+            });
+            Ok(response.body(Body::wrap_stream(stream)).unwrap())
         }
 
         pub async fn get_m3u8_playlist(
@@ -379,168 +1867,183 @@ mod models {
             scope_name: String,
             stream_name: String,
             opts: GetM3u8PlaylistOptions,
-        ) -> anyhow::Result<String> {
+            query_suffix: String,
+        ) -> anyhow::Result<warp::reply::Response> {
 
-            info!("get_m3u8_playlist: BEGIN: scope_name={}, stream_name={}, begin={:?}, end={:?}", scope_name, stream_name, opts.begin, opts.end);
+            info!("get_m3u8_playlist: BEGIN: scope_name={}, stream_name={}, begin={:?}, end={:?}, low_latency={}, hls_msn={:?}, hls_part={:?}",
+                scope_name, stream_name, opts.begin, opts.end, opts.low_latency, opts.hls_msn, opts.hls_part);
 
-            let index_stream_name = get_index_stream_name(&stream_name);
             let begin_timestamp = PravegaTimestamp::from(opts.begin).or(PravegaTimestamp::MIN);
             let end_timestamp = PravegaTimestamp::from(opts.end).or(PravegaTimestamp::MAX);
             info!("get_m3u8_playlist: begin_timestamp={}, end_timestamp={}", begin_timestamp, end_timestamp);
             assert!(begin_timestamp <= end_timestamp);
+            let part_target_seconds = if opts.low_latency { Some(self.part_target_seconds) } else { None };
 
             // Use spawn_blocking to allow Pravega non-async methods to block this thread.
             // See https://stackoverflow.com/a/65452213/5890553.
 
+            // The result is the playlist, or an error message if the requested segment is too far in the future.
             let playlist = tokio::task::spawn_blocking(move || {
                 let span = span!(Level::INFO, "get_m3u8_playlist: SPAWNED THREAD");
-                span.in_scope(|| {
+                span.in_scope(|| -> Result<Result<String, String>, std::io::Error> {
                     info!("BEGIN");
                     let client_factory = self.client_factory;
-                    let scoped_stream = ScopedStream {
-                        scope: Scope::from(scope_name),
-                        stream: Stream::from(index_stream_name),
-                    };
-                    let index_reader = client_factory.runtime_handle().block_on(client_factory.create_byte_reader(scoped_stream));
-                    let index_reader = SyncByteReader::new(index_reader, client_factory.runtime_handle());
-                    info!("Opened Pravega reader");
-
-                    let mut index_searcher = IndexSearcher::new(index_reader);
-                    let begin_index_record = index_searcher.search_timestamp_and_return_index_offset(
-                        begin_timestamp, SearchMethod::After)?;
-                    let end_index_record = index_searcher.search_timestamp_and_return_index_offset(
-                        end_timestamp, SearchMethod::After)?;
-                    // Determine whether we can possibly get more data in the future.
-                    // If the caller specified an end time and we already have an index record beyond this, then
-                    // future appends will not affect our result.
-                    // TODO: We can also guarantee this if the stream has been sealed.
-                    let have_all_data = end_index_record.0.timestamp >= end_timestamp;
-                    info!("begin_index_record={:?}, end_index_record={:?}, have_all_data={}",
-                            begin_index_record, end_index_record, have_all_data);
-                    let mut index_reader = index_searcher.into_inner();
-
-                    // Determine begin and end offsets of the index.
-                    let index_begin_offset = begin_index_record.1;
-                    let index_end_offset = end_index_record.1 + IndexRecord::RECORD_SIZE as u64;
-                    let index_size = index_end_offset - index_begin_offset;
-                    info!("index_begin_offset={}, index_end_offset={}, index_size={}", index_begin_offset, index_end_offset, index_size);
-
-                    // Position index reader at current beginning of the index.
-                    index_reader.seek(SeekFrom::Start(index_begin_offset)).unwrap();
-
-                    // Ensure EOF instead of waiting (potentially forever) for appends when we get to the current end.
-                    let mut index_reader = index_reader.take(index_size);
-
-                    // Media Sequence Number will always equal the index record number, even after truncation.
-                    let initial_media_sequence_number: u64 = index_begin_offset / IndexRecord::RECORD_SIZE as u64;
-                    info!("initial_media_sequence_number={}", initial_media_sequence_number);
-
-                    // Initial value for target duration. This will be updated with an exponential moving average, then rounded.
-                    let mut target_duration_seconds = 10.0;
-
-                    let mut playlist_body = String::new();
-                    let mut prev_index_record: Option<IndexRecord> = None;
-                    let mut next_segment_discont = false;
-
+                    let started = Instant::now();
+                    // Opened on the first wait and reused until the requested segment or part is available.
+                    let mut data_reader: Option<SyncByteReader> = None;
                     loop {
-                        let mut index_record_reader = IndexRecordReader::new();
-                        let index_record = match index_record_reader.read(&mut index_reader) {
-                            Ok(n) => n,
-                            Err(e) if e.kind() == ErrorKind::UnexpectedEof && index_reader.limit() == 0 => {
-                                trace!("Reached requested end");
-                                break;
-                            },
-                            Err(e) => return Err(e),
+                        let playlist = generate_m3u8_playlist(&client_factory, &self.index_cache, &scope_name, &stream_name,
+                            begin_timestamp, end_timestamp, part_target_seconds, &query_suffix)?;
+                        // Blocking playlist reload (LL-HLS): wait until the requested segment or part is available.
+                        let (hls_msn, next_part) = match (opts.hls_msn, &playlist.next_part) {
+                            (Some(hls_msn), Some(next_part)) => (hls_msn, next_part),
+                            _ => return Ok(Ok(playlist.text)),
+                        };
+                        let available = match opts.hls_part {
+                            Some(hls_part) => next_part.media_sequence_number > hls_msn
+                                || (next_part.media_sequence_number == hls_msn && next_part.part_number > hls_part),
+                            None => next_part.media_sequence_number > hls_msn,
                         };
-                        trace!("index_record={:?}", index_record);
-                        if let Some(prev_index_record) = prev_index_record {
-                            // If index_record indicates a discontinuity, then assume there is a gap in the data
-                            // between the previous record and this one.
-                            // Any recorded content that falls in this gap may be corrupt so we will not display it.
-                            // Instead, we'll play a short media segment containing blue video and silent audio.
-                            // The length of this replacement content will be fixed, regardless of the timestamps.
-                            // The EXT-X-GAP tag should be used for this but it doesn't appear to be supported by hls.js.
-                            // It is possible that the duration of the gap in the index is very short or even 0.
-                            // However, we still need to count the gap so that the Media Sequence Numbers
-                            // correspond to the index offset.
-                            // h264parse would add discontinuity flag in each I frames in some conditions. Just simply ignore
-                            // discontinuity flag before figure out why.
-
-                            let mut discont = false;
-                           
-                            if let Some(timestamp_nanos) = index_record.timestamp.nanoseconds() {
-                                let prev_timestamp_nanos = prev_index_record.timestamp.nanoseconds().unwrap();
-                                if timestamp_nanos < prev_timestamp_nanos {
-                                    let rewind_seconds = (prev_timestamp_nanos - timestamp_nanos) as f64 * 1e-9;
-                                    warn!("Detected discontinuity; rewind of {:.3} seconds from {} to {}",
-                                    rewind_seconds, prev_index_record.timestamp, index_record.timestamp);
-                                    discont = true;
-                                } else {
-                                    let duration_seconds = (timestamp_nanos - prev_timestamp_nanos) as f64 * 1e-9;
-                                    // If the timestamp increased by much more than the target duration,
-                                    // then assume we have a discontinuity.
-                                    if duration_seconds > target_duration_seconds + 1.0 {
-                                        warn!("Detected discontinuity; {:.3} second gap from {} to {}, target_duration_seconds={:.3}",
-                                            duration_seconds, prev_index_record.timestamp, index_record.timestamp, target_duration_seconds);
-                                        discont = true;
-                                    } else {
-                                        if next_segment_discont {
-                                            playlist_body.push_str("#EXT-X-DISCONTINUITY\n");
-                                            next_segment_discont = false;
-                                        }
-                                        let ema_alpha = 0.1;
-                                        target_duration_seconds = ema_alpha * duration_seconds + (1.0 - ema_alpha) * target_duration_seconds;
-                                        let begin_offset = prev_index_record.offset;
-                                        let end_offset = index_record.offset;
-                                        // "#EXTINF:10," where 10 is the duration of the segment in seconds
-                                        playlist_body.push_str(&format!("#EXTINF:{},\n", duration_seconds));
-                                        // "#EXT-X-PROGRAM-DATE-TIME:2010-02-19T14:54:23.123456789Z"
-                                        playlist_body.push_str(&format!("#EXT-X-PROGRAM-DATE-TIME:{}\n", prev_index_record.timestamp.to_iso_8601().unwrap()));
-                                        // "media?begin=0&end=204" where 0 and 204 are the begin and end byte offsets
-                                        playlist_body.push_str(&format!("media?begin={}&end={}\n", begin_offset, end_offset));
-                                    }
-                                }
-                            } else {
-                                warn!("Detected discontinuity; missing timestamp in index at offset {}",
-                                    index_record.offset);
-                                discont = true;
-                            }
-                            if discont {
-                                // warn!("Detected discontinuity; index_record={:?}", index_record);
-                                let gap_content_duration_seconds = 5;
-                                playlist_body.push_str("#EXT-X-DISCONTINUITY\n");
-                                playlist_body.push_str(&format!("#EXTINF:{},\n", gap_content_duration_seconds));
-                                playlist_body.push_str(&format!("/static/gap-{}s.mp4\n", gap_content_duration_seconds));
-                                next_segment_discont = true;
-                            }
+                        if available {
+                            return Ok(Ok(playlist.text));
                         }
-                        prev_index_record = Some(index_record);
-                    }
-
-                    let mut playlist = String::new();
-                    let target_duration_seconds = target_duration_seconds.round();
-                    info!("target_duration_seconds={}", target_duration_seconds);
-                    playlist.push_str("#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-ALLOW-CACHE:NO\n");
-                    playlist.push_str(&format!("#EXT-X-MEDIA-SEQUENCE:{}\n", initial_media_sequence_number));
-                    playlist.push_str(&format!("#EXT-X-TARGETDURATION:{}\n", target_duration_seconds));
-                    playlist.push_str(&playlist_body);
-
-                    // Write ENDLIST if we have all data up to the requested end time.
-                    // This will prevent the browser from polling for updated playlists.
-                    if have_all_data {
-                        playlist.push_str("#EXT-X-ENDLIST\n");
+                        // LL-HLS requires a request for a segment more than two segments in the future to be rejected.
+                        if hls_msn > next_part.media_sequence_number + 2 {
+                            warn!("Requested segment is too far in the future; hls_msn={}, hls_part={:?}, next_part={:?}",
+                                hls_msn, opts.hls_part, next_part);
+                            return Ok(Err(format!("_HLS_msn {} is more than two segments after the next segment {}",
+                                hls_msn, next_part.media_sequence_number)));
+                        }
+                        let timeout = Duration::from_secs_f64(3.0 * playlist.target_duration_seconds);
+                        if started.elapsed() > timeout {
+                            warn!("Unable to provide requested segment; hls_msn={}, hls_part={:?}, next_part={:?}",
+                                hls_msn, opts.hls_part, next_part);
+                            return Ok(Ok(playlist.text));
+                        }
+                        let reader = data_reader.get_or_insert_with(|| {
+                            let scoped_stream = ScopedStream {
+                                scope: Scope::from(scope_name.clone()),
+                                stream: Stream::from(stream_name.clone()),
+                            };
+                            let reader = client_factory.runtime_handle().block_on(client_factory.create_byte_reader(scoped_stream));
+                            SyncByteReader::new(reader, client_factory.runtime_handle())
+                        });
+                        wait_for_data(reader, next_part.tail_offset, started + timeout)?;
                     }
-                    info!("END");
-                    Ok(playlist)
                 })
             })
             .await??;
             trace!("get_m3u8_playlist: spawn_blocking done");
+            let playlist = match playlist {
+                Ok(playlist) => playlist,
+                Err(message) => return Ok(error_response(StatusCode::BAD_REQUEST, message)),
+            };
             trace!("get_m3u8_playlist: playlist={}", playlist);
             info!("get_m3u8_playlist: END");
+            Ok(warp::http::Response::builder()
+                .header("content-type", "application/x-mpegURL")
+                .body(Body::from(playlist))
+                .unwrap())
+        }
+
+        pub async fn get_master_m3u8_playlist(
+            self,
+            scope_name: String,
+            stream_name: String,
+            opts: GetM3u8PlaylistOptions,
+            query_suffix: String,
+        ) -> anyhow::Result<String> {
+
+            info!("get_master_m3u8_playlist: BEGIN: scope_name={}, stream_name={}, begin={:?}, end={:?}", scope_name, stream_name, opts.begin, opts.end);
+
+            // Used when the bandwidth cannot be estimated from the index.
+            const DEFAULT_BANDWIDTH: u64 = 5_000_000;
+
+            let (bandwidth, metadata) = tokio::task::spawn_blocking(move || {
+                let span = span!(Level::INFO, "get_master_m3u8_playlist: SPAWNED THREAD");
+                span.in_scope(|| {
+                    info!("BEGIN");
+                    let client_factory = self.client_factory;
+                    let metadata = get_stream_metadata(&client_factory, &self.metadata_cache, &scope_name, &stream_name, u64::MAX);
+                    let index_searcher = self.index_cache.get(&client_factory, &scope_name, &stream_name);
+
+                    // Estimate the average bandwidth from the first and last index records.
+                    let first_record = index_searcher.get_first_record()?;
+                    let last_record = index_searcher.get_last_record()?;
+                    let bandwidth = match (first_record.timestamp.nanoseconds(), last_record.timestamp.nanoseconds()) {
+                        (Some(first), Some(last)) if last > first && last_record.offset > first_record.offset => {
+                            let duration_seconds = (last - first) as f64 * 1e-9;
+                            ((last_record.offset - first_record.offset) as f64 * 8.0 / duration_seconds).ceil() as u64
+                        },
+                        _ => DEFAULT_BANDWIDTH,
+                    };
+                    info!("first_record={:?}, last_record={:?}, bandwidth={}", first_record, last_record, bandwidth);
+                    info!("END");
+                    Ok::<_, std::io::Error>((bandwidth, metadata))
+                })
+            })
+            .await??;
+
+            let mut query = Vec::new();
+            if let Some(begin) = opts.begin {
+                query.push(format!("begin={}", begin.to_rfc3339_opts(SecondsFormat::AutoSi, true)));
+            }
+            if let Some(end) = opts.end {
+                query.push(format!("end={}", end.to_rfc3339_opts(SecondsFormat::AutoSi, true)));
+            }
+            if opts.low_latency {
+                query.push("low_latency=true".to_owned());
+            }
+            if !query_suffix.is_empty() {
+                query.push(query_suffix.trim_start_matches('&').to_owned());
+            }
+            let mut uri = "m3u8".to_owned();
+            if !query.is_empty() {
+                uri.push('?');
+                uri.push_str(&query.join("&"));
+            }
+
+            let mut stream_inf = format!("#EXT-X-STREAM-INF:BANDWIDTH={}", bandwidth);
+            if let Some(codecs) = metadata.and_then(|metadata| metadata.codecs) {
+                stream_inf.push_str(&format!(",CODECS=\"{}\"", codecs));
+            }
+            let playlist = format!("#EXTM3U\n#EXT-X-VERSION:3\n{}\n{}\n", stream_inf, uri);
+            trace!("get_master_m3u8_playlist: playlist={}", playlist);
+            info!("get_master_m3u8_playlist: END");
             Ok(playlist)
         }
 
+        pub async fn get_mpd(
+            self,
+            scope_name: String,
+            stream_name: String,
+            opts: GetMpdOptions,
+            query_suffix: String,
+        ) -> anyhow::Result<String> {
+
+            info!("get_mpd: BEGIN: scope_name={}, stream_name={}, begin={:?}, end={:?}", scope_name, stream_name, opts.begin, opts.end);
+            let begin_timestamp = PravegaTimestamp::from(opts.begin).or(PravegaTimestamp::MIN);
+            let end_timestamp = PravegaTimestamp::from(opts.end).or(PravegaTimestamp::MAX);
+            anyhow::ensure!(begin_timestamp <= end_timestamp, "begin must not exceed end");
+
+            // Use spawn_blocking to allow Pravega non-async methods to block this thread.
+            // See https://stackoverflow.com/a/65452213/5890553.
+
+            let mpd = tokio::task::spawn_blocking(move || {
+                let span = span!(Level::INFO, "get_mpd: SPAWNED THREAD");
+                span.in_scope(|| {
+                    info!("BEGIN");
+                    let mpd = generate_mpd(&self.client_factory, &self.index_cache, &self.metadata_cache, &self.initialization_cache, self.key_ring.as_deref(), &scope_name, &stream_name, begin_timestamp, end_timestamp, &query_suffix);
+                    info!("END");
+                    mpd
+                })
+            })
+            .await??;
+            trace!("get_mpd: mpd={}", mpd);
+            info!("get_mpd: END");
+            Ok(mpd)
+        }
+
         pub async fn list_scopes(
             self
         ) -> anyhow::Result<ListScopesResult> {
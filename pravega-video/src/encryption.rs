@@ -0,0 +1,228 @@
+//
+// Copyright (c) Dell Inc., or its subsidiaries. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+
+//! Encryption of event payloads with AES-256-GCM.
+//!
+//! An encrypted payload has the following layout:
+//!
+//! ```text
+//! +----------------+----------------------+----------------+-------------------------+---------------+
+//! | key_id_length  | key_id               | nonce          | ciphertext              | tag           |
+//! | (8-bit uint)   | (key_id_length bytes)| (12 bytes)     | (same length as input)  | (16 bytes)    |
+//! +----------------+----------------------+----------------+-------------------------+---------------+
+//! ```
+//!
+//! The event header (bytes 8..20 of the frame) is authenticated as associated data,
+//! so the timestamp and flags of an encrypted event cannot be modified without detection.
+//!
+//! Keys are identified by a key id, which is stored in each encrypted payload.
+//! A [KeyRing] encrypts with its active key and decrypts with any of its keys.
+//! To rotate keys, add a new key as the active key and keep the old keys for as long as
+//! events encrypted with them must remain readable.
+
+use crate::utils::decode_hex;
+use aes_gcm::Aes256Gcm;
+use aes_gcm::aead::{AeadCore, AeadInPlace, KeyInit, Nonce, OsRng, Tag};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::ops::Range;
+use std::path::Path;
+
+pub const KEY_SIZE: usize = 32;
+pub const NONCE_SIZE: usize = 12;
+pub const TAG_SIZE: usize = 16;
+pub const MAX_KEY_ID_LENGTH: usize = 255;
+
+/// Returns the number of bytes added to a payload when it is encrypted with a key id of the given length.
+pub fn encryption_overhead(key_id_length: usize) -> usize {
+    1 + key_id_length + NONCE_SIZE + TAG_SIZE
+}
+
+/// A set of AES-256 keys, identified by key id.
+///
+/// Keys are parsed from text with one `key_id:hex_key` entry per line or separated by commas,
+/// where hex_key is 64 hexadecimal digits. Lines beginning with `#` are ignored.
+/// The first entry is the active key, used for encryption.
+#[derive(Clone)]
+pub struct KeyRing {
+    active_key_id: String,
+    keys: HashMap<String, Aes256Gcm>,
+}
+
+impl KeyRing {
+    pub fn parse(text: &str) -> Result<KeyRing> {
+        let mut active_key_id: Option<String> = None;
+        let mut keys = HashMap::new();
+        let entries = text.lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .flat_map(|line| line.split(','))
+            .map(|entry| entry.trim())
+            .filter(|entry| !entry.is_empty());
+        for entry in entries {
+            let (key_id, hex_key) = entry.split_once(':')
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Encryption key must be in the format key_id:hex_key"))?;
+            let key_id = key_id.trim();
+            if key_id.is_empty() || key_id.len() > MAX_KEY_ID_LENGTH {
+                return Err(Error::new(ErrorKind::InvalidData,
+                    format!("Encryption key id must have between 1 and {} bytes", MAX_KEY_ID_LENGTH)));
+            }
+            let key = decode_hex(hex_key.trim())
+                .filter(|key| key.len() == KEY_SIZE)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                    format!("Encryption key {} must be {} hexadecimal digits", key_id, 2 * KEY_SIZE)))?;
+            let cipher = Aes256Gcm::new_from_slice(&key).unwrap();
+            if keys.insert(key_id.to_owned(), cipher).is_some() {
+                return Err(Error::new(ErrorKind::InvalidData, format!("Duplicate encryption key id {}", key_id)));
+            }
+            active_key_id.get_or_insert_with(|| key_id.to_owned());
+        }
+        let active_key_id = active_key_id.ok_or_else(|| Error::new(ErrorKind::InvalidData, "No encryption keys"))?;
+        Ok(KeyRing { active_key_id, keys })
+    }
+
+    pub fn from_file(path: &Path) -> Result<KeyRing> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| Error::new(err.kind(), format!("Unable to read encryption key file {}: {}", path.display(), err)))?;
+        KeyRing::parse(&text)
+    }
+
+    /// Read the keys from an environment variable.
+    pub fn from_env(name: &str) -> Result<KeyRing> {
+        let text = std::env::var(name)
+            .map_err(|err| Error::new(ErrorKind::NotFound, format!("Unable to read environment variable {}: {}", name, err)))?;
+        KeyRing::parse(&text)
+    }
+
+    /// Read the keys from a file or an environment variable, whichever is specified.
+    /// Returns None if neither is specified.
+    pub fn load(key_file: Option<&str>, key_env: Option<&str>) -> Result<Option<KeyRing>> {
+        match (key_file, key_env) {
+            (Some(_), Some(_)) => Err(Error::new(ErrorKind::InvalidInput,
+                "Only one of an encryption key file or environment variable may be specified")),
+            (Some(key_file), None) => Ok(Some(KeyRing::from_file(Path::new(key_file))?)),
+            (None, Some(key_env)) => Ok(Some(KeyRing::from_env(key_env)?)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    pub fn active_key_id(&self) -> &str {
+        &self.active_key_id
+    }
+
+    /// The number of bytes added to a payload when it is encrypted with the active key.
+    pub fn overhead(&self) -> usize {
+        encryption_overhead(self.active_key_id.len())
+    }
+
+    /// Encrypt a payload with the active key and a random nonce.
+    /// The associated data is authenticated but not included in the result.
+    pub fn encrypt(&self, associated_data: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let cipher = &self.keys[&self.active_key_id];
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let key_id = self.active_key_id.as_bytes();
+        let prefix_length = 1 + key_id.len() + NONCE_SIZE;
+        let mut payload = Vec::with_capacity(plaintext.len() + self.overhead());
+        payload.push(key_id.len() as u8);
+        payload.extend_from_slice(key_id);
+        payload.extend_from_slice(&nonce);
+        payload.extend_from_slice(plaintext);
+        let tag = cipher.encrypt_in_place_detached(&nonce, associated_data, &mut payload[prefix_length..])
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "Unable to encrypt payload"))?;
+        payload.extend_from_slice(&tag);
+        Ok(payload)
+    }
+
+    /// Decrypt a payload that was produced by [KeyRing::encrypt], using the key identified in the payload.
+    /// The plaintext replaces the ciphertext in the payload buffer and the returned range locates it.
+    pub fn decrypt_in_place(&self, associated_data: &[u8], payload: &mut [u8]) -> Result<Range<usize>> {
+        let key_id_length = *payload.first()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Encrypted payload is empty"))? as usize;
+        if payload.len() < encryption_overhead(key_id_length) {
+            return Err(Error::new(ErrorKind::InvalidData, "Encrypted payload is too short"));
+        }
+        let key_id = String::from_utf8_lossy(&payload[1..1 + key_id_length]).into_owned();
+        let cipher = self.keys.get(&key_id)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Unknown encryption key id {}", key_id)))?;
+        let nonce_begin = 1 + key_id_length;
+        let ciphertext = nonce_begin + NONCE_SIZE..payload.len() - TAG_SIZE;
+        let nonce = Nonce::<Aes256Gcm>::clone_from_slice(&payload[nonce_begin..ciphertext.start]);
+        let tag = Tag::<Aes256Gcm>::clone_from_slice(&payload[ciphertext.end..]);
+        cipher.decrypt_in_place_detached(&nonce, associated_data, &mut payload[ciphertext.clone()], &tag)
+            .map_err(|_| Error::new(ErrorKind::InvalidData,
+                format!("Unable to decrypt payload with encryption key id {}", key_id)))?;
+        Ok(ciphertext)
+    }
+}
+
+/// Keys are not included.
+impl fmt::Debug for KeyRing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut key_ids: Vec<&String> = self.keys.keys().collect();
+        key_ids.sort();
+        f.debug_struct("KeyRing")
+            .field("active_key_id", &self.active_key_id)
+            .field("key_ids", &key_ids)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY1: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const KEY2: &str = "ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100";
+
+    #[test]
+    fn test_key_ring_parse() {
+        let key_ring = KeyRing::parse(&format!("# Keys\nkey2:{}\nkey1:{}\n", KEY2, KEY1)).unwrap();
+        assert_eq!(key_ring.active_key_id(), "key2");
+        assert_eq!(key_ring.overhead(), 1 + 4 + NONCE_SIZE + TAG_SIZE);
+        let key_ring = KeyRing::parse(&format!("key1:{}, key2:{}", KEY1, KEY2)).unwrap();
+        assert_eq!(key_ring.active_key_id(), "key1");
+        assert!(KeyRing::parse("").is_err());
+        assert!(KeyRing::parse(KEY1).is_err());
+        assert!(KeyRing::parse("key1:0011").is_err());
+        assert!(KeyRing::parse(&format!("key1:{}", &KEY1.replace('0', "x"))).is_err());
+        assert!(KeyRing::parse(&format!("key1:{},key1:{}", KEY1, KEY2)).is_err());
+        assert!(!format!("{:?}", key_ring).contains(KEY1));
+    }
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let old_key_ring = KeyRing::parse(&format!("key1:{}", KEY1)).unwrap();
+        let new_key_ring = KeyRing::parse(&format!("key2:{},key1:{}", KEY2, KEY1)).unwrap();
+        let plaintext = b"hello world";
+        let associated_data = b"header";
+
+        let mut payload = old_key_ring.encrypt(associated_data, plaintext).unwrap();
+        assert_eq!(payload.len(), plaintext.len() + old_key_ring.overhead());
+        assert_ne!(&payload[payload.len() - TAG_SIZE - plaintext.len()..payload.len() - TAG_SIZE], &plaintext[..]);
+        // The new key ring can read events written with the old key.
+        let mut copy = payload.clone();
+        let range = new_key_ring.decrypt_in_place(associated_data, &mut copy).unwrap();
+        assert_eq!(&copy[range], &plaintext[..]);
+        let range = old_key_ring.decrypt_in_place(associated_data, &mut payload).unwrap();
+        assert_eq!(&payload[range], &plaintext[..]);
+
+        // The old key ring cannot read events written with the new key.
+        let mut payload = new_key_ring.encrypt(associated_data, plaintext).unwrap();
+        assert_eq!(old_key_ring.decrypt_in_place(associated_data, &mut payload.clone()).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        // Modified associated data or ciphertext is detected.
+        assert!(new_key_ring.decrypt_in_place(b"HEADER", &mut payload.clone()).is_err());
+        let last = payload.len() - TAG_SIZE - 1;
+        payload[last] ^= 1;
+        assert!(new_key_ring.decrypt_in_place(associated_data, &mut payload).is_err());
+        assert!(new_key_ring.decrypt_in_place(associated_data, &mut [4, b'k']).is_err());
+    }
+}
@@ -11,8 +11,12 @@
 // Module for serialization of events for writing to a Pravega byte stream.
 
 use std::convert::TryInto;
+use std::fmt;
 use std::io::{Error, ErrorKind, Read, Write};
+use std::ops::Range;
+use std::sync::Arc;
 use enumflags2::BitFlags;
+use crate::encryption::KeyRing;
 use crate::timestamp::PravegaTimestamp;
 
 #[derive(BitFlags, Copy, Clone, Debug, PartialEq)]
@@ -23,12 +27,154 @@ pub(crate) enum EventHeaderFlags {
     DiscontinuityIndicator = 0b00000100,
 }
 
+/// Flags in byte 10 of the frame. Readers that predate these flags ignore this byte.
+#[derive(BitFlags, Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub(crate) enum EventHeaderExtensionFlags {
+    Encrypted              = 0b00000001,
+    Checksum               = 0b00000010,
+    MoreFragments          = 0b00000100,
+    Continuation           = 0b00001000,
+}
+
+/// The header version in byte 8 of the frame for events without an extended header.
+const HEADER_VERSION_LEGACY: u8 = 0;
+/// The header version in byte 8 of the frame for events with the version 1 extended header.
+const HEADER_VERSION_EXTENDED: u8 = 1;
+/// The header version in byte 8 of the frame for events with the version 2 extended header.
+const HEADER_VERSION_STREAM_RECORD_NUMBER: u8 = 2;
+/// The length of the version 1 extended header.
+const EXTENDED_HEADER_LENGTH: usize = 20;
+/// The length of the version 2 extended header, which adds the stream record number.
+const EXTENDED_HEADER_LENGTH_V2: usize = 28;
+
+/// Additional buffer metadata stored in the extended header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EventHeaderExtension {
+    /// The decoding timestamp, in the same time base as the timestamp. It is 0 (None) if unknown.
+    pub dts: PravegaTimestamp,
+    /// The duration in nanoseconds, or None if unknown.
+    pub duration: Option<u64>,
+    /// The bits of the GStreamer buffer flags (gst::BufferFlags).
+    pub buffer_flags: u32,
+    /// The number of the first index record for the entire stream or a video track that refers to this event.
+    /// See [crate::index::get_stream_record_number]. If set, the version 2 extended header is written.
+    pub stream_record_number: Option<u64>,
+}
+
+impl Default for EventHeaderExtension {
+    fn default() -> Self {
+        EventHeaderExtension {
+            dts: PravegaTimestamp::NONE,
+            duration: None,
+            buffer_flags: 0,
+            stream_record_number: None,
+        }
+    }
+}
+
+impl EventHeaderExtension {
+    /// The header version that is written for this extension.
+    fn version(&self) -> u8 {
+        if self.stream_record_number.is_some() { HEADER_VERSION_STREAM_RECORD_NUMBER } else { HEADER_VERSION_EXTENDED }
+    }
+
+    /// The number of bytes that are written for this extension.
+    fn length(&self) -> usize {
+        if self.stream_record_number.is_some() { EXTENDED_HEADER_LENGTH_V2 } else { EXTENDED_HEADER_LENGTH }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; self.length()];
+        bytes[0..8].copy_from_slice(&self.dts.nanoseconds().unwrap_or_default().to_be_bytes()[..]);
+        bytes[8..16].copy_from_slice(&self.duration.unwrap_or(u64::MAX).to_be_bytes()[..]);
+        bytes[16..20].copy_from_slice(&self.buffer_flags.to_be_bytes()[..]);
+        if let Some(stream_record_number) = self.stream_record_number {
+            bytes[20..28].copy_from_slice(&stream_record_number.to_be_bytes()[..]);
+        }
+        bytes
+    }
+
+    // Parses a version 1 or 2 extended header. Any additional bytes written by a later version are ignored.
+    fn from_bytes(version: u8, bytes: &[u8]) -> Self {
+        let dts = u64::from_be_bytes(bytes[0..8].try_into().unwrap());
+        let duration = u64::from_be_bytes(bytes[8..16].try_into().unwrap());
+        let buffer_flags = u32::from_be_bytes(bytes[16..20].try_into().unwrap());
+        let stream_record_number = if version >= HEADER_VERSION_STREAM_RECORD_NUMBER {
+            let stream_record_number = u64::from_be_bytes(bytes[20..28].try_into().unwrap());
+            if stream_record_number == u64::MAX { None } else { Some(stream_record_number) }
+        } else {
+            None
+        };
+        EventHeaderExtension {
+            dts: PravegaTimestamp::from_nanoseconds(if dts == 0 { None } else { Some(dts) }),
+            duration: if duration == u64::MAX { None } else { Some(duration) },
+            buffer_flags,
+            stream_record_number,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct EventHeader {
     pub timestamp: PravegaTimestamp,
     pub include_in_index: bool,
     pub random_access: bool,
     pub discontinuity: bool,
+    pub encrypted: bool,
+    /// True if the type code contains a CRC32C checksum.
+    pub checksum: bool,
+    /// True if the payload of the next event continues the same buffer.
+    pub more_fragments: bool,
+    /// True if the payload continues the buffer of the previous event.
+    pub continuation: bool,
+    /// The extended header. This is None for legacy events.
+    pub extension: Option<EventHeaderExtension>,
+}
+
+impl EventHeader {
+    /// Serialize to bytes 8 through the end of the header of the frame (reserved, timestamp, and extended header).
+    /// When the payload is encrypted, these bytes are the associated data.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = BitFlags::<EventHeaderFlags>::empty();
+        if self.include_in_index {
+            flags |= EventHeaderFlags::IncludeInIndex;
+        }
+        if self.random_access {
+            flags |= EventHeaderFlags::RandomAccessIndicator;
+        }
+        if self.discontinuity {
+            flags |= EventHeaderFlags::DiscontinuityIndicator;
+        }
+        let mut extension_flags = BitFlags::<EventHeaderExtensionFlags>::empty();
+        if self.encrypted {
+            extension_flags |= EventHeaderExtensionFlags::Encrypted;
+        }
+        if self.checksum {
+            extension_flags |= EventHeaderExtensionFlags::Checksum;
+        }
+        if self.more_fragments {
+            extension_flags |= EventHeaderExtensionFlags::MoreFragments;
+        }
+        if self.continuation {
+            extension_flags |= EventHeaderExtensionFlags::Continuation;
+        }
+        let mut bytes = vec![0; self.length()];
+        if let Some(extension) = self.extension {
+            bytes[0] = extension.version();
+            bytes[1] = extension.length() as u8;
+            bytes[12..12+extension.length()].copy_from_slice(&extension.to_bytes()[..]);
+        }
+        bytes[2] = extension_flags.bits();
+        bytes[3] = flags.bits();
+        bytes[4..12].copy_from_slice(&self.timestamp.nanoseconds().unwrap_or_default().to_be_bytes()[..]);
+        bytes
+    }
+
+    /// The number of bytes from reserved through the end of the header.
+    pub fn length(&self) -> usize {
+        12 + self.extension.map_or(0, |extension| extension.length())
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -37,6 +183,35 @@ pub struct EventWithHeader<'a> {
     pub payload: &'a [u8],
 }
 
+/// The error contained in an [std::io::Error] of kind InvalidData when the checksum of an event does not match its contents.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChecksumError {
+    /// The checksum stored in the event.
+    pub expected: u32,
+    /// The checksum calculated from the header and payload that were read.
+    pub actual: u32,
+}
+
+impl fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Checksum mismatch: expected {:08x}, calculated {:08x}", self.expected, self.actual)
+    }
+}
+
+impl std::error::Error for ChecksumError {}
+
+/// Returns true if the error was caused by a checksum mismatch.
+/// When this is returned by [EventReader::read_payload] or [EventReader::read_event],
+/// the entire event has been read and the reader is positioned at the next event.
+pub fn is_checksum_error(err: &Error) -> bool {
+    err.get_ref().map_or(false, |inner| inner.is::<ChecksumError>())
+}
+
+/// Calculate the checksum of an event from bytes 8 through the end of the payload.
+fn calculate_checksum(header_bytes: &[u8], payload: &[u8]) -> u32 {
+    crc32c::crc32c_append(crc32c::crc32c(header_bytes), payload)
+}
+
 /// ```text
 /**
    A struct to serialize an EventWithHeader for writing to a Pravega byte stream.
@@ -47,16 +222,16 @@ pub struct EventWithHeader<'a> {
     0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
    |                                                               |
-   |          type_code (32-bit BE signed int, set to 0)           |
+   |     type_code (32-bit BE signed int) or checksum (32-bit BE)  |
    |                                                               |
    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
    |            event_length (32-bit BE unsigned int)              |
    |    number of bytes from reserved to the end of the payload    |
    |                                                               |
    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
-   |                                                         |D|R|I|
-   |                    reserved (set to 0)                  |I|A|N|
-   |                                                         |S|N|D|
+   |    header     |   extended    |       |C|M|C|E|           |D|R|I|
+   |    version    | header length |  res. |O|O|H|N|  reserved |I|A|N|
+   |  (8-bit uint) | (8-bit uint)  |       |N|R|K|C|           |S|N|D|
    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
    |                                                               |
    |                                                               |
@@ -67,6 +242,10 @@ pub struct EventWithHeader<'a> {
    |                                                               |
    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
    |                                                               |
+   |       extended header (extended header length bytes)          |
+   |                                                               |
+   +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+   |                                                               |
    |                    payload (variable length)                  |
    |                                                               |
    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
@@ -74,11 +253,18 @@ pub struct EventWithHeader<'a> {
    One tick mark represents one bit position.
 
    type code:
-      The type code must be 0 which corresponds to pravega_wire_protocol::wire_commands::EventCommand.TYPE_CODE.
+      If CHK is false, the type code must be 0 which corresponds to pravega_wire_protocol::wire_commands::EventCommand.TYPE_CODE.
       This makes this byte stream compatible with a Pravega event stream reader.
+      If CHK is true, this is the checksum instead.
    event length:
       This is number of bytes from reserved to the end of the payload.
       Encoded as a 32-bit big-endian unsigned int.
+   header version:
+      0 for events without an extended header, which must have an extended header length of 0.
+      1 or 2 for events with the extended header defined below.
+   extended header length:
+      The number of bytes in the extended header. This is 20 for version 1 and 28 for version 2.
+      Readers skip bytes of later versions that they do not understand.
    reserved:
       All reserved bits must be 0.
       These may be utilized in the future for other purposes.
@@ -91,6 +277,25 @@ pub struct EventWithHeader<'a> {
       This is also known as IDR (Instantaneous Decoder Refresh).
       Usually, MPEG I-frames will have a true value for this field and all
       other events will have a false value.
+   ENC - encrypted:
+      True (1) if the payload is encrypted. See encryption.rs for the format of the encrypted payload.
+      The bytes from header version through the end of the extended header are authenticated as associated data.
+      Readers that predate this flag will return the encrypted payload.
+   CHK - checksum:
+      True (1) if the type code contains a checksum.
+      The checksum is the CRC32C (Castagnoli) of the bytes from header version through the end of the payload.
+      If the payload is encrypted, this is calculated from the encrypted payload.
+      All readers, including those that predate this flag, ignore the type code, so they return the payload unchanged.
+      An event with a checksum cannot be read by a Pravega event stream reader.
+   MOR - more fragments:
+      True (1) if the buffer was too large for a single event and the payload continues in the next event.
+   CON - continuation:
+      True (1) if the payload continues the buffer of the previous event.
+      The payloads of an event with MOR and the following events through the first without MOR
+      are concatenated to form the original buffer.
+      A buffer is incomplete if an event with MOR is not followed by an event with CON.
+      This can happen if the writer terminates ungracefully. Incomplete buffers should be dropped.
+      Readers that predate these flags will return each fragment as a separate buffer.
    IND - include in index:
       If true (1), this event should be included in the index.
       Typically, this will equal random_access but it is possible
@@ -108,6 +313,16 @@ pub struct EventWithHeader<'a> {
       For video frames, the timestamp will reflect when the image was captured by the camera.
       If DTS can differ from PTS, this timestamp should be the PTS.
       This allows different streams to be correlated precisely.
+   extended header:
+      Present only if header version is 1 or greater.
+      Readers that predate the extended header will return it as the first bytes of the payload.
+      Version 1 contains the following, each encoded as big-endian unsigned ints:
+        dts (64-bit): The decoding timestamp, in the same time base as timestamp, or 0 if unknown.
+        duration (64-bit): The duration in nanoseconds, or 2^64-1 if unknown.
+        buffer flags (32-bit): The bits of the GStreamer buffer flags (GstBufferFlags), such as HEADER, GAP, and DELTA_UNIT.
+      Version 2 adds the following:
+        stream record number (64-bit): The number of the first index record for the entire stream or a video track
+          that refers to this event, or 2^64-1 if unknown. See get_stream_record_number in index.rs.
    payload:
       Can be 0 or more MPEG TS packets, or any other payload.
       When encoding an MPEG transport stream, this is currently a single 188-byte MPEG TS packet.
@@ -116,52 +331,131 @@ pub struct EventWithHeader<'a> {
 */
 /// ```
 pub struct EventWriter {
+    // If set, payloads are encrypted with the active key.
+    key_ring: Option<Arc<KeyRing>>,
+    // If true, a checksum is written in the type code.
+    checksum: bool,
+    // If true, the extended header is written.
+    extended_header: bool,
 }
 
 impl EventWriter {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            key_ring: None,
+            checksum: false,
+            extended_header: false,
+        }
+    }
+
+    /// Create a writer that encrypts payloads with the active key of the key ring.
+    pub fn with_key_ring(key_ring: Arc<KeyRing>) -> Self {
+        Self {
+            key_ring: Some(key_ring),
+            checksum: false,
+            extended_header: false,
+        }
+    }
+
+    /// Enable or disable writing the extended header.
+    /// When enabled, the extension of each event is written, or a default extension if the event has none.
+    /// When disabled, the extension of each event is ignored.
+    pub fn with_extended_header(self, extended_header: bool) -> Self {
+        Self {
+            extended_header,
+            ..self
+        }
+    }
+
+    /// Enable or disable writing a CRC32C checksum in the type code.
+    pub fn with_checksum(self, checksum: bool) -> Self {
+        Self {
+            checksum,
+            ..self
+        }
+    }
+
+    /// The maximum size of a payload passed to write().
+    /// This is smaller when payloads are encrypted or have an extended header.
+    /// It allows for the longest extended header so that it does not depend on the extension of the event.
+    pub fn max_payload_size(&self) -> usize {
+        EventWithHeader::MAX_PAYLOAD_SIZE
+            - self.key_ring.as_ref().map_or(0, |key_ring| key_ring.overhead())
+            - if self.extended_header { EXTENDED_HEADER_LENGTH_V2 } else { 0 }
     }
 
     pub fn write<'a, W>(&mut self, event: &EventWithHeader<'a>, writer: &mut W) -> Result<(), Error>
     where
         W: Write,
     {
-        let mut flags = BitFlags::<EventHeaderFlags>::empty();
-        if event.header.include_in_index {
-            flags |= EventHeaderFlags::IncludeInIndex;
-        }
-        if event.header.random_access {
-            flags |= EventHeaderFlags::RandomAccessIndicator;
-        }
-        if event.header.discontinuity {
-            flags |= EventHeaderFlags::DiscontinuityIndicator;
-        }
         let payload_length = event.payload.len();
-        if payload_length > EventWithHeader::MAX_PAYLOAD_SIZE {
+        if payload_length > self.max_payload_size() {
             return Err(Error::new(ErrorKind::InvalidInput, format!("Payload of {} bytes exceeds {} bytes",
-                payload_length, EventWithHeader::MAX_PAYLOAD_SIZE)));
+                payload_length, self.max_payload_size())));
         }
-        let event_length: u32 = (payload_length + 12).try_into().unwrap();
-        let write_length = payload_length + 20;
+        let extension = if self.extended_header {
+            Some(event.header.extension.unwrap_or_default())
+        } else {
+            None
+        };
+        let header = EventHeader {
+            encrypted: self.key_ring.is_some(),
+            checksum: self.checksum,
+            extension,
+            ..event.header
+        };
+        let header_bytes = header.to_bytes();
+        let header_length = header_bytes.len();
+        let encrypted_payload = match self.key_ring.as_ref() {
+            Some(key_ring) => Some(key_ring.encrypt(&header_bytes, event.payload)?),
+            None => None,
+        };
+        let payload = encrypted_payload.as_deref().unwrap_or(event.payload);
+        let payload_length = payload.len();
+        let event_length: u32 = (header_length + payload_length).try_into().unwrap();
+        let write_length = 8 + header_length + payload_length;
+        let payload_offset = 8 + header_length;
         let mut bytes_to_write: Vec<u8> = vec![0; write_length];
         bytes_to_write[4..8].copy_from_slice(&event_length.to_be_bytes()[..]);
-        bytes_to_write[11..12].copy_from_slice(&flags.bits().to_be_bytes()[..]);
-        bytes_to_write[12..20].copy_from_slice(&event.header.timestamp.nanoseconds().unwrap_or_default().to_be_bytes()[..]);
-        bytes_to_write[20..20+payload_length].copy_from_slice(&event.payload[..]);
+        bytes_to_write[8..payload_offset].copy_from_slice(&header_bytes[..]);
+        bytes_to_write[payload_offset..payload_offset+payload_length].copy_from_slice(payload);
+        if self.checksum {
+            let checksum = calculate_checksum(&header_bytes[..], payload);
+            bytes_to_write[0..4].copy_from_slice(&checksum.to_be_bytes()[..]);
+        }
         writer.write_all(&bytes_to_write).unwrap();
         Ok(())
     }
 }
 
+/// Decrypt the payload of an event in place if it is encrypted.
+/// The header bytes are the associated data and must be the bytes that were read, from [EventReader::header_bytes].
+/// They are not rebuilt from the header because a later header version may have written bytes that this version does not parse.
+/// Returns the range of the payload buffer that contains the plaintext.
+/// An error is returned if the payload is encrypted and there is no key ring or the key ring does not have the key.
+pub fn decrypt_payload(key_ring: Option<&KeyRing>, header: &EventHeader, header_bytes: &[u8], payload: &mut [u8]) -> Result<Range<usize>, Error> {
+    if !header.encrypted {
+        return Ok(0..payload.len());
+    }
+    match key_ring {
+        Some(key_ring) => key_ring.decrypt_in_place(header_bytes, payload),
+        None => Err(Error::new(ErrorKind::InvalidData, "Payload is encrypted but no encryption keys were provided")),
+    }
+}
+
 pub struct EventReader {
     // This is a copy of the first 8 bytes of the serialized EventWithHeader.
-    // This currently contains only the event length but the unused bits may be used in the future.
+    // This contains the type code or checksum, and the event length.
     event_length_bytes: [u8; 8],
     // The number of bytes that follow the event length.
     event_length: usize,
     // The minimum buffer size required to read the entire EventWithHeader.
     required_buffer_length: usize,
+    // Bytes 8 through the end of the header of the serialized EventWithHeader, including the extended header.
+    // This is used to verify the checksum and is the associated data of an encrypted payload.
+    header_bytes: Vec<u8>,
+    // The checksum in the type code, if the header has been read and has a checksum.
+    checksum: Option<u32>,
 }
 
 // A struct to deserialize an EventWithHeader that was written to a Pravega byte stream.
@@ -171,6 +465,8 @@ impl EventReader {
             event_length_bytes: [0; 8],
             event_length: 0,
             required_buffer_length: 0,
+            header_bytes: Vec::new(),
+            checksum: None,
         }
     }
 
@@ -180,6 +476,8 @@ impl EventReader {
     where
         R: Read,
     {
+        self.header_bytes.clear();
+        self.checksum = None;
         rdr.read_exact(&mut self.event_length_bytes[0..8])?;
         let event_length_bytes: [u8; 4] = self.event_length_bytes[4..8].try_into().unwrap();
         self.event_length = u32::from_be_bytes(event_length_bytes) as usize;
@@ -203,23 +501,138 @@ impl EventReader {
         }
         //  Note that bytes 0..8 of buffer are unused. However, this keeps the byte ranges consistent with the writer.
         rdr.read_exact(&mut buffer[8..self.required_buffer_length])?;
-        let flags = BitFlags::<EventHeaderFlags>::from_bits(buffer[11]).unwrap();
+        self.header_bytes = buffer[8..20].to_vec();
+        let extension_length = self.check_extension_length()?;
+        self.header_bytes.extend_from_slice(&buffer[20..20+extension_length]);
+        let header = self.parse_and_check_header()?;
+        let payload_length = self.payload_length();
+        let payload_offset = 8 + self.header_bytes.len();
+        let payload = &buffer[payload_offset..payload_offset+payload_length];
+        self.verify_checksum(payload)?;
+        Ok(EventWithHeader {
+            header,
+            payload,
+        })
+    }
+
+    // Reads the rest of the EventHeader but not the payload.
+    // This must be called after read_required_buffer_length() has been called to determine the event length.
+    // The reader must be positioned at the byte immediatley after event_length.
+    // Returns the header. The caller should then allocate a buffer of payload_length() bytes and call read_payload().
+    // This allows the payload to be read directly into its final destination without an intermediate copy.
+    pub fn read_header<R>(&mut self, rdr: &mut R) -> Result<EventHeader, Error>
+    where
+        R: Read,
+    {
+        self.header_bytes.resize(12, 0);
+        rdr.read_exact(&mut self.header_bytes[..])?;
+        let extension_length = self.check_extension_length()?;
+        self.header_bytes.resize(12 + extension_length, 0);
+        rdr.read_exact(&mut self.header_bytes[12..])?;
+        self.parse_and_check_header()
+    }
+
+    // Reads the payload into the buffer, which must have a length of exactly payload_length().
+    // If the event has a checksum, it is verified. If it does not match, the buffer contains the payload
+    // that was read and an error is returned for which is_checksum_error() is true.
+    // This must be called after read_header().
+    pub fn read_payload<R>(&mut self, rdr: &mut R, buffer: &mut [u8]) -> Result<(), Error>
+    where
+        R: Read,
+    {
+        if buffer.len() != self.payload_length() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Buffer length {} does not equal payload length {}",
+                buffer.len(), self.payload_length())))
+        }
+        rdr.read_exact(buffer)?;
+        self.verify_checksum(buffer)
+    }
+
+    // The length of the payload of the event.
+    // This is valid after read_required_buffer_length() has been called.
+    // It does not include the extended header, if any, once the header has been read.
+    pub fn payload_length(&self) -> usize {
+        self.remaining_length()
+    }
+
+    // Bytes 8 through the end of the header exactly as they were read, including any extended header bytes
+    // written by a later header version.
+    // This is valid after read_header() or read_event() has been called.
+    // Pass this to decrypt_payload() as the associated data.
+    pub fn header_bytes(&self) -> &[u8] {
+        &self.header_bytes[..]
+    }
+
+    // The number of bytes from the end of the header to the end of the event.
+    // A reader that does not need the payload can skip this many bytes after read_header().
+    pub fn remaining_length(&self) -> usize {
+        self.event_length - usize::max(12, self.header_bytes.len())
+    }
+
+    // Returns the length of the extended header from the first 12 bytes of the header.
+    fn check_extension_length(&self) -> Result<usize, Error> {
+        let version = self.header_bytes[0];
+        let extension_length = self.header_bytes[1] as usize;
+        let valid = match version {
+            HEADER_VERSION_LEGACY => extension_length == 0,
+            HEADER_VERSION_EXTENDED => extension_length >= EXTENDED_HEADER_LENGTH,
+            _ => extension_length >= EXTENDED_HEADER_LENGTH_V2,
+        };
+        if !valid || self.event_length < 12 + extension_length {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Invalid extended header length {} for header version {} and event length {}",
+                extension_length, version, self.event_length)))
+        }
+        Ok(extension_length)
+    }
+
+    // Parses the header and gets the checksum, if any, from the type code.
+    fn parse_and_check_header(&mut self) -> Result<EventHeader, Error> {
+        let header = EventReader::parse_header(&self.header_bytes);
+        if header.checksum {
+            self.checksum = Some(u32::from_be_bytes(self.event_length_bytes[0..4].try_into().unwrap()));
+        }
+        Ok(header)
+    }
+
+    fn verify_checksum(&self, payload: &[u8]) -> Result<(), Error> {
+        if let Some(expected) = self.checksum {
+            let actual = calculate_checksum(&self.header_bytes[..], payload);
+            if actual != expected {
+                return Err(Error::new(ErrorKind::InvalidData, ChecksumError { expected, actual }))
+            }
+        }
+        Ok(())
+    }
+
+    // Parses bytes 8 through the end of the header of the serialized EventWithHeader (reserved, timestamp, and extended header).
+    fn parse_header(header_bytes: &[u8]) -> EventHeader {
+        let flags = BitFlags::<EventHeaderFlags>::from_bits(header_bytes[3]).unwrap();
         let include_in_index = flags.contains(EventHeaderFlags::IncludeInIndex);
         let random_access = flags.contains(EventHeaderFlags::RandomAccessIndicator);
         let discontinuity = flags.contains(EventHeaderFlags::DiscontinuityIndicator);
-        let timestamp = u64::from_be_bytes(buffer[12..20].try_into().unwrap());
+        let extension_flags = BitFlags::<EventHeaderExtensionFlags>::from_bits_truncate(header_bytes[2]);
+        let encrypted = extension_flags.contains(EventHeaderExtensionFlags::Encrypted);
+        let checksum = extension_flags.contains(EventHeaderExtensionFlags::Checksum);
+        let more_fragments = extension_flags.contains(EventHeaderExtensionFlags::MoreFragments);
+        let continuation = extension_flags.contains(EventHeaderExtensionFlags::Continuation);
+        let timestamp = u64::from_be_bytes(header_bytes[4..12].try_into().unwrap());
         let timestamp = if timestamp == 0 { None } else { Some(timestamp) };
-        let payload_length = self.event_length - 12;
-        let payload = &buffer[20..20+payload_length];
-        Ok(EventWithHeader {
-            header: EventHeader {
-                timestamp: PravegaTimestamp::from_nanoseconds(timestamp),
-                include_in_index,
-                random_access,
-                discontinuity,
-            },
-            payload,
-        })
+        let extension = if header_bytes[0] >= HEADER_VERSION_EXTENDED {
+            Some(EventHeaderExtension::from_bytes(header_bytes[0], &header_bytes[12..]))
+        } else {
+            None
+        };
+        EventHeader {
+            timestamp: PravegaTimestamp::from_nanoseconds(timestamp),
+            include_in_index,
+            random_access,
+            discontinuity,
+            encrypted,
+            checksum,
+            more_fragments,
+            continuation,
+            extension,
+        }
     }
 }
 
@@ -237,11 +650,40 @@ impl<'a> EventWithHeader<'a> {
                 include_in_index,
                 random_access,
                 discontinuity,
+                encrypted: false,
+                checksum: false,
+                more_fragments: false,
+                continuation: false,
+                extension: None,
             },
             payload: payload,
         }
     }
 
+    /// Set the extension that is written by an [EventWriter] with the extended header enabled.
+    pub fn with_extension(self, extension: EventHeaderExtension) -> Self {
+        Self {
+            header: EventHeader {
+                extension: Some(extension),
+                ..self.header
+            },
+            ..self
+        }
+    }
+
+    /// Mark this event as a fragment of a buffer that was split into multiple events.
+    /// `more_fragments` is true for all but the last fragment and `continuation` is true for all but the first.
+    pub fn with_fragment(self, more_fragments: bool, continuation: bool) -> Self {
+        Self {
+            header: EventHeader {
+                more_fragments,
+                continuation,
+                ..self.header
+            },
+            ..self
+        }
+    }
+
     pub fn max_payload_size() -> usize {
         EventWithHeader::MAX_PAYLOAD_SIZE
     }
@@ -249,12 +691,80 @@ impl<'a> EventWithHeader<'a> {
 
 #[cfg(test)]
 mod test {
-    use crate::event_serde::{EventWithHeader, EventWriter, EventReader};
+    use crate::encryption::KeyRing;
+    use crate::event_serde::{EventHeader, EventHeaderExtension, EventHeaderFlags, EventWithHeader, EventWriter, EventReader,
+        decrypt_payload, is_checksum_error};
     use crate::timestamp::PravegaTimestamp;
+    use enumflags2::BitFlags;
+    use std::convert::TryInto;
     use tracing::{info, trace};
     use rand::{RngCore, SeedableRng};
     use rand_chacha::ChaCha8Rng;
-    use std::io::{Cursor, ErrorKind};
+    use std::io::{Cursor, Error, ErrorKind};
+    use std::sync::Arc;
+
+    const TEST_KEY: &str = "key1:000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    fn test_key_ring() -> Arc<KeyRing> {
+        Arc::new(KeyRing::parse(TEST_KEY).unwrap())
+    }
+
+    /// Write each event with its writer and return the data positioned at the beginning.
+    fn write_events(events: Vec<(EventWriter, &EventWithHeader)>) -> Cursor<Vec<u8>> {
+        let mut data = Cursor::new(Vec::new());
+        for (mut event_writer, event) in events {
+            event_writer.write(event, &mut data).unwrap();
+        }
+        data.set_position(0);
+        data
+    }
+
+    /// An event read with read_header and read_payload. The payload is as stored, which may be encrypted.
+    struct ReadEvent {
+        header: EventHeader,
+        header_bytes: Vec<u8>,
+        payload: Vec<u8>,
+    }
+
+    impl ReadEvent {
+        fn plaintext(&self, key_ring: Option<&KeyRing>) -> Result<Vec<u8>, Error> {
+            let mut payload = self.payload.clone();
+            let range = decrypt_payload(key_ring, &self.header, &self.header_bytes, &mut payload[..])?;
+            Ok(payload[range].to_vec())
+        }
+    }
+
+    /// Read all events from the current position to the end of the data.
+    fn read_all_events(data: &mut Cursor<Vec<u8>>) -> Vec<ReadEvent> {
+        let mut read_events = Vec::new();
+        while (data.position() as usize) < data.get_ref().len() {
+            let mut event_reader = EventReader::new();
+            event_reader.read_required_buffer_length(data).unwrap();
+            let header = event_reader.read_header(data).unwrap();
+            let mut payload = vec![0; event_reader.payload_length()];
+            event_reader.read_payload(data, &mut payload[..]).unwrap();
+            read_events.push(ReadEvent {
+                header,
+                header_bytes: event_reader.header_bytes().to_vec(),
+                payload,
+            });
+        }
+        read_events
+    }
+
+    /// Read an event as a reader that predates the header version and the extended header flags.
+    /// It ignores the type code and bytes 8 through 10, and returns all bytes after the timestamp as the payload.
+    fn read_baseline_layout(bytes: &[u8]) -> EventWithHeader {
+        let event_length = u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as usize;
+        assert!(event_length >= 12 && 8 + event_length <= EventWithHeader::MAX_ATOMIC_WRITE_SIZE);
+        let flags = BitFlags::<EventHeaderFlags>::from_bits(bytes[11]).unwrap();
+        let timestamp = u64::from_be_bytes(bytes[12..20].try_into().unwrap());
+        EventWithHeader::new(&bytes[20..8+event_length],
+            PravegaTimestamp::from_nanoseconds(if timestamp == 0 { None } else { Some(timestamp) }),
+            flags.contains(EventHeaderFlags::IncludeInIndex),
+            flags.contains(EventHeaderFlags::RandomAccessIndicator),
+            flags.contains(EventHeaderFlags::DiscontinuityIndicator))
+    }
 
     #[test]
     fn test_event_writer_reader() {
@@ -288,7 +798,247 @@ mod test {
                 let deserialized_event = event_reader.read_event(&mut serialized_bytes_cursor, &mut read_buffer[..]).unwrap();
                 trace!("deserialized_event={:?}", deserialized_event);
                 assert_eq!(event, deserialized_event);
+                // Read event header and payload separately
+                serialized_bytes_cursor.set_position(0);
+                let mut event_reader = EventReader::new();
+                event_reader.read_required_buffer_length(&mut serialized_bytes_cursor).unwrap();
+                let header = event_reader.read_header(&mut serialized_bytes_cursor).unwrap();
+                assert_eq!(event.header, header);
+                assert_eq!(event_reader.payload_length(), payload.len());
+                let mut too_small_buffer: Vec<u8> = vec![0; payload.len() / 2];
+                if too_small_buffer.len() != payload.len() {
+                    let result = event_reader.read_payload(&mut serialized_bytes_cursor, &mut too_small_buffer[..]).map_err(|e| e.kind());
+                    assert_eq!(result, Err(ErrorKind::InvalidInput));
+                }
+                let mut payload_buffer: Vec<u8> = vec![0; event_reader.payload_length()];
+                event_reader.read_payload(&mut serialized_bytes_cursor, &mut payload_buffer[..]).unwrap();
+                assert_eq!(payload, payload_buffer);
+            }
+        }
+    }
+
+    #[test]
+    fn test_encrypted_event_writer_reader() {
+        let old_key_ring = test_key_ring();
+        let key2 = "key2:ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100";
+        let new_key_ring = Arc::new(KeyRing::parse(&format!("{},{}", key2, TEST_KEY)).unwrap());
+        let payload = b"hello world".to_vec();
+        let event = EventWithHeader::new(&payload[..], PravegaTimestamp::from_nanoseconds(Some(1000)), true, true, false);
+        let mut data = write_events(vec![
+            (EventWriter::with_key_ring(old_key_ring.clone()), &event),
+            (EventWriter::with_key_ring(new_key_ring.clone()), &event),
+            (EventWriter::new(), &event),
+        ]);
+        assert_eq!(EventWriter::with_key_ring(old_key_ring.clone()).max_payload_size(),
+            EventWithHeader::max_payload_size() - old_key_ring.overhead());
+        let read_events = read_all_events(&mut data);
+        assert_eq!(read_events.iter().map(|e| e.header.encrypted).collect::<Vec<_>>(), vec![true, true, false]);
+        for read_event in read_events.iter() {
+            assert_eq!(read_event.header.timestamp, event.header.timestamp);
+            assert_eq!(read_event.header.random_access, event.header.random_access);
+            if read_event.header.encrypted {
+                assert_ne!(read_event.payload, payload);
+                assert_eq!(read_event.plaintext(None).map_err(|e| e.kind()), Err(ErrorKind::InvalidData));
             }
+            // The new key ring can read events written with both keys.
+            assert_eq!(read_event.plaintext(Some(&new_key_ring)).unwrap(), payload);
+        }
+    }
+
+    #[test]
+    fn test_checksum_event_writer_reader() {
+        let key_ring = test_key_ring();
+        let payload = b"hello world".to_vec();
+        let event = EventWithHeader::new(&payload[..], PravegaTimestamp::from_nanoseconds(Some(1000)), true, true, false);
+        let mut data = write_events(vec![
+            (EventWriter::new().with_checksum(true), &event),
+            (EventWriter::with_key_ring(key_ring.clone()).with_checksum(true), &event),
+            (EventWriter::new(), &event),
+        ]);
+        // The checksum is in the type code, so it does not change the length of the event.
+        let event_size = 20 + payload.len();
+        assert_ne!(&data.get_ref()[0..4], &[0, 0, 0, 0]);
+        assert_eq!(&data.get_ref()[4..10], &[0, 0, 0, (event_size - 8) as u8, 0, 0]);
+        assert_eq!(&data.get_ref()[2 * event_size + key_ring.overhead()..][0..4], &[0, 0, 0, 0]);
+        assert_eq!(data.get_ref().len(), 3 * event_size + key_ring.overhead());
+        assert_eq!(EventWriter::new().with_checksum(true).max_payload_size(), EventWithHeader::max_payload_size());
+
+        // Read with read_header and read_payload.
+        let read_events = read_all_events(&mut data);
+        assert_eq!(read_events.iter().map(|e| e.header.checksum).collect::<Vec<_>>(), vec![true, true, false]);
+        for read_event in read_events.iter() {
+            assert_eq!(read_event.plaintext(Some(&key_ring)).unwrap(), payload);
+        }
+
+        // A reader with the original layout, which ignores the type code and the header version through byte 10,
+        // returns the payload unchanged.
+        let baseline_event = read_baseline_layout(&data.get_ref()[..event_size]);
+        assert_eq!(baseline_event, EventWithHeader::new(&payload[..], event.header.timestamp, true, true, false));
+
+        // Corrupt the payload of the first event and the timestamp of the second event.
+        data.get_mut()[event_size - payload.len()] ^= 1;
+        data.get_mut()[event_size + 19] ^= 1;
+        data.set_position(0);
+        for _ in 0..2 {
+            let mut event_reader = EventReader::new();
+            event_reader.read_required_buffer_length(&mut data).unwrap();
+            event_reader.read_header(&mut data).unwrap();
+            let mut read_payload = vec![0; event_reader.payload_length()];
+            let err = event_reader.read_payload(&mut data, &mut read_payload[..]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert!(is_checksum_error(&err));
+        }
+        // The reader is positioned at the next event after a checksum error.
+        let mut event_reader = EventReader::new();
+        let required_buffer_length = event_reader.read_required_buffer_length(&mut data).unwrap();
+        let mut read_buffer = vec![0; required_buffer_length];
+        let read_event = event_reader.read_event(&mut data, &mut read_buffer[..]).unwrap();
+        assert_eq!(read_event.payload, &payload[..]);
+
+        // read_event also verifies the checksum.
+        data.set_position(0);
+        let mut event_reader = EventReader::new();
+        let required_buffer_length = event_reader.read_required_buffer_length(&mut data).unwrap();
+        let mut read_buffer = vec![0; required_buffer_length];
+        let err = event_reader.read_event(&mut data, &mut read_buffer[..]).unwrap_err();
+        assert!(is_checksum_error(&err));
+        assert_eq!(data.position() as usize, event_size);
+    }
+
+    #[test]
+    fn test_extended_header_event_writer_reader() {
+        let key_ring = test_key_ring();
+        let payload = b"hello world".to_vec();
+        let extension = EventHeaderExtension {
+            dts: PravegaTimestamp::from_nanoseconds(Some(900)),
+            duration: Some(0),
+            buffer_flags: 0x2400,
+            stream_record_number: None,
+        };
+        let event = EventWithHeader::new(&payload[..], PravegaTimestamp::from_nanoseconds(Some(1000)), false, false, false)
+            .with_extension(extension);
+        let legacy_event = EventWithHeader::new(&payload[..], PravegaTimestamp::from_nanoseconds(Some(1000)), false, false, false);
+        let mut data = write_events(vec![
+            (EventWriter::new().with_extended_header(true), &event),
+            (EventWriter::with_key_ring(key_ring.clone()).with_checksum(true).with_extended_header(true), &event),
+            // Without an extension, a default extension is written.
+            (EventWriter::new().with_extended_header(true), &legacy_event),
+            // The extension is ignored unless the extended header is enabled.
+            (EventWriter::new(), &event),
+        ]);
+        assert_eq!(&data.get_ref()[4..10], &[0, 0, 0, (12 + 20 + payload.len()) as u8, 1, 20]);
+        assert_eq!(EventWriter::new().with_extended_header(true).max_payload_size(), EventWithHeader::max_payload_size() - 28);
+
+        let read_events = read_all_events(&mut data);
+        let expected_extensions = vec![Some(extension), Some(extension), Some(EventHeaderExtension::default()), None];
+        assert_eq!(read_events.iter().map(|e| e.header.extension).collect::<Vec<_>>(), expected_extensions);
+        for read_event in read_events.iter() {
+            assert_eq!(read_event.header.timestamp, event.header.timestamp);
+            assert_eq!(read_event.plaintext(Some(&key_ring)).unwrap(), payload);
+        }
+
+        // Read with read_event and skip with remaining_length.
+        data.set_position(0);
+        let mut event_reader = EventReader::new();
+        let required_buffer_length = event_reader.read_required_buffer_length(&mut data).unwrap();
+        assert_eq!(required_buffer_length, 20 + 20 + payload.len());
+        let mut read_buffer = vec![0; required_buffer_length];
+        let read_event = event_reader.read_event(&mut data, &mut read_buffer[..]).unwrap();
+        assert_eq!(read_event, event);
+        let mut event_reader = EventReader::new();
+        event_reader.read_required_buffer_length(&mut data).unwrap();
+        event_reader.read_header(&mut data).unwrap();
+        data.set_position(data.position() + event_reader.remaining_length() as u64);
+        let mut event_reader = EventReader::new();
+        event_reader.read_required_buffer_length(&mut data).unwrap();
+        assert_eq!(event_reader.read_header(&mut data).unwrap().extension, Some(EventHeaderExtension::default()));
+
+        // An extended header length that is too small for the header version is invalid.
+        data.get_mut()[9] = 8;
+        data.set_position(0);
+        let mut event_reader = EventReader::new();
+        event_reader.read_required_buffer_length(&mut data).unwrap();
+        let err = event_reader.read_header(&mut data).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_stream_record_number() {
+        let payload = b"hello world".to_vec();
+        let extension = EventHeaderExtension {
+            stream_record_number: Some(42),
+            ..Default::default()
+        };
+        let event = EventWithHeader::new(&payload[..], PravegaTimestamp::from_nanoseconds(Some(1000)), true, true, false)
+            .with_extension(extension);
+        let mut data = write_events(vec![
+            (EventWriter::new().with_extended_header(true).with_checksum(true), &event),
+        ]);
+        // The stream record number requires header version 2.
+        assert_eq!(&data.get_ref()[4..10], &[0, 0, 0, (12 + 28 + payload.len()) as u8, 2, 28]);
+        assert_eq!(&data.get_ref()[40..48], &42u64.to_be_bytes()[..]);
+        let read_events = read_all_events(&mut data);
+        assert_eq!(read_events[0].header.extension, Some(extension));
+        assert_eq!(read_events[0].payload, payload);
+
+        // Version 2 requires the stream record number.
+        data.get_mut()[9] = 20;
+        data.set_position(0);
+        let mut event_reader = EventReader::new();
+        event_reader.read_required_buffer_length(&mut data).unwrap();
+        let err = event_reader.read_header(&mut data).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_encrypted_forward_compatible_header() {
+        let key_ring = test_key_ring();
+        let payload = b"hello world".to_vec();
+        // A later header version whose extended header has 4 bytes that this version does not parse.
+        let mut header_bytes = vec![0; 12 + 32];
+        header_bytes[0] = 3;
+        header_bytes[1] = 32;
+        header_bytes[2] = 0b0001;
+        header_bytes[4..12].copy_from_slice(&1000u64.to_be_bytes()[..]);
+        header_bytes[32..40].copy_from_slice(&7u64.to_be_bytes()[..]);
+        header_bytes[40..44].copy_from_slice(&[1, 2, 3, 4]);
+        let ciphertext = key_ring.encrypt(&header_bytes, &payload).unwrap();
+        let mut bytes = vec![0; 4];
+        bytes.extend_from_slice(&((header_bytes.len() + ciphertext.len()) as u32).to_be_bytes()[..]);
+        bytes.extend_from_slice(&header_bytes[..]);
+        bytes.extend_from_slice(&ciphertext[..]);
+        let mut data = Cursor::new(bytes);
+
+        let read_events = read_all_events(&mut data);
+        let read_event = &read_events[0];
+        assert!(read_event.header.encrypted);
+        assert_eq!(read_event.header.extension.unwrap().stream_record_number, Some(7));
+        assert_eq!(read_event.header_bytes, header_bytes);
+        // The header rebuilt by this version is not the associated data.
+        assert_ne!(read_event.header.to_bytes(), header_bytes);
+        let result = decrypt_payload(Some(&key_ring), &read_event.header, &read_event.header.to_bytes(), &mut read_event.payload.clone());
+        assert!(result.is_err());
+        assert_eq!(read_event.plaintext(Some(&key_ring)).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_fragment_flags() {
+        let payload = b"hello world".to_vec();
+        let timestamp = PravegaTimestamp::from_nanoseconds(Some(1000));
+        let fragments = [(true, false), (true, true), (false, true), (false, false)];
+        let events: Vec<_> = fragments.iter().map(|(more_fragments, continuation)| {
+            EventWithHeader::new(&payload[..], timestamp, false, false, false).with_fragment(*more_fragments, *continuation)
+        }).collect();
+        let mut data = write_events(events.iter().map(|event| (EventWriter::new().with_checksum(true), event)).collect());
+        // MOR and CON are bits 2 and 3 of byte 10.
+        assert_eq!(data.get_ref()[10], 0b0110);
+
+        let read_events = read_all_events(&mut data);
+        let read_fragments: Vec<_> = read_events.iter().map(|e| (e.header.more_fragments, e.header.continuation)).collect();
+        assert_eq!(read_fragments, fragments.to_vec());
+        for read_event in read_events.iter() {
+            assert!(read_event.header.checksum);
+            assert_eq!(read_event.payload, payload);
         }
     }
 }
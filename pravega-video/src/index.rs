@@ -10,18 +10,54 @@
 
 // Module for writing and reading an index in a Pravega stream.
 
-use crate::event_serde::EventHeaderFlags;
+use crate::event_serde::{EventHeader, EventHeaderFlags, EventReader};
 use crate::timestamp::PravegaTimestamp;
 use crate::utils::CurrentHead;
 use enumflags2::BitFlags;
+use std::collections::VecDeque;
 use std::convert::TryInto;
 use std::io::{BufReader, Error, ErrorKind, Read, Write, Seek, SeekFrom};
+use std::sync::Mutex;
 use tracing::{debug, trace};
 
 pub fn get_index_stream_name(stream_name: &str) -> String {
     format!("{}-index", stream_name)
 }
 
+/// The type of elementary stream that an index record refers to.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
+pub enum TrackType {
+    Video,
+    Audio,
+    Other,
+}
+
+impl TrackType {
+    fn to_u8(self) -> u8 {
+        match self {
+            TrackType::Video => 1,
+            TrackType::Audio => 2,
+            TrackType::Other => 3,
+        }
+    }
+
+    fn from_u8(value: u8) -> TrackType {
+        match value {
+            1 => TrackType::Video,
+            2 => TrackType::Audio,
+            _ => TrackType::Other,
+        }
+    }
+}
+
+/// An elementary stream in a multiplexed data stream.
+/// For MPEG transport streams, the id is the PID.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Track {
+    pub track_type: TrackType,
+    pub id: u16,
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct IndexRecord {
     pub timestamp: PravegaTimestamp,
@@ -29,10 +65,17 @@ pub struct IndexRecord {
     pub offset: u64,
     pub random_access: bool,
     pub discontinuity: bool,
+    /// If set, random_access applies only to this track.
+    /// If None, the record applies to the entire data stream.
+    pub track: Option<Track>,
 }
 
 impl IndexRecord {
     pub const RECORD_SIZE: usize = 20;
+    /// The original record version. It does not have a track.
+    pub const VERSION_0: u8 = 0;
+    /// A record with a track.
+    pub const VERSION_1: u8 = 1;
 
     pub fn new(timestamp: PravegaTimestamp, offset: u64,
                random_access: bool, discontinuity: bool) -> Self {
@@ -41,8 +84,27 @@ impl IndexRecord {
             offset,
             random_access,
             discontinuity,
+            track: None,
         }
     }
+
+    pub fn new_with_track(timestamp: PravegaTimestamp, offset: u64,
+               random_access: bool, discontinuity: bool, track: Track) -> Self {
+        Self {
+            timestamp,
+            offset,
+            random_access,
+            discontinuity,
+            track: Some(track),
+        }
+    }
+
+    /// Returns true if this record applies to the entire data stream or to a video track.
+    /// Only these records can be used to start reading the entire data stream.
+    /// A random access point of an audio track may precede the next video key frame.
+    pub fn is_stream_record(&self) -> bool {
+        self.track.map_or(true, |track| track.track_type == TrackType::Video)
+    }
 }
 
 /// ```text
@@ -54,9 +116,9 @@ impl IndexRecord {
     0                   1                   2                   3
     0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
-   |                                                         |D|R|R|
-   |                    reserved (set to 0)                  |I|A|E|
-   |                                                         |S|N|S|
+   |       |       |                               |         |D|R|R|
+   |  VER  |  TT   |     track id (16-bit BE)      |   RES   |I|A|E|
+   |       |       |                               |         |S|N|S|
    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
    |                                                               |
    |                                                               |
@@ -80,6 +142,19 @@ impl IndexRecord {
    reserved, RES:
       All reserved bits must be 0.
       These may be utilized in the future for other purposes.
+   version, VER:
+      0 - The record applies to the entire data stream. TT and track id must be 0.
+          This is the only version written by older versions of pravegasink.
+      1 - The record refers to a single track (elementary stream) identified by TT and track id.
+          RAN indicates a random access point for this track only.
+          If random access points of several tracks are in the same event, there is one record for each track,
+          all with the same timestamp and offset, in the order video, audio, other.
+          Searches for a position to start reading the entire data stream ignore records of audio and other tracks.
+      Readers that only understand version 0 will treat all records as version 0.
+   track type, TT:
+      1 - video, 2 - audio, 3 - other
+   track id:
+      Identifies the track within the data stream. For MPEG transport streams, this is the PID.
    DIS - discontinuity indicator
    RAN - random access indicator
    timestamp:
@@ -104,6 +179,15 @@ impl IndexRecord {
       beginning at time T1 and for a duration of TN - T1.
    4. If index records 2 through N have DIS of 0, then it is guaranteed that
       the bytes between O1 and ON were written continuously.
+
+   Stream record numbers:
+      Records for the entire stream or a video track (stream records) are numbered consecutively,
+      for instance to obtain HLS Media Sequence Numbers that do not change when the index is truncated.
+      If the index has only version 0 records, the number of a record is its offset divided by the record size.
+      Otherwise, pravegasink writes the number of the stream record in the extended header of the event
+      that the record refers to. If several stream records refer to the same offset, such as the final record
+      written when pravegasink stops and the first record written when it restarts, the event has the number
+      of the first of these. See get_stream_record_number.
 */
 /// ```
 pub struct IndexRecordWriter {
@@ -130,6 +214,10 @@ impl IndexRecordWriter {
             return Err(Error::new(ErrorKind::InvalidInput, "Timestamp is none or 0"));
         }
         let mut bytes_to_write: Vec<u8> = vec![0; IndexRecord::RECORD_SIZE];
+        if let Some(track) = record.track {
+            bytes_to_write[0] = (IndexRecord::VERSION_1 << 4) | track.track_type.to_u8();
+            bytes_to_write[1..3].copy_from_slice(&track.id.to_be_bytes()[..]);
+        }
         bytes_to_write[3..4].copy_from_slice(&flags.bits().to_be_bytes()[..]);
         bytes_to_write[4..12].copy_from_slice(&timestamp_nanos.to_be_bytes()[..]);
         bytes_to_write[12..20].copy_from_slice(&record.offset.to_be_bytes()[..]);
@@ -153,6 +241,16 @@ impl IndexRecordReader {
     {
         let mut buffer: Vec<u8> = vec![0; IndexRecord::RECORD_SIZE];
         rdr.read_exact(&mut buffer[..])?;
+        // Records with an unknown future version are read without the track.
+        let version = buffer[0] >> 4;
+        let track = if version == IndexRecord::VERSION_1 {
+            Some(Track {
+                track_type: TrackType::from_u8(buffer[0] & 0x0F),
+                id: u16::from_be_bytes(buffer[1..3].try_into().unwrap()),
+            })
+        } else {
+            None
+        };
         let flags = BitFlags::<EventHeaderFlags>::from_bits(buffer[3]).unwrap();
         let random_access = flags.contains(EventHeaderFlags::RandomAccessIndicator);
         let discontinuity = flags.contains(EventHeaderFlags::DiscontinuityIndicator);
@@ -165,6 +263,7 @@ impl IndexRecordReader {
             offset,
             random_access,
             discontinuity,
+            track,
         })
     }
 }
@@ -256,6 +355,19 @@ impl<R: Read + Seek + CurrentHead> IndexSearcher<R> {
                 },
             }
         })();
+        // Do not truncate at a record of an audio or other track, which may be before the next video key frame.
+        let forward = match method {
+            SearchMethod::Before => false,
+            SearchMethod::After => true,
+        };
+        let result = result.and_then(|(index_record, index_offset)| {
+            if index_record.is_stream_record() {
+                return Ok((index_record, index_offset));
+            }
+            self.find_stream_record(index_offset, forward)
+                .or_else(|_| self.find_stream_record(index_offset, !forward))
+                .or(Ok((index_record, index_offset)))
+        });
         debug!("IndexSearcher::search_size_and_return_index_offset({}, {:?}) = {:?}", size_bytes, method, result);
         result
     }
@@ -267,10 +379,56 @@ impl<R: Read + Seek + CurrentHead> IndexSearcher<R> {
     /// If the desired timestamp exceeds the first and last timestamp in the index, returns the nearest index record.
     /// If the index has no records, returns an UnexpectedEof error.
     /// Otherwise, it uses the specified SearchMethod.
+    /// Records of audio and other tracks are skipped in favor of the nearest record in the search direction
+    /// that applies to the entire stream or a video track. See IndexRecord::is_stream_record.
     /// TODO: Add flag to not consider records with random_access=false.
     /// TODO: Make this method private.
     pub fn search_timestamp_and_return_index_offset(&mut self, timestamp: PravegaTimestamp, method: SearchMethod)
             -> Result<(IndexRecord, u64), Error> {
+        let forward = match method {
+            SearchMethod::Before => false,
+            SearchMethod::After => true,
+        };
+        let (index_record, index_offset) = self.search_any_timestamp_and_return_index_offset(timestamp, method)?;
+        if index_record.is_stream_record() {
+            return Ok((index_record, index_offset));
+        }
+        // If the index has only track records that are not for video, such as for an audio-only stream, use them.
+        self.find_stream_record(index_offset, forward)
+            .or_else(|_| self.find_stream_record(index_offset, !forward))
+            .or(Ok((index_record, index_offset)))
+    }
+
+    /// Returns the nearest record at or after (forward) or at or before (!forward) the index offset
+    /// for which IndexRecord::is_stream_record is true.
+    /// If there is no such record, returns a NotFound error.
+    fn find_stream_record(&mut self, mut index_offset: u64, forward: bool) -> Result<(IndexRecord, u64), Error> {
+        let first_index_offset = self.reader.get_ref().current_head()?;
+        let tail_offset = self.reader.seek(SeekFrom::End(0))?;
+        let mut index_record_reader = IndexRecordReader::new();
+        loop {
+            self.reader.seek(SeekFrom::Start(index_offset))?;
+            let index_record = index_record_reader.read(&mut self.reader)?;
+            if index_record.is_stream_record() {
+                return Ok((index_record, index_offset));
+            }
+            if forward {
+                index_offset += IndexRecord::RECORD_SIZE as u64;
+                if index_offset + IndexRecord::RECORD_SIZE as u64 > tail_offset {
+                    return Err(Error::new(ErrorKind::NotFound, "No record for the entire stream after offset"));
+                }
+            } else {
+                if index_offset < first_index_offset + IndexRecord::RECORD_SIZE as u64 {
+                    return Err(Error::new(ErrorKind::NotFound, "No record for the entire stream before offset"));
+                }
+                index_offset -= IndexRecord::RECORD_SIZE as u64;
+            }
+        }
+    }
+
+    /// Same as search_timestamp_and_return_index_offset but any record can be returned.
+    fn search_any_timestamp_and_return_index_offset(&mut self, timestamp: PravegaTimestamp, method: SearchMethod)
+            -> Result<(IndexRecord, u64), Error> {
 
         let result = (|| {
             let mut index_record_reader = IndexRecordReader::new();
@@ -329,7 +487,7 @@ impl<R: Read + Seek + CurrentHead> IndexSearcher<R> {
                 },
             }
         })();
-        debug!("IndexSearcher::search_timestamp_and_return_index_offset({}, {:?}) = {:?}", timestamp, method, result);
+        debug!("IndexSearcher::search_any_timestamp_and_return_index_offset({}, {:?}) = {:?}", timestamp, method, result);
         result
     }
 
@@ -351,6 +509,93 @@ impl<R: Read + Seek + CurrentHead> IndexSearcher<R> {
         result.map(|x| x.0)
     }
 
+    /// Returns the last index record at or before the desired timestamp that is a random access point for a track of
+    /// the specified type, such as the first video key frame at or before a timestamp.
+    /// Only records with a track (version 1) are considered.
+    /// If there is no such record, returns a NotFound error.
+    pub fn search_track_random_access_before(&mut self, timestamp: PravegaTimestamp, track_type: TrackType)
+            -> Result<(IndexRecord, u64), Error> {
+        let result = (|| {
+            let (_, mut index_offset) = self.search_any_timestamp_and_return_index_offset(timestamp, SearchMethod::Before)?;
+            let first_index_offset = self.reader.get_ref().current_head()?;
+            let tail_offset = self.reader.seek(SeekFrom::End(0))?;
+            let mut index_record_reader = IndexRecordReader::new();
+            // Multiple records may have the same timestamp. Move to the last record that is not after the timestamp.
+            self.reader.seek(SeekFrom::Start(index_offset))?;
+            while index_offset + 2 * IndexRecord::RECORD_SIZE as u64 <= tail_offset {
+                self.reader.seek(SeekFrom::Start(index_offset + IndexRecord::RECORD_SIZE as u64))?;
+                let next_record = index_record_reader.read(&mut self.reader)?;
+                if next_record.timestamp > timestamp {
+                    break;
+                }
+                index_offset += IndexRecord::RECORD_SIZE as u64;
+            }
+            // Scan backwards for a matching record.
+            loop {
+                self.reader.seek(SeekFrom::Start(index_offset))?;
+                let index_record = index_record_reader.read(&mut self.reader)?;
+                trace!("IndexSearcher::search_track_random_access_before: index_record={:?}", index_record);
+                let is_match = index_record.random_access
+                    && index_record.timestamp <= timestamp
+                    && index_record.track.map_or(false, |t| t.track_type == track_type);
+                if is_match {
+                    return Ok((index_record, index_offset));
+                }
+                if index_offset < first_index_offset + IndexRecord::RECORD_SIZE as u64 {
+                    return Err(Error::new(ErrorKind::NotFound, format!("No random access point for {:?} track", track_type)));
+                }
+                index_offset -= IndexRecord::RECORD_SIZE as u64;
+            }
+        })();
+        debug!("IndexSearcher::search_track_random_access_before({}, {:?}) = {:?}", timestamp, track_type, result);
+        result
+    }
+
+    /// Returns the nearest index record that is a random access point for the entire stream or a video track.
+    /// With SearchMethod::Before, returns the last such record with a timestamp at or before the desired timestamp.
+    /// With SearchMethod::After, returns the first such record with a timestamp at or after the desired timestamp.
+    /// This is used for trick mode playback, which only reads key frames.
+    /// If there is no such record, returns a NotFound error.
+    pub fn search_random_access(&mut self, timestamp: PravegaTimestamp, method: SearchMethod)
+            -> Result<(IndexRecord, u64), Error> {
+        let result = (|| {
+            let forward = match method {
+                SearchMethod::Before => false,
+                SearchMethod::After => true,
+            };
+            let (_, mut index_offset) = self.search_timestamp_and_return_index_offset(timestamp, method)?;
+            let first_index_offset = self.reader.get_ref().current_head()?;
+            let tail_offset = self.reader.seek(SeekFrom::End(0))?;
+            let mut index_record_reader = IndexRecordReader::new();
+            loop {
+                self.reader.seek(SeekFrom::Start(index_offset))?;
+                let index_record = index_record_reader.read(&mut self.reader)?;
+                trace!("IndexSearcher::search_random_access: index_record={:?}", index_record);
+                let is_match = index_record.random_access && index_record.is_stream_record() && if forward {
+                    index_record.timestamp >= timestamp
+                } else {
+                    index_record.timestamp <= timestamp
+                };
+                if is_match {
+                    return Ok((index_record, index_offset));
+                }
+                if forward {
+                    index_offset += IndexRecord::RECORD_SIZE as u64;
+                    if index_offset + IndexRecord::RECORD_SIZE as u64 > tail_offset {
+                        return Err(Error::new(ErrorKind::NotFound, "No random access point after timestamp"));
+                    }
+                } else {
+                    if index_offset < first_index_offset + IndexRecord::RECORD_SIZE as u64 {
+                        return Err(Error::new(ErrorKind::NotFound, "No random access point before timestamp"));
+                    }
+                    index_offset -= IndexRecord::RECORD_SIZE as u64;
+                }
+            }
+        })();
+        debug!("IndexSearcher::search_random_access({}) = {:?}", timestamp, result);
+        result
+    }
+
     /// This is expected to be used to determine the offset at which to start reading.
     /// TODO: This should only consider index records with random_access=true.
     pub fn get_first_record(&mut self) -> Result<IndexRecord, Error> {
@@ -386,12 +631,439 @@ impl<R: Read + Seek + CurrentHead> IndexSearcher<R> {
     }
 }
 
+/// Statistics for a CachedIndexSearcher.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IndexCacheStats {
+    /// The index offset of the first cached record.
+    pub first_offset: u64,
+    /// The index offset after the last cached record. This is the tail of the index when it was last refreshed.
+    pub tail_offset: u64,
+    /// The number of cached records.
+    pub records: usize,
+    /// The number of records read from the index while refreshing the cache.
+    pub records_read: u64,
+    /// The number of records read from the index because they were not in the cache.
+    pub records_missed: u64,
+    /// The number of times the cache was cleared because the index was truncated or recreated.
+    pub invalidations: u64,
+}
+
+struct CachedIndexState<R: Read + Seek + CurrentHead> {
+    reader: BufReader<R>,
+    /// The most recent records in the index, in order. The first record is at stats.first_offset.
+    records: VecDeque<IndexRecord>,
+    max_records: usize,
+    stats: IndexCacheStats,
+}
+
+// An index searcher that keeps the most recent index records in memory.
+// Each search refreshes the cache by reading only the records appended since the last search.
+// Records that have been truncated from the index are removed from the cache.
+// If the cache is full, the oldest records are evicted. Searches for older records will read them from the index.
+// This can be shared between threads, for instance with an Arc.
+pub struct CachedIndexSearcher<R: Read + Seek + CurrentHead> {
+    state: Mutex<CachedIndexState<R>>,
+}
+
+impl<R: Read + Seek + CurrentHead> CachedIndexSearcher<R> {
+    /// The default maximum number of cached records. Each record uses about 40 bytes of memory.
+    pub const DEFAULT_MAX_RECORDS: usize = 100_000;
+
+    pub fn new(reader: R, max_records: usize) -> Self {
+        assert!(max_records > 0, "max_records must be greater than 0");
+        Self {
+            state: Mutex::new(CachedIndexState {
+                reader: BufReader::with_capacity(64*1024, reader),
+                records: VecDeque::new(),
+                max_records,
+                stats: IndexCacheStats::default(),
+            }),
+        }
+    }
+
+    /// Returns a tuple containing an IndexRecord and index_offset.
+    /// This has the same behavior as IndexSearcher::search_timestamp_and_return_index_offset,
+    /// except that if multiple records have the desired timestamp, the first one is returned with SearchMethod::After
+    /// and the last one is returned with SearchMethod::Before.
+    /// As with IndexSearcher, records of audio and other tracks are skipped.
+    pub fn search_timestamp_and_return_index_offset(&self, timestamp: PravegaTimestamp, method: SearchMethod)
+            -> Result<(IndexRecord, u64), Error> {
+        let mut state = self.state.lock().unwrap();
+        let result = (|| {
+            let (head_offset, tail_offset) = state.refresh()?;
+            let num_records = (tail_offset - head_offset) / IndexRecord::RECORD_SIZE as u64;
+            if num_records == 0 {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "Index has no records"));
+            }
+            let index_offset_of = |i: u64| head_offset + i * IndexRecord::RECORD_SIZE as u64;
+            // Find the number of records with a timestamp less than (After) or less than or equal to (Before) the desired timestamp.
+            let mut low = 0;
+            let mut high = num_records;
+            while low < high {
+                let middle = (low + high) / 2;
+                let record = state.get_record(index_offset_of(middle))?;
+                let is_before = match method {
+                    SearchMethod::Before => record.timestamp <= timestamp,
+                    SearchMethod::After => record.timestamp < timestamp,
+                };
+                if is_before {
+                    low = middle + 1;
+                } else {
+                    high = middle;
+                }
+            }
+            let i = match method {
+                SearchMethod::Before => low.max(1) - 1,
+                SearchMethod::After => low.min(num_records - 1),
+            };
+            // Move to the nearest record for the entire stream or a video track, preferring the search direction.
+            let candidates: Box<dyn Iterator<Item = u64>> = match method {
+                SearchMethod::Before => Box::new((0..=i).rev().chain(i + 1..num_records)),
+                SearchMethod::After => Box::new((i..num_records).chain((0..i).rev())),
+            };
+            for j in candidates {
+                let record = state.get_record(index_offset_of(j))?;
+                if record.is_stream_record() {
+                    return Ok((record, index_offset_of(j)));
+                }
+            }
+            let index_offset = index_offset_of(i);
+            Ok((state.get_record(index_offset)?, index_offset))
+        })();
+        debug!("CachedIndexSearcher::search_timestamp_and_return_index_offset({}, {:?}) = {:?}", timestamp, method, result);
+        result
+    }
+
+    /// If a non-exact match is found, return the index record immediately before the desired timestamp.
+    pub fn search_timestamp(&self, timestamp: PravegaTimestamp) -> Result<IndexRecord, Error> {
+        self.search_timestamp_and_return_index_offset(timestamp, SearchMethod::Before).map(|x| x.0)
+    }
+
+    /// If a non-exact match is found, return the index record immediately after the desired timestamp.
+    pub fn search_timestamp_after(&self, timestamp: PravegaTimestamp) -> Result<IndexRecord, Error> {
+        self.search_timestamp_and_return_index_offset(timestamp, SearchMethod::After).map(|x| x.0)
+    }
+
+    pub fn get_first_record(&self) -> Result<IndexRecord, Error> {
+        self.search_timestamp(PravegaTimestamp::MIN)
+    }
+
+    pub fn get_last_record(&self) -> Result<IndexRecord, Error> {
+        self.search_timestamp(PravegaTimestamp::MAX)
+    }
+
+    /// Returns the index records, and their index offsets, between begin_index_offset (inclusive)
+    /// and end_index_offset (exclusive).
+    /// This does not refresh the cache, so the offsets should have been returned by a previous search.
+    /// Records that have been truncated are not returned.
+    pub fn get_index_records(&self, begin_index_offset: u64, end_index_offset: u64) -> Result<Vec<(IndexRecord, u64)>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let mut records = Vec::new();
+        let mut index_offset = begin_index_offset.max(state.reader.get_ref().current_head()?);
+        while index_offset + IndexRecord::RECORD_SIZE as u64 <= end_index_offset {
+            records.push((state.get_record(index_offset)?, index_offset));
+            index_offset += IndexRecord::RECORD_SIZE as u64;
+        }
+        Ok(records)
+    }
+
+    pub fn stats(&self) -> IndexCacheStats {
+        let state = self.state.lock().unwrap();
+        IndexCacheStats {
+            records: state.records.len(),
+            ..state.stats
+        }
+    }
+}
+
+impl<R: Read + Seek + CurrentHead> CachedIndexState<R> {
+    /// Read records appended to the index and remove truncated records.
+    /// Returns the head and tail offsets of the index.
+    fn refresh(&mut self) -> Result<(u64, u64), Error> {
+        let head_offset = self.reader.get_ref().current_head()?;
+        let tail_offset = self.reader.seek(SeekFrom::End(0))?;
+        // Ignore an incomplete record at the tail.
+        let tail_offset = tail_offset - (tail_offset - head_offset) % IndexRecord::RECORD_SIZE as u64;
+        if tail_offset < self.stats.tail_offset || head_offset >= self.stats.tail_offset {
+            // The index was recreated or all cached records were truncated.
+            if !self.records.is_empty() {
+                debug!("CachedIndexSearcher: invalidating cache; head_offset={}, tail_offset={}, stats={:?}",
+                    head_offset, tail_offset, self.stats);
+                self.records.clear();
+                self.stats.invalidations += 1;
+            }
+            // Start with the most recent records.
+            let available_records = (tail_offset - head_offset) / IndexRecord::RECORD_SIZE as u64;
+            let first_offset = tail_offset - available_records.min(self.max_records as u64) * IndexRecord::RECORD_SIZE as u64;
+            self.stats.first_offset = first_offset;
+            self.stats.tail_offset = first_offset;
+        } else {
+            // Remove truncated records.
+            while self.stats.first_offset < head_offset && self.records.pop_front().is_some() {
+                self.stats.first_offset += IndexRecord::RECORD_SIZE as u64;
+            }
+        }
+        if self.stats.tail_offset < tail_offset {
+            self.reader.seek(SeekFrom::Start(self.stats.tail_offset))?;
+            let mut index_record_reader = IndexRecordReader::new();
+            while self.stats.tail_offset < tail_offset {
+                let record = index_record_reader.read(&mut self.reader)?;
+                trace!("CachedIndexSearcher: read index_record={:?}", record);
+                self.records.push_back(record);
+                self.stats.tail_offset += IndexRecord::RECORD_SIZE as u64;
+                self.stats.records_read += 1;
+                if self.records.len() > self.max_records {
+                    self.records.pop_front();
+                    self.stats.first_offset += IndexRecord::RECORD_SIZE as u64;
+                }
+            }
+        }
+        Ok((head_offset, tail_offset))
+    }
+
+    /// Returns the record at the index offset, from the cache if possible.
+    fn get_record(&mut self, index_offset: u64) -> Result<IndexRecord, Error> {
+        if self.stats.first_offset <= index_offset && index_offset < self.stats.tail_offset {
+            let i = (index_offset - self.stats.first_offset) / IndexRecord::RECORD_SIZE as u64;
+            Ok(self.records[i as usize])
+        } else {
+            self.stats.records_missed += 1;
+            self.reader.seek(SeekFrom::Start(index_offset))?;
+            IndexRecordReader::new().read(&mut self.reader)
+        }
+    }
+}
+
+/// The maximum number of index records that should be passed to get_stream_record_number
+/// before the record whose number is desired.
+pub const STREAM_RECORD_NUMBER_LOOKBACK: usize = 100;
+
+/// Returns the stream record number of records[position], which must be a stream record (see IndexRecord::is_stream_record).
+/// records must be consecutive index records and should include up to STREAM_RECORD_NUMBER_LOOKBACK records before position.
+/// The number is read from the extended header of the event that the nearest stream record refers to,
+/// at or before position, that is within the data stream.
+/// Returns None if this event does not have a stream record number or if there is no such record.
+/// In this case, the caller should use the index offset of the record divided by the record size.
+pub fn get_stream_record_number<R>(records: &[(IndexRecord, u64)], position: usize, data_reader: &mut R) -> Result<Option<u64>, Error>
+where
+    R: Read + Seek + CurrentHead,
+{
+    let data_head_offset = data_reader.current_head()?;
+    let data_tail_offset = data_reader.seek(SeekFrom::End(0))?;
+    // The number of stream records after the record whose event is read, through position.
+    let mut later_stream_records = 0;
+    for (i, (record, _)) in records[..=position].iter().enumerate().rev() {
+        if !record.is_stream_record() {
+            continue;
+        }
+        // The final record written by pravegasink refers to the tail of the data stream, where there is no event yet.
+        if data_head_offset <= record.offset && record.offset < data_tail_offset {
+            data_reader.seek(SeekFrom::Start(record.offset))?;
+            let mut event_reader = EventReader::new();
+            event_reader.read_required_buffer_length(data_reader)?;
+            let header = event_reader.read_header(data_reader)?;
+            let number = header.extension.and_then(|extension| extension.stream_record_number);
+            trace!("get_stream_record_number: record={:?}, number={:?}", record, number);
+            return Ok(number.map(|number| {
+                // The event has the number of the first stream record with its offset.
+                let same_offset_stream_records = records[..i].iter().rev()
+                    .take_while(|(other, _)| other.offset == record.offset)
+                    .filter(|(other, _)| other.is_stream_record())
+                    .count() as u64;
+                number + same_offset_stream_records + later_stream_records
+            }));
+        }
+        later_stream_records += 1;
+    }
+    Ok(None)
+}
+
+/// Options to control the spacing of index records, equivalent to the pravegasink properties
+/// index-min-sec and index-max-sec.
+#[derive(Debug, Clone, Copy)]
+pub struct IndexSpacing {
+    /// An index record will not be created at a random access point if the previous index record
+    /// is less than this many nanoseconds earlier, unless it is also a discontinuity.
+    pub index_min_nanos: u64,
+    /// An index record will be created at a non-random access point if there have been no
+    /// random access points for this many nanoseconds.
+    pub index_max_nanos: u64,
+}
+
+impl Default for IndexSpacing {
+    fn default() -> Self {
+        Self {
+            index_min_nanos: 500_000_000,
+            index_max_nanos: 10_000_000_000,
+        }
+    }
+}
+
+/// Decides which events in a data stream should have an index record, using the same rules as pravegasink.
+/// The flags and timestamp in each event header are used.
+/// Records are not associated with tracks.
+pub struct IndexRebuilder {
+    spacing: IndexSpacing,
+    first_valid_time: Option<u64>,
+    last_index_time: Option<u64>,
+    last_event_time: Option<u64>,
+    /// True if a discontinuity was skipped and must be recorded in the next index record.
+    pending_discontinuity: bool,
+    last_record: Option<IndexRecord>,
+}
+
+impl IndexRebuilder {
+    pub fn new(spacing: IndexSpacing) -> Self {
+        Self {
+            spacing,
+            first_valid_time: None,
+            last_index_time: None,
+            last_event_time: None,
+            pending_discontinuity: true,
+            last_record: None,
+        }
+    }
+
+    /// Returns the index record that should be written for the event at the given offset, if any.
+    /// Events must be provided in order.
+    pub fn add_event(&mut self, offset: u64, header: &EventHeader) -> Option<IndexRecord> {
+        let timestamp = match header.timestamp.nanoseconds() {
+            Some(timestamp) if timestamp > 0 => timestamp,
+            // Events with an invalid timestamp are never indexed.
+            _ => return None,
+        };
+        if header.discontinuity {
+            self.pending_discontinuity = true;
+        }
+        // Fragments of a large buffer must not be indexed. Events written before the continuation flag existed
+        // are identified by having the same timestamp as the first fragment.
+        let is_fragment = header.continuation
+            || (self.last_event_time == Some(timestamp) && !header.random_access);
+        self.last_event_time = Some(timestamp);
+        let first_valid_time = *self.first_valid_time.get_or_insert(timestamp);
+        // Events marked for the index by pravegasink without a random access point were forced by index-max-sec.
+        let random_access = header.random_access || header.include_in_index;
+        let include_in_index = if is_fragment {
+            false
+        } else {
+            match self.last_index_time {
+                Some(last_index_time) => {
+                    if random_access {
+                        (header.discontinuity && header.random_access)
+                            || timestamp >= last_index_time + self.spacing.index_min_nanos
+                    } else {
+                        timestamp > last_index_time + self.spacing.index_max_nanos
+                    }
+                },
+                None => random_access || timestamp > first_valid_time + self.spacing.index_max_nanos,
+            }
+        };
+        if include_in_index {
+            let record = IndexRecord::new(PravegaTimestamp::from_nanoseconds(Some(timestamp)), offset,
+                header.random_access, self.pending_discontinuity);
+            self.pending_discontinuity = false;
+            self.last_index_time = Some(timestamp);
+            self.last_record = Some(record);
+            Some(record)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the final index record at the end of the data stream, if needed.
+    /// This has a timestamp 1 nanosecond after the last event, allowing the data after the last index record to be played.
+    /// Events before an index record must have a strictly lower timestamp.
+    pub fn finish(&mut self, end_offset: u64) -> Option<IndexRecord> {
+        match (self.last_record, self.last_event_time) {
+            (Some(last_record), Some(last_event_time)) if end_offset > last_record.offset => {
+                let record = IndexRecord::new(PravegaTimestamp::from_nanoseconds(Some(last_event_time + 1)), end_offset,
+                    false, false);
+                self.last_record = Some(record);
+                Some(record)
+            },
+            _ => None,
+        }
+    }
+}
+
+/// The result of rebuild_index.
+#[derive(Debug, Clone, Default)]
+pub struct IndexRebuildSummary {
+    /// The offset of the first event that was read.
+    pub begin_offset: u64,
+    /// The offset after the last event that was read.
+    pub end_offset: u64,
+    pub events: u64,
+    pub index_records: u64,
+    pub first_record: Option<IndexRecord>,
+    pub last_record: Option<IndexRecord>,
+}
+
+/// Regenerate the index of a data stream.
+/// The data stream is read from its current head to end_offset, which should be the tail of the data stream.
+/// Only event headers are read; payloads are skipped.
+/// Index records are written to index_writer.
+/// If the last event is incomplete, it is ignored.
+pub fn rebuild_index<R, W>(data_reader: &mut R, end_offset: u64, index_writer: &mut W, spacing: IndexSpacing)
+        -> Result<IndexRebuildSummary, Error>
+where
+    R: Read + Seek + CurrentHead,
+    W: Write,
+{
+    let begin_offset = data_reader.current_head()?;
+    data_reader.seek(SeekFrom::Start(begin_offset))?;
+    let mut reader = BufReader::with_capacity(1024*1024, data_reader);
+    let mut rebuilder = IndexRebuilder::new(spacing);
+    let mut index_record_writer = IndexRecordWriter::new();
+    let mut summary = IndexRebuildSummary {
+        begin_offset,
+        end_offset: begin_offset,
+        ..Default::default()
+    };
+    let mut write_record = |record: IndexRecord, summary: &mut IndexRebuildSummary| -> Result<(), Error> {
+        trace!("rebuild_index: index_record={:?}", record);
+        index_record_writer.write(&record, index_writer)?;
+        summary.index_records += 1;
+        summary.first_record.get_or_insert(record);
+        summary.last_record = Some(record);
+        Ok(())
+    };
+    let mut offset = begin_offset;
+    // Each event begins with an 8-byte type code and length.
+    while offset + 8 <= end_offset {
+        let mut event_reader = EventReader::new();
+        let required_buffer_length = event_reader.read_required_buffer_length(&mut reader)? as u64;
+        if offset + required_buffer_length > end_offset {
+            debug!("rebuild_index: ignoring incomplete event at offset {}", offset);
+            break;
+        }
+        let header = event_reader.read_header(&mut reader)?;
+        reader.seek_relative(event_reader.remaining_length() as i64)?;
+        if let Some(record) = rebuilder.add_event(offset, &header) {
+            write_record(record, &mut summary)?;
+        }
+        summary.events += 1;
+        offset += required_buffer_length;
+        summary.end_offset = offset;
+    }
+    if let Some(record) = rebuilder.finish(summary.end_offset) {
+        write_record(record, &mut summary)?;
+    }
+    debug!("rebuild_index: summary={:?}", summary);
+    Ok(summary)
+}
+
 #[cfg(test)]
 mod test {
-    use crate::index::{IndexRecord, IndexRecordWriter, IndexRecordReader, IndexSearcher, SearchMethod};
+    use crate::event_serde::{EventHeaderExtension, EventWithHeader, EventWriter};
+    use crate::index::{CachedIndexSearcher, IndexRecord, IndexRecordWriter, IndexRecordReader, IndexSearcher, IndexSpacing,
+        SearchMethod, Track, TrackType, get_stream_record_number, rebuild_index};
     use crate::timestamp::PravegaTimestamp;
+    use crate::utils::CurrentHead;
     use tracing::info;
-    use std::io::Cursor;
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicU64, Ordering};
 
     #[test]
     fn test_index_writer_reader() {
@@ -411,6 +1083,114 @@ mod test {
         assert_eq!(index_record, deserialized_index_record);
     }
 
+    #[test]
+    fn test_index_writer_reader_track() {
+        let index_record = IndexRecord::new_with_track(
+            PravegaTimestamp::from_nanoseconds(Some(1_600_000_000_000_000_000)),
+            300, true, false, Track { track_type: TrackType::Audio, id: 0x101 });
+        let mut serialized_bytes_cursor = Cursor::new(vec![0 as u8; IndexRecord::RECORD_SIZE]);
+        let mut index_record_writer = IndexRecordWriter::new();
+        index_record_writer.write(&index_record, &mut serialized_bytes_cursor).unwrap();
+        assert_eq!(&serialized_bytes_cursor.get_ref()[0..4], &[0x12, 0x01, 0x01, 0x02]);
+        serialized_bytes_cursor.set_position(0);
+        let mut index_record_reader = IndexRecordReader::new();
+        let deserialized_index_record = index_record_reader.read(&mut serialized_bytes_cursor).unwrap();
+        assert_eq!(index_record, deserialized_index_record);
+    }
+
+    #[test]
+    fn test_index_reader_legacy() {
+        // A record written by an older version of pravegasink.
+        let mut bytes = vec![0, 0, 0, 0x03];
+        bytes.extend_from_slice(&1_600_000_000_000_000_000u64.to_be_bytes());
+        bytes.extend_from_slice(&300u64.to_be_bytes());
+        let mut index_record_reader = IndexRecordReader::new();
+        let index_record = index_record_reader.read(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(index_record, IndexRecord::new(
+            PravegaTimestamp::from_nanoseconds(Some(1_600_000_000_000_000_000)), 300, true, true));
+    }
+
+    #[test]
+    fn test_index_searcher_track() {
+        let video = Track { track_type: TrackType::Video, id: 0x100 };
+        let audio = Track { track_type: TrackType::Audio, id: 0x101 };
+        let ts = |t: u64| PravegaTimestamp::from_nanoseconds(Some(1_600_000_000_000_000_000 + t));
+        let records = vec![
+            IndexRecord::new(ts(0), 0, true, true),
+            IndexRecord::new_with_track(ts(100), 100, true, false, video),
+            IndexRecord::new_with_track(ts(200), 200, true, false, audio),
+            IndexRecord::new_with_track(ts(300), 300, false, false, video),
+            IndexRecord::new_with_track(ts(400), 400, true, false, audio),
+            IndexRecord::new_with_track(ts(400), 450, true, false, video),
+            IndexRecord::new_with_track(ts(500), 500, true, false, audio),
+        ];
+        let mut memory_index_cursor = Cursor::new(Vec::new());
+        let mut index_record_writer = IndexRecordWriter::new();
+        for rec in records.iter() {
+            index_record_writer.write(rec, &mut memory_index_cursor).unwrap();
+        }
+        memory_index_cursor.set_position(0);
+        let mut index_searcher = IndexSearcher::new(memory_index_cursor);
+        let search = |index_searcher: &mut IndexSearcher<_>, t: u64, track_type: TrackType| {
+            index_searcher.search_track_random_access_before(ts(t), track_type).map(|r| r.0.offset).ok()
+        };
+        assert_eq!(search(&mut index_searcher, 50, TrackType::Video), None);
+        assert_eq!(search(&mut index_searcher, 100, TrackType::Video), Some(100));
+        assert_eq!(search(&mut index_searcher, 399, TrackType::Video), Some(100));
+        assert_eq!(search(&mut index_searcher, 400, TrackType::Video), Some(450));
+        assert_eq!(search(&mut index_searcher, 1000, TrackType::Video), Some(450));
+        assert_eq!(search(&mut index_searcher, 400, TrackType::Audio), Some(400));
+        assert_eq!(search(&mut index_searcher, 150, TrackType::Audio), None);
+        assert_eq!(search(&mut index_searcher, 1000, TrackType::Audio), Some(500));
+
+        // Searches for a position to start reading the entire stream skip the audio records.
+        let expected = vec![
+            (250, SearchMethod::Before, 100),
+            (150, SearchMethod::After, 300),
+            (1000, SearchMethod::Before, 450),
+            (1000, SearchMethod::After, 450),
+        ];
+        for (t, method, offset) in expected.into_iter() {
+            assert_eq!(index_searcher.search_timestamp_and_return_index_offset(ts(t), method).unwrap().0.offset, offset);
+        }
+        assert_eq!(index_searcher.search_random_access(ts(450), SearchMethod::Before).unwrap().0.offset, 450);
+        assert_eq!(index_searcher.search_random_access(ts(150), SearchMethod::After).unwrap().0.offset, 450);
+        let cached_index_searcher = CachedIndexSearcher::new(index_searcher.into_inner(), 2);
+        let expected = vec![
+            (250, SearchMethod::Before, 100),
+            (150, SearchMethod::After, 300),
+            (1000, SearchMethod::Before, 450),
+            (1000, SearchMethod::After, 450),
+        ];
+        for (t, method, offset) in expected.into_iter() {
+            assert_eq!(cached_index_searcher.search_timestamp_and_return_index_offset(ts(t), method).unwrap().0.offset, offset);
+        }
+    }
+
+    #[test]
+    fn test_index_searcher_random_access() {
+        let mut cursor = Cursor::new(Vec::new());
+        let mut index_record_writer = IndexRecordWriter::new();
+        for (timestamp, random_access) in [(100, true), (200, false), (300, true), (400, false), (500, false)].iter() {
+            let record = IndexRecord::new(PravegaTimestamp::from_nanoseconds(Some(*timestamp)), *timestamp * 10, *random_access, false);
+            index_record_writer.write(&record, &mut cursor).unwrap();
+        }
+        let mut index_searcher = IndexSearcher::new(cursor);
+        let mut search = |timestamp: u64, method: SearchMethod| {
+            index_searcher.search_random_access(PravegaTimestamp::from_nanoseconds(Some(timestamp)), method)
+                .ok().map(|(r, index_offset)| (r.timestamp.nanoseconds().unwrap(), index_offset))
+        };
+        assert_eq!(search(50, SearchMethod::After), Some((100, 0)));
+        assert_eq!(search(100, SearchMethod::After), Some((100, 0)));
+        assert_eq!(search(101, SearchMethod::After), Some((300, 40)));
+        assert_eq!(search(301, SearchMethod::After), None);
+        assert_eq!(search(1000, SearchMethod::After), None);
+        assert_eq!(search(1000, SearchMethod::Before), Some((300, 40)));
+        assert_eq!(search(299, SearchMethod::Before), Some((100, 0)));
+        assert_eq!(search(100, SearchMethod::Before), Some((100, 0)));
+        assert_eq!(search(99, SearchMethod::Before), None);
+    }
+
     #[test]
     fn test_index_searcher() {
         // env_logger::init();
@@ -481,4 +1261,203 @@ mod test {
             }
         }
     }
+
+    /// An in-memory index that can be appended to and truncated while it is being read.
+    #[derive(Clone, Default)]
+    struct SharedIndex {
+        data: Arc<Mutex<Vec<u8>>>,
+        head: Arc<AtomicU64>,
+        position: u64,
+    }
+
+    impl Read for SharedIndex {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let data = self.data.lock().unwrap();
+            let mut cursor = Cursor::new(&data[..]);
+            cursor.set_position(self.position);
+            let n = cursor.read(buf)?;
+            self.position += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Seek for SharedIndex {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            let len = self.data.lock().unwrap().len() as u64;
+            self.position = match pos {
+                SeekFrom::Start(n) => n,
+                SeekFrom::End(n) => (len as i64 + n) as u64,
+                SeekFrom::Current(n) => (self.position as i64 + n) as u64,
+            };
+            Ok(self.position)
+        }
+    }
+
+    impl CurrentHead for SharedIndex {
+        fn current_head(&self) -> std::io::Result<u64> {
+            Ok(self.head.load(Ordering::SeqCst))
+        }
+    }
+
+    #[test]
+    fn test_cached_index_searcher() {
+        let index = SharedIndex::default();
+        let append = |first: u64, last: u64| {
+            let mut data = index.data.lock().unwrap();
+            for t in first..=last {
+                IndexRecordWriter::new().write(&IndexRecord::new(
+                    PravegaTimestamp::from_nanoseconds(Some(t * 100)), t * 1000, true, false), &mut *data).unwrap();
+            }
+        };
+        let searcher = CachedIndexSearcher::new(index.clone(), 5);
+        let search = |t: u64, method: SearchMethod| {
+            searcher.search_timestamp_and_return_index_offset(PravegaTimestamp::from_nanoseconds(Some(t)), method)
+                .ok().map(|(r, index_offset)| (r.timestamp.nanoseconds().unwrap(), index_offset))
+        };
+        assert_eq!(search(100, SearchMethod::Before), None);
+
+        append(1, 3);
+        assert_eq!(search(50, SearchMethod::Before), Some((100, 0)));
+        assert_eq!(search(50, SearchMethod::After), Some((100, 0)));
+        assert_eq!(search(250, SearchMethod::Before), Some((200, 20)));
+        assert_eq!(search(250, SearchMethod::After), Some((300, 40)));
+        assert_eq!(search(1000, SearchMethod::After), Some((300, 40)));
+        assert_eq!(searcher.stats().records_read, 3);
+
+        // New records are read incrementally. The oldest records are evicted but can still be found.
+        append(4, 10);
+        assert_eq!(search(1000, SearchMethod::Before), Some((1000, 180)));
+        let stats = searcher.stats();
+        assert_eq!(stats.records_read, 10);
+        assert_eq!(stats.records, 5);
+        assert_eq!(stats.first_offset, 100);
+        assert_eq!(search(200, SearchMethod::Before), Some((200, 20)));
+        assert!(searcher.stats().records_missed > 0);
+        let records = searcher.get_index_records(140, 200).unwrap();
+        assert_eq!(records.iter().map(|(r, i)| (r.offset, *i)).collect::<Vec<_>>(), vec![(8000, 140), (9000, 160), (10000, 180)]);
+
+        // Truncated records are removed and are not returned.
+        index.head.store(120, Ordering::SeqCst);
+        assert_eq!(search(200, SearchMethod::Before), Some((700, 120)));
+        assert_eq!(searcher.stats().records, 4);
+
+        // The cache is invalidated if all cached records are truncated.
+        append(11, 12);
+        index.head.store(220, Ordering::SeqCst);
+        assert_eq!(search(0, SearchMethod::After), Some((1200, 220)));
+        assert_eq!(searcher.stats().invalidations, 1);
+        assert_eq!(searcher.stats().first_offset, 220);
+
+        // The cache is invalidated if the index is recreated.
+        index.head.store(0, Ordering::SeqCst);
+        index.data.lock().unwrap().clear();
+        append(20, 20);
+        assert_eq!(search(0, SearchMethod::After), Some((2000, 0)));
+        assert_eq!(searcher.stats().invalidations, 2);
+    }
+
+    #[test]
+    fn test_cached_index_searcher_is_shareable() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<CachedIndexSearcher<SharedIndex>>();
+    }
+
+    #[test]
+    fn test_rebuild_index() {
+        const SECOND: u64 = 1_000_000_000;
+        let ts = |t: u64| PravegaTimestamp::from_nanoseconds(Some(1_600_000_000_000_000_000 + t));
+        // (timestamp, include_in_index, random_access, discontinuity)
+        let events = vec![
+            (0, false, false, false),               // delta unit before the first key frame
+            (SECOND / 10, true, true, true),        // first key frame
+            (SECOND / 5, false, false, false),
+            (SECOND * 3 / 10, false, true, false),  // key frame too soon after previous index record
+            (SECOND, true, true, false),            // key frame
+            (SECOND * 11 / 10, false, true, true),  // discontinuity at key frame is always indexed
+            (SECOND * 12 / 10, false, false, false),
+            (SECOND * 12 / 10, false, false, false),// fragment of previous buffer
+            (SECOND * 12, false, false, false),     // delta unit forced after index-max-sec
+            (SECOND * 13, false, false, false),
+        ];
+        let mut data = Cursor::new(Vec::new());
+        let mut offsets = Vec::new();
+        for (t, include_in_index, random_access, discontinuity) in events.iter() {
+            offsets.push(data.position());
+            let payload = vec![0; 100];
+            let event = EventWithHeader::new(&payload[..], ts(*t), *include_in_index, *random_access, *discontinuity);
+            EventWriter::new().write(&event, &mut data).unwrap();
+        }
+        let end_offset = data.position();
+        // Add a partial event that must be ignored.
+        data.get_mut().extend_from_slice(&[0, 0, 0, 0, 0, 0, 1, 0, 0, 0]);
+        let data_length = data.get_ref().len() as u64;
+
+        let mut index = Cursor::new(Vec::new());
+        let summary = rebuild_index(&mut data, data_length, &mut index, IndexSpacing::default()).unwrap();
+        info!("summary={:?}", summary);
+        assert_eq!(summary.events, events.len() as u64);
+        assert_eq!(summary.end_offset, end_offset);
+
+        index.set_position(0);
+        let mut index_searcher = IndexSearcher::new(index);
+        let records: Vec<_> = index_searcher.get_index_records().unwrap().into_iter().map(|(record, _)| record).collect();
+        assert_eq!(records, vec![
+            IndexRecord::new(ts(SECOND / 10), offsets[1], true, true),
+            IndexRecord::new(ts(SECOND), offsets[4], true, false),
+            IndexRecord::new(ts(SECOND * 11 / 10), offsets[5], true, true),
+            IndexRecord::new(ts(SECOND * 12), offsets[8], false, false),
+            IndexRecord::new(ts(SECOND * 13 + 1), end_offset, false, false),
+        ]);
+        assert_eq!(summary.index_records, records.len() as u64);
+    }
+
+    #[test]
+    fn test_get_stream_record_number() {
+        let ts = |t: u64| PravegaTimestamp::from_nanoseconds(Some(1_600_000_000_000_000_000 + t));
+        let video = Track { track_type: TrackType::Video, id: 256 };
+        let audio = Track { track_type: TrackType::Audio, id: 257 };
+        // Events with the stream record numbers 5, 6, and 7.
+        // The event with number 7 was written by a restarted pravegasink at the offset of the final record of the previous one.
+        let mut data = Cursor::new(Vec::new());
+        let mut offsets = Vec::new();
+        for number in [Some(5), Some(6), Some(7), None].iter() {
+            offsets.push(data.position());
+            let payload = vec![0; 100];
+            let event = EventWithHeader::new(&payload[..], ts(offsets.len() as u64), true, true, false)
+                .with_extension(EventHeaderExtension {
+                    stream_record_number: *number,
+                    ..Default::default()
+                });
+            EventWriter::new().with_extended_header(true).write(&event, &mut data).unwrap();
+        }
+        let legacy_offset = offsets[3];
+        let tail_offset = data.position();
+        let records: Vec<_> = vec![
+            IndexRecord::new_with_track(ts(1), offsets[0], true, false, video),
+            IndexRecord::new_with_track(ts(1), offsets[0], true, false, audio),
+            IndexRecord::new_with_track(ts(2), offsets[1], true, false, video),
+            // Final record of the first pravegasink.
+            IndexRecord::new(ts(3), offsets[2], false, false),
+            IndexRecord::new_with_track(ts(3), offsets[2], true, true, video),
+            IndexRecord::new_with_track(ts(3), offsets[2], true, true, audio),
+            IndexRecord::new_with_track(ts(4), legacy_offset, true, false, video),
+            // Final record at the tail of the data stream.
+            IndexRecord::new(ts(5), tail_offset, false, false),
+        ].into_iter().enumerate().map(|(i, record)| (record, (i * IndexRecord::RECORD_SIZE) as u64)).collect();
+        let number = |position: usize, data: &mut Cursor<Vec<u8>>| get_stream_record_number(&records, position, data).unwrap();
+        assert_eq!(number(0, &mut data), Some(5));
+        assert_eq!(number(2, &mut data), Some(6));
+        assert_eq!(number(3, &mut data), Some(7));
+        assert_eq!(number(4, &mut data), Some(8));
+        // An event without a stream record number.
+        assert_eq!(number(6, &mut data), None);
+        // Without the event without a stream record number, the number of the final record is counted from the previous event.
+        data.get_mut().truncate(legacy_offset as usize);
+        let records = &records[..6];
+        let mut records = records.to_vec();
+        records.push((IndexRecord::new(ts(5), legacy_offset, false, false), 6 * IndexRecord::RECORD_SIZE as u64));
+        assert_eq!(get_stream_record_number(&records, 6, &mut data).unwrap(), Some(9));
+        // An index without any readable event.
+        assert_eq!(get_stream_record_number(&records[6..], 0, &mut data).unwrap(), None);
+    }
 }
@@ -0,0 +1,663 @@
+//
+// Copyright (c) Dell Inc., or its subsidiaries. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+
+//! Tamper-evident hash chain over a data stream.
+//!
+//! pravegasink maintains a rolling SHA-256 hash over the serialized events that it writes to the data stream.
+//! For each event, the hash is updated to `SHA-256(previous_hash || event)`, where `event` is the entire
+//! serialized event including the event length, header, payload, and checksum.
+//! A chain begins with a hash of 32 zero bytes at the offset of the first event written by a pravegasink instance.
+//!
+//! Each video stream that is written with an integrity key has a companion integrity stream named `<stream>-integrity`.
+//! pravegasink periodically appends an [IntegrityCheckpoint] to it, when it writes an index record.
+//! A checkpoint records the hash of all events from the beginning of the chain up to the checkpoint offset,
+//! and the offset of the index record in the index stream.
+//! Each checkpoint is signed with an Ed25519 private key.
+//! Checkpoints are framed with [EventWriter] and the payload is JSON.
+//! The integrity stream is small and is never truncated.
+//!
+//! To verify the events between two consecutive checkpoints of the same chain, the hash is recomputed from the
+//! first checkpoint and compared with the second.
+//! This allows any time range to be verified without reading the stream from the beginning of the chain,
+//! and it remains possible after the beginning of the data stream has been truncated.
+
+use crate::event_serde::{EventReader, EventWithHeader, EventWriter};
+use crate::timestamp::PravegaTimestamp;
+use crate::utils::{CurrentHead, decode_hex, encode_hex};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer, Verifier};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{BufReader, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use tracing::{debug, trace};
+
+pub const HASH_SIZE: usize = 32;
+
+/// Prefix of the message that is signed, to prevent signatures from being used in another context.
+const SIGNATURE_CONTEXT: &[u8] = b"pravega-video-integrity-v1";
+
+pub fn get_integrity_stream_name(stream_name: &str) -> String {
+    format!("{}-integrity", stream_name)
+}
+
+/// A rolling SHA-256 hash over consecutive events in a data stream.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HashChain {
+    hash: [u8; HASH_SIZE],
+    offset: u64,
+}
+
+impl HashChain {
+    /// Begin a new chain at the given offset of the data stream.
+    pub fn new(offset: u64) -> Self {
+        HashChain {
+            hash: [0; HASH_SIZE],
+            offset,
+        }
+    }
+
+    /// Continue a chain from a checkpoint.
+    pub fn from_checkpoint(checkpoint: &IntegrityCheckpoint) -> Self {
+        HashChain {
+            hash: checkpoint.hash,
+            offset: checkpoint.offset,
+        }
+    }
+
+    /// Add a serialized event, which must begin at the current offset.
+    pub fn add_event(&mut self, event: &[u8]) {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.hash);
+        hasher.update(event);
+        self.hash = hasher.finalize().into();
+        self.offset += event.len() as u64;
+    }
+
+    pub fn hash(&self) -> &[u8; HASH_SIZE] {
+        &self.hash
+    }
+
+    /// The offset of the data stream after the last event in the chain.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+/// A signed hash of all events in a chain up to an offset of the data stream.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IntegrityCheckpoint {
+    /// The timestamp of the event at the offset, or the end of the last event if there are no more events.
+    pub timestamp: PravegaTimestamp,
+    /// The offset of the data stream after the last event included in the hash.
+    pub offset: u64,
+    /// The offset in the index stream of the index record written at this offset.
+    /// If no index record was written at this offset, this is the offset of the next index record.
+    pub index_offset: u64,
+    /// The offset of the data stream at which the chain begins.
+    pub chain_begin_offset: u64,
+    pub hash: [u8; HASH_SIZE],
+    /// Ed25519 signature of the fields above.
+    pub signature: Vec<u8>,
+}
+
+impl IntegrityCheckpoint {
+    /// Returns an unsigned checkpoint for the current state of the chain.
+    pub fn new(timestamp: PravegaTimestamp, chain: &HashChain, index_offset: u64, chain_begin_offset: u64) -> Self {
+        IntegrityCheckpoint {
+            timestamp,
+            offset: chain.offset(),
+            index_offset,
+            chain_begin_offset,
+            hash: *chain.hash(),
+            signature: Vec::new(),
+        }
+    }
+
+    /// Returns true if this is the first checkpoint of a chain.
+    pub fn is_chain_begin(&self) -> bool {
+        self.offset == self.chain_begin_offset
+    }
+
+    /// The message that is signed.
+    pub fn signed_message(&self) -> Vec<u8> {
+        let mut message = Vec::with_capacity(SIGNATURE_CONTEXT.len() + 4 * 8 + HASH_SIZE);
+        message.extend_from_slice(SIGNATURE_CONTEXT);
+        message.extend_from_slice(&self.chain_begin_offset.to_be_bytes());
+        message.extend_from_slice(&self.offset.to_be_bytes());
+        message.extend_from_slice(&self.index_offset.to_be_bytes());
+        message.extend_from_slice(&self.timestamp.nanoseconds().unwrap_or_default().to_be_bytes());
+        message.extend_from_slice(&self.hash);
+        message
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedIntegrityCheckpoint {
+    offset: u64,
+    index_offset: u64,
+    chain_begin_offset: u64,
+    hash: String,
+    signature: String,
+}
+
+fn read_key_file(path: &Path, description: &str) -> Result<[u8; 32], Error> {
+    let contents = fs::read_to_string(path).map_err(|e| {
+        Error::new(e.kind(), format!("Unable to read {} file {}: {}", description, path.display(), e))
+    })?;
+    decode_hex(contents.trim())
+        .filter(|key| key.len() == 32)
+        .map(|key| <[u8; 32]>::try_from(&key[..]).unwrap())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+            format!("The {} file {} must contain 64 hexadecimal digits", description, path.display())))
+}
+
+/// Signs checkpoints with an Ed25519 private key.
+pub struct IntegritySigner {
+    keypair: Keypair,
+}
+
+impl IntegritySigner {
+    pub fn new(secret_key: &[u8; 32]) -> Self {
+        let secret = SecretKey::from_bytes(&secret_key[..]).unwrap();
+        let public = PublicKey::from(&secret);
+        IntegritySigner {
+            keypair: Keypair { secret, public },
+        }
+    }
+
+    /// Load a private key from a file containing the 32-byte Ed25519 secret key as 64 hexadecimal digits.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(IntegritySigner::new(&read_key_file(path.as_ref(), "integrity private key")?))
+    }
+
+    /// The public key that verifies signatures of this signer, as 64 hexadecimal digits.
+    pub fn public_key_hex(&self) -> String {
+        encode_hex(self.keypair.public.as_bytes())
+    }
+
+    pub fn sign(&self, checkpoint: &mut IntegrityCheckpoint) {
+        let signature = self.keypair.sign(&checkpoint.signed_message());
+        checkpoint.signature = signature.to_bytes().to_vec();
+    }
+}
+
+/// Verifies signatures of checkpoints with an Ed25519 public key.
+pub struct IntegrityVerifier {
+    public_key: PublicKey,
+}
+
+impl IntegrityVerifier {
+    pub fn new(public_key: &[u8; 32]) -> Result<Self, Error> {
+        let public_key = PublicKey::from_bytes(&public_key[..])
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Invalid integrity public key: {}", e)))?;
+        Ok(IntegrityVerifier { public_key })
+    }
+
+    /// Load a public key from a file containing the 32-byte Ed25519 public key as 64 hexadecimal digits.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        IntegrityVerifier::new(&read_key_file(path.as_ref(), "integrity public key")?)
+    }
+
+    pub fn verify(&self, checkpoint: &IntegrityCheckpoint) -> bool {
+        match Signature::try_from(&checkpoint.signature[..]) {
+            Ok(signature) => self.public_key.verify(&checkpoint.signed_message(), &signature).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+pub struct IntegrityCheckpointWriter {
+}
+
+impl IntegrityCheckpointWriter {
+    pub fn new() -> Self {
+        IntegrityCheckpointWriter {}
+    }
+
+    /// Writes the checkpoint with a single call to `write_all`.
+    pub fn write<W>(&mut self, checkpoint: &IntegrityCheckpoint, writer: &mut W) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        let serialized = SerializedIntegrityCheckpoint {
+            offset: checkpoint.offset,
+            index_offset: checkpoint.index_offset,
+            chain_begin_offset: checkpoint.chain_begin_offset,
+            hash: encode_hex(&checkpoint.hash),
+            signature: encode_hex(&checkpoint.signature),
+        };
+        let payload = serde_json::to_vec(&serialized)?;
+        let event = EventWithHeader::new(&payload[..], checkpoint.timestamp, false, false, false);
+        let mut event_writer = EventWriter::new();
+        event_writer.write(&event, writer)
+    }
+}
+
+pub struct IntegrityCheckpointReader {
+}
+
+impl IntegrityCheckpointReader {
+    pub fn new() -> Self {
+        IntegrityCheckpointReader {}
+    }
+
+    pub fn read<R>(&mut self, rdr: &mut R) -> Result<IntegrityCheckpoint, Error>
+    where
+        R: Read,
+    {
+        let mut event_reader = EventReader::new();
+        let required_buffer_length = event_reader.read_required_buffer_length(rdr)?;
+        let mut read_buffer: Vec<u8> = vec![0; required_buffer_length];
+        let event = event_reader.read_event(rdr, &mut read_buffer[..])?;
+        let serialized: SerializedIntegrityCheckpoint = serde_json::from_slice(event.payload)?;
+        let hash = decode_hex(&serialized.hash)
+            .filter(|hash| hash.len() == HASH_SIZE)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Invalid checkpoint hash {}", serialized.hash)))?;
+        let signature = decode_hex(&serialized.signature)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Invalid checkpoint signature {}", serialized.signature)))?;
+        Ok(IntegrityCheckpoint {
+            timestamp: event.header.timestamp,
+            offset: serialized.offset,
+            index_offset: serialized.index_offset,
+            chain_begin_offset: serialized.chain_begin_offset,
+            hash: <[u8; HASH_SIZE]>::try_from(&hash[..]).unwrap(),
+            signature,
+        })
+    }
+}
+
+/// Read all checkpoints from the current head to the current tail of an integrity stream.
+/// A partially written checkpoint at the tail is ignored.
+pub fn read_checkpoints<R>(reader: &mut R) -> Result<Vec<IntegrityCheckpoint>, Error>
+where
+    R: Read + Seek + CurrentHead,
+{
+    let head_offset = reader.current_head()?;
+    let tail_offset = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(head_offset))?;
+    let mut reader = BufReader::new(reader).take(tail_offset - head_offset);
+    let mut checkpoint_reader = IntegrityCheckpointReader::new();
+    let mut checkpoints = Vec::new();
+    loop {
+        match checkpoint_reader.read(&mut reader) {
+            Ok(checkpoint) => {
+                trace!("read_checkpoints: checkpoint={:?}", checkpoint);
+                checkpoints.push(checkpoint);
+            },
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+    }
+    debug!("read_checkpoints: read {} checkpoints", checkpoints.len());
+    Ok(checkpoints)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegrityViolationKind {
+    /// A checkpoint does not have a valid signature. It is ignored.
+    InvalidSignature,
+    /// Two checkpoints of the same chain at the same offset have different hashes.
+    ConflictingCheckpoints,
+    /// The hash of the events between two checkpoints does not match the later checkpoint.
+    HashMismatch,
+    /// The events between two checkpoints do not end at the offset of the later checkpoint.
+    EventBoundaryMismatch,
+    /// The data stream ends before the offset of a checkpoint.
+    MissingData,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IntegrityViolation {
+    pub kind: IntegrityViolationKind,
+    /// The offset in the data stream where verification failed.
+    pub offset: u64,
+    pub message: String,
+}
+
+/// A range of offsets in the data stream, from begin (inclusive) to end (exclusive).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct OffsetRange {
+    pub begin: u64,
+    pub end: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub begin_timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_timestamp: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IntegrityReport {
+    pub begin_offset: u64,
+    pub end_offset: u64,
+    /// The number of checkpoints with valid signatures.
+    pub checkpoint_count: u64,
+    /// The number of events whose hashes were recomputed.
+    pub event_count: u64,
+    /// Ranges of the data stream that match a signed checkpoint.
+    /// A range may extend beyond the requested offsets to the surrounding checkpoints.
+    pub verified: Vec<OffsetRange>,
+    /// Ranges of the requested offsets that are not covered by two checkpoints of the same chain,
+    /// or that were truncated from the data stream.
+    pub unverified: Vec<OffsetRange>,
+    pub violations: Vec<IntegrityViolation>,
+}
+
+impl IntegrityReport {
+    /// Returns true if there are no violations and the entire requested range was verified.
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty() && self.unverified.is_empty()
+    }
+
+    fn add_violation(&mut self, kind: IntegrityViolationKind, offset: u64, message: String) {
+        trace!("verify_integrity: {:?} at offset {}: {}", kind, offset, message);
+        self.violations.push(IntegrityViolation { kind, offset, message });
+    }
+}
+
+/// Recompute the hash chain from the checkpoint `begin` and compare it with the checkpoint `end`.
+/// Returns the number of events that were hashed.
+fn verify_segment<R: Read + Seek>(data_reader: &mut R, data_tail: u64, begin: &IntegrityCheckpoint, end: &IntegrityCheckpoint,
+        report: &mut IntegrityReport) -> Result<u64, Error> {
+    if end.offset > data_tail {
+        report.add_violation(IntegrityViolationKind::MissingData, data_tail,
+            format!("The data stream ends at {} but a checkpoint is at {}", data_tail, end.offset));
+        return Ok(0);
+    }
+    data_reader.seek(SeekFrom::Start(begin.offset))?;
+    let mut reader = BufReader::with_capacity(1024*1024, data_reader);
+    let mut chain = HashChain::from_checkpoint(begin);
+    let mut buffer = Vec::new();
+    let mut event_count = 0;
+    while chain.offset() < end.offset {
+        // Read the event length and then the rest of the event.
+        let mut event_length_bytes = [0; 8];
+        reader.read_exact(&mut event_length_bytes)?;
+        let required_buffer_length = match EventReader::new().read_required_buffer_length(&mut &event_length_bytes[..]) {
+            Ok(length) => length,
+            Err(err) if err.kind() == ErrorKind::InvalidData => {
+                report.add_violation(IntegrityViolationKind::EventBoundaryMismatch, chain.offset(),
+                    format!("{}; events between checkpoints at {} and {} cannot be read", err, begin.offset, end.offset));
+                return Ok(event_count);
+            },
+            Err(err) => return Err(err),
+        };
+        if chain.offset() + required_buffer_length as u64 > end.offset {
+            report.add_violation(IntegrityViolationKind::EventBoundaryMismatch, chain.offset(),
+                format!("Event of {} bytes extends beyond the checkpoint at {}", required_buffer_length, end.offset));
+            return Ok(event_count);
+        }
+        buffer.resize(required_buffer_length, 0);
+        buffer[0..8].copy_from_slice(&event_length_bytes);
+        reader.read_exact(&mut buffer[8..])?;
+        chain.add_event(&buffer[..]);
+        event_count += 1;
+    }
+    if chain.hash() != &end.hash {
+        report.add_violation(IntegrityViolationKind::HashMismatch, begin.offset,
+            format!("The events from offset {} to {} do not match the signed checkpoint; expected hash {}, actual hash {}",
+                begin.offset, end.offset, encode_hex(&end.hash), encode_hex(chain.hash())));
+    }
+    Ok(event_count)
+}
+
+/// Append a range to a list of ranges, merging it with the last range if they are adjacent.
+fn push_range(ranges: &mut Vec<OffsetRange>, range: OffsetRange) {
+    if range.begin >= range.end {
+        return;
+    }
+    if let Some(last) = ranges.last_mut() {
+        if last.end == range.begin {
+            last.end = range.end;
+            last.end_timestamp = range.end_timestamp;
+            return;
+        }
+    }
+    ranges.push(range);
+}
+
+/// Verify the events of a data stream between begin_offset and end_offset against signed checkpoints.
+/// The data stream is read from the checkpoint before begin_offset to the checkpoint after end_offset.
+/// Only I/O errors are returned as errors. All other problems are included in the report.
+pub fn verify_integrity<R>(data_reader: &mut R, data_tail: u64, checkpoints: &[IntegrityCheckpoint], verifier: &IntegrityVerifier,
+        begin_offset: u64, end_offset: u64) -> Result<IntegrityReport, Error>
+where
+    R: Read + Seek + CurrentHead,
+{
+    let mut report = IntegrityReport {
+        begin_offset,
+        end_offset,
+        ..Default::default()
+    };
+    let data_head = data_reader.current_head()?;
+    debug!("verify_integrity: BEGIN: begin_offset={}, end_offset={}, data_head={}, data_tail={}, checkpoints={}",
+        begin_offset, end_offset, data_head, data_tail, checkpoints.len());
+
+    // Group checkpoints with valid signatures by chain, ordered by offset.
+    let mut chains: BTreeMap<u64, BTreeMap<u64, &IntegrityCheckpoint>> = BTreeMap::new();
+    for checkpoint in checkpoints.iter() {
+        if !verifier.verify(checkpoint) {
+            report.add_violation(IntegrityViolationKind::InvalidSignature, checkpoint.offset,
+                format!("Checkpoint does not have a valid signature: {:?}", checkpoint));
+            continue;
+        }
+        report.checkpoint_count += 1;
+        let chain = chains.entry(checkpoint.chain_begin_offset).or_default();
+        match chain.get(&checkpoint.offset) {
+            Some(existing) if existing.hash != checkpoint.hash => {
+                report.add_violation(IntegrityViolationKind::ConflictingCheckpoints, checkpoint.offset,
+                    format!("Checkpoints {:?} and {:?} have different hashes", existing, checkpoint));
+            },
+            Some(_) => {},
+            None => {
+                chain.insert(checkpoint.offset, checkpoint);
+            },
+        }
+    }
+
+    // Verify each segment between consecutive checkpoints that overlaps the requested range.
+    let mut verified = Vec::new();
+    for chain in chains.values() {
+        let chain: Vec<&IntegrityCheckpoint> = chain.values().cloned().collect();
+        for pair in chain.windows(2) {
+            let (begin, end) = (pair[0], pair[1]);
+            if end.offset <= begin_offset || begin.offset >= end_offset || begin.offset == end.offset {
+                continue;
+            }
+            if begin.offset < data_head {
+                debug!("verify_integrity: segment from {} to {} has been truncated", begin.offset, end.offset);
+                continue;
+            }
+            trace!("verify_integrity: verifying segment from {} to {}", begin.offset, end.offset);
+            let violation_count = report.violations.len();
+            report.event_count += verify_segment(data_reader, data_tail, begin, end, &mut report)?;
+            if report.violations.len() == violation_count {
+                verified.push(OffsetRange {
+                    begin: begin.offset,
+                    end: end.offset,
+                    begin_timestamp: begin.timestamp.to_iso_8601(),
+                    end_timestamp: end.timestamp.to_iso_8601(),
+                });
+            }
+        }
+    }
+    verified.sort_by_key(|range| range.begin);
+    for range in verified {
+        push_range(&mut report.verified, range);
+    }
+
+    // Find the parts of the requested range that are not covered by verified ranges.
+    let mut offset = begin_offset;
+    for range in report.verified.iter() {
+        if range.begin > offset {
+            push_range(&mut report.unverified, OffsetRange {
+                begin: offset,
+                end: u64::min(range.begin, end_offset),
+                begin_timestamp: None,
+                end_timestamp: None,
+            });
+        }
+        offset = u64::max(offset, range.end);
+    }
+    if offset < end_offset {
+        push_range(&mut report.unverified, OffsetRange {
+            begin: offset,
+            end: end_offset,
+            begin_timestamp: None,
+            end_timestamp: None,
+        });
+    }
+
+    debug!("verify_integrity: END: verified={:?}, unverified={:?}, violations={}",
+        report.verified, report.unverified, report.violations.len());
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    const SECRET_KEY: [u8; 32] = [7; 32];
+
+    fn ts(t: u64) -> PravegaTimestamp {
+        PravegaTimestamp::from_nanoseconds(Some(1_600_000_000_000_000_000 + t))
+    }
+
+    /// Write events to a data stream, in the same way as pravegasink, with a checkpoint every 10 events.
+    fn create_stream(num_events: u64, signer: &IntegritySigner) -> (Cursor<Vec<u8>>, Vec<IntegrityCheckpoint>) {
+        let mut data = Cursor::new(Vec::new());
+        let mut chain = HashChain::new(0);
+        let mut checkpoints = Vec::new();
+        for i in 0..num_events {
+            if i % 10 == 0 {
+                let mut checkpoint = IntegrityCheckpoint::new(ts(i), &chain, i / 10 * 20, 0);
+                signer.sign(&mut checkpoint);
+                checkpoints.push(checkpoint);
+            }
+            let payload = vec![i as u8; 100];
+            let event = EventWithHeader::new(&payload[..], ts(i), i % 10 == 0, i % 10 == 0, i == 0);
+            let mut serialized = Vec::new();
+            EventWriter::new().write(&event, &mut serialized).unwrap();
+            chain.add_event(&serialized[..]);
+            data.write_all(&serialized[..]).unwrap();
+        }
+        let mut checkpoint = IntegrityCheckpoint::new(ts(num_events), &chain, num_events / 10 * 20, 0);
+        signer.sign(&mut checkpoint);
+        checkpoints.push(checkpoint);
+        (data, checkpoints)
+    }
+
+    fn verify(data: &mut Cursor<Vec<u8>>, checkpoints: &[IntegrityCheckpoint], verifier: &IntegrityVerifier,
+            begin_offset: u64, end_offset: u64) -> IntegrityReport {
+        let data_tail = data.get_ref().len() as u64;
+        let report = verify_integrity(data, data_tail, checkpoints, verifier, begin_offset, end_offset).unwrap();
+        trace!("{}", serde_json::to_string_pretty(&report).unwrap());
+        report
+    }
+
+    #[test]
+    fn test_checkpoint_writer_reader() {
+        let signer = IntegritySigner::new(&SECRET_KEY);
+        let (_, checkpoints) = create_stream(25, &signer);
+        let mut cursor = Cursor::new(Vec::new());
+        let mut writer = IntegrityCheckpointWriter::new();
+        for checkpoint in checkpoints.iter() {
+            writer.write(checkpoint, &mut cursor).unwrap();
+        }
+        // A partially written checkpoint is ignored.
+        cursor.write_all(&[0, 0, 0, 0, 0, 0]).unwrap();
+        let actual = read_checkpoints(&mut cursor).unwrap();
+        assert_eq!(actual, checkpoints);
+        assert!(actual[0].is_chain_begin());
+        assert!(!actual[1].is_chain_begin());
+    }
+
+    #[test]
+    fn test_signature() {
+        let signer = IntegritySigner::new(&SECRET_KEY);
+        let public_key = decode_hex(&signer.public_key_hex()).unwrap();
+        let verifier = IntegrityVerifier::new(&<[u8; 32]>::try_from(&public_key[..]).unwrap()).unwrap();
+        let (_, checkpoints) = create_stream(10, &signer);
+        assert!(verifier.verify(&checkpoints[1]));
+        let mut modified = checkpoints[1].clone();
+        modified.index_offset += 1;
+        assert!(!verifier.verify(&modified));
+        let other_verifier = IntegrityVerifier::new(PublicKey::from(&SecretKey::from_bytes(&[8; 32]).unwrap()).as_bytes()).unwrap();
+        assert!(!other_verifier.verify(&checkpoints[1]));
+    }
+
+    #[test]
+    fn test_verify_integrity_valid() {
+        let signer = IntegritySigner::new(&SECRET_KEY);
+        let verifier = IntegrityVerifier::new(signer.keypair.public.as_bytes()).unwrap();
+        let (mut data, checkpoints) = create_stream(35, &signer);
+        let data_tail = data.get_ref().len() as u64;
+        let report = verify(&mut data, &checkpoints, &verifier, 0, data_tail);
+        assert!(report.is_ok());
+        assert_eq!(report.event_count, 35);
+        assert_eq!(report.verified.len(), 1);
+        assert_eq!((report.verified[0].begin, report.verified[0].end), (0, data_tail));
+
+        // Only the segments that overlap the requested range are read.
+        let event_size = data_tail / 35;
+        let report = verify(&mut data, &checkpoints, &verifier, 12 * event_size, 15 * event_size);
+        assert!(report.is_ok());
+        assert_eq!(report.event_count, 10);
+        assert_eq!((report.verified[0].begin, report.verified[0].end), (10 * event_size, 20 * event_size));
+    }
+
+    #[test]
+    fn test_verify_integrity_tampered() {
+        let signer = IntegritySigner::new(&SECRET_KEY);
+        let verifier = IntegrityVerifier::new(signer.keypair.public.as_bytes()).unwrap();
+        let (mut data, mut checkpoints) = create_stream(35, &signer);
+        let data_tail = data.get_ref().len() as u64;
+        let event_size = data_tail / 35;
+        // Modify a payload byte in the second segment.
+        data.get_mut()[(15 * event_size + 30) as usize] ^= 1;
+        // Modify a checkpoint without signing it.
+        checkpoints[3].hash[0] ^= 1;
+        let report = verify(&mut data, &checkpoints, &verifier, 0, data_tail);
+        assert!(!report.is_ok());
+        let kinds: Vec<_> = report.violations.iter().map(|v| (v.kind, v.offset)).collect();
+        assert_eq!(kinds, vec![
+            (IntegrityViolationKind::InvalidSignature, 30 * event_size),
+            (IntegrityViolationKind::HashMismatch, 10 * event_size),
+        ]);
+        // The segment after the checkpoint with the invalid signature is verified against the following checkpoint.
+        assert_eq!(report.verified.iter().map(|r| (r.begin, r.end)).collect::<Vec<_>>(),
+            vec![(0, 10 * event_size), (20 * event_size, data_tail)]);
+        assert_eq!(report.unverified.iter().map(|r| (r.begin, r.end)).collect::<Vec<_>>(), vec![(10 * event_size, 20 * event_size)]);
+    }
+
+    #[test]
+    fn test_verify_integrity_truncated_and_missing_checkpoint() {
+        let signer = IntegritySigner::new(&SECRET_KEY);
+        let verifier = IntegrityVerifier::new(signer.keypair.public.as_bytes()).unwrap();
+        let (mut data, checkpoints) = create_stream(35, &signer);
+        let data_tail = data.get_ref().len() as u64;
+        let event_size = data_tail / 35;
+        // Without the final checkpoint, the events after the last checkpoint cannot be verified.
+        let checkpoints = &checkpoints[..checkpoints.len() - 1];
+        let report = verify(&mut data, checkpoints, &verifier, 0, data_tail);
+        assert!(report.violations.is_empty());
+        assert!(!report.is_ok());
+        assert_eq!(report.unverified.iter().map(|r| (r.begin, r.end)).collect::<Vec<_>>(), vec![(30 * event_size, data_tail)]);
+
+        // A truncated data stream is missing events required by a checkpoint.
+        let mut truncated = Cursor::new(data.get_ref()[..(25 * event_size) as usize].to_vec());
+        let report = verify(&mut truncated, checkpoints, &verifier, 0, 25 * event_size);
+        assert_eq!(report.violations.iter().map(|v| v.kind).collect::<Vec<_>>(), vec![IntegrityViolationKind::MissingData]);
+    }
+}
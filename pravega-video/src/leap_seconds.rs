@@ -0,0 +1,330 @@
+//
+// Copyright (c) Dell Inc., or its subsidiaries. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+
+//! Leap second table used to convert between UTC and TAI.
+//!
+//! A table of all leap seconds is built in.
+//! It can be replaced by a newer IETF `leap-seconds.list` file,
+//! either by calling `load_leap_seconds_file` or by setting the environment variable
+//! `PRAVEGA_VIDEO_LEAP_SECONDS_FILE` before the first timestamp conversion.
+//! The latest file can be downloaded from https://data.iana.org/time-zones/tzdb/leap-seconds.list.
+
+use anyhow::{anyhow, Context};
+use once_cell::sync::Lazy;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Difference between NTP and Unix epochs.
+/// Times in leap-seconds.list are the number of seconds since the NTP epoch 1900-01-01 00:00:00 UTC.
+const UNIX_TO_NTP_SECONDS: u64 = (70 * 365 + 17) * 24 * 60 * 60;
+
+/// The environment variable with the path of a leap-seconds.list file to use instead of the built-in table.
+pub const LEAP_SECONDS_FILE_ENV_VAR: &str = "PRAVEGA_VIDEO_LEAP_SECONDS_FILE";
+
+/// The leap second table in the format of the IETF leap-seconds.list file.
+/// Each line contains the NTP time when the offset takes effect and the offset TAI - UTC in seconds.
+const BUILTIN_LEAP_SECONDS_LIST: &str = "
+2272060800  10  # 1 Jan 1972
+2287785600  11  # 1 Jul 1972
+2303683200  12  # 1 Jan 1973
+2335219200  13  # 1 Jan 1974
+2366755200  14  # 1 Jan 1975
+2398291200  15  # 1 Jan 1976
+2429913600  16  # 1 Jan 1977
+2461449600  17  # 1 Jan 1978
+2492985600  18  # 1 Jan 1979
+2524521600  19  # 1 Jan 1980
+2571782400  20  # 1 Jul 1981
+2603318400  21  # 1 Jul 1982
+2634854400  22  # 1 Jul 1983
+2698012800  23  # 1 Jul 1985
+2776982400  24  # 1 Jan 1988
+2840140800  25  # 1 Jan 1990
+2871676800  26  # 1 Jan 1991
+2918937600  27  # 1 Jul 1992
+2950473600  28  # 1 Jul 1993
+2982009600  29  # 1 Jul 1994
+3029443200  30  # 1 Jan 1996
+3076704000  31  # 1 Jul 1997
+3124137600  32  # 1 Jan 1999
+3345062400  33  # 1 Jan 2006
+3439756800  34  # 1 Jan 2009
+3550089600  35  # 1 Jul 2012
+3644697600  36  # 1 Jul 2015
+3692217600  37  # 1 Jan 2017
+";
+
+/// A change of the offset between TAI and UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeapSecondEntry {
+    /// The number of seconds since 1970-01-01 00:00:00 UTC, excluding leap seconds,
+    /// when this offset takes effect. This is always midnight UTC.
+    pub unix_seconds: u64,
+    /// TAI - UTC in seconds, beginning at unix_seconds.
+    pub tai_minus_utc: u64,
+}
+
+/// A UTC time that can represent a positive leap second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UtcTime {
+    /// The number of nanoseconds since 1970-01-01 00:00:00 UTC, excluding leap seconds (POSIX time).
+    /// During a positive leap second, this repeats the last second of the day (23:59:59).
+    pub unix_nanoseconds: u64,
+    /// True during a positive leap second (23:59:60).
+    pub is_leap_second: bool,
+}
+
+/// A table of leap seconds, sorted by time.
+/// Before the first entry, TAI and UTC are considered to be equal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeapSecondTable {
+    entries: Vec<LeapSecondEntry>,
+    /// The number of seconds since 1970-01-01 00:00:00 UTC when the table expires.
+    /// After this time, additional leap seconds may have been scheduled.
+    expires_unix_seconds: Option<u64>,
+}
+
+impl LeapSecondTable {
+    /// Returns the built-in leap second table.
+    pub fn builtin() -> LeapSecondTable {
+        LeapSecondTable::parse(BUILTIN_LEAP_SECONDS_LIST).unwrap()
+    }
+
+    /// Parse the contents of an IETF leap-seconds.list file.
+    /// The expiration time (line beginning with "#@") is used but the hash (line beginning with "#h") is not verified.
+    pub fn parse(text: &str) -> anyhow::Result<LeapSecondTable> {
+        let mut entries: Vec<LeapSecondEntry> = Vec::new();
+        let mut expires_unix_seconds = None;
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if let Some(expires) = line.strip_prefix("#@") {
+                let expires_ntp_seconds: u64 = expires.trim().parse()
+                    .with_context(|| format!("invalid expiration time on line {}", line_number + 1))?;
+                expires_unix_seconds = Some(expires_ntp_seconds.saturating_sub(UNIX_TO_NTP_SECONDS));
+                continue;
+            }
+            // Remove comments.
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (ntp_seconds, tai_minus_utc) = match (fields.next(), fields.next()) {
+                (Some(ntp_seconds), Some(tai_minus_utc)) => (ntp_seconds, tai_minus_utc),
+                _ => return Err(anyhow!("expected 2 fields on line {}", line_number + 1)),
+            };
+            let ntp_seconds: u64 = ntp_seconds.parse()
+                .with_context(|| format!("invalid time on line {}", line_number + 1))?;
+            let tai_minus_utc: u64 = tai_minus_utc.parse()
+                .with_context(|| format!("invalid offset on line {}", line_number + 1))?;
+            let unix_seconds = ntp_seconds.checked_sub(UNIX_TO_NTP_SECONDS)
+                .ok_or_else(|| anyhow!("time before 1970 on line {}", line_number + 1))?;
+            if let Some(last) = entries.last() {
+                if unix_seconds <= last.unix_seconds {
+                    return Err(anyhow!("times are not in increasing order on line {}", line_number + 1));
+                }
+            }
+            entries.push(LeapSecondEntry { unix_seconds, tai_minus_utc });
+        }
+        if entries.is_empty() {
+            return Err(anyhow!("no leap seconds found"));
+        }
+        Ok(LeapSecondTable { entries, expires_unix_seconds })
+    }
+
+    /// Read and parse an IETF leap-seconds.list file.
+    pub fn from_file(file_name: &str) -> anyhow::Result<LeapSecondTable> {
+        let text = std::fs::read_to_string(file_name)
+            .with_context(|| format!("unable to read leap seconds file {}", file_name))?;
+        LeapSecondTable::parse(&text)
+            .with_context(|| format!("unable to parse leap seconds file {}", file_name))
+    }
+
+    pub fn entries(&self) -> &[LeapSecondEntry] {
+        &self.entries
+    }
+
+    pub fn expires_unix_seconds(&self) -> Option<u64> {
+        self.expires_unix_seconds
+    }
+
+    /// Returns true if the table has an expiration time that is before the given time.
+    pub fn is_expired(&self, unix_seconds: u64) -> bool {
+        matches!(self.expires_unix_seconds, Some(expires) if expires < unix_seconds)
+    }
+
+    /// Returns TAI - UTC in seconds at the given UTC time.
+    pub fn tai_minus_utc(&self, unix_nanoseconds: u64) -> u64 {
+        let unix_seconds = unix_nanoseconds / NANOSECONDS_PER_SECOND;
+        match self.entries.iter().rev().find(|entry| entry.unix_seconds <= unix_seconds) {
+            Some(entry) => entry.tai_minus_utc,
+            None => 0,
+        }
+    }
+
+    /// Convert UTC to the number of nanoseconds since 1970-01-01 00:00:00 TAI.
+    pub fn utc_to_tai(&self, utc: UtcTime) -> u64 {
+        if utc.is_leap_second {
+            // The leap second ends at the beginning of the next second, when the new offset takes effect.
+            let end_unix_nanoseconds = (utc.unix_nanoseconds / NANOSECONDS_PER_SECOND + 1) * NANOSECONDS_PER_SECOND;
+            let end_tai_nanoseconds = end_unix_nanoseconds + self.tai_minus_utc(end_unix_nanoseconds) * NANOSECONDS_PER_SECOND;
+            end_tai_nanoseconds - NANOSECONDS_PER_SECOND + utc.unix_nanoseconds % NANOSECONDS_PER_SECOND
+        } else {
+            utc.unix_nanoseconds + self.tai_minus_utc(utc.unix_nanoseconds) * NANOSECONDS_PER_SECOND
+        }
+    }
+
+    /// Convert the number of nanoseconds since 1970-01-01 00:00:00 TAI to UTC.
+    pub fn tai_to_utc(&self, tai_nanoseconds: u64) -> UtcTime {
+        for (i, entry) in self.entries.iter().enumerate().rev() {
+            let begin_unix_nanoseconds = entry.unix_seconds * NANOSECONDS_PER_SECOND;
+            let begin_tai_nanoseconds = begin_unix_nanoseconds + entry.tai_minus_utc * NANOSECONDS_PER_SECOND;
+            if tai_nanoseconds >= begin_tai_nanoseconds {
+                return UtcTime {
+                    unix_nanoseconds: tai_nanoseconds - entry.tai_minus_utc * NANOSECONDS_PER_SECOND,
+                    is_leap_second: false,
+                };
+            }
+            // Positive leap seconds are inserted between the previous offset and this one.
+            let prev_tai_minus_utc = if i > 0 { self.entries[i - 1].tai_minus_utc } else { 0 };
+            let leap_begin_tai_nanoseconds = begin_unix_nanoseconds + prev_tai_minus_utc * NANOSECONDS_PER_SECOND;
+            if tai_nanoseconds >= leap_begin_tai_nanoseconds {
+                // UTC can only represent a single leap second (23:59:60).
+                // The 10 second offset when the table begins in 1972 will be clamped to it.
+                let leap_nanoseconds = u64::min(tai_nanoseconds - leap_begin_tai_nanoseconds, NANOSECONDS_PER_SECOND - 1);
+                return UtcTime {
+                    unix_nanoseconds: begin_unix_nanoseconds - NANOSECONDS_PER_SECOND + leap_nanoseconds,
+                    is_leap_second: true,
+                };
+            }
+        }
+        UtcTime {
+            unix_nanoseconds: tai_nanoseconds,
+            is_leap_second: false,
+        }
+    }
+}
+
+static LEAP_SECOND_TABLE: Lazy<RwLock<Arc<LeapSecondTable>>> = Lazy::new(|| {
+    let builtin = LeapSecondTable::builtin();
+    let table = match std::env::var(LEAP_SECONDS_FILE_ENV_VAR) {
+        Ok(file_name) if !file_name.is_empty() => {
+            match LeapSecondTable::from_file(&file_name).and_then(|table| validate_table(&builtin, table)) {
+                Ok(table) => table,
+                Err(err) => {
+                    warn!("Using built-in leap second table: {:#}", err);
+                    builtin
+                },
+            }
+        },
+        _ => builtin,
+    };
+    RwLock::new(Arc::new(table))
+});
+
+/// Ensure that a table loaded from a file is not older than the built-in table.
+fn validate_table(builtin: &LeapSecondTable, table: LeapSecondTable) -> anyhow::Result<LeapSecondTable> {
+    if !table.entries.starts_with(&builtin.entries) {
+        return Err(anyhow!("leap second table does not include all built-in leap seconds"));
+    }
+    let now_unix_seconds = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    if table.is_expired(now_unix_seconds) {
+        warn!("Leap second table has expired; additional leap seconds may have been scheduled");
+    }
+    Ok(table)
+}
+
+/// Returns the leap second table used for all timestamp conversions.
+pub fn leap_second_table() -> Arc<LeapSecondTable> {
+    LEAP_SECOND_TABLE.read().unwrap().clone()
+}
+
+/// Replace the leap second table used for all timestamp conversions with the contents of
+/// an IETF leap-seconds.list file.
+/// The file must include all leap seconds in the built-in table.
+/// This can be called periodically to pick up newly scheduled leap seconds.
+pub fn load_leap_seconds_file(file_name: &str) -> anyhow::Result<()> {
+    let table = validate_table(&LeapSecondTable::builtin(), LeapSecondTable::from_file(file_name)?)?;
+    info!("Loaded {} leap seconds from {}", table.entries.len(), file_name);
+    *LEAP_SECOND_TABLE.write().unwrap() = Arc::new(table);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const NS: u64 = NANOSECONDS_PER_SECOND;
+    // 2017-01-01T00:00:00Z
+    const UNIX_2017: u64 = 1483228800;
+
+    #[test]
+    fn test_builtin_table() {
+        let table = LeapSecondTable::builtin();
+        assert_eq!(table.entries().len(), 28);
+        // 1972-01-01T00:00:00Z
+        assert_eq!(table.entries()[0], LeapSecondEntry { unix_seconds: 63072000, tai_minus_utc: 10 });
+        assert_eq!(table.entries()[27], LeapSecondEntry { unix_seconds: UNIX_2017, tai_minus_utc: 37 });
+        assert_eq!(table.tai_minus_utc(0), 0);
+        // 1985-01-01T00:00:00Z
+        assert_eq!(table.tai_minus_utc(473385600 * NS), 22);
+        assert_eq!(table.tai_minus_utc(UNIX_2017 * NS - 1), 36);
+        assert_eq!(table.tai_minus_utc(UNIX_2017 * NS), 37);
+    }
+
+    #[test]
+    fn test_leap_second_conversion() {
+        let table = LeapSecondTable::builtin();
+        // 2016-12-31T23:59:59.5Z
+        let before = UtcTime { unix_nanoseconds: UNIX_2017 * NS - NS / 2, is_leap_second: false };
+        // 2016-12-31T23:59:60.5Z
+        let leap = UtcTime { unix_nanoseconds: UNIX_2017 * NS - NS / 2, is_leap_second: true };
+        // 2017-01-01T00:00:00.5Z
+        let after = UtcTime { unix_nanoseconds: UNIX_2017 * NS + NS / 2, is_leap_second: false };
+        assert_eq!(table.utc_to_tai(before), (UNIX_2017 + 35) * NS + NS / 2);
+        assert_eq!(table.utc_to_tai(leap), (UNIX_2017 + 36) * NS + NS / 2);
+        assert_eq!(table.utc_to_tai(after), (UNIX_2017 + 37) * NS + NS / 2);
+        for utc in [before, leap, after].iter() {
+            assert_eq!(table.tai_to_utc(table.utc_to_tai(*utc)), *utc);
+        }
+        // Before the first leap second, TAI equals UTC.
+        let utc = UtcTime { unix_nanoseconds: 1_000, is_leap_second: false };
+        assert_eq!(table.utc_to_tai(utc), 1_000);
+        assert_eq!(table.tai_to_utc(1_000), utc);
+    }
+
+    #[test]
+    fn test_parse_leap_seconds_list() {
+        let text = "
+#	Updated through IERS Bulletin C
+#$	 3913697179
+#@	3960057600
+#
+2272060800	10	# 1 Jan 1972
+3692217600	37	# 1 Jan 2017
+4000000000	38	# hypothetical
+#h	0 0 0 0 0
+";
+        let table = LeapSecondTable::parse(text).unwrap();
+        assert_eq!(table.entries().len(), 3);
+        assert_eq!(table.expires_unix_seconds(), Some(3960057600 - UNIX_TO_NTP_SECONDS));
+        assert!(!table.is_expired(UNIX_2017));
+        assert!(table.is_expired(4000000000));
+        assert_eq!(table.entries()[2].unix_seconds, 4000000000 - UNIX_TO_NTP_SECONDS);
+        // A table with fewer leap seconds than the built-in table is rejected.
+        assert!(validate_table(&LeapSecondTable::builtin(), table).is_err());
+        assert!(LeapSecondTable::parse("").is_err());
+        assert!(LeapSecondTable::parse("3692217600 37\n2272060800 10\n").is_err());
+        assert!(LeapSecondTable::parse("3692217600\n").is_err());
+    }
+}
@@ -8,8 +8,17 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 //
 
+pub mod encryption;
 pub mod event_serde;
 pub mod index;
+pub mod integrity;
+pub mod leap_seconds;
+pub mod metadata;
+pub mod mp4;
+pub mod mpegts;
+pub mod retention;
+pub mod storage;
 pub mod timestamp;
 pub mod tracing;
 pub mod utils;
+pub mod verify;
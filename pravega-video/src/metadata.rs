@@ -0,0 +1,285 @@
+//
+// Copyright (c) Dell Inc., or its subsidiaries. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+
+//! Stream metadata describing the format of the data stream.
+//!
+//! Each video stream has a companion metadata stream named `<stream>-meta`.
+//! pravegasink appends a [MetadataRecord] to the metadata stream whenever the input caps change.
+//! Each record applies to the data stream starting at the record's offset, until the next record.
+//! Records are framed with [EventWriter] and the payload is JSON.
+//! The metadata stream is small and is never truncated.
+
+use crate::event_serde::{EventReader, EventWithHeader, EventWriter};
+use crate::timestamp::PravegaTimestamp;
+use crate::utils::CurrentHead;
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use tracing::{debug, trace};
+
+pub const CONTENT_TYPE_MP4: &str = "video/mp4";
+pub const CONTENT_TYPE_MPEG_TS: &str = "video/mp2t";
+
+pub fn get_metadata_stream_name(stream_name: &str) -> String {
+    format!("{}-meta", stream_name)
+}
+
+/// Map the media type of GStreamer caps to a MIME content type.
+/// Returns None if the media type is not a known container.
+pub fn caps_to_content_type(caps: &str) -> Option<String> {
+    let media_type = caps.split(|c| c == ',' || c == ';').next()?.trim();
+    let content_type = match media_type {
+        "video/quicktime" => CONTENT_TYPE_MP4,
+        "video/mpegts" => CONTENT_TYPE_MPEG_TS,
+        "video/x-matroska" => "video/x-matroska",
+        "video/webm" => "video/webm",
+        "audio/x-m4a" => "audio/mp4",
+        "audio/mpeg" => "audio/mpeg",
+        _ => return None,
+    };
+    Some(content_type.to_owned())
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StreamMetadata {
+    /// The GStreamer caps of the data stream, serialized as a string.
+    pub caps: String,
+    /// The MIME content type of the container, such as "video/mp4".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// The codecs in the container, as used by the RFC 6381 codecs parameter, such as "avc1.640028,mp4a.40.2".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codecs: Option<String>,
+}
+
+impl StreamMetadata {
+    pub fn new(caps: String) -> Self {
+        let content_type = caps_to_content_type(&caps);
+        StreamMetadata {
+            caps,
+            content_type,
+            codecs: None,
+        }
+    }
+
+    /// Returns true if the codecs can be determined from the data but have not yet been.
+    pub fn needs_codecs(&self) -> bool {
+        self.codecs.is_none() && self.content_type.as_deref() == Some(CONTENT_TYPE_MP4)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetadataRecord {
+    /// The timestamp of the first buffer written with this metadata.
+    pub timestamp: PravegaTimestamp,
+    /// The offset in the data stream at which this metadata begins to apply.
+    pub offset: u64,
+    pub metadata: StreamMetadata,
+}
+
+impl MetadataRecord {
+    pub fn new(timestamp: PravegaTimestamp, offset: u64, metadata: StreamMetadata) -> MetadataRecord {
+        MetadataRecord {
+            timestamp,
+            offset,
+            metadata,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SerializedMetadataRecordRef<'a> {
+    offset: u64,
+    metadata: &'a StreamMetadata,
+}
+
+#[derive(Deserialize)]
+struct SerializedMetadataRecord {
+    offset: u64,
+    metadata: StreamMetadata,
+}
+
+pub struct MetadataRecordWriter {
+}
+
+impl MetadataRecordWriter {
+    pub fn new() -> Self {
+        MetadataRecordWriter {}
+    }
+
+    /// Writes the record with a single call to `write_all`.
+    pub fn write<W>(&mut self, record: &MetadataRecord, writer: &mut W) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        let serialized = SerializedMetadataRecordRef {
+            offset: record.offset,
+            metadata: &record.metadata,
+        };
+        let payload = serde_json::to_vec(&serialized)?;
+        let event = EventWithHeader::new(&payload[..], record.timestamp, false, false, false);
+        let mut event_writer = EventWriter::new();
+        event_writer.write(&event, writer)
+    }
+}
+
+pub struct MetadataRecordReader {
+}
+
+impl MetadataRecordReader {
+    pub fn new() -> Self {
+        MetadataRecordReader {}
+    }
+
+    pub fn read<R>(&mut self, rdr: &mut R) -> Result<MetadataRecord, Error>
+    where
+        R: Read,
+    {
+        let mut event_reader = EventReader::new();
+        let required_buffer_length = event_reader.read_required_buffer_length(rdr)?;
+        let mut read_buffer: Vec<u8> = vec![0; required_buffer_length];
+        let event = event_reader.read_event(rdr, &mut read_buffer[..])?;
+        let serialized: SerializedMetadataRecord = serde_json::from_slice(event.payload)?;
+        Ok(MetadataRecord {
+            timestamp: event.header.timestamp,
+            offset: serialized.offset,
+            metadata: serialized.metadata,
+        })
+    }
+}
+
+/// Maintains all records in a metadata stream.
+/// Call `refresh` to read records that have been appended since the last call.
+pub struct MetadataSearcher<R: Read + Seek + CurrentHead> {
+    reader: R,
+    next_offset: u64,
+    records: Vec<MetadataRecord>,
+}
+
+impl<R: Read + Seek + CurrentHead> MetadataSearcher<R> {
+    pub fn new(reader: R) -> Self {
+        MetadataSearcher {
+            reader,
+            next_offset: 0,
+            records: Vec::new(),
+        }
+    }
+
+    /// Read all records that are currently in the stream and that have not been read yet.
+    /// This will not block waiting for new records.
+    /// Returns the number of new records.
+    pub fn refresh(&mut self) -> Result<usize, Error> {
+        let head_offset = self.reader.current_head()?;
+        let tail_offset = self.reader.seek(SeekFrom::End(0))?;
+        if tail_offset < self.next_offset {
+            // The stream was recreated.
+            debug!("MetadataSearcher::refresh: invalidating {} records; next_offset={}, tail_offset={}",
+                self.records.len(), self.next_offset, tail_offset);
+            self.records.clear();
+            self.next_offset = 0;
+        }
+        let begin_offset = u64::max(head_offset, self.next_offset);
+        if begin_offset >= tail_offset {
+            return Ok(0);
+        }
+        self.reader.seek(SeekFrom::Start(begin_offset))?;
+        // Ensure EOF instead of waiting for appends when we get to the current end.
+        let mut reader = (&mut self.reader).take(tail_offset - begin_offset);
+        let mut record_reader = MetadataRecordReader::new();
+        let mut count = 0;
+        loop {
+            match record_reader.read(&mut reader) {
+                Ok(record) => {
+                    trace!("MetadataSearcher::refresh: record={:?}", record);
+                    self.records.push(record);
+                    self.next_offset = tail_offset - reader.limit();
+                    count += 1;
+                },
+                // A partially written record will be read again on the next refresh.
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+        }
+        debug!("MetadataSearcher::refresh: read {} records; next_offset={}", count, self.next_offset);
+        Ok(count)
+    }
+
+    pub fn records(&self) -> &[MetadataRecord] {
+        &self.records[..]
+    }
+
+    pub fn get_last_record(&self) -> Option<&MetadataRecord> {
+        self.records.last()
+    }
+
+    /// Returns the most recently written record that applies to the data at the given offset.
+    pub fn search_offset(&self, offset: u64) -> Option<&MetadataRecord> {
+        self.records.iter().rev().find(|r| r.offset <= offset)
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_caps_to_content_type() {
+        assert_eq!(caps_to_content_type("video/quicktime, variant=(string)iso-fragmented"), Some(CONTENT_TYPE_MP4.to_owned()));
+        assert_eq!(caps_to_content_type("video/mpegts, systemstream=(boolean)true, packetsize=(int)188"), Some(CONTENT_TYPE_MPEG_TS.to_owned()));
+        assert_eq!(caps_to_content_type("video/x-h264"), None);
+        assert_eq!(caps_to_content_type(""), None);
+    }
+
+    #[test]
+    fn test_metadata_searcher() {
+        let ts_metadata = StreamMetadata::new("video/mpegts, systemstream=(boolean)true".to_owned());
+        let mut mp4_metadata = StreamMetadata::new("video/quicktime, variant=(string)iso-fragmented".to_owned());
+        assert!(!ts_metadata.needs_codecs());
+        assert!(mp4_metadata.needs_codecs());
+        mp4_metadata.codecs = Some("avc1.640028".to_owned());
+        let record1 = MetadataRecord::new(PravegaTimestamp::from_nanoseconds(Some(100)), 0, ts_metadata);
+        let record2 = MetadataRecord::new(PravegaTimestamp::from_nanoseconds(Some(200)), 1000, mp4_metadata);
+
+        let mut cursor = Cursor::new(Vec::new());
+        let mut writer = MetadataRecordWriter::new();
+        writer.write(&record1, &mut cursor).unwrap();
+        let mut searcher = MetadataSearcher::new(cursor);
+        assert_eq!(searcher.refresh().unwrap(), 1);
+        assert_eq!(searcher.refresh().unwrap(), 0);
+        assert_eq!(searcher.search_offset(2000), Some(&record1));
+
+        let mut cursor = searcher.into_inner();
+        cursor.seek(SeekFrom::End(0)).unwrap();
+        writer.write(&record2, &mut cursor).unwrap();
+        let mut searcher = MetadataSearcher::new(cursor);
+        assert_eq!(searcher.refresh().unwrap(), 2);
+        assert_eq!(searcher.records(), &[record1.clone(), record2.clone()]);
+        assert_eq!(searcher.search_offset(0), Some(&record1));
+        assert_eq!(searcher.search_offset(999), Some(&record1));
+        assert_eq!(searcher.search_offset(1000), Some(&record2));
+        assert_eq!(searcher.get_last_record(), Some(&record2));
+
+        // If the stream is recreated, the records are read again.
+        let cursor = searcher.get_mut();
+        cursor.get_mut().clear();
+        cursor.set_position(0);
+        writer.write(&record2, cursor).unwrap();
+        assert_eq!(searcher.refresh().unwrap(), 1);
+        assert_eq!(searcher.records(), &[record2.clone()]);
+    }
+}
@@ -0,0 +1,223 @@
+//
+// Copyright (c) Dell Inc., or its subsidiaries. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+
+// A minimal ISO base media file format (MP4) parser that determines the codecs of each track
+// from the sample descriptions in the moov box.
+// The codecs are formatted as in RFC 6381, for use in the HLS CODECS attribute.
+
+use std::convert::TryInto;
+use tracing::trace;
+
+/// Returns the child boxes in the data as a list of (type, body).
+/// Parsing stops at the first incomplete box.
+fn get_boxes(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut boxes = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let size = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as u64;
+        let box_type = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+        let (header_size, size) = match size {
+            0 => (8, (data.len() - pos) as u64),
+            1 => {
+                if pos + 16 > data.len() {
+                    break;
+                }
+                let mut largesize = [0; 8];
+                largesize.copy_from_slice(&data[pos + 8..pos + 16]);
+                (16, u64::from_be_bytes(largesize))
+            },
+            _ => (8, size),
+        };
+        if size < header_size || pos as u64 + size > data.len() as u64 {
+            break;
+        }
+        let end = pos + size as usize;
+        boxes.push((box_type, &data[pos + header_size as usize..end]));
+        pos = end;
+    }
+    boxes
+}
+
+fn find_box<'a>(data: &'a [u8], box_type: &[u8; 4]) -> Option<&'a [u8]> {
+    get_boxes(data).into_iter().find(|(t, _)| t == box_type).map(|(_, body)| body)
+}
+
+/// Returns the codecs of all tracks in the moov box, separated by commas.
+/// The data should begin with a top-level box, as in an MP4 initialization segment.
+/// Returns None if there is no moov box or no recognized sample entry.
+pub fn get_codecs(data: &[u8]) -> Option<String> {
+    let moov = find_box(data, b"moov")?;
+    let codecs: Vec<String> = get_boxes(moov).into_iter()
+        .filter(|(t, _)| t == b"trak")
+        .filter_map(|(_, trak)| {
+            let mdia = find_box(trak, b"mdia")?;
+            let minf = find_box(mdia, b"minf")?;
+            let stbl = find_box(minf, b"stbl")?;
+            let stsd = find_box(stbl, b"stsd")?;
+            // Skip version, flags, and entry_count.
+            let (entry_type, entry) = *get_boxes(stsd.get(8..)?).first()?;
+            get_sample_entry_codec(&entry_type, entry)
+        })
+        .collect();
+    trace!("get_codecs: codecs={:?}", codecs);
+    if codecs.is_empty() {
+        None
+    } else {
+        Some(codecs.join(","))
+    }
+}
+
+fn get_sample_entry_codec(entry_type: &[u8; 4], entry: &[u8]) -> Option<String> {
+    // The fields of VisualSampleEntry and AudioSampleEntry (version 0) precede the child boxes.
+    const VISUAL_SAMPLE_ENTRY_SIZE: usize = 78;
+    const AUDIO_SAMPLE_ENTRY_SIZE: usize = 28;
+    let fourcc = std::str::from_utf8(entry_type).ok()?;
+    match entry_type {
+        b"avc1" | b"avc3" => {
+            let avcc = find_box(entry.get(VISUAL_SAMPLE_ENTRY_SIZE..)?, b"avcC")?;
+            // configurationVersion, AVCProfileIndication, profile_compatibility, AVCLevelIndication
+            Some(format!("{}.{:02X}{:02X}{:02X}", fourcc, avcc.get(1)?, avcc.get(2)?, avcc.get(3)?))
+        },
+        b"hvc1" | b"hev1" => {
+            let hvcc = find_box(entry.get(VISUAL_SAMPLE_ENTRY_SIZE..)?, b"hvcC")?;
+            get_hevc_codec(fourcc, hvcc)
+        },
+        b"mp4a" => {
+            let esds = find_box(entry.get(AUDIO_SAMPLE_ENTRY_SIZE..)?, b"esds")?;
+            // Skip version and flags.
+            get_mp4a_codec(esds.get(4..)?)
+        },
+        _ => None,
+    }
+}
+
+/// Format the codec of an HEVCDecoderConfigurationRecord as in ISO/IEC 14496-15 Annex E.
+fn get_hevc_codec(fourcc: &str, hvcc: &[u8]) -> Option<String> {
+    let b = hvcc.get(1)?;
+    let profile_space = ["", "A", "B", "C"][(b >> 6) as usize];
+    let tier = if b & 0x20 != 0 { "H" } else { "L" };
+    let profile_idc = b & 0x1F;
+    let compatibility_flags = u32::from_be_bytes(hvcc.get(2..6)?.try_into().ok()?).reverse_bits();
+    let constraint_flags = hvcc.get(6..12)?;
+    let level_idc = hvcc.get(12)?;
+    let mut codec = format!("{}.{}{}.{:X}.{}{}", fourcc, profile_space, profile_idc, compatibility_flags, tier, level_idc);
+    // Trailing bytes of the constraint flags that are 0 are omitted.
+    let constraint_length = constraint_flags.iter().rposition(|&f| f != 0).map_or(0, |p| p + 1);
+    for flags in &constraint_flags[..constraint_length] {
+        codec.push_str(&format!(".{:X}", flags));
+    }
+    Some(codec)
+}
+
+/// Read the length of an MPEG-4 descriptor (ISO/IEC 14496-1 8.3.3).
+/// Returns (length, number of bytes used to encode the length).
+fn get_descriptor_length(data: &[u8]) -> Option<(usize, usize)> {
+    let mut length = 0;
+    for (i, b) in data.iter().take(4).enumerate() {
+        length = (length << 7) | (b & 0x7F) as usize;
+        if b & 0x80 == 0 {
+            return Some((length, i + 1));
+        }
+    }
+    None
+}
+
+/// Returns the descriptor body if the data begins with a descriptor with the given tag.
+fn get_descriptor(data: &[u8], tag: u8) -> Option<&[u8]> {
+    if *data.first()? != tag {
+        return None;
+    }
+    let (length, length_size) = get_descriptor_length(&data[1..])?;
+    data.get(1 + length_size..1 + length_size + length)
+}
+
+/// Format the codec of an ES_Descriptor as in RFC 6381 3.3.
+fn get_mp4a_codec(es_descriptor: &[u8]) -> Option<String> {
+    const ES_DESCRIPTOR_TAG: u8 = 0x03;
+    const DECODER_CONFIG_DESCRIPTOR_TAG: u8 = 0x04;
+    const DECODER_SPECIFIC_INFO_TAG: u8 = 0x05;
+    let es = get_descriptor(es_descriptor, ES_DESCRIPTOR_TAG)?;
+    // Skip ES_ID and the optional fields indicated by the flags.
+    let flags = es.get(2)?;
+    let mut pos = 3;
+    if flags & 0x80 != 0 {
+        pos += 2;
+    }
+    if flags & 0x40 != 0 {
+        pos += 1 + *es.get(pos)? as usize;
+    }
+    if flags & 0x20 != 0 {
+        pos += 2;
+    }
+    let decoder_config = get_descriptor(es.get(pos..)?, DECODER_CONFIG_DESCRIPTOR_TAG)?;
+    let object_type_indication = decoder_config.first()?;
+    // objectTypeIndication, streamType, bufferSizeDB, maxBitrate, avgBitrate
+    let audio_object_type = decoder_config.get(13..)
+        .and_then(|d| get_descriptor(d, DECODER_SPECIFIC_INFO_TAG))
+        .and_then(|audio_specific_config| {
+            let aot = audio_specific_config.first()? >> 3;
+            if aot == 31 {
+                let b = audio_specific_config.get(..2)?;
+                Some(32 + (((b[0] & 0x07) << 3) | (b[1] >> 5)))
+            } else {
+                Some(aot)
+            }
+        });
+    Some(match audio_object_type {
+        Some(aot) => format!("mp4a.{:02x}.{}", object_type_indication, aot),
+        None => format!("mp4a.{:02x}", object_type_indication),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_box(box_type: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(box_type);
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn make_trak(sample_entry: Vec<u8>) -> Vec<u8> {
+        let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stsd.extend(sample_entry);
+        let stbl = make_box(b"stbl", &make_box(b"stsd", &stsd));
+        let minf = make_box(b"minf", &stbl);
+        let mdia = make_box(b"mdia", &[make_box(b"mdhd", &[0; 24]), minf].concat());
+        make_box(b"trak", &[make_box(b"tkhd", &[0; 84]), mdia].concat())
+    }
+
+    #[test]
+    fn test_get_codecs() {
+        let avcc = make_box(b"avcC", &[0x01, 0x64, 0x00, 0x28, 0xFF, 0xE1]);
+        let avc1 = make_box(b"avc1", &[vec![0; 78], avcc].concat());
+        let esds = make_box(b"esds", &[
+            0, 0, 0, 0,
+            0x03, 0x19, 0x00, 0x01, 0x00,
+            0x04, 0x11, 0x40, 0x15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0x05, 0x02, 0x12, 0x10,
+            0x06, 0x01, 0x02]);
+        let mp4a = make_box(b"mp4a", &[vec![0; 28], esds].concat());
+        let moov = make_box(b"moov", &[make_box(b"mvhd", &[0; 100]), make_trak(avc1), make_trak(mp4a)].concat());
+        let data = [make_box(b"ftyp", b"iso6\0\0\0\0"), moov, make_box(b"moof", &[])].concat();
+        assert_eq!(get_codecs(&data), Some("avc1.640028,mp4a.40.2".to_owned()));
+        assert_eq!(get_codecs(&make_box(b"moof", &[])), None);
+        assert_eq!(get_codecs(&data[..20]), None);
+    }
+
+    #[test]
+    fn test_get_hevc_codec() {
+        // Main profile, main tier, level 4.0
+        let hvcc = [0x01, 0x01, 0x60, 0x00, 0x00, 0x00, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00, 0x78];
+        assert_eq!(get_hevc_codec("hvc1", &hvcc), Some("hvc1.1.6.L120.90".to_owned()));
+    }
+}
@@ -0,0 +1,209 @@
+//
+// Copyright (c) Dell Inc., or its subsidiaries. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+
+// A minimal MPEG transport stream parser that identifies random access points for each elementary stream.
+// Only the PAT and PMT are decoded. PSI sections are assumed to fit in a single TS packet.
+
+use crate::index::{Track, TrackType};
+use std::collections::{HashMap, HashSet};
+use tracing::trace;
+
+pub const TS_PACKET_SIZE: usize = 188;
+const TS_SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0x0000;
+const PAT_TABLE_ID: u8 = 0x00;
+const PMT_TABLE_ID: u8 = 0x02;
+
+/// Map an MPEG-TS stream_type (ISO/IEC 13818-1 Table 2-34) to a track type.
+pub fn stream_type_to_track_type(stream_type: u8) -> TrackType {
+    match stream_type {
+        // MPEG-1/2 video, MPEG-4 part 2, H.264, H.265
+        0x01 | 0x02 | 0x10 | 0x1B | 0x24 => TrackType::Video,
+        // MPEG-1/2 audio, AAC (ADTS), AAC (LATM), AC-3, E-AC-3
+        0x03 | 0x04 | 0x0F | 0x11 | 0x81 | 0x87 => TrackType::Audio,
+        _ => TrackType::Other,
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MpegTsParser {
+    pmt_pids: HashSet<u16>,
+    /// Elementary streams by PID.
+    streams: HashMap<u16, TrackType>,
+}
+
+impl MpegTsParser {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the elementary stream tracks that have been identified in the PMT.
+    pub fn tracks(&self) -> Vec<Track> {
+        let mut tracks: Vec<Track> = self.streams.iter().map(|(&id, &track_type)| Track { track_type, id }).collect();
+        tracks.sort_by_key(|t| t.id);
+        tracks
+    }
+
+    /// Parse all TS packets in the buffer.
+    /// Returns the tracks that have a random access point that begins in this buffer, in the order they were found.
+    /// A video random access point is identified by the random_access_indicator in the adaptation field.
+    /// An audio random access point is the start of any PES packet.
+    /// Incomplete packets at the end of the buffer are ignored.
+    pub fn parse(&mut self, buffer: &[u8]) -> Vec<Track> {
+        let mut random_access_tracks = Vec::new();
+        for packet in buffer.chunks_exact(TS_PACKET_SIZE) {
+            if packet[0] != TS_SYNC_BYTE {
+                trace!("MpegTsParser::parse: lost sync");
+                break;
+            }
+            let payload_unit_start = packet[1] & 0x40 != 0;
+            let pid = (((packet[1] & 0x1F) as u16) << 8) | packet[2] as u16;
+            let adaptation_field_control = (packet[3] >> 4) & 0x3;
+            let mut random_access = false;
+            let mut payload_offset = 4;
+            if adaptation_field_control & 0x2 != 0 {
+                let adaptation_field_length = packet[4] as usize;
+                if adaptation_field_length > 0 {
+                    random_access = packet[5] & 0x40 != 0;
+                }
+                payload_offset = 5 + adaptation_field_length;
+            }
+            let payload = if adaptation_field_control & 0x1 != 0 && payload_offset < TS_PACKET_SIZE {
+                &packet[payload_offset..]
+            } else {
+                &[]
+            };
+            if pid == PAT_PID {
+                if payload_unit_start {
+                    self.parse_pat(payload);
+                }
+            } else if self.pmt_pids.contains(&pid) {
+                if payload_unit_start {
+                    self.parse_pmt(payload);
+                }
+            } else if let Some(&track_type) = self.streams.get(&pid) {
+                let is_random_access_point = match track_type {
+                    TrackType::Video => random_access,
+                    TrackType::Audio => payload_unit_start,
+                    TrackType::Other => random_access,
+                };
+                let track = Track { track_type, id: pid };
+                if is_random_access_point && !random_access_tracks.contains(&track) {
+                    random_access_tracks.push(track);
+                }
+            }
+        }
+        random_access_tracks
+    }
+
+    /// Returns the PSI section in the payload if it has the expected table id.
+    fn get_section(payload: &[u8], table_id: u8) -> Option<&[u8]> {
+        let pointer_field = *payload.first()? as usize;
+        let section = payload.get(1 + pointer_field..)?;
+        if *section.first()? != table_id || section.len() < 3 {
+            return None;
+        }
+        let section_length = (((section[1] & 0x0F) as usize) << 8) | section[2] as usize;
+        // Exclude the CRC32.
+        section.get(..(3 + section_length).checked_sub(4)?)
+    }
+
+    fn parse_pat(&mut self, payload: &[u8]) {
+        if let Some(section) = Self::get_section(payload, PAT_TABLE_ID) {
+            for program in section.get(8..).unwrap_or_default().chunks_exact(4) {
+                let program_number = ((program[0] as u16) << 8) | program[1] as u16;
+                let pid = (((program[2] & 0x1F) as u16) << 8) | program[3] as u16;
+                if program_number != 0 {
+                    self.pmt_pids.insert(pid);
+                }
+            }
+            trace!("MpegTsParser::parse_pat: pmt_pids={:?}", self.pmt_pids);
+        }
+    }
+
+    fn parse_pmt(&mut self, payload: &[u8]) {
+        if let Some(section) = Self::get_section(payload, PMT_TABLE_ID) {
+            if section.len() < 12 {
+                return;
+            }
+            let program_info_length = (((section[10] & 0x0F) as usize) << 8) | section[11] as usize;
+            let mut pos = 12 + program_info_length;
+            while pos + 5 <= section.len() {
+                let stream_type = section[pos];
+                let pid = (((section[pos + 1] & 0x1F) as u16) << 8) | section[pos + 2] as u16;
+                let es_info_length = (((section[pos + 3] & 0x0F) as usize) << 8) | section[pos + 4] as usize;
+                self.streams.insert(pid, stream_type_to_track_type(stream_type));
+                pos += 5 + es_info_length;
+            }
+            trace!("MpegTsParser::parse_pmt: streams={:?}", self.streams);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn packet(pid: u16, payload_unit_start: bool, random_access: bool, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0xFF; TS_PACKET_SIZE];
+        packet[0] = TS_SYNC_BYTE;
+        packet[1] = ((pid >> 8) as u8 & 0x1F) | if payload_unit_start { 0x40 } else { 0 };
+        packet[2] = pid as u8;
+        if random_access {
+            packet[3] = 0x30;
+            packet[4] = 1;
+            packet[5] = 0x40;
+            packet[6..6 + payload.len()].copy_from_slice(payload);
+        } else {
+            packet[3] = 0x10;
+            packet[4..4 + payload.len()].copy_from_slice(payload);
+        }
+        packet
+    }
+
+    fn pat() -> Vec<u8> {
+        // pointer, table_id, section_length=13, tsid, version, section numbers, program 1 -> PMT PID 0x1000, CRC
+        packet(PAT_PID, true, false, &[0x00, 0x00, 0xB0, 0x0D, 0x00, 0x01, 0xC1, 0x00, 0x00,
+            0x00, 0x01, 0xF0, 0x00, 0, 0, 0, 0])
+    }
+
+    fn pmt() -> Vec<u8> {
+        // H.264 on PID 0x100, AAC on PID 0x101
+        packet(0x1000, true, false, &[0x00, 0x02, 0xB0, 0x17, 0x00, 0x01, 0xC1, 0x00, 0x00,
+            0xE1, 0x00, 0xF0, 0x00,
+            0x1B, 0xE1, 0x00, 0xF0, 0x00,
+            0x0F, 0xE1, 0x01, 0xF0, 0x00,
+            0, 0, 0, 0])
+    }
+
+    #[test]
+    fn test_mpegts_parser() {
+        let video = Track { track_type: TrackType::Video, id: 0x100 };
+        let audio = Track { track_type: TrackType::Audio, id: 0x101 };
+        let mut parser = MpegTsParser::new();
+        let mut buffer = Vec::new();
+        buffer.extend(pat());
+        buffer.extend(pmt());
+        buffer.extend(packet(0x100, true, true, &[0, 0, 1, 0xE0]));
+        buffer.extend(packet(0x100, false, false, &[]));
+        buffer.extend(packet(0x101, true, false, &[0, 0, 1, 0xC0]));
+        assert_eq!(parser.parse(&buffer), vec![video, audio]);
+        assert_eq!(parser.tracks(), vec![video, audio]);
+
+        // Video delta frame and audio continuation.
+        let mut buffer = Vec::new();
+        buffer.extend(packet(0x100, true, false, &[0, 0, 1, 0xE0]));
+        buffer.extend(packet(0x101, false, false, &[]));
+        assert_eq!(parser.parse(&buffer), vec![]);
+
+        // Audio only.
+        assert_eq!(parser.parse(&packet(0x101, true, false, &[0, 0, 1, 0xC0])), vec![audio]);
+    }
+}
@@ -0,0 +1,350 @@
+//
+// Copyright (c) Dell Inc., or its subsidiaries. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+
+//! Retention of video streams by age and size.
+//!
+//! A stream is truncated at an index record so that the data stream and the index remain consistent.
+//! The index is truncated first so that it never refers to truncated data.
+
+use crate::index::{IndexRecord, IndexSearcher, SearchMethod, get_index_stream_name};
+use crate::storage::Storage;
+use crate::timestamp::{PravegaTimestamp, SECOND};
+use crate::utils::CurrentHead;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Error, ErrorKind, Read, Result, Seek};
+use std::path::Path;
+use tracing::debug;
+
+pub fn days_to_seconds(days: f64) -> i128 {
+    let seconds = days * 24.0 * 60.0 * 60.0;
+    seconds.round() as i128
+}
+
+/// Returns the index record and index offset before which data should be truncated
+/// so that data is not older than the given age and does not exceed the given size.
+/// If both are given, the later truncation point is returned.
+pub fn search_truncation_point<R: Read + Seek + CurrentHead>(index_searcher: &mut IndexSearcher<R>, now: PravegaTimestamp,
+        seconds: Option<i128>, bytes: Option<u64>) -> Option<(IndexRecord, u64)> {
+    let mut result: Option<(IndexRecord, u64)> = None;
+    if let Some(sec) = seconds {
+        let truncate_at_timestamp = now - sec * SECOND;
+        debug!("Searching for data prior to {}", truncate_at_timestamp);
+        if let Ok(found) = index_searcher.search_timestamp_and_return_index_offset(truncate_at_timestamp, SearchMethod::Before) {
+            result = Some(found);
+        }
+    }
+    if let Some(bytes) = bytes {
+        debug!("Searching for data larger than {} bytes", bytes);
+        if let Ok(found) = index_searcher.search_size_and_return_index_offset(bytes, SearchMethod::Before) {
+            if result.map_or(true, |result| found.0.offset > result.0.offset) {
+                result = Some(found);
+            }
+        }
+    }
+    result
+}
+
+/// Returns the truncation point limited so that data at and after the consumer checkpoint is kept.
+/// A checkpoint of PravegaTimestamp::NONE means that the consumer has not processed any data,
+/// in which case None is returned and nothing should be truncated.
+pub fn hold_truncation_point<R: Read + Seek + CurrentHead>(index_searcher: &mut IndexSearcher<R>, target: (IndexRecord, u64),
+        checkpoint: PravegaTimestamp) -> Option<(IndexRecord, u64)> {
+    if checkpoint.is_none() {
+        return None;
+    }
+    match index_searcher.search_timestamp_and_return_index_offset(checkpoint, SearchMethod::Before) {
+        Ok(limit) if limit.0.offset < target.0.offset => Some(limit),
+        Ok(_) => Some(target),
+        Err(_) => None,
+    }
+}
+
+/// The consumers of a stream, which limit retention to the data that they have processed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsumerRetention {
+    /// The oldest checkpoint of all consumers, or PravegaTimestamp::NONE if any consumer has not processed any data.
+    pub oldest_checkpoint: PravegaTimestamp,
+    /// Data older than this many days will be deleted even if consumers have not processed it.
+    pub max_days: Option<f64>,
+    /// The oldest data will be deleted when the data size exceeds this many bytes, even if consumers have not processed it.
+    pub max_bytes: Option<u64>,
+}
+
+/// A retention policy that applies to the streams that match the scope and stream patterns.
+/// Patterns may contain `*` to match any sequence of characters and `?` to match any single character.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RetentionRule {
+    #[serde(default = "match_all")]
+    pub scope: String,
+    #[serde(default = "match_all")]
+    pub stream: String,
+    /// Data older than this many days will be deleted.
+    #[serde(default)]
+    pub retention_days: Option<f64>,
+    /// The oldest data will be deleted when the data size exceeds this many bytes.
+    #[serde(default)]
+    pub retention_bytes: Option<u64>,
+    /// Locations of the pravegatc checkpoint stores of pipelines that read the stream,
+    /// as `scope/table` or `file:///path/to/dir`.
+    /// Data will not be deleted if these consumers have not processed it.
+    #[serde(default)]
+    pub consumers: Vec<String>,
+    /// Data older than this many days will be deleted even if consumers have not processed it.
+    #[serde(default)]
+    pub max_retention_days: Option<f64>,
+    /// The oldest data will be deleted when the data size exceeds this many bytes, even if consumers have not processed it.
+    #[serde(default)]
+    pub max_retention_bytes: Option<u64>,
+}
+
+fn match_all() -> String {
+    "*".to_owned()
+}
+
+impl RetentionRule {
+    pub fn matches(&self, scope: &str, stream: &str) -> bool {
+        pattern_matches(&self.scope, scope) && pattern_matches(&self.stream, stream)
+    }
+}
+
+/// A set of retention rules, usually loaded from a JSON file such as:
+///
+/// ```json
+/// {
+///   "rules": [
+///     {"scope": "examples", "stream": "camera-*", "retention_days": 7.0},
+///     {"scope": "examples", "retention_days": 30.0, "retention_bytes": 1000000000000},
+///     {"scope": "archive", "retention_days": 7.0, "consumers": ["archive/checkpoints"], "max_retention_days": 30.0}
+///   ]
+/// }
+/// ```
+///
+/// The first rule that matches a stream applies to it.
+/// Streams that do not match any rule are not truncated.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RetentionConfig {
+    pub rules: Vec<RetentionRule>,
+}
+
+impl RetentionConfig {
+    pub fn load(path: &Path) -> Result<RetentionConfig> {
+        let bytes = fs::read(path)?;
+        RetentionConfig::parse(&bytes)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<RetentionConfig> {
+        let config: RetentionConfig = serde_json::from_slice(bytes)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, format!("Failed to parse retention config: {}", err)))?;
+        for rule in config.rules.iter() {
+            if rule.retention_days.is_none() && rule.retention_bytes.is_none() {
+                return Err(Error::new(ErrorKind::InvalidData,
+                    format!("Retention rule for {}/{} must have retention_days or retention_bytes", rule.scope, rule.stream)));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Returns the first rule that matches the stream.
+    pub fn find_rule(&self, scope: &str, stream: &str) -> Option<&RetentionRule> {
+        self.rules.iter().find(|rule| rule.matches(scope, stream))
+    }
+}
+
+/// Returns true if the text matches the pattern, which may contain `*` and `?`.
+pub fn pattern_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // The position of the last `*` in the pattern and the text position it was matched at.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            // Let the last `*` match one more character.
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// A record of a truncation that was performed.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TruncationReport {
+    pub scope: String,
+    pub stream: String,
+    /// The timestamp of the first index record that was kept.
+    pub timestamp: Option<u64>,
+    pub timestamp_iso_8601: Option<String>,
+    pub index_offset: u64,
+    pub data_offset: u64,
+    /// The number of data bytes deleted by this truncation.
+    pub truncated_bytes: u64,
+}
+
+/// Truncate a data stream and its index so that data is not older than the given age and does not exceed the given size.
+/// If consumers are given, data that they have not processed is kept unless it exceeds their maximum age or size.
+/// Returns None if there is nothing to truncate.
+pub fn truncate_stream(storage: &dyn Storage, scope: &str, stream: &str, now: PravegaTimestamp,
+        retention_days: Option<f64>, retention_bytes: Option<u64>, consumers: Option<&ConsumerRetention>) -> Result<Option<TruncationReport>> {
+    let index_stream_name = get_index_stream_name(stream);
+    let index_reader = storage.create_reader(scope, &index_stream_name)?;
+    let mut index_searcher = IndexSearcher::new(index_reader);
+    let seconds = retention_days.map(days_to_seconds);
+    let mut target = search_truncation_point(&mut index_searcher, now, seconds, retention_bytes);
+    if let Some(consumers) = consumers {
+        if let Some(requested) = target {
+            target = hold_truncation_point(&mut index_searcher, requested, consumers.oldest_checkpoint);
+            debug!("Retention held back from {:?} to {:?} by consumer checkpoint {}", requested, target, consumers.oldest_checkpoint);
+        }
+        let max_seconds = consumers.max_days.map(days_to_seconds);
+        if let Some(max_target) = search_truncation_point(&mut index_searcher, now, max_seconds, consumers.max_bytes) {
+            if target.map_or(true, |target| max_target.0.offset > target.0.offset) {
+                target = Some(max_target);
+            }
+        }
+    }
+    let (index_record, index_offset) = match target {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    let data_head = storage.create_reader(scope, stream)?.current_head()?;
+    if index_record.offset <= data_head {
+        return Ok(None);
+    }
+    storage.create_writer(scope, &index_stream_name)?.truncate_data_before(index_offset)?;
+    storage.create_writer(scope, stream)?.truncate_data_before(index_record.offset)?;
+    Ok(Some(TruncationReport {
+        scope: scope.to_owned(),
+        stream: stream.to_owned(),
+        timestamp: index_record.timestamp.nanoseconds(),
+        timestamp_iso_8601: index_record.timestamp.to_iso_8601(),
+        index_offset,
+        data_offset: index_record.offset,
+        truncated_bytes: index_record.offset - data_head,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::index::IndexRecordWriter;
+    use crate::storage::FileStorage;
+    use std::io::Write;
+
+    #[test]
+    fn test_pattern_matches() {
+        assert!(pattern_matches("*", ""));
+        assert!(pattern_matches("*", "camera1"));
+        assert!(pattern_matches("camera1", "camera1"));
+        assert!(!pattern_matches("camera1", "camera10"));
+        assert!(pattern_matches("camera-*", "camera-lobby"));
+        assert!(!pattern_matches("camera-*", "cam-lobby"));
+        assert!(pattern_matches("*-index", "camera-index"));
+        assert!(pattern_matches("camera?", "camera1"));
+        assert!(!pattern_matches("camera?", "camera"));
+        assert!(pattern_matches("a*b*c", "aXbYbZc"));
+        assert!(!pattern_matches("a*b*c", "aXbYbZ"));
+    }
+
+    #[test]
+    fn test_retention_config() {
+        let config = RetentionConfig::parse(br#"{
+            "rules": [
+                {"scope": "examples", "stream": "camera-*", "retention_days": 7.0},
+                {"scope": "examples", "retention_days": 30.0, "retention_bytes": 1000},
+                {"retention_bytes": 2000}
+            ]
+        }"#).unwrap();
+        assert_eq!(config.find_rule("examples", "camera-1").unwrap().retention_days, Some(7.0));
+        assert_eq!(config.find_rule("examples", "other").unwrap().retention_bytes, Some(1000));
+        assert_eq!(config.find_rule("scope2", "camera-1").unwrap().retention_bytes, Some(2000));
+        assert_eq!(RetentionConfig::parse(br#"{"rules": []}"#).unwrap().find_rule("examples", "camera-1"), None);
+        assert!(RetentionConfig::parse(br#"{"rules": [{"scope": "examples"}]}"#).is_err());
+    }
+
+    #[test]
+    fn test_truncate_stream() {
+        let root = std::env::temp_dir().join(format!("pravega-video-test_truncate_stream-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let storage = FileStorage::new(root.clone());
+        storage.create_stream("scope1", "stream1", None).unwrap();
+        storage.create_stream("scope1", "stream1-index", None).unwrap();
+        let now = PravegaTimestamp::now();
+        // Write one index record per day for 10 days, with 100 bytes of data each.
+        let mut data_writer = storage.create_writer("scope1", "stream1").unwrap();
+        let mut index_writer = storage.create_writer("scope1", "stream1-index").unwrap();
+        let mut index_record_writer = IndexRecordWriter::new();
+        for i in 0..10u64 {
+            let timestamp = now - ((10 - i) as i128 * 24 * 60 * 60) * SECOND;
+            let record = IndexRecord::new(timestamp, i * 100, true, false);
+            index_record_writer.write(&record, &mut index_writer).unwrap();
+            data_writer.write_all(&[0u8; 100]).unwrap();
+        }
+        data_writer.flush().unwrap();
+        index_writer.flush().unwrap();
+
+        // Nothing is older than 30 days.
+        assert_eq!(truncate_stream(&storage, "scope1", "stream1", now, Some(30.0), None, None).unwrap(), None);
+
+        // Keep 5.5 days. The record at 6 days ago is the last one before the truncation time.
+        let report = truncate_stream(&storage, "scope1", "stream1", now, Some(5.5), None, None).unwrap().unwrap();
+        assert_eq!(report.data_offset, 400);
+        assert_eq!(report.index_offset, 4 * IndexRecord::RECORD_SIZE as u64);
+        assert_eq!(report.truncated_bytes, 400);
+        assert_eq!(storage.create_reader("scope1", "stream1").unwrap().current_head().unwrap(), 400);
+        assert_eq!(storage.create_reader("scope1", "stream1-index").unwrap().current_head().unwrap(),
+            4 * IndexRecord::RECORD_SIZE as u64);
+
+        // Repeating the same truncation does nothing.
+        assert_eq!(truncate_stream(&storage, "scope1", "stream1", now, Some(5.5), None, None).unwrap(), None);
+
+        // The size limit truncates more than the age limit. At least 250 bytes are kept.
+        let report = truncate_stream(&storage, "scope1", "stream1", now, Some(5.5), Some(250), None).unwrap().unwrap();
+        assert_eq!(report.data_offset, 600);
+        assert_eq!(report.truncated_bytes, 200);
+
+        // A consumer that has processed data up to 2.5 days ago holds back a 1 day retention.
+        let consumers = ConsumerRetention {
+            oldest_checkpoint: now - days_to_seconds(2.5) * SECOND,
+            max_days: None,
+            max_bytes: None,
+        };
+        let report = truncate_stream(&storage, "scope1", "stream1", now, Some(1.0), None, Some(&consumers)).unwrap().unwrap();
+        assert_eq!(report.data_offset, 700);
+
+        // A consumer that has not processed any data holds back all truncation.
+        let consumers = ConsumerRetention {
+            oldest_checkpoint: PravegaTimestamp::NONE,
+            max_days: None,
+            max_bytes: None,
+        };
+        assert_eq!(truncate_stream(&storage, "scope1", "stream1", now, Some(1.0), None, Some(&consumers)).unwrap(), None);
+
+        // The maximum age applies even if consumers have not processed the data.
+        let consumers = ConsumerRetention {
+            oldest_checkpoint: PravegaTimestamp::NONE,
+            max_days: Some(1.5),
+            max_bytes: None,
+        };
+        let report = truncate_stream(&storage, "scope1", "stream1", now, Some(1.0), None, Some(&consumers)).unwrap().unwrap();
+        assert_eq!(report.data_offset, 800);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
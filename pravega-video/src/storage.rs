@@ -0,0 +1,768 @@
+//
+// Copyright (c) Dell Inc., or its subsidiaries. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+
+//! Storage backends for the byte streams used by pravegasink and pravegasrc.
+//!
+//! A video stream consists of a data stream and an index stream.
+//! Both are append-only byte streams that can be truncated from the head.
+//! The [Storage] trait allows these to be stored in Pravega or in a local directory.
+//!
+//! The backend is selected by the stream URI.
+//! A URI of the form `file:///var/video/scope/stream` uses [FileStorage] with the root directory `/var/video`.
+//! A URI of the form `scope/stream` uses [PravegaStorage].
+
+use pravega_client::byte::ByteWriter;
+use pravega_client::client_factory::ClientFactoryAsync;
+use pravega_client_shared::{ScaleType, Scaling, Scope, ScopedStream, Stream, StreamConfiguration};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+use crate::utils::{CurrentHead, SyncByteReader};
+
+pub const FILE_URI_PREFIX: &str = "file://";
+
+/// A writer for an append-only byte stream.
+/// Each call to `write` is appended atomically.
+pub trait StorageWriter: Write + Seek + Send {
+    /// Seal the stream. No further writes will be allowed.
+    fn seal(&mut self) -> Result<()>;
+
+    /// Delete all data before the given offset.
+    fn truncate_data_before(&mut self, offset: u64) -> Result<()>;
+
+    /// Delete all data at and after the given offset and position the writer at the new tail.
+    /// Pravega byte streams cannot be truncated at the tail, so this returns an error of kind Unsupported for them.
+    fn truncate_data_after(&mut self, offset: u64) -> Result<()>;
+
+    /// Position the writer at the current tail of the stream and return the tail offset.
+    fn seek_to_tail(&mut self) -> Result<u64>;
+}
+
+/// A reader for an append-only byte stream.
+/// Reads at the tail of an unsealed stream will block until data is available.
+/// Reads at the tail of a sealed stream will return 0.
+pub trait StorageReader: Read + Seek + CurrentHead + Send {
+    /// Read data at the given offset.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.read(buf)
+    }
+
+    /// Return the offset of the tail of the stream without changing the read position.
+    fn current_tail(&mut self) -> Result<u64> {
+        let position = self.seek(SeekFrom::Current(0))?;
+        let tail = self.seek(SeekFrom::End(0))?;
+        self.seek(SeekFrom::Start(position))?;
+        Ok(tail)
+    }
+}
+
+/// A storage system that contains byte streams identified by a scope and stream name.
+pub trait Storage: Send + Sync {
+    /// Create a scope. This does nothing if the scope already exists.
+    fn create_scope(&self, scope: &str) -> Result<()>;
+
+    /// Create a stream. This does nothing if the stream already exists.
+    fn create_stream(&self, scope: &str, stream: &str, tags: Option<Vec<String>>) -> Result<()>;
+
+    /// Create a writer positioned at the tail of the stream.
+    fn create_writer(&self, scope: &str, stream: &str) -> Result<Box<dyn StorageWriter>>;
+
+    /// Create a reader positioned at the head of the stream.
+    fn create_reader(&self, scope: &str, stream: &str) -> Result<Box<dyn StorageReader>>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageType {
+    Pravega,
+    /// Byte streams are stored in directories under the root.
+    File { root: PathBuf },
+}
+
+/// The location of a video stream, parsed from a stream URI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamLocation {
+    pub storage_type: StorageType,
+    pub scope: String,
+    pub stream: String,
+}
+
+impl StreamLocation {
+    /// Parse `scope/stream` or `file:///root/scope/stream`.
+    pub fn parse(uri: &str) -> std::result::Result<StreamLocation, String> {
+        let (storage_type, path) = if let Some(path) = uri.strip_prefix(FILE_URI_PREFIX) {
+            let path = Path::new(path);
+            if !path.is_absolute() {
+                return Err(format!("File URI must have an absolute path: {}", uri));
+            }
+            let root = path.parent().and_then(|p| p.parent())
+                .ok_or_else(|| format!("File URI must be in the format file:///root/scope/stream: {}", uri))?;
+            (StorageType::File { root: root.to_path_buf() }, path.to_str().unwrap_or_default().trim_start_matches('/'))
+        } else {
+            (StorageType::Pravega, uri)
+        };
+        let components: Vec<&str> = path.split('/').collect();
+        if components.len() < 2 {
+            return Err(format!("Stream must be in the format scope/stream or file:///root/scope/stream: {}", uri));
+        }
+        let scope = components[components.len() - 2];
+        let stream = components[components.len() - 1];
+        if scope.is_empty() || stream.is_empty() || (storage_type == StorageType::Pravega && components.len() != 2) {
+            return Err(format!("Stream must be in the format scope/stream or file:///root/scope/stream: {}", uri));
+        }
+        Ok(StreamLocation {
+            storage_type,
+            scope: scope.to_owned(),
+            stream: stream.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for StreamLocation {
+    /// Format as `scope/stream` or `file:///root/scope/stream`, which can be parsed by `StreamLocation::parse`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.storage_type {
+            StorageType::Pravega => write!(f, "{}/{}", self.scope, self.stream),
+            StorageType::File { ref root } => write!(f, "{}{}", FILE_URI_PREFIX, root.join(&self.scope).join(&self.stream).display()),
+        }
+    }
+}
+
+//
+// Pravega storage
+//
+
+/// Byte streams stored in Pravega.
+pub struct PravegaStorage {
+    client_factory: ClientFactoryAsync,
+}
+
+impl PravegaStorage {
+    pub fn new(client_factory: ClientFactoryAsync) -> Self {
+        Self { client_factory }
+    }
+
+    fn scoped_stream(scope: &str, stream: &str) -> ScopedStream {
+        ScopedStream {
+            scope: Scope::from(scope.to_owned()),
+            stream: Stream::from(stream.to_owned()),
+        }
+    }
+}
+
+impl Storage for PravegaStorage {
+    fn create_scope(&self, scope: &str) -> Result<()> {
+        let controller_client = self.client_factory.controller_client();
+        self.client_factory.runtime_handle()
+            .block_on(controller_client.create_scope(&Scope::from(scope.to_owned())))
+            .map(|_| ())
+            .map_err(|err| Error::new(ErrorKind::Other, format!("Failed to create Pravega scope {}: {:?}", scope, err)))
+    }
+
+    fn create_stream(&self, scope: &str, stream: &str, tags: Option<Vec<String>>) -> Result<()> {
+        let controller_client = self.client_factory.controller_client();
+        let stream_config = StreamConfiguration {
+            scoped_stream: Self::scoped_stream(scope, stream),
+            scaling: Scaling {
+                scale_type: ScaleType::FixedNumSegments,
+                min_num_segments: 1,
+                ..Default::default()
+            },
+            retention: Default::default(),
+            tags,
+        };
+        self.client_factory.runtime_handle()
+            .block_on(controller_client.create_stream(&stream_config))
+            .map(|_| ())
+            .map_err(|err| Error::new(ErrorKind::Other, format!("Failed to create Pravega stream {}/{}: {:?}", scope, stream, err)))
+    }
+
+    fn create_writer(&self, scope: &str, stream: &str) -> Result<Box<dyn StorageWriter>> {
+        let runtime_handle = self.client_factory.runtime_handle();
+        let writer = runtime_handle.block_on(self.client_factory.create_byte_writer(Self::scoped_stream(scope, stream)));
+        let mut writer = SeekableByteWriter::new(writer, runtime_handle);
+        writer.seek_to_tail()?;
+        Ok(Box::new(writer))
+    }
+
+    fn create_reader(&self, scope: &str, stream: &str) -> Result<Box<dyn StorageReader>> {
+        let runtime_handle = self.client_factory.runtime_handle();
+        let reader = runtime_handle.block_on(self.client_factory.create_byte_reader(Self::scoped_stream(scope, stream)));
+        Ok(Box::new(SyncByteReader::new(reader, runtime_handle)))
+    }
+}
+
+/// A ByteWriter that implements Seek.
+pub struct SeekableByteWriter {
+    inner: ByteWriter,
+    runtime_handle: Handle,
+}
+
+impl SeekableByteWriter {
+    pub fn new(writer: ByteWriter, runtime_handle: Handle) ->  Self {
+        Self {
+            inner: writer,
+            runtime_handle,
+        }
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &ByteWriter {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut ByteWriter {
+        &mut self.inner
+    }
+}
+
+impl Write for SeekableByteWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.runtime_handle.block_on(self.inner.write(buf)).map_err(|err|{Error::new(ErrorKind::Other, err.to_string())})
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.runtime_handle.block_on(self.inner.flush()).map_err(|err|{Error::new(ErrorKind::Other, err.to_string())})
+    }
+}
+
+impl Seek for SeekableByteWriter {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        match pos {
+            SeekFrom::Current(0) => Ok(self.inner.current_offset() as u64),
+            _ => Err(Error::new(ErrorKind::InvalidInput, "Seek is not allowed")),
+        }
+    }
+}
+
+impl StorageWriter for SeekableByteWriter {
+    fn seal(&mut self) -> Result<()> {
+        self.runtime_handle.block_on(self.inner.seal()).map_err(|err|{Error::new(ErrorKind::Other, err.to_string())})
+    }
+
+    fn truncate_data_before(&mut self, offset: u64) -> Result<()> {
+        self.runtime_handle.block_on(self.inner.truncate_data_before(offset as i64)).map_err(|err|{Error::new(ErrorKind::Other, err.to_string())})
+    }
+
+    fn truncate_data_after(&mut self, _offset: u64) -> Result<()> {
+        Err(Error::new(ErrorKind::Unsupported, "Pravega byte streams cannot be truncated at the tail"))
+    }
+
+    fn seek_to_tail(&mut self) -> Result<u64> {
+        self.runtime_handle.block_on(self.inner.seek_to_tail());
+        Ok(self.inner.current_offset() as u64)
+    }
+}
+
+impl StorageReader for SyncByteReader {}
+
+//
+// File storage
+//
+
+/// By default, new writes will go to a new chunk file when the current chunk file reaches this size.
+const FILE_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+const FILE_CHUNK_EXTENSION: &str = "chunk";
+const FILE_HEAD_NAME: &str = "head";
+const FILE_SEALED_NAME: &str = "sealed";
+/// Readers at the tail of an unsealed stream will check for new data at this interval.
+const FILE_TAIL_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Byte streams stored in a local directory.
+///
+/// Each stream is a directory `<root>/<scope>/<stream>` containing:
+///   - chunk files named by the offset of their first byte (e.g. `00000000000000000000.chunk`)
+///   - an optional file `head` containing the offset of the first readable byte, written when truncated
+///   - an optional empty file `sealed`, written when sealed
+///
+/// Writes are never split across chunk files. Truncation deletes chunk files that are entirely before the head.
+/// Truncation at the tail deletes chunk files that begin after the new tail and shortens the last remaining chunk file.
+pub struct FileStorage {
+    root: PathBuf,
+    chunk_size: u64,
+}
+
+impl FileStorage {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            chunk_size: FILE_CHUNK_SIZE,
+        }
+    }
+
+    /// Set the size at which writers start a new chunk file.
+    pub fn with_chunk_size(self, chunk_size: u64) -> Self {
+        Self { chunk_size, ..self }
+    }
+
+    fn stream_dir(&self, scope: &str, stream: &str) -> PathBuf {
+        self.root.join(scope).join(stream)
+    }
+}
+
+impl Storage for FileStorage {
+    fn create_scope(&self, scope: &str) -> Result<()> {
+        fs::create_dir_all(self.root.join(scope))
+    }
+
+    fn create_stream(&self, scope: &str, stream: &str, _tags: Option<Vec<String>>) -> Result<()> {
+        fs::create_dir_all(self.stream_dir(scope, stream))
+    }
+
+    fn create_writer(&self, scope: &str, stream: &str) -> Result<Box<dyn StorageWriter>> {
+        Ok(Box::new(FileStreamWriter::open(self.stream_dir(scope, stream))?.with_chunk_size(self.chunk_size)))
+    }
+
+    fn create_reader(&self, scope: &str, stream: &str) -> Result<Box<dyn StorageReader>> {
+        Ok(Box::new(FileStreamReader::open(self.stream_dir(scope, stream))?))
+    }
+}
+
+fn chunk_path(dir: &Path, offset: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", offset, FILE_CHUNK_EXTENSION))
+}
+
+/// Return the starting offsets of all chunk files, in ascending order.
+fn list_chunks(dir: &Path) -> Result<Vec<u64>> {
+    let mut chunks = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) == Some(FILE_CHUNK_EXTENSION) {
+            if let Some(offset) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<u64>().ok()) {
+                chunks.push(offset);
+            }
+        }
+    }
+    chunks.sort_unstable();
+    Ok(chunks)
+}
+
+fn read_head(dir: &Path) -> Result<u64> {
+    match fs::read_to_string(dir.join(FILE_HEAD_NAME)) {
+        Ok(s) => s.trim().parse::<u64>().map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string())),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err),
+    }
+}
+
+fn is_sealed(dir: &Path) -> bool {
+    dir.join(FILE_SEALED_NAME).exists()
+}
+
+/// Return the tail offset given the list of chunks.
+fn tail_offset(dir: &Path, chunks: &[u64]) -> Result<u64> {
+    match chunks.last() {
+        Some(&start) => Ok(start + fs::metadata(chunk_path(dir, start))?.len()),
+        None => read_head(dir),
+    }
+}
+
+pub struct FileStreamWriter {
+    dir: PathBuf,
+    file: Option<File>,
+    chunk_size: u64,
+    chunk_start: u64,
+    offset: u64,
+}
+
+impl FileStreamWriter {
+    pub fn open(dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let mut writer = Self {
+            dir,
+            file: None,
+            chunk_size: FILE_CHUNK_SIZE,
+            chunk_start: 0,
+            offset: 0,
+        };
+        writer.seek_to_tail()?;
+        Ok(writer)
+    }
+
+    /// Set the size at which a new chunk file is started.
+    pub fn with_chunk_size(self, chunk_size: u64) -> Self {
+        Self { chunk_size, ..self }
+    }
+}
+
+impl Write for FileStreamWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if is_sealed(&self.dir) {
+            return Err(Error::new(ErrorKind::PermissionDenied, "Stream is sealed"));
+        }
+        if self.file.is_none() || self.offset - self.chunk_start >= self.chunk_size {
+            if let Some(file) = self.file.as_mut() {
+                file.sync_data()?;
+            }
+            let file = OpenOptions::new().create_new(true).append(true).open(chunk_path(&self.dir, self.offset))?;
+            self.file = Some(file);
+            self.chunk_start = self.offset;
+        }
+        self.file.as_mut().unwrap().write_all(buf)?;
+        self.offset += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.sync_data(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Seek for FileStreamWriter {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        match pos {
+            SeekFrom::Current(0) => Ok(self.offset),
+            _ => Err(Error::new(ErrorKind::InvalidInput, "Seek is not allowed")),
+        }
+    }
+}
+
+impl StorageWriter for FileStreamWriter {
+    fn seal(&mut self) -> Result<()> {
+        self.flush()?;
+        File::create(self.dir.join(FILE_SEALED_NAME))?;
+        Ok(())
+    }
+
+    fn truncate_data_before(&mut self, offset: u64) -> Result<()> {
+        let chunks = list_chunks(&self.dir)?;
+        let tail = tail_offset(&self.dir, &chunks)?;
+        if offset > tail {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Truncation offset {} is beyond the tail {}", offset, tail)));
+        }
+        if offset <= read_head(&self.dir)? {
+            return Ok(());
+        }
+        // Write the new head atomically.
+        let tmp_path = self.dir.join(format!("{}.tmp", FILE_HEAD_NAME));
+        {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(offset.to_string().as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, self.dir.join(FILE_HEAD_NAME))?;
+        // Delete chunks that end at or before the new head. The last chunk is never deleted.
+        for pair in chunks.windows(2) {
+            if pair[1] <= offset {
+                fs::remove_file(chunk_path(&self.dir, pair[0]))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn truncate_data_after(&mut self, offset: u64) -> Result<()> {
+        if is_sealed(&self.dir) {
+            return Err(Error::new(ErrorKind::PermissionDenied, "Stream is sealed"));
+        }
+        let chunks = list_chunks(&self.dir)?;
+        let head = read_head(&self.dir)?;
+        let tail = tail_offset(&self.dir, &chunks)?;
+        if offset < head || offset > tail {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                format!("Truncation offset {} is not between the head {} and the tail {}", offset, head, tail)));
+        }
+        self.file = None;
+        // Delete chunks from the end so that the stream never has a gap. The first chunk is never deleted.
+        for (i, &start) in chunks.iter().enumerate().rev() {
+            if i > 0 && start >= offset {
+                fs::remove_file(chunk_path(&self.dir, start))?;
+            } else {
+                let file = OpenOptions::new().write(true).open(chunk_path(&self.dir, start))?;
+                file.set_len(offset - start)?;
+                file.sync_all()?;
+                break;
+            }
+        }
+        self.seek_to_tail()?;
+        Ok(())
+    }
+
+    fn seek_to_tail(&mut self) -> Result<u64> {
+        let chunks = list_chunks(&self.dir)?;
+        match chunks.last() {
+            Some(&start) => {
+                let file = OpenOptions::new().append(true).open(chunk_path(&self.dir, start))?;
+                self.offset = start + file.metadata()?.len();
+                self.chunk_start = start;
+                self.file = Some(file);
+            },
+            None => {
+                self.offset = read_head(&self.dir)?;
+                self.chunk_start = self.offset;
+                self.file = None;
+            },
+        }
+        Ok(self.offset)
+    }
+}
+
+pub struct FileStreamReader {
+    dir: PathBuf,
+    offset: u64,
+    chunks: Vec<u64>,
+    /// The starting offset and handle of the currently open chunk file.
+    file: Option<(u64, File)>,
+}
+
+impl FileStreamReader {
+    pub fn open(dir: PathBuf) -> Result<Self> {
+        let chunks = list_chunks(&dir)?;
+        let offset = read_head(&dir)?;
+        Ok(Self {
+            dir,
+            offset,
+            chunks,
+            file: None,
+        })
+    }
+
+    /// Read data that is currently available without blocking.
+    /// Returns 0 if the read position is at the tail.
+    /// Returns an error if the read position is before the head, as with Pravega.
+    fn read_available(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if self.offset < read_head(&self.dir)? {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("Offset {} has been truncated", self.offset)));
+            }
+            let index = match self.chunks.iter().rposition(|&start| start <= self.offset) {
+                Some(index) => index,
+                None => {
+                    self.chunks = list_chunks(&self.dir)?;
+                    match self.chunks.iter().rposition(|&start| start <= self.offset) {
+                        Some(index) => index,
+                        None => return Ok(0),
+                    }
+                },
+            };
+            let start = self.chunks[index];
+            let is_open = matches!(self.file, Some((open_start, _)) if open_start == start);
+            if !is_open {
+                let file = File::open(chunk_path(&self.dir, start)).map_err(|err| {
+                    if err.kind() == ErrorKind::NotFound {
+                        Error::new(ErrorKind::InvalidInput, format!("Offset {} has been truncated", self.offset))
+                    } else {
+                        err
+                    }
+                })?;
+                self.file = Some((start, file));
+            }
+            let file = &mut self.file.as_mut().unwrap().1;
+            file.seek(SeekFrom::Start(self.offset - start))?;
+            let n = file.read(buf)?;
+            if n > 0 {
+                self.offset += n as u64;
+                return Ok(n);
+            }
+            // At the end of this chunk. Continue with the next chunk if there is one.
+            if index + 1 == self.chunks.len() {
+                self.chunks = list_chunks(&self.dir)?;
+                if self.chunks.last() == Some(&start) {
+                    return Ok(0);
+                }
+            }
+            if let Some(&next) = self.chunks.get(index + 1) {
+                if next > self.offset {
+                    return Err(Error::new(ErrorKind::InvalidData, format!("Gap between chunks at offset {}", self.offset)));
+                }
+            }
+        }
+    }
+}
+
+impl Read for FileStreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            // Check if sealed before reading so that data written before sealing is always returned.
+            let sealed = is_sealed(&self.dir);
+            let n = self.read_available(buf)?;
+            if n > 0 || sealed {
+                return Ok(n);
+            }
+            thread::sleep(FILE_TAIL_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Seek for FileStreamReader {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let new_offset = match pos {
+            SeekFrom::Start(offset) => offset as i128,
+            SeekFrom::Current(delta) => self.offset as i128 + delta as i128,
+            SeekFrom::End(delta) => {
+                self.chunks = list_chunks(&self.dir)?;
+                tail_offset(&self.dir, &self.chunks)? as i128 + delta as i128
+            },
+        };
+        if new_offset < 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Invalid seek to a negative offset"));
+        }
+        self.offset = new_offset as u64;
+        Ok(self.offset)
+    }
+}
+
+impl CurrentHead for FileStreamReader {
+    fn current_head(&self) -> Result<u64> {
+        read_head(&self.dir)
+    }
+}
+
+impl StorageReader for FileStreamReader {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("pravega-video-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        root
+    }
+
+    #[test]
+    fn test_stream_location_parse() {
+        assert_eq!(StreamLocation::parse("scope1/stream1").unwrap(), StreamLocation {
+            storage_type: StorageType::Pravega,
+            scope: "scope1".to_owned(),
+            stream: "stream1".to_owned(),
+        });
+        assert_eq!(StreamLocation::parse("file:///var/video/scope1/stream1").unwrap(), StreamLocation {
+            storage_type: StorageType::File { root: PathBuf::from("/var/video") },
+            scope: "scope1".to_owned(),
+            stream: "stream1".to_owned(),
+        });
+        assert_eq!(StreamLocation::parse("file:///scope1/stream1").unwrap().storage_type,
+            StorageType::File { root: PathBuf::from("/") });
+        assert!(StreamLocation::parse("stream1").is_err());
+        assert!(StreamLocation::parse("a/b/c").is_err());
+        assert!(StreamLocation::parse("file://scope1/stream1").is_err());
+        assert!(StreamLocation::parse("file:///stream1").is_err());
+    }
+
+    #[test]
+    fn test_stream_location_display() {
+        for uri in ["scope1/stream1", "file:///var/video/scope1/stream1", "file:///scope1/stream1"].iter() {
+            assert_eq!(StreamLocation::parse(uri).unwrap().to_string(), *uri);
+        }
+    }
+
+    #[test]
+    fn test_file_storage() {
+        let root = temp_root("test_file_storage");
+        let storage = FileStorage::new(root.clone());
+        storage.create_scope("scope1").unwrap();
+        storage.create_stream("scope1", "stream1", None).unwrap();
+
+        let mut writer = storage.create_writer("scope1", "stream1").unwrap();
+        assert_eq!(writer.seek(SeekFrom::Current(0)).unwrap(), 0);
+        writer.write_all(b"hello ").unwrap();
+        writer.write_all(b"world").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.seek(SeekFrom::Current(0)).unwrap(), 11);
+
+        let mut reader = storage.create_reader("scope1", "stream1").unwrap();
+        assert_eq!(reader.current_head().unwrap(), 0);
+        assert_eq!(reader.current_tail().unwrap(), 11);
+        let mut buf = [0u8; 5];
+        assert_eq!(reader.read_at(6, &mut buf).unwrap(), 5);
+        assert_eq!(&buf, b"world");
+
+        // A new writer continues at the tail.
+        let mut writer = storage.create_writer("scope1", "stream1").unwrap();
+        assert_eq!(writer.seek(SeekFrom::Current(0)).unwrap(), 11);
+        writer.write_all(b"!").unwrap();
+        writer.truncate_data_before(6).unwrap();
+        assert_eq!(reader.current_head().unwrap(), 6);
+        writer.seal().unwrap();
+        assert!(writer.write_all(b"x").is_err());
+
+        reader.seek(SeekFrom::Start(6)).unwrap();
+        let mut s = String::new();
+        reader.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "world!");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_file_storage_chunks() {
+        let root = temp_root("test_file_storage_chunks");
+        let dir = root.join("scope1").join("stream1");
+        let mut writer = FileStreamWriter::open(dir.clone()).unwrap().with_chunk_size(10);
+        // The first chunk is full after this write, so the next write goes to a new chunk.
+        writer.write_all(b"0123456789").unwrap();
+        writer.write_all(b"abcdefghij").unwrap();
+        assert_eq!(list_chunks(&dir).unwrap(), vec![0, 10]);
+
+        let mut reader = FileStreamReader::open(dir.clone()).unwrap();
+        let mut buf = [0u8; 8];
+        assert_eq!(reader.read_at(6, &mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"6789");
+        reader.read_exact(&mut buf[..6]).unwrap();
+        assert_eq!(&buf[..6], b"abcdef");
+
+        // Truncating within the second chunk deletes the first chunk.
+        writer.truncate_data_before(12).unwrap();
+        assert_eq!(list_chunks(&dir).unwrap(), vec![10]);
+        assert!(reader.read_at(4, &mut buf).is_err());
+        // Offsets before the head are not readable even if the chunk still exists.
+        let err = reader.read_at(10, &mut buf).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(reader.read_at(12, &mut buf).unwrap(), 8);
+        assert_eq!(&buf, b"cdefghij");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_file_storage_truncate_data_after() {
+        let root = temp_root("test_file_storage_truncate_data_after");
+        let dir = root.join("scope1").join("stream1");
+        let mut writer = FileStreamWriter::open(dir.clone()).unwrap().with_chunk_size(10);
+        writer.write_all(b"0123456789").unwrap();
+        writer.write_all(b"abcdefghij").unwrap();
+        writer.write_all(b"ABCDEFGHIJ").unwrap();
+        assert_eq!(list_chunks(&dir).unwrap(), vec![0, 10, 20]);
+
+        // Truncating within the second chunk deletes the third chunk.
+        writer.truncate_data_after(15).unwrap();
+        assert_eq!(list_chunks(&dir).unwrap(), vec![0, 10]);
+        assert_eq!(writer.seek(SeekFrom::Current(0)).unwrap(), 15);
+        writer.write_all(b"xyz").unwrap();
+        let mut reader = FileStreamReader::open(dir.clone()).unwrap();
+        assert_eq!(reader.current_tail().unwrap(), 18);
+        let mut buf = [0u8; 8];
+        assert_eq!(reader.read_at(10, &mut buf).unwrap(), 8);
+        assert_eq!(&buf, b"abcdexyz");
+
+        // Truncating at the beginning of a chunk deletes it.
+        writer.truncate_data_after(10).unwrap();
+        assert_eq!(list_chunks(&dir).unwrap(), vec![0]);
+        assert_eq!(reader.current_tail().unwrap(), 10);
+
+        // Offsets before the head or beyond the tail are not allowed.
+        writer.truncate_data_before(5).unwrap();
+        assert_eq!(writer.truncate_data_after(4).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(writer.truncate_data_after(11).unwrap_err().kind(), ErrorKind::InvalidInput);
+        writer.truncate_data_after(5).unwrap();
+        assert_eq!(reader.current_tail().unwrap(), 5);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -8,7 +8,9 @@
 // http://www.apache.org/licenses/LICENSE-2.0
 //
 
+use crate::leap_seconds::{leap_second_table, UtcTime};
 use anyhow;
+use chrono::{DateTime, TimeZone, Utc};
 use std::convert::{TryInto, TryFrom};
 use std::fmt;
 use std::ops::{Add, Mul, Sub, Div};
@@ -28,16 +30,6 @@ impl PravegaTimestamp {
     // See [https://stackoverflow.com/a/29138806/5890553].
     pub const UNIX_TO_NTP_SECONDS: u64 = (70 * 365 + 17) * 24 * 60 * 60;
 
-    // UTC to TAI offset.
-    // Below is valid for dates between 2017-01-01 and the next leap second.
-    // Beyond this range, we must use a table that incorporates the leap second schedule.
-    // See [https://en.wikipedia.org/wiki/International_Atomic_Time].
-    const UTC_TO_TAI_SECONDS: u64 = 37;
-
-    // No leap seconds occurred within this many seconds of the TAI epoch.
-    // The first leap second occurred on 1972-01-01.
-    const ZERO_LEAP_SECONDS_UNTIL_SECONDS: u64 = (1972 - 1970) * 365 * 24 * 60 * 60;
-
     // Create a PravegaTimestamp from the number of nanoseconds since the TAI epoch 1970-01-01 00:00:00 TAI.
     pub fn from_nanoseconds(nanoseconds: Option<u64>) -> PravegaTimestamp {
         PravegaTimestamp(nanoseconds)
@@ -62,14 +54,36 @@ impl PravegaTimestamp {
 
     /// Create a PravegaTimestamp from the number of nanoseconds since the Unix epoch 1970-01-01 00:00:00 UTC,
     /// minus leap seconds.
+    /// The offset between UTC and TAI is obtained from the leap second table.
     /// TODO: Return an error if time cannot be represented.
     pub fn from_unix_nanoseconds(nanoseconds: Option<u64>) -> PravegaTimestamp {
         match nanoseconds {
-            Some(nanoseconds) => PravegaTimestamp(Some(nanoseconds + PravegaTimestamp::UTC_TO_TAI_SECONDS * 1_000_000_000)),
+            Some(nanoseconds) => PravegaTimestamp::from_utc(UtcTime { unix_nanoseconds: nanoseconds, is_leap_second: false }),
             None => PravegaTimestamp(None),
         }
     }
 
+    /// Create a PravegaTimestamp from a UTC time, which may be during a leap second.
+    pub fn from_utc(utc: UtcTime) -> PravegaTimestamp {
+        PravegaTimestamp(Some(leap_second_table().utc_to_tai(utc)))
+    }
+
+    /// Create a PravegaTimestamp from a chrono DateTime.
+    /// A leap second is represented by chrono as the last second of the day (23:59:59)
+    /// with 1 to 2 billion nanoseconds.
+    /// Returns a None timestamp if the time is before 1970.
+    pub fn from_datetime<Tz: TimeZone>(t: &DateTime<Tz>) -> PravegaTimestamp {
+        let seconds = match u64::try_from(t.timestamp()) {
+            Ok(seconds) => seconds,
+            Err(_) => return PravegaTimestamp::NONE,
+        };
+        let subsec_nanoseconds = t.timestamp_subsec_nanos() as u64;
+        PravegaTimestamp::from_utc(UtcTime {
+            unix_nanoseconds: seconds * 1_000_000_000 + subsec_nanoseconds % 1_000_000_000,
+            is_leap_second: subsec_nanoseconds >= 1_000_000_000,
+        })
+    }
+
     pub fn now() -> PravegaTimestamp {
         PravegaTimestamp::from(SystemTime::now())
     }
@@ -91,29 +105,34 @@ impl PravegaTimestamp {
         self.0
     }
 
+    /// Return the number of nanoseconds since the Unix epoch 1970-01-01 00:00:00 UTC, minus leap seconds.
+    /// During a positive leap second, this repeats the last second of the day, as with POSIX time.
     pub fn to_unix_nanoseconds(&self) -> Option<u64> {
-        match self.nanoseconds() {
-            Some(nanoseconds) => {
-                if nanoseconds < PravegaTimestamp::ZERO_LEAP_SECONDS_UNTIL_SECONDS * 1_000_000_000 {
-                    Some(nanoseconds)
-                } else {
-                    Some(nanoseconds - PravegaTimestamp::UTC_TO_TAI_SECONDS * 1_000_000_000)
-                }
-            },
-            None => None,
-        }
+        self.to_utc().map(|utc| utc.unix_nanoseconds)
     }
 
+    /// Convert to UTC using the leap second table.
+    pub fn to_utc(&self) -> Option<UtcTime> {
+        self.nanoseconds().map(|nanoseconds| leap_second_table().tai_to_utc(nanoseconds))
+    }
+
+    /// Convert to a chrono DateTime.
+    /// During a positive leap second, this will be the last second of the day (23:59:59)
+    /// with 1 to 2 billion nanoseconds, which chrono formats as 23:59:60.
+    pub fn to_datetime(&self) -> Option<DateTime<Utc>> {
+        self.to_utc().and_then(|utc| {
+            let seconds = (utc.unix_nanoseconds / 1_000_000_000) as i64;
+            let mut subsec_nanoseconds = (utc.unix_nanoseconds % 1_000_000_000) as u32;
+            if utc.is_leap_second {
+                subsec_nanoseconds += 1_000_000_000;
+            }
+            Utc.timestamp_opt(seconds, subsec_nanoseconds).single()
+        })
+    }
+
+    /// Format as 2001-02-03T04:00:04.200000000Z. A leap second will be formatted as 23:59:60.
     pub fn to_iso_8601(&self) -> Option<String> {
-        match self.nanoseconds() {
-            Some(_) => {
-                let system_time: SystemTime = (*self).into();
-                let datetime: chrono::DateTime<chrono::offset::Utc> = system_time.into();
-                let formatted_time = datetime.format("%Y-%m-%dT%T.%9fZ");
-                Some(format!("{}", formatted_time))
-                },
-            None => None,
-        }
+        self.to_datetime().map(|datetime| format!("{}", datetime.format("%Y-%m-%dT%T.%9fZ")))
     }
 
     /// Convert to format h:mm:ss.fffffffff
@@ -165,7 +184,7 @@ impl From<SystemTime> for PravegaTimestamp {
 impl From<Option<chrono::DateTime<chrono::Utc>>> for PravegaTimestamp {
     fn from(t: Option<chrono::DateTime<chrono::Utc>>) -> PravegaTimestamp {
         match t {
-            Some(t) => PravegaTimestamp::from_unix_nanoseconds(u64::try_from(t.timestamp_nanos()).ok()),
+            Some(t) => PravegaTimestamp::from_datetime(&t),
             None => PravegaTimestamp::NONE,
         }
     }
@@ -178,8 +197,10 @@ impl TryFrom<Option<&str>> for PravegaTimestamp {
         match t {
             Some(t) => {
                 let dt = chrono::DateTime::parse_from_rfc3339(t)?;
-                let nanos = u64::try_from(dt.timestamp_nanos())?;
-                Ok(PravegaTimestamp::from_unix_nanoseconds(Some(nanos)))
+                match PravegaTimestamp::from_datetime(&dt) {
+                    PravegaTimestamp(None) => Err(anyhow::anyhow!("timestamp is before 1970: {}", t)),
+                    pt => Ok(pt),
+                }
             },
             None => Ok(PravegaTimestamp::NONE),
         }
@@ -197,9 +218,7 @@ impl TryFrom<Option<String>> for PravegaTimestamp {
 impl TryFrom<&String> for PravegaTimestamp {
     type Error = anyhow::Error;
     fn try_from(t:&String) -> Result<Self, Self::Error> {
-        let dt = chrono::DateTime::parse_from_rfc3339(t)?;
-        let nanos = u64::try_from(dt.timestamp_nanos())?;
-        Ok(PravegaTimestamp::from_unix_nanoseconds(Some(nanos)))
+        PravegaTimestamp::try_from(Some(t.as_str()))
     }
 }
 
@@ -215,13 +234,8 @@ impl TryFrom<String> for PravegaTimestamp {
 /// For example: 2001-02-03T04:00:04.200000000Z
 impl fmt::Display for PravegaTimestamp {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        match self.nanoseconds() {
-            Some(_) => {
-                let system_time: SystemTime = (*self).into();
-                let datetime: chrono::DateTime<chrono::offset::Utc> = system_time.into();
-                let formatted_time = datetime.format("%Y-%m-%dT%T.%9fZ");
-                f.write_fmt(format_args!("{}", formatted_time))
-                },
+        match self.to_iso_8601() {
+            Some(formatted_time) => f.write_str(&formatted_time),
             None => f.write_str("None"),
         }
     }
@@ -233,10 +247,8 @@ impl fmt::Debug for PravegaTimestamp {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self.nanoseconds() {
             Some(nanoseconds) => {
-                let system_time: SystemTime = (*self).into();
-                let datetime: chrono::DateTime<chrono::offset::Utc> = system_time.into();
-                let formatted_time = datetime.format("%Y-%m-%dT%T.%9fZ");
-                f.write_fmt(format_args!("{} ({} ns, {})", formatted_time, nanoseconds, self.to_hms().unwrap_or_default()))
+                f.write_fmt(format_args!("{} ({} ns, {})", self.to_iso_8601().unwrap_or_default(), nanoseconds,
+                    self.to_hms().unwrap_or_default()))
                 },
             None => f.write_str("None"),
         }
@@ -570,4 +582,34 @@ mod test {
         let s2 = pt1.to_iso_8601().unwrap();
         assert_eq!(s1, s2);
     }
+
+    #[test]
+    fn test_pravega_timestamp_leap_seconds() {
+        // TAI - UTC was 32 seconds from 1999-01-01 until 2006-01-01.
+        let pt1 = PravegaTimestamp::try_from(Some("2001-02-03T04:00:00Z")).unwrap();
+        assert_eq!(pt1.nanoseconds().unwrap(), (981172800 + 32) * 1_000_000_000);
+        assert_eq!(pt1.to_unix_nanoseconds().unwrap(), 981172800 * 1_000_000_000);
+
+        // A leap second was inserted at the end of 2016-12-31.
+        let s2 = "2016-12-31T23:59:59.500000000Z";
+        let s3 = "2016-12-31T23:59:60.500000000Z";
+        let s4 = "2017-01-01T00:00:00.500000000Z";
+        let pt2 = PravegaTimestamp::try_from(Some(s2)).unwrap();
+        let pt3 = PravegaTimestamp::try_from(Some(s3)).unwrap();
+        let pt4 = PravegaTimestamp::try_from(Some(s4)).unwrap();
+        assert_eq!(pt3 - pt2, SECOND);
+        assert_eq!(pt4 - pt3, SECOND);
+        assert_eq!(pt2.to_iso_8601().unwrap(), s2);
+        assert_eq!(pt3.to_iso_8601().unwrap(), s3);
+        assert_eq!(pt4.to_iso_8601().unwrap(), s4);
+        assert_eq!((pt2 + SECOND).to_iso_8601().unwrap(), s3);
+        assert_eq!(format!("{}", pt3), s3);
+        // POSIX time repeats 23:59:59 during the leap second.
+        assert_eq!(pt3.to_unix_nanoseconds(), pt2.to_unix_nanoseconds());
+        assert_eq!(PravegaTimestamp::from(pt3.to_datetime()), pt3);
+
+        // Times before the first leap second are unchanged.
+        let pt5 = PravegaTimestamp::from_nanoseconds(Some(1_000_000_000));
+        assert_eq!(pt5.to_iso_8601().unwrap(), "1970-01-01T00:00:01.000000000Z");
+    }
 }
@@ -66,6 +66,12 @@ impl CurrentHead for SyncByteReader {
 
 impl<T> CurrentHead for std::io::Cursor<T> {}
 
+impl<T: CurrentHead + ?Sized> CurrentHead for Box<T> {
+    fn current_head(&self) -> std::io::Result<u64> {
+        (**self).current_head()
+    }
+}
+
 pub fn parse_controller_uri(controller: String) -> Result<SocketAddr, AddrParseError> {
     controller.parse::<SocketAddr>()
 }
@@ -132,3 +138,18 @@ pub fn get_video_tags() -> Option<Vec<String>> {
 pub fn get_video_tag_query() -> String {
     "video".to_string()
 }
+
+/// Encode bytes as lowercase hexadecimal digits.
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decode hexadecimal digits to bytes. Returns None if the string is not valid hexadecimal.
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
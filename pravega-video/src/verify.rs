@@ -0,0 +1,531 @@
+//
+// Copyright (c) Dell Inc., or its subsidiaries. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+
+//! Consistency checker for a data stream and its index.
+//!
+//! The data stream and index are read sequentially, in a single pass, from their current heads
+//! to the provided tails.
+//! Any violations of the constraints documented in index.rs and event_serde.rs are reported.
+//! Payloads are read only to verify the checksums of events that have them.
+
+use crate::event_serde::{EventReader, is_checksum_error};
+use crate::index::{IndexRecord, IndexRecordReader, Track};
+use crate::utils::CurrentHead;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{BufReader, Error, ErrorKind, Read, Seek, SeekFrom};
+use tracing::{debug, trace};
+
+#[derive(Debug, Clone, Copy)]
+pub struct VerifyOptions {
+    /// Stop recording violations after this many. Checking continues and the counts remain accurate.
+    pub max_violations: usize,
+    /// Report a gap if consecutive index records are more than this many nanoseconds apart.
+    pub max_gap_nanos: u64,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        Self {
+            max_violations: 1000,
+            max_gap_nanos: 30_000_000_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// The stream may not be readable or seekable.
+    Error,
+    /// The stream is readable but has unexpected contents.
+    Warning,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamKind {
+    Data,
+    Index,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationKind {
+    /// An event has an invalid length. Events after this cannot be read.
+    InvalidEventLength,
+    /// The checksum of an event does not match its header and payload.
+    ChecksumMismatch,
+    /// The last event extends beyond the tail of the data stream.
+    IncompleteEvent,
+    /// The index stream length is not a multiple of the record size.
+    IncompleteIndexRecord,
+    /// An event has a timestamp of 0 (unknown).
+    EventZeroTimestamp,
+    /// An index record has a timestamp of 0, which is not allowed.
+    IndexZeroTimestamp,
+    /// An index record has a timestamp less than the previous index record of the same track.
+    IndexTimestampDecreased,
+    /// An index record has an offset less than the previous index record.
+    IndexOffsetDecreased,
+    /// An index record has an offset that is not at the beginning of an event.
+    IndexOffsetNotOnEventBoundary,
+    /// An index record has an offset before the head of the data stream.
+    IndexOffsetTruncated,
+    /// An index record has an offset after the tail of the data stream.
+    IndexOffsetBeyondTail,
+    /// An event has a timestamp earlier than the index record that precedes it.
+    EventTimestampBeforeIndexRecord,
+    /// An event has a timestamp equal to or later than the index record that follows it.
+    EventTimestampAfterNextIndexRecord,
+}
+
+/// A violation of a stream constraint.
+#[derive(Debug, Clone, Serialize)]
+pub struct Violation {
+    pub severity: Severity,
+    pub kind: ViolationKind,
+    pub stream: StreamKind,
+    /// The offset of the event or index record in its stream.
+    pub offset: u64,
+    pub message: String,
+}
+
+/// A gap in time between consecutive index records, caused by a discontinuity or a large time difference.
+#[derive(Debug, Clone, Serialize)]
+pub struct Gap {
+    /// The offset in the data stream of the index record after the gap.
+    pub offset: u64,
+    pub begin_timestamp: Option<String>,
+    pub end_timestamp: Option<String>,
+    pub seconds: f64,
+    pub discontinuity: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StreamSummary {
+    pub head: u64,
+    pub tail: u64,
+    /// The number of events or index records.
+    pub count: u64,
+    pub first_timestamp: Option<String>,
+    pub last_timestamp: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VerifyReport {
+    pub data: StreamSummary,
+    pub index: StreamSummary,
+    pub error_count: u64,
+    pub warning_count: u64,
+    pub violations: Vec<Violation>,
+    /// True if some violations were not recorded because of max_violations.
+    pub violations_truncated: bool,
+    pub gaps: Vec<Gap>,
+}
+
+impl VerifyReport {
+    /// Returns true if there are no errors. Warnings are allowed.
+    pub fn is_ok(&self) -> bool {
+        self.error_count == 0
+    }
+
+    fn add_violation(&mut self, options: &VerifyOptions, severity: Severity, kind: ViolationKind, stream: StreamKind,
+            offset: u64, message: String) {
+        trace!("verify: {:?} {:?} {:?} at offset {}: {}", severity, kind, stream, offset, message);
+        match severity {
+            Severity::Error => self.error_count += 1,
+            Severity::Warning => self.warning_count += 1,
+        }
+        if self.violations.len() < options.max_violations {
+            self.violations.push(Violation { severity, kind, stream, offset, message });
+        } else {
+            self.violations_truncated = true;
+        }
+    }
+}
+
+/// Reads index records sequentially, checking constraints between consecutive records.
+struct IndexCursor<R: Read> {
+    reader: BufReader<R>,
+    offset: u64,
+    tail: u64,
+    prev_record: Option<IndexRecord>,
+    /// The previous record of each track, or of the entire stream for the key None.
+    prev_track_records: HashMap<Option<Track>, IndexRecord>,
+}
+
+impl<R: Read> IndexCursor<R> {
+    /// Returns the next index record, or None at the tail of the index.
+    fn next(&mut self, report: &mut VerifyReport, options: &VerifyOptions) -> Result<Option<IndexRecord>, Error> {
+        if self.offset + IndexRecord::RECORD_SIZE as u64 > self.tail {
+            if self.offset < self.tail {
+                report.add_violation(options, Severity::Warning, ViolationKind::IncompleteIndexRecord, StreamKind::Index,
+                    self.offset, format!("{} bytes at the end of the index are not a complete record", self.tail - self.offset));
+                self.offset = self.tail;
+            }
+            return Ok(None);
+        }
+        let record = IndexRecordReader::new().read(&mut self.reader)?;
+        let index_offset = self.offset;
+        self.offset += IndexRecord::RECORD_SIZE as u64;
+        report.index.count += 1;
+        if record.timestamp.is_none() {
+            report.add_violation(options, Severity::Error, ViolationKind::IndexZeroTimestamp, StreamKind::Index,
+                index_offset, format!("Index record has a zero timestamp: {:?}", record));
+        } else {
+            if report.index.first_timestamp.is_none() {
+                report.index.first_timestamp = record.timestamp.to_iso_8601();
+            }
+            report.index.last_timestamp = record.timestamp.to_iso_8601();
+        }
+        // Records for individual tracks may be interleaved, so timestamps are compared with the previous record of the same track.
+        // Gaps are reported only for records of the entire stream or a video track.
+        if let Some(prev_record) = self.prev_track_records.get(&record.track).copied() {
+            if record.timestamp.is_some() {
+                if record.timestamp < prev_record.timestamp {
+                    report.add_violation(options, Severity::Error, ViolationKind::IndexTimestampDecreased, StreamKind::Index,
+                        index_offset, format!("Timestamp {} is less than previous timestamp {}", record.timestamp, prev_record.timestamp));
+                }
+                let delta = record.timestamp - prev_record.timestamp;
+                let seconds = delta.nanoseconds().unwrap_or_default() as f64 * 1e-9;
+                if record.is_stream_record() &&
+                        (record.discontinuity || delta.nanoseconds().unwrap_or_default() > options.max_gap_nanos as i128) {
+                    report.gaps.push(Gap {
+                        offset: record.offset,
+                        begin_timestamp: prev_record.timestamp.to_iso_8601(),
+                        end_timestamp: record.timestamp.to_iso_8601(),
+                        seconds,
+                        discontinuity: record.discontinuity,
+                    });
+                }
+            }
+        }
+        if let Some(prev_record) = self.prev_record {
+            if record.offset < prev_record.offset {
+                report.add_violation(options, Severity::Error, ViolationKind::IndexOffsetDecreased, StreamKind::Index,
+                    index_offset, format!("Offset {} is less than previous offset {}", record.offset, prev_record.offset));
+            }
+        }
+        self.prev_record = Some(record);
+        if record.timestamp.is_some() {
+            self.prev_track_records.insert(record.track, record);
+        }
+        Ok(Some(record))
+    }
+}
+
+/// Check the consistency of a data stream and its index.
+/// The data stream is read from its current head to data_tail.
+/// The index is read from its current head to index_tail.
+/// Only I/O errors are returned as errors. All other problems are included in the report.
+pub fn verify_stream<D, I>(data_reader: &mut D, data_tail: u64, index_reader: &mut I, index_tail: u64, options: VerifyOptions)
+        -> Result<VerifyReport, Error>
+where
+    D: Read + Seek + CurrentHead,
+    I: Read + Seek + CurrentHead,
+{
+    let mut report = VerifyReport::default();
+    report.data.head = data_reader.current_head()?;
+    report.data.tail = data_tail;
+    report.index.head = index_reader.current_head()?;
+    report.index.tail = index_tail;
+    debug!("verify_stream: BEGIN: data={:?}, index={:?}", report.data, report.index);
+
+    data_reader.seek(SeekFrom::Start(report.data.head))?;
+    let mut data = BufReader::with_capacity(1024*1024, data_reader);
+    index_reader.seek(SeekFrom::Start(report.index.head))?;
+    let mut index = IndexCursor {
+        reader: BufReader::with_capacity(64*1024, index_reader),
+        offset: report.index.head,
+        tail: index_tail,
+        prev_record: None,
+        prev_track_records: HashMap::new(),
+    };
+
+    // The index record that precedes the current event and the next index record.
+    let mut current_record: Option<IndexRecord> = None;
+    let mut next_record = index.next(&mut report, &options)?;
+
+    let mut offset = report.data.head;
+    loop {
+        // Process index records that should be at or before this event.
+        while let Some(record) = next_record {
+            if record.offset > offset {
+                break;
+            }
+            if record.offset < report.data.head {
+                report.add_violation(&options, Severity::Warning, ViolationKind::IndexOffsetTruncated, StreamKind::Index,
+                    index.offset - IndexRecord::RECORD_SIZE as u64,
+                    format!("Index record offset {} is before the data stream head {}", record.offset, report.data.head));
+            } else if record.offset < offset {
+                report.add_violation(&options, Severity::Error, ViolationKind::IndexOffsetNotOnEventBoundary, StreamKind::Index,
+                    index.offset - IndexRecord::RECORD_SIZE as u64,
+                    format!("Index record offset {} is not at the beginning of an event; the next event begins at {}", record.offset, offset));
+            } else if record.track.is_none() {
+                // An event may contain several tracks, so event timestamps are only checked against records of the entire stream.
+                current_record = Some(record);
+            }
+            next_record = index.next(&mut report, &options)?;
+        }
+
+        if offset + 8 > data_tail {
+            if offset < data_tail {
+                report.add_violation(&options, Severity::Warning, ViolationKind::IncompleteEvent, StreamKind::Data, offset,
+                    format!("{} bytes at the end of the data stream are not a complete event", data_tail - offset));
+            }
+            break;
+        }
+        let mut event_reader = EventReader::new();
+        let required_buffer_length = match event_reader.read_required_buffer_length(&mut data) {
+            Ok(length) => length as u64,
+            Err(err) if err.kind() == ErrorKind::InvalidData => {
+                report.add_violation(&options, Severity::Error, ViolationKind::InvalidEventLength, StreamKind::Data, offset,
+                    format!("{}; the rest of the data stream cannot be read", err));
+                break;
+            },
+            Err(err) => return Err(err),
+        };
+        if offset + required_buffer_length > data_tail {
+            report.add_violation(&options, Severity::Warning, ViolationKind::IncompleteEvent, StreamKind::Data, offset,
+                format!("Event of {} bytes extends beyond the tail {}", required_buffer_length, data_tail));
+            break;
+        }
+        let header = match event_reader.read_header(&mut data) {
+            Ok(header) => header,
+            Err(err) if err.kind() == ErrorKind::InvalidData => {
+                report.add_violation(&options, Severity::Error, ViolationKind::InvalidEventLength, StreamKind::Data, offset,
+                    format!("{}; the rest of the data stream cannot be read", err));
+                break;
+            },
+            Err(err) => return Err(err),
+        };
+        if header.checksum {
+            let mut payload = vec![0; event_reader.payload_length()];
+            match event_reader.read_payload(&mut data, &mut payload[..]) {
+                Ok(()) => {},
+                Err(err) if is_checksum_error(&err) => {
+                    report.add_violation(&options, Severity::Error, ViolationKind::ChecksumMismatch, StreamKind::Data, offset,
+                        err.to_string());
+                },
+                Err(err) => return Err(err),
+            }
+        } else {
+            data.seek_relative(event_reader.remaining_length() as i64)?;
+        }
+        report.data.count += 1;
+
+        match header.timestamp.nanoseconds() {
+            Some(timestamp) if timestamp > 0 => {
+                if report.data.first_timestamp.is_none() {
+                    report.data.first_timestamp = header.timestamp.to_iso_8601();
+                }
+                report.data.last_timestamp = header.timestamp.to_iso_8601();
+                // Events between two index records must have timestamps between the timestamps of the records.
+                if let Some(current_record) = current_record {
+                    if header.timestamp < current_record.timestamp {
+                        report.add_violation(&options, Severity::Error, ViolationKind::EventTimestampBeforeIndexRecord,
+                            StreamKind::Data, offset,
+                            format!("Timestamp {} is before the preceding index record {:?}", header.timestamp, current_record));
+                    }
+                    if let Some(next_record) = next_record.filter(|record| record.track.is_none() && record.timestamp.is_some()) {
+                        if header.timestamp >= next_record.timestamp {
+                            report.add_violation(&options, Severity::Error, ViolationKind::EventTimestampAfterNextIndexRecord,
+                                StreamKind::Data, offset,
+                                format!("Timestamp {} is not before the next index record {:?}", header.timestamp, next_record));
+                        }
+                    }
+                }
+            },
+            _ => {
+                report.add_violation(&options, Severity::Warning, ViolationKind::EventZeroTimestamp, StreamKind::Data, offset,
+                    "Event has a zero timestamp".to_owned());
+            },
+        }
+        offset += required_buffer_length;
+    }
+
+    // The final index record can be at the end of the last event. Records beyond it are not readable.
+    while let Some(record) = next_record {
+        if record.offset > offset {
+            report.add_violation(&options, Severity::Error, ViolationKind::IndexOffsetBeyondTail, StreamKind::Index,
+                index.offset - IndexRecord::RECORD_SIZE as u64,
+                format!("Index record offset {} is beyond the last readable event at {}", record.offset, offset));
+        }
+        next_record = index.next(&mut report, &options)?;
+    }
+
+    debug!("verify_stream: END: error_count={}, warning_count={}", report.error_count, report.warning_count);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::event_serde::{EventWithHeader, EventWriter};
+    use crate::index::{IndexRecordWriter, IndexSpacing, TrackType, rebuild_index};
+    use crate::timestamp::PravegaTimestamp;
+    use std::io::Cursor;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    fn ts(t: u64) -> PravegaTimestamp {
+        PravegaTimestamp::from_nanoseconds(Some(1_600_000_000_000_000_000 + t))
+    }
+
+    /// Returns a data stream with an event every 100 ms and a key frame every second.
+    fn create_data_stream(num_events: u64) -> Cursor<Vec<u8>> {
+        let mut data = Cursor::new(Vec::new());
+        for i in 0..num_events {
+            let payload = vec![0; 100];
+            let random_access = i % 10 == 0;
+            let event = EventWithHeader::new(&payload[..], ts(i * SECOND / 10), random_access, random_access, i == 0);
+            EventWriter::new().write(&event, &mut data).unwrap();
+        }
+        data
+    }
+
+    fn verify(data: &mut Cursor<Vec<u8>>, index: &mut Cursor<Vec<u8>>) -> VerifyReport {
+        let data_tail = data.get_ref().len() as u64;
+        let index_tail = index.get_ref().len() as u64;
+        let report = verify_stream(data, data_tail, index, index_tail, VerifyOptions::default()).unwrap();
+        debug!("verify: report={}", serde_json::to_string_pretty(&report).unwrap());
+        report
+    }
+
+    #[test]
+    fn test_verify_valid_stream() {
+        let mut data = create_data_stream(50);
+        let mut index = Cursor::new(Vec::new());
+        rebuild_index(&mut data, data.get_ref().len() as u64, &mut index, IndexSpacing::default()).unwrap();
+        let report = verify(&mut data, &mut index);
+        assert!(report.is_ok());
+        assert_eq!(report.warning_count, 0);
+        assert_eq!(report.data.count, 50);
+        assert_eq!(report.index.count, 6);
+        assert!(report.gaps.is_empty());
+    }
+
+    #[test]
+    fn test_verify_invalid_stream() {
+        let mut data = create_data_stream(30);
+        let event_size = data.get_ref().len() as u64 / 30;
+        let records = vec![
+            IndexRecord::new(ts(0), 0, true, true),
+            // Not on an event boundary.
+            IndexRecord::new(ts(SECOND), 10 * event_size + 1, true, false),
+            // Timestamp decreased and events after it are earlier.
+            IndexRecord::new(ts(SECOND / 2), 10 * event_size, true, false),
+            // Events before this have later timestamps.
+            IndexRecord::new(ts(SECOND + 1), 20 * event_size, true, false),
+            // Discontinuity and beyond the tail.
+            IndexRecord::new(ts(3 * SECOND), 40 * event_size, true, true),
+        ];
+        let mut index = Cursor::new(Vec::new());
+        for record in records.iter() {
+            IndexRecordWriter::new().write(record, &mut index).unwrap();
+        }
+        // Corrupt the length of the last event.
+        let corrupt_offset = 29 * event_size as usize;
+        data.get_mut()[corrupt_offset + 4..corrupt_offset + 8].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF]);
+
+        let report = verify(&mut data, &mut index);
+        assert!(!report.is_ok());
+        let kinds: Vec<_> = report.violations.iter().map(|violation| violation.kind).collect();
+        assert!(kinds.contains(&ViolationKind::IndexOffsetDecreased));
+        assert!(kinds.contains(&ViolationKind::IndexTimestampDecreased));
+        assert!(kinds.contains(&ViolationKind::IndexOffsetNotOnEventBoundary));
+        assert!(kinds.contains(&ViolationKind::EventTimestampAfterNextIndexRecord));
+        assert!(kinds.contains(&ViolationKind::InvalidEventLength));
+        assert!(kinds.contains(&ViolationKind::IndexOffsetBeyondTail));
+        assert_eq!(report.data.count, 29);
+        assert_eq!(report.gaps.len(), 1);
+    }
+
+    #[test]
+    fn test_verify_multi_track_index() {
+        let mut data = create_data_stream(30);
+        let event_size = data.get_ref().len() as u64 / 30;
+        let video = Track { track_type: TrackType::Video, id: 0x100 };
+        let audio = Track { track_type: TrackType::Audio, id: 0x101 };
+        let write_index = |records: &[IndexRecord]| {
+            let mut index = Cursor::new(Vec::new());
+            for record in records.iter() {
+                IndexRecordWriter::new().write(record, &mut index).unwrap();
+            }
+            index
+        };
+
+        // Timestamps of different tracks may be interleaved.
+        let mut index = write_index(&[
+            IndexRecord::new_with_track(ts(0), 0, true, true, video),
+            IndexRecord::new_with_track(ts(SECOND / 10), event_size, true, false, audio),
+            IndexRecord::new_with_track(ts(SECOND), 10 * event_size, true, false, video),
+            IndexRecord::new_with_track(ts(SECOND / 2), 10 * event_size, true, false, audio),
+            IndexRecord::new_with_track(ts(2 * SECOND), 20 * event_size, true, false, video),
+            IndexRecord::new_with_track(ts(2 * SECOND - 1), 20 * event_size, true, false, audio),
+        ]);
+        let report = verify(&mut data, &mut index);
+        assert!(report.is_ok());
+        assert_eq!(report.warning_count, 0);
+        assert_eq!(report.index.count, 6);
+        assert!(report.gaps.is_empty());
+
+        let mut index = write_index(&[
+            IndexRecord::new_with_track(ts(0), 0, true, true, video),
+            IndexRecord::new_with_track(ts(SECOND / 2), event_size, true, false, audio),
+            IndexRecord::new_with_track(ts(SECOND), 10 * event_size, true, false, video),
+            // Timestamp decreased for the audio track.
+            IndexRecord::new_with_track(ts(SECOND / 10), 10 * event_size, true, false, audio),
+            // Not on an event boundary.
+            IndexRecord::new_with_track(ts(SECOND), 10 * event_size + 1, true, false, audio),
+            // Discontinuities are gaps only for video tracks.
+            IndexRecord::new_with_track(ts(3 * SECOND), 20 * event_size, true, true, video),
+            IndexRecord::new_with_track(ts(3 * SECOND), 20 * event_size, true, true, audio),
+            // Beyond the tail.
+            IndexRecord::new_with_track(ts(5 * SECOND), 40 * event_size, true, false, video),
+        ]);
+        let report = verify(&mut data, &mut index);
+        assert!(!report.is_ok());
+        let count = |kind: ViolationKind| report.violations.iter().filter(|violation| violation.kind == kind).count();
+        assert_eq!(count(ViolationKind::IndexTimestampDecreased), 1);
+        assert_eq!(count(ViolationKind::IndexOffsetNotOnEventBoundary), 1);
+        assert_eq!(count(ViolationKind::IndexOffsetBeyondTail), 1);
+        assert_eq!(count(ViolationKind::IndexOffsetDecreased), 0);
+        assert_eq!(report.error_count, 3);
+        assert_eq!(report.gaps.len(), 1);
+        assert_eq!(report.gaps[0].offset, 20 * event_size);
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        let mut data = Cursor::new(Vec::new());
+        for i in 0..20 {
+            let payload = vec![i as u8; 100];
+            let random_access = i % 10 == 0;
+            let event = EventWithHeader::new(&payload[..], ts(i * SECOND / 10), random_access, random_access, i == 0);
+            EventWriter::new().with_checksum(true).write(&event, &mut data).unwrap();
+        }
+        let mut index = Cursor::new(Vec::new());
+        rebuild_index(&mut data, data.get_ref().len() as u64, &mut index, IndexSpacing::default()).unwrap();
+        let report = verify(&mut data, &mut index);
+        assert!(report.is_ok());
+        assert_eq!(report.data.count, 20);
+
+        // Corrupt the payload of event 5.
+        let event_size = data.get_ref().len() / 20;
+        data.get_mut()[5 * event_size + 50] ^= 0xFF;
+        let report = verify(&mut data, &mut index);
+        assert_eq!(report.error_count, 1);
+        assert_eq!(report.violations[0].kind, ViolationKind::ChecksumMismatch);
+        assert_eq!(report.violations[0].offset, 5 * event_size as u64);
+        assert_eq!(report.data.count, 20);
+    }
+}